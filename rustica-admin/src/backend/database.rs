@@ -0,0 +1,293 @@
+/// Reads and changes a Rustica SQLite database without going through the
+/// server. The schema and every change come from rustica-database, which the
+/// server uses too, and a database whose schema does not match the
+/// migrations this was built with is refused rather than guessed at.
+use super::AdminError;
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+
+use crate::rustica::{
+    Grants, IssuedCertificate, Permissions, RegisteredKey, X509Authorization,
+};
+use rustica_database::grants;
+use rustica_database::ledger::{
+    issued_certificate_identities, issued_certificates, IssuedCertificateRow,
+};
+use rustica_database::{models, prepare_schema, sqlite_connection, DatabaseConnection};
+
+use std::collections::HashMap;
+
+pub struct Database {
+    conn: DatabaseConnection,
+    /// The issuance ledger is kept in its own database
+    ledger: Option<SqliteConnection>,
+    /// Used for requests that do not name an authority
+    default_authority: Option<String>,
+}
+
+fn database_error<E: std::fmt::Display>(e: E) -> AdminError {
+    AdminError::DatabaseError(e.to_string())
+}
+
+fn open(path: &str) -> Result<SqliteConnection, AdminError> {
+    // Unlike Rustica, never create a database that does not exist
+    if !std::path::Path::new(path).is_file() {
+        return Err(AdminError::DatabaseError(format!("{path} does not exist")));
+    }
+
+    let mut conn = SqliteConnection::establish(path).map_err(database_error)?;
+    // Rustica may be using the database at the same time
+    conn.batch_execute("PRAGMA busy_timeout = 5000;")
+        .map_err(database_error)?;
+    Ok(conn)
+}
+
+rustica_database::admin_conversions!(Grants, Permissions, RegisteredKey, X509Authorization);
+
+fn to_issued_certificate(
+    row: IssuedCertificateRow,
+    mtls_identities: Vec<String>,
+) -> Result<IssuedCertificate, AdminError> {
+    Ok(IssuedCertificate {
+        certificate_type: row.certificate_type,
+        serial: row.serial,
+        fingerprint: row.fingerprint,
+        signed_by: row.signed_by,
+        authority: row.authority,
+        mtls_identities,
+        principals: serde_json::from_str(&row.principals).map_err(database_error)?,
+        extensions: serde_json::from_str(&row.extensions).map_err(database_error)?,
+        critical_options: serde_json::from_str(&row.critical_options).map_err(database_error)?,
+        // Stored with `as` so the full u64 range survives
+        valid_after: row.valid_after as u64,
+        valid_before: row.valid_before as u64,
+        issued_at: row.issued_at as u64,
+        requester_ip: row.requester_ip,
+    })
+}
+
+impl Database {
+    pub fn open(
+        path: &str,
+        ledger: Option<&str>,
+        default_authority: Option<String>,
+    ) -> Result<Self, AdminError> {
+        let mut conn = sqlite_connection(open(path)?);
+        prepare_schema(&mut conn, false).map_err(database_error)?;
+
+        let ledger = ledger.map(open).transpose()?;
+        Ok(Self {
+            conn,
+            ledger,
+            default_authority,
+        })
+    }
+
+    /// Empty authorities mean the default authority, the same as in the
+    /// RusticaAdmin service. Only the server's configuration names it so it
+    /// has to be given when using a database file.
+    fn resolve_authority(&self, authority: String) -> Result<String, AdminError> {
+        if !authority.is_empty() {
+            return Ok(authority);
+        }
+
+        self.default_authority.clone().ok_or_else(|| {
+            AdminError::Unsupported(
+                "The default authority is only known to the server. Pass --authority or --default-authority when using a database file.".to_owned(),
+            )
+        })
+    }
+
+    pub fn list_grants(
+        &mut self,
+        fingerprint: Option<String>,
+        authority: Option<String>,
+    ) -> Result<Vec<Grants>, AdminError> {
+        Ok(grants::list_grants(&mut self.conn, fingerprint, authority)
+            .map_err(database_error)?
+            .into_iter()
+            .map(to_grants)
+            .collect())
+    }
+
+    pub fn grant_principals(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        principals: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        let authority = self.resolve_authority(authority)?;
+        grants::grant_principals(&mut self.conn, &fingerprint, &authority, principals)
+            .map_err(database_error)
+    }
+
+    pub fn revoke_principals(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        principals: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        let authority = self.resolve_authority(authority)?;
+        grants::revoke_principals(&mut self.conn, &fingerprint, &authority, &principals)
+            .map_err(database_error)
+    }
+
+    pub fn grant_hosts(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        hosts: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        let authority = self.resolve_authority(authority)?;
+        grants::grant_hosts(&mut self.conn, &fingerprint, &authority, hosts)
+            .map_err(database_error)
+    }
+
+    pub fn revoke_hosts(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        hosts: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        let authority = self.resolve_authority(authority)?;
+        grants::revoke_hosts(&mut self.conn, &fingerprint, &authority, &hosts)
+            .map_err(database_error)
+    }
+
+    /// Extensions with an empty value are stored without one
+    pub fn grant_extensions(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        extensions: HashMap<String, String>,
+    ) -> Result<u64, AdminError> {
+        let authority = self.resolve_authority(authority)?;
+        let extensions = extensions
+            .into_iter()
+            .map(|(name, value)| (name, Some(value).filter(|value| !value.is_empty())))
+            .collect();
+        grants::grant_extensions(&mut self.conn, &fingerprint, &authority, extensions)
+            .map_err(database_error)
+    }
+
+    pub fn revoke_extensions(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        extensions: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        let authority = self.resolve_authority(authority)?;
+        grants::revoke_extensions(&mut self.conn, &fingerprint, &authority, &extensions)
+            .map_err(database_error)
+    }
+
+    pub fn set_permissions(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        permissions: Permissions,
+    ) -> Result<u64, AdminError> {
+        let authority = self.resolve_authority(authority)?;
+        let permissions = models::FingerprintPermission {
+            fingerprint,
+            host_unrestricted: permissions.host_unrestricted,
+            principal_unrestricted: permissions.principal_unrestricted,
+            can_create_host_certs: permissions.can_create_host_certs,
+            can_create_user_certs: permissions.can_create_user_certs,
+            max_creation_time: permissions.max_creation_time.min(i64::MAX as u64) as i64,
+            authority,
+        };
+        grants::set_permissions(&mut self.conn, &permissions).map_err(database_error)
+    }
+
+    pub fn delete_permissions(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+    ) -> Result<u64, AdminError> {
+        let authority = self.resolve_authority(authority)?;
+        grants::delete_permissions(&mut self.conn, &fingerprint, &authority)
+            .map_err(database_error)
+    }
+
+    pub fn list_registered_keys(
+        &mut self,
+        user: Option<String>,
+    ) -> Result<Vec<RegisteredKey>, AdminError> {
+        Ok(grants::list_registered_keys(&mut self.conn, user.as_deref())
+            .map_err(database_error)?
+            .into_iter()
+            .map(to_registered_key)
+            .collect())
+    }
+
+    pub fn list_x509_authorizations(
+        &mut self,
+        user: Option<String>,
+    ) -> Result<Vec<X509Authorization>, AdminError> {
+        Ok(grants::list_x509_authorizations(&mut self.conn, user)
+            .map_err(database_error)?
+            .into_iter()
+            .map(to_x509_authorization)
+            .collect())
+    }
+
+    pub fn set_x509_authorization(
+        &mut self,
+        authorization: X509Authorization,
+    ) -> Result<u64, AdminError> {
+        let authorization = models::X509Authorization {
+            authority: self.resolve_authority(authorization.authority)?,
+            user: authorization.user,
+            hsm_serial: authorization.hsm_serial,
+            require_touch: authorization.require_touch,
+        };
+        grants::set_x509_authorization(&mut self.conn, &authorization).map_err(database_error)
+    }
+
+    pub fn delete_x509_authorization(
+        &mut self,
+        user: String,
+        hsm_serial: String,
+    ) -> Result<u64, AdminError> {
+        grants::delete_x509_authorization(&mut self.conn, &user, &hsm_serial)
+            .map_err(database_error)
+    }
+
+    pub fn find_issued_certificates(
+        &mut self,
+        serial: String,
+        fingerprint: Option<String>,
+    ) -> Result<Vec<IssuedCertificate>, AdminError> {
+        let conn = self.ledger.as_mut().ok_or_else(|| {
+            AdminError::Unsupported(
+                "Pass --ledger to look up certificates when using a database file".to_owned(),
+            )
+        })?;
+
+        let mut query = issued_certificates::table
+            .filter(issued_certificates::serial.eq(serial))
+            .order(issued_certificates::issued_at.desc())
+            .into_boxed();
+        if let Some(fingerprint) = fingerprint {
+            query = query.filter(issued_certificates::fingerprint.eq(fingerprint));
+        }
+        let rows = query
+            .load::<IssuedCertificateRow>(conn)
+            .map_err(database_error)?;
+
+        let mut certificates = vec![];
+        for row in rows {
+            let mtls_identities = issued_certificate_identities::table
+                .filter(issued_certificate_identities::certificate_id.eq(row.id))
+                .select(issued_certificate_identities::identity)
+                .load::<String>(conn)
+                .map_err(database_error)?;
+
+            certificates.push(to_issued_certificate(row, mtls_identities)?);
+        }
+        Ok(certificates)
+    }
+}
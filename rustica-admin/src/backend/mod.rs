@@ -0,0 +1,259 @@
+/// The places rustica-admin reads and changes authorizations. Normally this
+/// is a running Rustica server, reached over mTLS, so every change is checked
+/// and logged by the server. The SQLite database can also be opened directly
+/// for when the server cannot be reached.
+mod database;
+mod server;
+
+pub use database::Database;
+pub use server::Server;
+
+use crate::rustica::{
+    Authority, Grants, IssuedCertificate, Permissions, RegisteredKey, X509Authorization,
+};
+
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub enum AdminError {
+    /// None of the configured servers could be reached
+    ConnectionError(String),
+    /// The server refused or failed the call
+    ServerError(String),
+    DatabaseError(String),
+    /// The backend cannot do what was asked
+    Unsupported(String),
+}
+
+impl std::fmt::Display for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ConnectionError(e) => write!(f, "Could not connect to Rustica: {e}"),
+            Self::ServerError(e) => write!(f, "Rustica returned {e}"),
+            Self::DatabaseError(e) => write!(f, "Database error: {e}"),
+            Self::Unsupported(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<tonic::Status> for AdminError {
+    fn from(e: tonic::Status) -> Self {
+        if e.message().is_empty() {
+            Self::ServerError(format!("{:?}", e.code()))
+        } else {
+            Self::ServerError(format!("{:?}: {}", e.code(), e.message()))
+        }
+    }
+}
+
+impl std::error::Error for AdminError {}
+
+pub enum Backend {
+    Server(Server),
+    Database(Database),
+}
+
+/// Empty authorities mean the default authority in every call, the same as
+/// the RusticaAdmin service.
+impl Backend {
+    pub async fn list_grants(
+        &mut self,
+        fingerprint: Option<String>,
+        authority: Option<String>,
+    ) -> Result<Vec<Grants>, AdminError> {
+        match self {
+            Self::Server(server) => server.list_grants(fingerprint, authority).await,
+            Self::Database(database) => database.list_grants(fingerprint, authority),
+        }
+    }
+
+    pub async fn grant_principals(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        principals: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        match self {
+            Self::Server(server) => {
+                server
+                    .grant_principals(fingerprint, authority, principals)
+                    .await
+            }
+            Self::Database(database) => {
+                database.grant_principals(fingerprint, authority, principals)
+            }
+        }
+    }
+
+    pub async fn revoke_principals(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        principals: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        match self {
+            Self::Server(server) => {
+                server
+                    .revoke_principals(fingerprint, authority, principals)
+                    .await
+            }
+            Self::Database(database) => {
+                database.revoke_principals(fingerprint, authority, principals)
+            }
+        }
+    }
+
+    pub async fn grant_hosts(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        hosts: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        match self {
+            Self::Server(server) => server.grant_hosts(fingerprint, authority, hosts).await,
+            Self::Database(database) => database.grant_hosts(fingerprint, authority, hosts),
+        }
+    }
+
+    pub async fn revoke_hosts(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        hosts: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        match self {
+            Self::Server(server) => server.revoke_hosts(fingerprint, authority, hosts).await,
+            Self::Database(database) => database.revoke_hosts(fingerprint, authority, hosts),
+        }
+    }
+
+    /// Extensions without a value have an empty string
+    pub async fn grant_extensions(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        extensions: HashMap<String, String>,
+    ) -> Result<u64, AdminError> {
+        match self {
+            Self::Server(server) => {
+                server
+                    .grant_extensions(fingerprint, authority, extensions)
+                    .await
+            }
+            Self::Database(database) => {
+                database.grant_extensions(fingerprint, authority, extensions)
+            }
+        }
+    }
+
+    pub async fn revoke_extensions(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        extensions: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        match self {
+            Self::Server(server) => {
+                server
+                    .revoke_extensions(fingerprint, authority, extensions)
+                    .await
+            }
+            Self::Database(database) => {
+                database.revoke_extensions(fingerprint, authority, extensions)
+            }
+        }
+    }
+
+    pub async fn set_permissions(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+        permissions: Permissions,
+    ) -> Result<u64, AdminError> {
+        match self {
+            Self::Server(server) => {
+                server
+                    .set_permissions(fingerprint, authority, permissions)
+                    .await
+            }
+            Self::Database(database) => {
+                database.set_permissions(fingerprint, authority, permissions)
+            }
+        }
+    }
+
+    pub async fn delete_permissions(
+        &mut self,
+        fingerprint: String,
+        authority: String,
+    ) -> Result<u64, AdminError> {
+        match self {
+            Self::Server(server) => server.delete_permissions(fingerprint, authority).await,
+            Self::Database(database) => database.delete_permissions(fingerprint, authority),
+        }
+    }
+
+    pub async fn list_registered_keys(
+        &mut self,
+        user: Option<String>,
+    ) -> Result<Vec<RegisteredKey>, AdminError> {
+        match self {
+            Self::Server(server) => server.list_registered_keys(user).await,
+            Self::Database(database) => database.list_registered_keys(user),
+        }
+    }
+
+    pub async fn list_x509_authorizations(
+        &mut self,
+        user: Option<String>,
+    ) -> Result<Vec<X509Authorization>, AdminError> {
+        match self {
+            Self::Server(server) => server.list_x509_authorizations(user).await,
+            Self::Database(database) => database.list_x509_authorizations(user),
+        }
+    }
+
+    pub async fn set_x509_authorization(
+        &mut self,
+        authorization: X509Authorization,
+    ) -> Result<u64, AdminError> {
+        match self {
+            Self::Server(server) => server.set_x509_authorization(authorization).await,
+            Self::Database(database) => database.set_x509_authorization(authorization),
+        }
+    }
+
+    pub async fn delete_x509_authorization(
+        &mut self,
+        user: String,
+        hsm_serial: String,
+    ) -> Result<u64, AdminError> {
+        match self {
+            Self::Server(server) => server.delete_x509_authorization(user, hsm_serial).await,
+            Self::Database(database) => database.delete_x509_authorization(user, hsm_serial),
+        }
+    }
+
+    /// Only the server knows its authorities and their keys
+    pub async fn authorities(&mut self) -> Result<Vec<Authority>, AdminError> {
+        match self {
+            Self::Server(server) => server.authorities().await,
+            Self::Database(_) => Err(AdminError::Unsupported(
+                "Authorities can only be read from a running Rustica server".to_owned(),
+            )),
+        }
+    }
+
+    /// Find a certificate in the issuance ledger by its serial, and its key
+    /// fingerprint when known
+    pub async fn find_issued_certificates(
+        &mut self,
+        serial: String,
+        fingerprint: Option<String>,
+    ) -> Result<Vec<IssuedCertificate>, AdminError> {
+        match self {
+            Self::Server(server) => server.find_issued_certificates(serial, fingerprint).await,
+            Self::Database(database) => database.find_issued_certificates(serial, fingerprint),
+        }
+    }
+}
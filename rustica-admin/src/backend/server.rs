@@ -0,0 +1,317 @@
+use super::AdminError;
+
+use crate::rustica::{
+    AuthoritiesRequest, Authority, DeletePermissionsRequest, DeleteX509AuthorizationRequest,
+    GrantExtensionsRequest, Grants, HostsRequest, IssuedCertificate, ListGrantsRequest,
+    ListRegisteredKeysRequest, ListX509AuthorizationsRequest, Permissions, PrincipalsRequest,
+    RegisteredKey, RevokeExtensionsRequest, SearchIssuedCertificatesRequest, SetPermissionsRequest,
+    SetX509AuthorizationRequest, X509Authorization,
+};
+use crate::config::RusticaServer;
+use crate::rustica::rustica_admin_client::RusticaAdminClient;
+use crate::rustica::rustica_client::RusticaClient;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+
+/// Open an mTLS connection to a server, the same way rustica-agent does
+async fn channel(server: &RusticaServer) -> Result<Channel, String> {
+    let tls = ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(&server.ca_pem))
+        .identity(Identity::from_pem(&server.mtls_cert, &server.mtls_key));
+
+    Channel::from_shared(server.address.clone())
+        .map_err(|_| String::from("Invalid server address"))?
+        .timeout(Duration::from_secs(10))
+        .connect_timeout(Duration::from_secs(5))
+        .tls_config(tls)
+        .map_err(|e| e.to_string())?
+        .connect()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Clients for both services share one connection
+pub struct Server {
+    channel: Channel,
+}
+
+impl Server {
+    /// Connect to the first server that can be reached
+    pub async fn connect(servers: &[RusticaServer]) -> Result<Self, AdminError> {
+        let mut last_error = String::from("No servers are configured");
+        for server in servers {
+            match channel(server).await {
+                Ok(channel) => return Ok(Self { channel }),
+                Err(e) => {
+                    warn!("Could not connect to {}: {e}", server.address);
+                    last_error = format!("{}: {e}", server.address);
+                }
+            }
+        }
+        Err(AdminError::ConnectionError(last_error))
+    }
+
+    fn admin(&self) -> RusticaAdminClient<Channel> {
+        RusticaAdminClient::new(self.channel.clone())
+    }
+
+    fn rustica(&self) -> RusticaClient<Channel> {
+        RusticaClient::new(self.channel.clone())
+    }
+
+    pub async fn list_grants(
+        &self,
+        fingerprint: Option<String>,
+        authority: Option<String>,
+    ) -> Result<Vec<Grants>, AdminError> {
+        let request = ListGrantsRequest {
+            fingerprint: fingerprint.unwrap_or_default(),
+            authority: authority.unwrap_or_default(),
+        };
+        Ok(self.admin().list_grants(request).await?.into_inner().grants)
+    }
+
+    pub async fn grant_principals(
+        &self,
+        fingerprint: String,
+        authority: String,
+        principals: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        let request = PrincipalsRequest {
+            fingerprint,
+            authority,
+            principals,
+        };
+        Ok(self
+            .admin()
+            .grant_principals(request)
+            .await?
+            .into_inner()
+            .changed)
+    }
+
+    pub async fn revoke_principals(
+        &self,
+        fingerprint: String,
+        authority: String,
+        principals: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        let request = PrincipalsRequest {
+            fingerprint,
+            authority,
+            principals,
+        };
+        Ok(self
+            .admin()
+            .revoke_principals(request)
+            .await?
+            .into_inner()
+            .changed)
+    }
+
+    pub async fn grant_hosts(
+        &self,
+        fingerprint: String,
+        authority: String,
+        hosts: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        let request = HostsRequest {
+            fingerprint,
+            authority,
+            hosts,
+        };
+        Ok(self
+            .admin()
+            .grant_hosts(request)
+            .await?
+            .into_inner()
+            .changed)
+    }
+
+    pub async fn revoke_hosts(
+        &self,
+        fingerprint: String,
+        authority: String,
+        hosts: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        let request = HostsRequest {
+            fingerprint,
+            authority,
+            hosts,
+        };
+        Ok(self
+            .admin()
+            .revoke_hosts(request)
+            .await?
+            .into_inner()
+            .changed)
+    }
+
+    pub async fn grant_extensions(
+        &self,
+        fingerprint: String,
+        authority: String,
+        extensions: HashMap<String, String>,
+    ) -> Result<u64, AdminError> {
+        let request = GrantExtensionsRequest {
+            fingerprint,
+            authority,
+            extensions,
+        };
+        Ok(self
+            .admin()
+            .grant_extensions(request)
+            .await?
+            .into_inner()
+            .changed)
+    }
+
+    pub async fn revoke_extensions(
+        &self,
+        fingerprint: String,
+        authority: String,
+        extensions: Vec<String>,
+    ) -> Result<u64, AdminError> {
+        let request = RevokeExtensionsRequest {
+            fingerprint,
+            authority,
+            extensions,
+        };
+        Ok(self
+            .admin()
+            .revoke_extensions(request)
+            .await?
+            .into_inner()
+            .changed)
+    }
+
+    pub async fn set_permissions(
+        &self,
+        fingerprint: String,
+        authority: String,
+        permissions: Permissions,
+    ) -> Result<u64, AdminError> {
+        let request = SetPermissionsRequest {
+            fingerprint,
+            authority,
+            permissions: Some(permissions),
+        };
+        Ok(self
+            .admin()
+            .set_permissions(request)
+            .await?
+            .into_inner()
+            .changed)
+    }
+
+    pub async fn delete_permissions(
+        &self,
+        fingerprint: String,
+        authority: String,
+    ) -> Result<u64, AdminError> {
+        let request = DeletePermissionsRequest {
+            fingerprint,
+            authority,
+        };
+        Ok(self
+            .admin()
+            .delete_permissions(request)
+            .await?
+            .into_inner()
+            .changed)
+    }
+
+    pub async fn list_registered_keys(
+        &self,
+        user: Option<String>,
+    ) -> Result<Vec<RegisteredKey>, AdminError> {
+        let request = ListRegisteredKeysRequest {
+            user: user.unwrap_or_default(),
+        };
+        Ok(self
+            .admin()
+            .list_registered_keys(request)
+            .await?
+            .into_inner()
+            .keys)
+    }
+
+    pub async fn list_x509_authorizations(
+        &self,
+        user: Option<String>,
+    ) -> Result<Vec<X509Authorization>, AdminError> {
+        let request = ListX509AuthorizationsRequest {
+            user: user.unwrap_or_default(),
+        };
+        Ok(self
+            .admin()
+            .list_x509_authorizations(request)
+            .await?
+            .into_inner()
+            .authorizations)
+    }
+
+    pub async fn set_x509_authorization(
+        &self,
+        authorization: X509Authorization,
+    ) -> Result<u64, AdminError> {
+        let request = SetX509AuthorizationRequest {
+            authorization: Some(authorization),
+        };
+        Ok(self
+            .admin()
+            .set_x509_authorization(request)
+            .await?
+            .into_inner()
+            .changed)
+    }
+
+    pub async fn delete_x509_authorization(
+        &self,
+        user: String,
+        hsm_serial: String,
+    ) -> Result<u64, AdminError> {
+        let request = DeleteX509AuthorizationRequest { user, hsm_serial };
+        Ok(self
+            .admin()
+            .delete_x509_authorization(request)
+            .await?
+            .into_inner()
+            .changed)
+    }
+
+    pub async fn authorities(&self) -> Result<Vec<Authority>, AdminError> {
+        let request = AuthoritiesRequest {
+            known_hosts_pattern: String::new(),
+        };
+        Ok(self
+            .rustica()
+            .get_authorities(request)
+            .await?
+            .into_inner()
+            .authorities)
+    }
+
+    pub async fn find_issued_certificates(
+        &self,
+        serial: String,
+        fingerprint: Option<String>,
+    ) -> Result<Vec<IssuedCertificate>, AdminError> {
+        let request = SearchIssuedCertificatesRequest {
+            serial,
+            fingerprint: fingerprint.unwrap_or_default(),
+            // The most the server returns
+            limit: 1000,
+            ..Default::default()
+        };
+        Ok(self
+            .rustica()
+            .search_issued_certificates(request)
+            .await?
+            .into_inner()
+            .certificates)
+    }
+}
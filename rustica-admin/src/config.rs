@@ -0,0 +1,679 @@
+use clap::{Arg, ArgMatches, Command};
+
+use crate::rustica::{Permissions, X509Authorization};
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+use crate::transfer::Format;
+
+#[derive(Debug)]
+pub enum ConfigurationError {
+    BadConfiguration(String),
+    BadArgument(String),
+    NoMode,
+}
+
+impl std::fmt::Display for ConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadConfiguration(e) => write!(f, "Could not read the configuration: {e}"),
+            Self::BadArgument(e) => write!(f, "{e}"),
+            Self::NoMode => write!(f, "No command was given"),
+        }
+    }
+}
+
+/// How to reach a Rustica server over mTLS
+#[derive(Clone, Debug, Deserialize)]
+pub struct RusticaServer {
+    pub address: String,
+    pub ca_pem: String,
+    pub mtls_cert: String,
+    pub mtls_key: String,
+}
+
+/// The servers in a RusticaAgent configuration. Both the original format
+/// with a single server and the versioned format with a list of servers are
+/// read, and everything else in the file is ignored.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AgentConfiguration {
+    Servers {
+        servers: Vec<RusticaServer>,
+    },
+    Server {
+        server: String,
+        ca_pem: String,
+        mtls_cert: String,
+        mtls_key: String,
+    },
+}
+
+impl From<AgentConfiguration> for Vec<RusticaServer> {
+    fn from(configuration: AgentConfiguration) -> Self {
+        match configuration {
+            AgentConfiguration::Servers { servers } => servers,
+            AgentConfiguration::Server {
+                server,
+                ca_pem,
+                mtls_cert,
+                mtls_key,
+            } => vec![RusticaServer {
+                address: server,
+                ca_pem,
+                mtls_cert,
+                mtls_key,
+            }],
+        }
+    }
+}
+
+fn parse_servers(configuration: &str) -> Result<Vec<RusticaServer>, ConfigurationError> {
+    toml::from_str::<AgentConfiguration>(configuration)
+        .map(Vec::from)
+        .map_err(|e| ConfigurationError::BadConfiguration(e.to_string()))
+}
+
+/// Where changes are made
+pub enum Target {
+    /// A running Rustica server, reached with the mTLS credentials in a
+    /// rustica-agent configuration
+    Server(Vec<RusticaServer>),
+    /// A SQLite database opened directly, along with the issuance ledger if
+    /// one was given
+    Database {
+        path: String,
+        ledger: Option<String>,
+        /// The server's default authority, used when no authority is given
+        default_authority: Option<String>,
+    },
+}
+
+/// Principals, hosts, and extensions to grant to or revoke from a key
+pub struct GrantChange {
+    pub fingerprint: String,
+    pub authority: String,
+    pub principals: Vec<String>,
+    pub hosts: Vec<String>,
+    /// Extensions without a value have an empty string
+    pub extensions: HashMap<String, String>,
+}
+
+pub enum RusticaAdminAction {
+    ListGrants {
+        fingerprint: Option<String>,
+        authority: Option<String>,
+    },
+    Grant(GrantChange),
+    Revoke(GrantChange),
+    SetPermissions {
+        fingerprint: String,
+        authority: String,
+        permissions: Permissions,
+    },
+    DeletePermissions {
+        fingerprint: String,
+        authority: String,
+    },
+    ListKeys {
+        user: Option<String>,
+    },
+    ListX509 {
+        user: Option<String>,
+    },
+    SetX509(X509Authorization),
+    DeleteX509 {
+        user: String,
+        hsm_serial: String,
+    },
+    Export {
+        format: Format,
+        out: Option<String>,
+    },
+    Import {
+        path: String,
+        format: Format,
+    },
+    Explain {
+        path: String,
+    },
+    Authorities {
+        authority: Option<String>,
+    },
+}
+
+fn comma_separated(matches: &ArgMatches, name: &str) -> Vec<String> {
+    matches
+        .value_of(name)
+        .map(|v| {
+            v.split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn optional(matches: &ArgMatches, name: &str) -> Option<String> {
+    matches.value_of(name).map(|v| v.to_string())
+}
+
+/// Empty authorities mean the default authority
+fn authority(matches: &ArgMatches) -> String {
+    optional(matches, "authority").unwrap_or_default()
+}
+
+fn fingerprint_arg<'a>() -> Arg<'a> {
+    Arg::new("fingerprint")
+        .help("The SHA256 fingerprint of the key, without the SHA256: prefix")
+        .long("fingerprint")
+        .short('f')
+        .takes_value(true)
+}
+
+fn authority_arg<'a>() -> Arg<'a> {
+    Arg::new("authority")
+        .help("The authority to use. Defaults to the server's default authority.")
+        .long("authority")
+        .short('a')
+        .takes_value(true)
+}
+
+fn format_arg<'a>() -> Arg<'a> {
+    Arg::new("format")
+        .help("The file format. Defaults to the file's extension, or TOML.")
+        .long("format")
+        .possible_value("toml")
+        .possible_value("json")
+        .takes_value(true)
+}
+
+fn new_grant_change_subcommand<'a>(name: &'a str, about: &'a str) -> Command<'a> {
+    Command::new(name)
+        .about(about)
+        .arg(fingerprint_arg().required(true))
+        .arg(authority_arg())
+        .arg(
+            Arg::new("principals")
+                .help("A comma separated list of principals")
+                .long("principals")
+                .short('n')
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("hosts")
+                .help("A comma separated list of hostnames")
+                .long("hosts")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("extensions")
+                .help("A comma separated list of extensions. Values can be given as name=value when granting.")
+                .long("extensions")
+                .takes_value(true),
+        )
+}
+
+fn parse_grant_change(matches: &ArgMatches) -> GrantChange {
+    let extensions = comma_separated(matches, "extensions")
+        .into_iter()
+        .map(|extension| match extension.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => (extension, String::new()),
+        })
+        .collect();
+
+    GrantChange {
+        fingerprint: optional(matches, "fingerprint").unwrap_or_default(),
+        authority: authority(matches),
+        principals: comma_separated(matches, "principals"),
+        hosts: comma_separated(matches, "hosts"),
+        extensions,
+    }
+}
+
+fn parse_format(matches: &ArgMatches, path: Option<&str>) -> Format {
+    match matches.value_of("format") {
+        Some("json") => Format::Json,
+        Some(_) => Format::Toml,
+        None => path.map(Format::from_path).unwrap_or(Format::Toml),
+    }
+}
+
+fn parse_target(matches: &ArgMatches) -> Result<Target, ConfigurationError> {
+    if let Some(path) = matches.value_of("database") {
+        return Ok(Target::Database {
+            path: path.to_string(),
+            ledger: optional(matches, "ledger"),
+            default_authority: optional(matches, "default-authority"),
+        });
+    }
+
+    let configuration = std::fs::read_to_string(matches.value_of("config").unwrap())
+        .map_err(|e| ConfigurationError::BadConfiguration(e.to_string()))?;
+    Ok(Target::Server(parse_servers(&configuration)?))
+}
+
+fn parse_action(matches: &ArgMatches) -> Result<RusticaAdminAction, ConfigurationError> {
+    if let Some(cmd) = matches.subcommand_matches("grants") {
+        return Ok(RusticaAdminAction::ListGrants {
+            fingerprint: optional(cmd, "fingerprint"),
+            authority: optional(cmd, "authority"),
+        });
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("grant") {
+        return Ok(RusticaAdminAction::Grant(parse_grant_change(cmd)));
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("revoke") {
+        return Ok(RusticaAdminAction::Revoke(parse_grant_change(cmd)));
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("set-permissions") {
+        let max_creation_time = cmd
+            .value_of("max-creation-time")
+            .unwrap()
+            .parse::<u64>()
+            .map_err(|_| {
+                ConfigurationError::BadArgument(
+                    "max-creation-time must be a number of seconds".to_owned(),
+                )
+            })?;
+
+        return Ok(RusticaAdminAction::SetPermissions {
+            fingerprint: optional(cmd, "fingerprint").unwrap_or_default(),
+            authority: authority(cmd),
+            permissions: Permissions {
+                host_unrestricted: cmd.is_present("host-unrestricted"),
+                principal_unrestricted: cmd.is_present("principal-unrestricted"),
+                can_create_host_certs: cmd.is_present("host-certs"),
+                can_create_user_certs: cmd.is_present("user-certs"),
+                max_creation_time,
+            },
+        });
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("delete-permissions") {
+        return Ok(RusticaAdminAction::DeletePermissions {
+            fingerprint: optional(cmd, "fingerprint").unwrap_or_default(),
+            authority: authority(cmd),
+        });
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("keys") {
+        return Ok(RusticaAdminAction::ListKeys {
+            user: optional(cmd, "user"),
+        });
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("x509") {
+        return Ok(RusticaAdminAction::ListX509 {
+            user: optional(cmd, "user"),
+        });
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("set-x509") {
+        return Ok(RusticaAdminAction::SetX509(X509Authorization {
+            user: optional(cmd, "user").unwrap_or_default(),
+            hsm_serial: optional(cmd, "hsm-serial").unwrap_or_default(),
+            require_touch: cmd.is_present("require-touch"),
+            authority: authority(cmd),
+        }));
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("delete-x509") {
+        return Ok(RusticaAdminAction::DeleteX509 {
+            user: optional(cmd, "user").unwrap_or_default(),
+            hsm_serial: optional(cmd, "hsm-serial").unwrap_or_default(),
+        });
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("export") {
+        let out = optional(cmd, "out");
+        return Ok(RusticaAdminAction::Export {
+            format: parse_format(cmd, out.as_deref()),
+            out,
+        });
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("import") {
+        let path = optional(cmd, "path").unwrap_or_default();
+        return Ok(RusticaAdminAction::Import {
+            format: parse_format(cmd, Some(&path)),
+            path,
+        });
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("explain") {
+        return Ok(RusticaAdminAction::Explain {
+            path: optional(cmd, "path").unwrap_or_default(),
+        });
+    }
+
+    if let Some(cmd) = matches.subcommand_matches("authorities") {
+        return Ok(RusticaAdminAction::Authorities {
+            authority: optional(cmd, "authority"),
+        });
+    }
+
+    Err(ConfigurationError::NoMode)
+}
+
+fn command() -> Command<'static> {
+    let user_arg = || {
+        Arg::new("user")
+            .help("Only show entries for this user")
+            .long("user")
+            .short('u')
+            .takes_value(true)
+    };
+
+    let x509_args = |cmd: Command<'static>| {
+        cmd.arg(
+            Arg::new("user")
+                .help("The user the key belongs to")
+                .long("user")
+                .short('u')
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("hsm-serial")
+                .help("The serial of the Yubikey holding the key")
+                .long("hsm-serial")
+                .required(true)
+                .takes_value(true),
+        )
+    };
+
+    Command::new("rustica-admin")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Manage the authorizations and certificates of a Rustica server")
+        .arg(
+            Arg::new("config")
+                .help("A rustica-agent configuration with the server address and admin mTLS credentials")
+                .long("config")
+                .default_value("/etc/rustica/config.toml")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("database")
+                .help("Use this SQLite database directly instead of a server. Changes are not checked or logged by Rustica.")
+                .long("database")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("ledger")
+                .help("The issuance ledger SQLite database to search when using --database")
+                .long("ledger")
+                .global(true)
+                .requires("database")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("default-authority")
+                .help("The authority to use when --authority is not given with --database. This should be the server's default authority.")
+                .long("default-authority")
+                .global(true)
+                .requires("database")
+                .takes_value(true),
+        )
+        .subcommand(
+            Command::new("grants")
+                .about("List the grants of every key, or only one key or authority")
+                .arg(fingerprint_arg())
+                .arg(authority_arg().help("Only show grants for this authority")),
+        )
+        .subcommand(new_grant_change_subcommand(
+            "grant",
+            "Grant principals, hosts, or extensions to a key",
+        ))
+        .subcommand(new_grant_change_subcommand(
+            "revoke",
+            "Revoke principals, hosts, or extensions from a key",
+        ))
+        .subcommand(
+            Command::new("set-permissions")
+                .about("Set what kind of certificates a key can get. Keys without permissions cannot get certificates.")
+                .arg(fingerprint_arg().required(true))
+                .arg(authority_arg())
+                .arg(
+                    Arg::new("max-creation-time")
+                        .help("The longest certificate the key can get, in seconds")
+                        .long("max-creation-time")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::new("user-certs")
+                        .help("Allow user certificates")
+                        .long("user-certs"),
+                )
+                .arg(
+                    Arg::new("host-certs")
+                        .help("Allow host certificates")
+                        .long("host-certs"),
+                )
+                .arg(
+                    Arg::new("principal-unrestricted")
+                        .help("Allow any principal")
+                        .long("principal-unrestricted"),
+                )
+                .arg(
+                    Arg::new("host-unrestricted")
+                        .help("Allow any host")
+                        .long("host-unrestricted"),
+                ),
+        )
+        .subcommand(
+            Command::new("delete-permissions")
+                .about("Remove a key's permissions so it cannot get certificates")
+                .arg(fingerprint_arg().required(true))
+                .arg(authority_arg()),
+        )
+        .subcommand(
+            Command::new("keys")
+                .about("List registered keys")
+                .arg(user_arg()),
+        )
+        .subcommand(
+            Command::new("x509")
+                .about("List X509 authorizations")
+                .arg(user_arg()),
+        )
+        .subcommand(
+            x509_args(Command::new("set-x509"))
+                .about("Authorize a Yubikey to get attested X509 certificates, replacing any existing authorization")
+                .arg(authority_arg())
+                .arg(
+                    Arg::new("require-touch")
+                        .help("Only allow keys that require touch")
+                        .long("require-touch"),
+                ),
+        )
+        .subcommand(x509_args(Command::new("delete-x509")).about("Remove an X509 authorization"))
+        .subcommand(
+            Command::new("export")
+                .about("Export every grant and X509 authorization")
+                .arg(format_arg())
+                .arg(
+                    Arg::new("out")
+                        .help("Write to this file instead of stdout")
+                        .long("out")
+                        .short('o')
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("Add the grants and X509 authorizations in an exported file. Nothing is removed.")
+                .arg(format_arg())
+                .arg(Arg::new("path").help("The file to import").required(true)),
+        )
+        .subcommand(
+            Command::new("explain")
+                .about("Decode an SSH or X509 certificate and show who it was issued to")
+                .arg(
+                    Arg::new("path")
+                        .help("The certificate file, or - for stdin")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("authorities")
+                .about("Print the CA keys of the server's authorities")
+                .arg(authority_arg().help("Only show this authority")),
+        )
+}
+
+pub fn configure() -> Result<(Target, RusticaAdminAction), ConfigurationError> {
+    let command_configuration = command();
+    let mut cc_help = command_configuration.clone();
+
+    let matches = command_configuration.get_matches();
+    match parse_action(&matches) {
+        Ok(action) => Ok((parse_target(&matches)?, action)),
+        Err(ConfigurationError::NoMode) => {
+            cc_help.print_help().unwrap();
+            Err(ConfigurationError::NoMode)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(args: &[&str]) -> Result<RusticaAdminAction, ConfigurationError> {
+        let matches = command()
+            .try_get_matches_from(std::iter::once("rustica-admin").chain(args.iter().copied()))
+            .unwrap();
+        parse_action(&matches)
+    }
+
+    #[test]
+    fn grant_changes_are_split_into_lists() {
+        let change = match action(&[
+            "grant",
+            "-f",
+            "d/Ofi1fZuphAvf7DXj85VnSbz0CSI6RFTfqLb1SBSYA",
+            "--principals",
+            "alice,,bob",
+            "--extensions",
+            "permit-pty,force-command=/bin/true",
+        ]) {
+            Ok(RusticaAdminAction::Grant(change)) => change,
+            _ => panic!("Expected a grant"),
+        };
+
+        assert_eq!(
+            change.fingerprint,
+            "d/Ofi1fZuphAvf7DXj85VnSbz0CSI6RFTfqLb1SBSYA"
+        );
+        // Empty authorities are the default authority
+        assert_eq!(change.authority, "");
+        assert_eq!(change.principals, vec!["alice", "bob"]);
+        assert!(change.hosts.is_empty());
+        assert_eq!(change.extensions.len(), 2);
+        assert_eq!(change.extensions["permit-pty"], "");
+        assert_eq!(change.extensions["force-command"], "/bin/true");
+    }
+
+    #[test]
+    fn permissions_are_converted() {
+        match action(&[
+            "set-permissions",
+            "-f",
+            "fingerprint",
+            "-a",
+            "example",
+            "--max-creation-time",
+            "3600",
+            "--user-certs",
+            "--principal-unrestricted",
+        ]) {
+            Ok(RusticaAdminAction::SetPermissions {
+                fingerprint,
+                authority,
+                permissions,
+            }) => {
+                assert_eq!(fingerprint, "fingerprint");
+                assert_eq!(authority, "example");
+                assert_eq!(
+                    permissions,
+                    Permissions {
+                        host_unrestricted: false,
+                        principal_unrestricted: true,
+                        can_create_host_certs: false,
+                        can_create_user_certs: true,
+                        max_creation_time: 3600,
+                    }
+                );
+            }
+            _ => panic!("Expected permissions to be set"),
+        }
+
+        assert!(matches!(
+            action(&[
+                "set-permissions",
+                "-f",
+                "fingerprint",
+                "--max-creation-time",
+                "soon"
+            ]),
+            Err(ConfigurationError::BadArgument(_))
+        ));
+    }
+
+    #[test]
+    fn x509_authorizations_are_converted() {
+        match action(&[
+            "set-x509",
+            "-u",
+            "alice",
+            "--hsm-serial",
+            "1234",
+            "--require-touch",
+        ]) {
+            Ok(RusticaAdminAction::SetX509(authorization)) => assert_eq!(
+                authorization,
+                X509Authorization {
+                    user: String::from("alice"),
+                    hsm_serial: String::from("1234"),
+                    require_touch: true,
+                    authority: String::new(),
+                }
+            ),
+            _ => panic!("Expected an X509 authorization"),
+        }
+    }
+
+    #[test]
+    fn formats_follow_the_file_extension() {
+        assert!(matches!(
+            action(&["export", "-o", "grants.json"]),
+            Ok(RusticaAdminAction::Export {
+                format: Format::Json,
+                ..
+            })
+        ));
+        assert!(matches!(
+            action(&["import", "grants.json", "--format", "toml"]),
+            Ok(RusticaAdminAction::Import {
+                format: Format::Toml,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn a_command_is_required() {
+        assert!(matches!(action(&[]), Err(ConfigurationError::NoMode)));
+    }
+}
@@ -0,0 +1,233 @@
+/// Explains a certificate issued by Rustica: what it allows, which authority
+/// signed it, and who it was issued to according to the issuance ledger.
+use crate::backend::{AdminError, Backend};
+use crate::{join, join_options};
+
+use crate::rustica::{Authority, AuthorityKey, IssuedCertificate};
+
+use sshcerts::ssh::Certificate;
+use sshcerts::PublicKey;
+
+use x509_parser::extensions::GeneralName;
+use x509_parser::pem::{parse_x509_pem, Pem};
+
+use std::error::Error;
+use std::time::SystemTime;
+
+pub async fn explain(backend: &mut Backend, contents: &str) -> Result<(), Box<dyn Error>> {
+    if contents.contains("-----BEGIN CERTIFICATE-----") {
+        explain_x509(backend, contents).await
+    } else {
+        explain_ssh(backend, contents).await
+    }
+}
+
+fn status(valid_after: u64, valid_before: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|ts| ts.as_secs())
+        .unwrap_or_default();
+
+    if now < valid_after {
+        String::from("not valid yet")
+    } else if now >= valid_before {
+        String::from("expired")
+    } else {
+        format!("valid for another {} seconds", valid_before - now)
+    }
+}
+
+/// Old servers only return the key new certificates are signed with
+fn ca_keys(keys: &[AuthorityKey], current: &str) -> Vec<AuthorityKey> {
+    if keys.is_empty() && !current.is_empty() {
+        return vec![AuthorityKey {
+            public_key: current.to_owned(),
+            state: String::from("active"),
+        }];
+    }
+    keys.to_vec()
+}
+
+/// Rustica writes the little endian bytes of a signed serial as the X509
+/// serial. Leading zeros can be dropped when it is encoded so they are
+/// added back before reading it.
+fn rustica_x509_serial(raw: &[u8]) -> Option<i64> {
+    let raw: Vec<u8> = raw.iter().skip_while(|b| **b == 0).copied().collect();
+    if raw.len() > 8 {
+        return None;
+    }
+
+    let mut bytes = [0; 8];
+    bytes[8 - raw.len()..].copy_from_slice(&raw);
+    Some(i64::from_le_bytes(bytes))
+}
+
+fn print_authority(
+    authorities: &Result<Vec<Authority>, AdminError>,
+    find: impl Fn(&Authority) -> Option<String>,
+) {
+    match authorities {
+        Ok(authorities) => match authorities.iter().find_map(|a| find(a).map(|key| (a, key))) {
+            Some((authority, key)) => {
+                println!("Authority: {} (signed with the {key})", authority.name)
+            }
+            None => println!("Authority: none of the server's authorities signed this certificate"),
+        },
+        Err(e) => println!("Authority: unknown ({e})"),
+    }
+}
+
+/// Serials are not unique, the authorizers that ship with Rustica use the
+/// same one for every certificate, so the validity is matched as well
+fn issued_as(
+    issued: Vec<IssuedCertificate>,
+    certificate_type: &str,
+    valid_after: u64,
+    valid_before: u64,
+) -> Vec<IssuedCertificate> {
+    issued
+        .into_iter()
+        .filter(|c| certificate_type.is_empty() || c.certificate_type == certificate_type)
+        .filter(|c| c.valid_after == valid_after && c.valid_before == valid_before)
+        .collect()
+}
+
+fn print_issuance(issued: Result<Vec<IssuedCertificate>, AdminError>) {
+    match issued {
+        Ok(issued) if issued.is_empty() => println!("Issued to: not found in the ledger"),
+        Ok(issued) => {
+            for certificate in issued {
+                println!("Issued to: {}", join(&certificate.mtls_identities));
+                println!(
+                    "Issued at: {} from {}",
+                    certificate.issued_at, certificate.requester_ip
+                );
+                println!("Recorded authority: {}", certificate.authority);
+            }
+        }
+        Err(e) => println!("Issued to: unknown ({e})"),
+    }
+}
+
+async fn explain_ssh(backend: &mut Backend, contents: &str) -> Result<(), Box<dyn Error>> {
+    let cert = Certificate::from_string(contents.trim())
+        .map_err(|e| format!("Could not parse the certificate: {e}"))?;
+    let signed_by = cert.signature_key.fingerprint().hash;
+    let fingerprint = cert.key.fingerprint().hash;
+
+    println!("Type: {}", cert.cert_type);
+    println!("Key ID: {}", cert.key_id);
+    println!("Serial: {}", cert.serial);
+    println!("Key: {fingerprint} ({})", cert.key.key_type.name);
+    println!("Signed by: {signed_by}");
+
+    let authorities = backend.authorities().await;
+    print_authority(&authorities, |authority| {
+        let keys = [
+            (
+                "user",
+                ca_keys(&authority.user_ca_keys, &authority.user_ca_public_key),
+            ),
+            (
+                "host",
+                ca_keys(&authority.host_ca_keys, &authority.host_ca_public_key),
+            ),
+        ];
+        keys.into_iter().find_map(|(kind, keys)| {
+            keys.into_iter()
+                .find(|key| {
+                    PublicKey::from_string(&key.public_key)
+                        .is_ok_and(|key| key.fingerprint().hash == signed_by)
+                })
+                .map(|key| format!("{} {kind} CA key", key.state))
+        })
+    });
+
+    println!("Principals: {}", join(&cert.principals));
+    println!("Valid after: {}", cert.valid_after);
+    println!("Valid before: {}", cert.valid_before);
+    println!("Status: {}", status(cert.valid_after, cert.valid_before));
+    println!("Critical options: {}", join_options(&cert.critical_options));
+    println!("Extensions: {}", join_options(&cert.extensions));
+
+    print_issuance(
+        backend
+            .find_issued_certificates(cert.serial.to_string(), Some(fingerprint))
+            .await
+            .map(|issued| issued_as(issued, "", cert.valid_after, cert.valid_before)),
+    );
+    Ok(())
+}
+
+async fn explain_x509(backend: &mut Backend, contents: &str) -> Result<(), Box<dyn Error>> {
+    let (_, pem) = parse_x509_pem(contents.trim().as_bytes())
+        .map_err(|e| format!("Could not read the PEM: {e}"))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|e| format!("Could not parse the certificate: {e}"))?;
+    let serial = rustica_x509_serial(cert.raw_serial());
+
+    println!("Type: X509 certificate");
+    println!("Subject: {}", cert.subject());
+    println!("Issuer: {}", cert.issuer());
+    match serial {
+        Some(serial) => println!("Serial: {serial}"),
+        None => println!(
+            "Serial: {} (not a Rustica serial)",
+            cert.raw_serial_as_string()
+        ),
+    }
+
+    let authorities = backend.authorities().await;
+    print_authority(&authorities, |authority| {
+        let bundles = [
+            (
+                "attested X509",
+                &authority.attested_x509_certificate_authority,
+            ),
+            ("client", &authority.client_certificate_authority),
+        ];
+        bundles.into_iter().find_map(|(kind, bundle)| {
+            Pem::iter_from_buffer(bundle.as_bytes())
+                .flatten()
+                .any(|ca| {
+                    ca.parse_x509()
+                        .is_ok_and(|ca| cert.verify_signature(Some(ca.public_key())).is_ok())
+                })
+                .then(|| format!("{kind} certificate authority"))
+        })
+    });
+
+    let sans: Vec<String> = match cert.subject_alternative_name() {
+        Ok(Some(sans)) => sans
+            .value
+            .general_names
+            .iter()
+            .map(|name| match name {
+                GeneralName::RFC822Name(name)
+                | GeneralName::DNSName(name)
+                | GeneralName::URI(name) => name.to_string(),
+                name => name.to_string(),
+            })
+            .collect(),
+        _ => vec![],
+    };
+    let valid_after = cert.validity().not_before.timestamp().max(0) as u64;
+    let valid_before = cert.validity().not_after.timestamp().max(0) as u64;
+
+    println!("Subject alternative names: {}", join(&sans));
+    println!("Valid after: {valid_after}");
+    println!("Valid before: {valid_before}");
+    println!("Status: {}", status(valid_after, valid_before));
+
+    match serial {
+        Some(serial) => print_issuance(
+            backend
+                .find_issued_certificates(serial.to_string(), None)
+                .await
+                .map(|issued| issued_as(issued, "X509", valid_after, valid_before)),
+        ),
+        None => println!("Issued to: not found in the ledger"),
+    }
+    Ok(())
+}
@@ -0,0 +1,296 @@
+#[macro_use]
+extern crate log;
+
+mod backend;
+mod config;
+mod explain;
+mod transfer;
+
+pub mod rustica {
+    tonic::include_proto!("rustica");
+}
+
+use crate::backend::{Backend, Database, Server};
+use crate::config::{RusticaAdminAction, Target};
+use crate::transfer::Authorizations;
+
+use crate::rustica::{Authority, AuthorityKey, Grants};
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+
+pub fn join(values: &[String]) -> String {
+    if values.is_empty() {
+        String::from("none")
+    } else {
+        values.join(", ")
+    }
+}
+
+pub fn join_options(options: &HashMap<String, String>) -> String {
+    let mut options: Vec<String> = options
+        .iter()
+        .map(|(name, value)| {
+            if value.is_empty() {
+                name.clone()
+            } else {
+                format!("{name}={value}")
+            }
+        })
+        .collect();
+    options.sort();
+    join(&options)
+}
+
+fn print_grants(grants: &Grants) {
+    println!("{} ({})", grants.fingerprint, grants.authority);
+    match &grants.permissions {
+        Some(p) => {
+            let mut allowed = vec![];
+            if p.can_create_user_certs {
+                allowed.push("user certificates");
+            }
+            if p.can_create_host_certs {
+                allowed.push("host certificates");
+            }
+            if p.principal_unrestricted {
+                allowed.push("any principal");
+            }
+            if p.host_unrestricted {
+                allowed.push("any host");
+            }
+            if allowed.is_empty() {
+                allowed.push("nothing");
+            }
+            println!(
+                "\tPermissions: {} for up to {} seconds",
+                allowed.join(", "),
+                p.max_creation_time
+            );
+        }
+        None => println!("\tPermissions: none, the key cannot get certificates"),
+    }
+
+    let mut principals = grants.principals.clone();
+    let mut hosts = grants.hosts.clone();
+    principals.sort();
+    hosts.sort();
+
+    println!("\tPrincipals: {}", join(&principals));
+    println!("\tHosts: {}", join(&hosts));
+    println!("\tExtensions: {}", join_options(&grants.extensions));
+}
+
+fn print_ca_keys(kind: &str, keys: &[AuthorityKey]) {
+    for key in keys {
+        println!("\t{kind} CA key ({}): {}", key.state, key.public_key);
+    }
+}
+
+fn print_authority(authority: &Authority) {
+    if authority.is_default {
+        println!("{} (default)", authority.name);
+    } else {
+        println!("{}", authority.name);
+    }
+
+    // Servers without key rotation only return the current keys
+    if authority.user_ca_keys.is_empty() && !authority.user_ca_public_key.is_empty() {
+        println!("\tUser CA key: {}", authority.user_ca_public_key);
+    }
+    if authority.host_ca_keys.is_empty() && !authority.host_ca_public_key.is_empty() {
+        println!("\tHost CA key: {}", authority.host_ca_public_key);
+    }
+    print_ca_keys("User", &authority.user_ca_keys);
+    print_ca_keys("Host", &authority.host_ca_keys);
+
+    if !authority.attested_x509_certificate_authority.is_empty() {
+        println!("\tAttested X509 certificate authority:");
+        print!("{}", authority.attested_x509_certificate_authority);
+    }
+    if !authority.client_certificate_authority.is_empty() {
+        println!("\tClient certificate authority:");
+        print!("{}", authority.client_certificate_authority);
+    }
+}
+
+fn read_input(path: &str) -> Result<String, std::io::Error> {
+    let mut contents = String::new();
+    if path == "-" {
+        std::io::stdin().read_to_string(&mut contents)?;
+    } else {
+        File::open(path)?.read_to_string(&mut contents)?;
+    }
+    Ok(contents)
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let (target, action) = match config::configure() {
+        Ok(configured) => configured,
+        Err(config::ConfigurationError::NoMode) => return,
+        Err(e) => {
+            println!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = run(target, action).await {
+        println!("Error: {e}");
+        std::process::exit(1);
+    }
+}
+
+async fn run(target: Target, action: RusticaAdminAction) -> Result<(), Box<dyn std::error::Error>> {
+    let mut backend = match target {
+        Target::Server(servers) => Backend::Server(Server::connect(&servers).await?),
+        Target::Database {
+            path,
+            ledger,
+            default_authority,
+        } => Backend::Database(Database::open(&path, ledger.as_deref(), default_authority)?),
+    };
+
+    match action {
+        RusticaAdminAction::ListGrants {
+            fingerprint,
+            authority,
+        } => {
+            for grants in backend.list_grants(fingerprint, authority).await? {
+                print_grants(&grants);
+            }
+        }
+        RusticaAdminAction::Grant(change) => {
+            let mut changed = 0;
+            if !change.principals.is_empty() {
+                changed += backend
+                    .grant_principals(
+                        change.fingerprint.clone(),
+                        change.authority.clone(),
+                        change.principals,
+                    )
+                    .await?;
+            }
+            if !change.hosts.is_empty() {
+                changed += backend
+                    .grant_hosts(
+                        change.fingerprint.clone(),
+                        change.authority.clone(),
+                        change.hosts,
+                    )
+                    .await?;
+            }
+            if !change.extensions.is_empty() {
+                changed += backend
+                    .grant_extensions(change.fingerprint, change.authority, change.extensions)
+                    .await?;
+            }
+            println!("Changed: {changed}");
+        }
+        RusticaAdminAction::Revoke(change) => {
+            let mut changed = 0;
+            if !change.principals.is_empty() {
+                changed += backend
+                    .revoke_principals(
+                        change.fingerprint.clone(),
+                        change.authority.clone(),
+                        change.principals,
+                    )
+                    .await?;
+            }
+            if !change.hosts.is_empty() {
+                changed += backend
+                    .revoke_hosts(
+                        change.fingerprint.clone(),
+                        change.authority.clone(),
+                        change.hosts,
+                    )
+                    .await?;
+            }
+            if !change.extensions.is_empty() {
+                let extensions = change.extensions.into_keys().collect();
+                changed += backend
+                    .revoke_extensions(change.fingerprint, change.authority, extensions)
+                    .await?;
+            }
+            println!("Changed: {changed}");
+        }
+        RusticaAdminAction::SetPermissions {
+            fingerprint,
+            authority,
+            permissions,
+        } => {
+            let changed = backend
+                .set_permissions(fingerprint, authority, permissions)
+                .await?;
+            println!("Changed: {changed}");
+        }
+        RusticaAdminAction::DeletePermissions {
+            fingerprint,
+            authority,
+        } => {
+            let changed = backend.delete_permissions(fingerprint, authority).await?;
+            println!("Changed: {changed}");
+        }
+        RusticaAdminAction::ListKeys { user } => {
+            for key in backend.list_registered_keys(user).await? {
+                let attestation = if key.attestation.is_empty() {
+                    "unattested"
+                } else {
+                    &key.attestation
+                };
+                println!(
+                    "{}\t{}\t{attestation}\t{}",
+                    key.fingerprint, key.user, key.hsm_serial
+                );
+            }
+        }
+        RusticaAdminAction::ListX509 { user } => {
+            for authorization in backend.list_x509_authorizations(user).await? {
+                let touch = if authorization.require_touch {
+                    "touch required"
+                } else {
+                    "touch not required"
+                };
+                println!(
+                    "{}\t{}\t{}\t{touch}",
+                    authorization.user, authorization.hsm_serial, authorization.authority
+                );
+            }
+        }
+        RusticaAdminAction::SetX509(authorization) => {
+            let changed = backend.set_x509_authorization(authorization).await?;
+            println!("Changed: {changed}");
+        }
+        RusticaAdminAction::DeleteX509 { user, hsm_serial } => {
+            let changed = backend.delete_x509_authorization(user, hsm_serial).await?;
+            println!("Changed: {changed}");
+        }
+        RusticaAdminAction::Export { format, out } => {
+            let exported = transfer::export(&mut backend).await?.serialize(format)?;
+            match out {
+                Some(out) => File::create(out)?.write_all(exported.as_bytes())?,
+                None => print!("{exported}"),
+            }
+        }
+        RusticaAdminAction::Import { path, format } => {
+            let authorizations = Authorizations::parse(&read_input(&path)?, format)?;
+            let changed = transfer::import(&mut backend, authorizations).await?;
+            println!("Changed: {changed}");
+        }
+        RusticaAdminAction::Explain { path } => {
+            explain::explain(&mut backend, &read_input(&path)?).await?;
+        }
+        RusticaAdminAction::Authorities { authority } => {
+            for found in backend.authorities().await? {
+                if authority.as_ref().is_none_or(|name| *name == found.name) {
+                    print_authority(&found);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
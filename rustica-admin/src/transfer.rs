@@ -0,0 +1,211 @@
+/// Export and import of the authorization database. An export holds every
+/// grant and X509 authorization so it can be reviewed, kept in version
+/// control, or loaded into another database. Registered keys are not
+/// included since they can only be added by registering the key.
+use crate::backend::{AdminError, Backend};
+
+use crate::rustica::{Permissions, X509Authorization};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".json") {
+            Format::Json
+        } else {
+            Format::Toml
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Authorizations {
+    #[serde(default)]
+    pub grants: Vec<Grant>,
+    #[serde(default)]
+    pub x509_authorizations: Vec<X509Grant>,
+}
+
+/// Everything a key has been granted for one authority
+#[derive(Serialize, Deserialize)]
+pub struct Grant {
+    pub fingerprint: String,
+    /// Empty for the default authority when importing
+    #[serde(default)]
+    pub authority: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub principals: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    /// Extensions without a value have an empty string
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extensions: BTreeMap<String, String>,
+    /// Keys without permissions cannot get certificates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<GrantPermissions>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct GrantPermissions {
+    #[serde(default)]
+    pub host_unrestricted: bool,
+    #[serde(default)]
+    pub principal_unrestricted: bool,
+    #[serde(default)]
+    pub can_create_host_certs: bool,
+    #[serde(default)]
+    pub can_create_user_certs: bool,
+    pub max_creation_time: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct X509Grant {
+    pub user: String,
+    pub hsm_serial: String,
+    #[serde(default)]
+    pub require_touch: bool,
+    #[serde(default)]
+    pub authority: String,
+}
+
+impl Authorizations {
+    pub fn parse(contents: &str, format: Format) -> Result<Self, Box<dyn Error>> {
+        Ok(match format {
+            Format::Toml => toml::from_str(contents)?,
+            Format::Json => serde_json::from_str(contents)?,
+        })
+    }
+
+    pub fn serialize(&self, format: Format) -> Result<String, Box<dyn Error>> {
+        Ok(match format {
+            Format::Toml => toml::to_string(self)?,
+            Format::Json => serde_json::to_string_pretty(self)?,
+        })
+    }
+}
+
+pub async fn export(backend: &mut Backend) -> Result<Authorizations, AdminError> {
+    let grants = backend
+        .list_grants(None, None)
+        .await?
+        .into_iter()
+        .map(|grant| {
+            let mut principals = grant.principals;
+            let mut hosts = grant.hosts;
+            principals.sort();
+            hosts.sort();
+
+            Grant {
+                fingerprint: grant.fingerprint,
+                authority: grant.authority,
+                principals,
+                hosts,
+                extensions: grant.extensions.into_iter().collect(),
+                permissions: grant.permissions.map(|p| GrantPermissions {
+                    host_unrestricted: p.host_unrestricted,
+                    principal_unrestricted: p.principal_unrestricted,
+                    can_create_host_certs: p.can_create_host_certs,
+                    can_create_user_certs: p.can_create_user_certs,
+                    max_creation_time: p.max_creation_time,
+                }),
+            }
+        })
+        .collect();
+
+    let x509_authorizations = backend
+        .list_x509_authorizations(None)
+        .await?
+        .into_iter()
+        .map(|authorization| X509Grant {
+            user: authorization.user,
+            hsm_serial: authorization.hsm_serial,
+            require_touch: authorization.require_touch,
+            authority: authorization.authority,
+        })
+        .collect();
+
+    Ok(Authorizations {
+        grants,
+        x509_authorizations,
+    })
+}
+
+/// Adds everything in the file. Permissions and X509 authorizations replace
+/// the existing ones for the same key, everything else is only added.
+/// Returns how many rows were changed.
+pub async fn import(
+    backend: &mut Backend,
+    authorizations: Authorizations,
+) -> Result<u64, AdminError> {
+    let mut changed = 0;
+    for grant in authorizations.grants {
+        if let Some(p) = grant.permissions {
+            let permissions = Permissions {
+                host_unrestricted: p.host_unrestricted,
+                principal_unrestricted: p.principal_unrestricted,
+                can_create_host_certs: p.can_create_host_certs,
+                can_create_user_certs: p.can_create_user_certs,
+                max_creation_time: p.max_creation_time,
+            };
+            changed += backend
+                .set_permissions(
+                    grant.fingerprint.clone(),
+                    grant.authority.clone(),
+                    permissions,
+                )
+                .await?;
+        }
+
+        if !grant.principals.is_empty() {
+            changed += backend
+                .grant_principals(
+                    grant.fingerprint.clone(),
+                    grant.authority.clone(),
+                    grant.principals,
+                )
+                .await?;
+        }
+
+        if !grant.hosts.is_empty() {
+            changed += backend
+                .grant_hosts(
+                    grant.fingerprint.clone(),
+                    grant.authority.clone(),
+                    grant.hosts,
+                )
+                .await?;
+        }
+
+        if !grant.extensions.is_empty() {
+            changed += backend
+                .grant_extensions(
+                    grant.fingerprint,
+                    grant.authority,
+                    grant.extensions.into_iter().collect(),
+                )
+                .await?;
+        }
+    }
+
+    for authorization in authorizations.x509_authorizations {
+        changed += backend
+            .set_x509_authorization(X509Authorization {
+                user: authorization.user,
+                hsm_serial: authorization.hsm_serial,
+                require_touch: authorization.require_touch,
+                authority: authorization.authority,
+            })
+            .await?;
+    }
+
+    Ok(changed)
+}
@@ -6,6 +6,7 @@ mod multimode;
 mod provisionpiv;
 mod refresh_attested_x509_certificate;
 mod register;
+mod renewaccess;
 mod singlemode;
 mod allowed_signers;
 
@@ -56,6 +57,7 @@ pub enum RusticaAgentAction {
     GitConfig(PublicKey),
     RefreshAttestedX509(refresh_attested_x509_certificate::RefreshAttestedX509Config),
     GetAllowedSigners(allowed_signers::GetAllowedSignersConfig),
+    RenewAccess(renewaccess::RenewAccessConfig),
 }
 
 impl From<std::io::Error> for ConfigurationError {
@@ -150,6 +152,12 @@ pub fn add_request_options(cmd: Command) -> Command {
             .long("authority")
             .takes_value(true),
     )
+    .arg(
+        Arg::new("extensions")
+            .help("A comma separated list of the extensions you are requesting. The certificate will only have the ones you are also authorized for. Defaults to the standard OpenSSH extensions")
+            .long("extensions")
+            .takes_value(true),
+    )
 }
 
 pub fn add_daemon_options(cmd: Command) -> Command {
@@ -197,6 +205,10 @@ fn parse_certificate_config_from_args(
         certificate_options.authority = authority.to_owned();
     }
 
+    if let Some(extensions) = matches.value_of("extensions") {
+        certificate_options.extensions = Some(extensions.split(',').map(|s| s.to_string()).collect());
+    }
+
     Ok(certificate_options)
 }
 
@@ -267,6 +279,11 @@ pub async fn configure() -> Result<RusticaAgentAction, ConfigurationError> {
 
     let allowed_signers = new_run_agent_subcommand("allowed-signers", "Fetch a list of all signers and their keys");
 
+    let renew_access = renewaccess::add_configuration(new_run_agent_subcommand(
+        "renew-access",
+        "Renew the access certificate used to connect to the server if it is due for renewal. The new key is generated locally.",
+    ));
+
     let command_configuration = command_configuration
         .subcommand(immediate_mode)
         .subcommand(multi_mode)
@@ -278,7 +295,8 @@ pub async fn configure() -> Result<RusticaAgentAction, ConfigurationError> {
         .subcommand(list_fido_devices)
         .subcommand(git_config)
         .subcommand(refresh_x509)
-        .subcommand(allowed_signers);
+        .subcommand(allowed_signers)
+        .subcommand(renew_access);
     let mut cc_help = command_configuration.clone();
 
     let matches = command_configuration.get_matches();
@@ -328,6 +346,10 @@ pub async fn configure() -> Result<RusticaAgentAction, ConfigurationError> {
         return allowed_signers::configure_allowed_signers(allowed_signers_config).await;
     }
 
+    if let Some(renew_access_config) = matches.subcommand_matches("renew-access") {
+        return renewaccess::configure_renew_access(renew_access_config).await;
+    }
+
     cc_help.print_help().unwrap();
     Err(ConfigurationError::NoMode)
 }
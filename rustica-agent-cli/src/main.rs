@@ -154,6 +154,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Err(e) => return Err(Box::new(e))?,
             }
         }
+        // Renew the mTLS access credentials without needing an SSH certificate.
+        // The new private key is generated here and never sent to the server.
+        Ok(RusticaAgentAction::RenewAccess(mut config)) => {
+            match rustica_agent::renew_access_credentials(
+                &mut config.updatable_configuration,
+                &config.signatory,
+                &config.certificate_options,
+                &None,
+            )
+            .await
+            {
+                Ok(true) => println!("Your access credentials to the server have been updated"),
+                Ok(false) => println!("Your access credentials are not due for renewal yet"),
+                Err(e) => return Err(Box::new(e))?,
+            }
+        }
         // Normal operation: Starts RusticaAgent as an SSHAgent and waits to answer
         // requests from SSH clients.
         Ok(RusticaAgentAction::Run(config)) => {
@@ -13,6 +13,7 @@ pub struct Options {
     pub kind: Option<String>,
     pub duration: Option<u64>,
     pub authority: Option<String>,
+    pub extensions: Option<Vec<String>>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
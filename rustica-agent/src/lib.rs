@@ -43,6 +43,10 @@ pub struct CertificateConfig {
     pub cert_type: CertType,
     pub duration: u64,
     pub authority: String,
+    /// The extensions to request. If this is not set, the standard OpenSSH
+    /// extensions are requested. The server will not add any extension that
+    /// is not requested.
+    pub extensions: Option<Vec<String>>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -80,6 +84,19 @@ pub struct MtlsCredentials {
     key: String,
 }
 
+impl MtlsCredentials {
+    /// Replace the access credentials used for a server with these ones
+    fn apply(self, server: &mut RusticaServer) {
+        if !self.certificate.is_empty() {
+            server.mtls_cert = self.certificate.replace('\r', "");
+        }
+
+        if !self.key.is_empty() {
+            server.mtls_key = self.key.replace('\r', "");
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum RusticaAgentLibraryError {
     CouldNotOpenYubikey(u32),
@@ -91,6 +108,8 @@ pub enum RusticaAgentLibraryError {
     BadConfiguration(String),
     UnknownConfigurationVersion(u64),
     NoServersReturnedAllowedSigners,
+    NoServersRenewedAccessCredential,
+    CouldNotWriteConfigurationFile(String),
 }
 
 impl std::fmt::Display for RusticaAgentLibraryError {
@@ -128,6 +147,12 @@ impl std::fmt::Display for RusticaAgentLibraryError {
                     "All servers failed to return allowed signers when requested"
                 )
             }
+            RusticaAgentLibraryError::NoServersRenewedAccessCredential => {
+                write!(f, "All servers failed to renew access credentials")
+            }
+            RusticaAgentLibraryError::CouldNotWriteConfigurationFile(e) => {
+                write!(f, "Could not write configuration file: {e}")
+            }
         }
     }
 }
@@ -187,6 +212,7 @@ impl From<Option<Options>> for CertificateConfig {
                 hosts: vec![],
                 principals: vec![],
                 authority: String::new(),
+                extensions: None,
             },
             Some(co) => CertificateConfig {
                 cert_type: CertType::try_from(
@@ -197,6 +223,7 @@ impl From<Option<Options>> for CertificateConfig {
                 hosts: co.hosts.unwrap_or_default(),
                 principals: co.principals.unwrap_or_default(),
                 authority: co.authority.unwrap_or_default(),
+                extensions: co.extensions,
             },
         }
     }
@@ -656,13 +683,7 @@ pub async fn fetch_new_certificate(
                     .map_err(|e| RusticaAgentLibraryError::ServerReturnedInvalidCertificate(e))?;
 
                 if let Some(mtls_credentials) = mtls_credentials {
-                    if !mtls_credentials.certificate.is_empty() {
-                        server.mtls_cert = mtls_credentials.certificate.replace("\r", "");
-                    }
-
-                    if !mtls_credentials.key.is_empty() {
-                        server.mtls_key = mtls_credentials.key.replace("\r", "");
-                    }
+                    mtls_credentials.apply(server);
 
                     if let Err(e) = configuration.write() {
                         error!("Server returned new mTLS credentials but the configuration file couldn't be updated: {e}");
@@ -684,6 +705,40 @@ pub async fn fetch_new_certificate(
     Err(RusticaAgentLibraryError::NoServersReturnedCertificate)
 }
 
+/// Renew the access credentials for the first server in the list that
+/// responds and save them to the configuration. Returns false if the current
+/// credentials are not yet due for renewal.
+pub async fn renew_access_credentials(
+    configuration: &mut UpdatableConfiguration,
+    signatory: &Signatory,
+    options: &CertificateConfig,
+    notification_function: &Option<Box<dyn Fn() + Send + Sync>>,
+) -> Result<bool, RusticaAgentLibraryError> {
+    for server in configuration.get_servers_mut() {
+        match server
+            .renew_access_credential_async(signatory, options, notification_function)
+            .await
+        {
+            Ok(None) => return Ok(false),
+            Ok(Some(mtls_credentials)) => {
+                mtls_credentials.apply(server);
+
+                return configuration
+                    .write()
+                    .map(|_| true)
+                    .map_err(|e| RusticaAgentLibraryError::CouldNotWriteConfigurationFile(e.to_string()));
+            }
+            Err(e) => {
+                error!(
+                    "Could not renew access credentials with: {}. Gave error: {}",
+                    server.address, e
+                )
+            }
+        }
+    }
+    Err(RusticaAgentLibraryError::NoServersRenewedAccessCredential)
+}
+
 /// Fetch a new X509 certificate from one of the provided servers
 /// in the list. We will try them in order and error if none
 /// return a usable certificate
@@ -0,0 +1,73 @@
+use super::error::RefreshError;
+use super::{RenewAccessCredentialRequest, Signatory};
+use crate::{CertificateConfig, MtlsCredentials, RusticaServer};
+
+/// A newly generated key for an access certificate along with a CSR for it.
+/// The key is generated here so it never has to leave this machine.
+pub struct AccessKey {
+    /// DER encoded CSR to send to the server
+    pub csr: Vec<u8>,
+    /// PEM encoded private key
+    pub key: String,
+}
+
+impl AccessKey {
+    pub fn generate() -> Result<Self, rcgen::RcgenError> {
+        // The server only uses the public key from the CSR so no other
+        // parameters need to be set
+        let params = rcgen::CertificateParams::new(vec![]);
+        let request = rcgen::Certificate::from_params(params)?;
+        let csr = request.serialize_request_der()?;
+
+        Ok(Self {
+            csr,
+            key: request.serialize_private_key_pem(),
+        })
+    }
+}
+
+impl RusticaServer {
+    /// Renew the access certificate used to talk to this server. Returns
+    /// None if the current access certificate is not yet due for renewal.
+    ///
+    /// Servers that do not support RenewAccessCredential only renew access
+    /// certificates when an SSH certificate is requested, so one is requested
+    /// (and thrown away) using the provided options instead.
+    pub async fn renew_access_credential_async(
+        &self,
+        signatory: &Signatory,
+        options: &CertificateConfig,
+        notification_function: &Option<Box<dyn Fn() + Send + Sync>>,
+    ) -> Result<Option<MtlsCredentials>, RefreshError> {
+        let (mut client, challenge) =
+            super::complete_rustica_challenge(self, signatory, notification_function).await?;
+
+        let access_key = AccessKey::generate().map_err(|_| RefreshError::SigningError)?;
+        let request = tonic::Request::new(RenewAccessCredentialRequest {
+            challenge: Some(challenge),
+            csr: access_key.csr,
+        });
+
+        match client.renew_access_credential(request).await {
+            Ok(response) => {
+                let certificate = response.into_inner().certificate;
+                if certificate.is_empty() {
+                    return Ok(None);
+                }
+
+                Ok(Some(MtlsCredentials {
+                    certificate,
+                    key: access_key.key,
+                }))
+            }
+            Err(status) if status.code() == tonic::Code::Unimplemented => {
+                debug!("Server does not support RenewAccessCredential, requesting a certificate instead");
+                let (_, mtls_credentials) = self
+                    .refresh_certificate_async(signatory, options, notification_function)
+                    .await?;
+                Ok(mtls_credentials)
+            }
+            Err(status) => Err(status.into()),
+        }
+    }
+}
@@ -1,11 +1,48 @@
+use super::access::AccessKey;
 use super::error::{RefreshError, ServerError};
-use super::{CertificateRequest, RusticaCert, Signatory};
+use super::rustica_proto::{ApprovalState, ApprovedCertificateRequest};
+use super::{CertificateRequest, RusticaCert, RusticaClient, Signatory};
 use crate::{CertificateConfig, MtlsCredentials, RusticaServer};
 use sshcerts::Certificate;
 use tokio::runtime::Handle;
+use tonic::transport::Channel;
 
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
+
+/// The error code the server returns when a certificate must be approved by
+/// other people before it is issued
+const PENDING_APPROVAL: i64 = 12;
+
+/// How often to check if a certificate waiting for approval has been decided
+const APPROVAL_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Wait until a certificate request is approved or denied and return the
+/// certificate. The server stops knowing about the request once it expires,
+/// which ends the wait with an error.
+async fn wait_for_approval(
+    client: &mut RusticaClient<Channel>,
+    request_id: &str,
+) -> Result<String, RefreshError> {
+    println!("Your certificate request must be approved before it is issued. Request ID: {request_id}");
+
+    loop {
+        tokio::time::sleep(APPROVAL_POLL_INTERVAL).await;
+
+        let response = client
+            .approved_certificate(ApprovedCertificateRequest {
+                request_id: request_id.to_owned(),
+            })
+            .await?
+            .into_inner();
+
+        match response.state() {
+            ApprovalState::Approved => return Ok(response.certificate),
+            ApprovalState::Denied => return Err(RefreshError::ApprovalDenied(response.reason)),
+            ApprovalState::Pending => (),
+        }
+    }
+}
 
 impl RusticaServer {
     pub async fn refresh_certificate_async(
@@ -22,20 +59,39 @@ impl RusticaServer {
             Err(_e) => 0xFFFFFFFFFFFFFFFF,
         };
 
+        // The server only uses this if our access certificate is due for
+        // renewal but we can't know that ahead of time
+        let access_key = AccessKey::generate().map_err(|_| RefreshError::SigningError)?;
+
+        let extensions = match &options.extensions {
+            Some(extensions) => extensions
+                .iter()
+                .map(|extension| (extension.clone(), String::new()))
+                .collect(),
+            None => Certificate::standard_extensions(),
+        };
+
         let request = tonic::Request::new(CertificateRequest {
             cert_type: options.cert_type as u32,
             key_id: options.authority.clone(),
             critical_options: HashMap::new(),
-            extensions: Certificate::standard_extensions(),
+            extensions,
             servers: options.hosts.clone(),
             principals: options.principals.clone(),
             valid_before: current_timestamp + options.duration,
             valid_after: current_timestamp,
             challenge: Some(challenge),
+            access_csr: access_key.csr,
         });
 
         let response = client.certificate(request).await?;
-        let response = response.into_inner();
+        let mut response = response.into_inner();
+
+        if response.error_code == PENDING_APPROVAL && !response.approval_request_id.is_empty() {
+            response.certificate =
+                wait_for_approval(&mut client, &response.approval_request_id).await?;
+            response.error_code = 0;
+        }
 
         if response.error_code != 0 {
             return Err(RefreshError::RusticaServerError(ServerError {
@@ -45,12 +101,19 @@ impl RusticaServer {
         }
 
         // If there is a certificate, then create a new MtlsCredentials struct
-        // and return it. It's possible in the future the server will only
-        // return the certificate which is why we only check the certificate.
+        // and return it. Servers that support CSRs only return the
+        // certificate for the key we generated. Older servers generate the
+        // key themselves and return it alongside the certificate.
         let mtls_credentials = if !response.new_client_certificate.is_empty() {
+            let key = if response.new_client_key.is_empty() {
+                access_key.key
+            } else {
+                response.new_client_key
+            };
+
             Some(MtlsCredentials {
                 certificate: response.new_client_certificate,
-                key: response.new_client_key,
+                key,
             })
         } else {
             None
@@ -19,6 +19,7 @@ pub enum RefreshError {
     BadEncodedData(hex::FromHexError),
     RusticaServerError(ServerError),
     BadAllowedSigners,
+    ApprovalDenied(String),
     UnknownError,
 }
 
@@ -36,6 +37,7 @@ impl fmt::Display for RefreshError {
             RefreshError::BadEncodedData(ref err) => write!(f, "Bad hex encoding: {}", err),
             RefreshError::RusticaServerError(ref err) => write!(f, "Error from server: {}", err.message),
             RefreshError::BadAllowedSigners => write!(f, "Bad allowed signers data"),
+            RefreshError::ApprovalDenied(ref reason) => write!(f, "Certificate request was not approved: {}", reason),
             RefreshError::UnknownError => write!(f, "Unknown error occured"),
         }
     }
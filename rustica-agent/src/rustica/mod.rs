@@ -1,3 +1,4 @@
+pub mod access;
 pub mod cert;
 pub mod error;
 pub mod key;
@@ -9,11 +10,12 @@ use std::time::Duration;
 
 pub use error::RefreshError;
 
+pub use rustica_proto::rustica_admin_client::RusticaAdminClient;
 pub use rustica_proto::rustica_client::RusticaClient;
 pub use rustica_proto::{
     AttestedX509CertificateRequest, AttestedX509CertificateResponse, CertificateRequest,
     CertificateResponse, Challenge, ChallengeRequest, RegisterKeyRequest, RegisterU2fKeyRequest,
-    AllowedSignersRequest, AllowedSignersResponse,
+    AllowedSignersRequest, AllowedSignersResponse, RenewAccessCredentialRequest,
 };
 
 use sshcerts::ssh::Certificate as SSHCertificate;
@@ -34,6 +36,13 @@ pub struct RusticaCert {
 pub async fn get_rustica_client(
     server: &RusticaServer,
 ) -> Result<RusticaClient<tonic::transport::Channel>, RefreshError> {
+    let channel = get_rustica_channel(server).await?;
+    Ok(RusticaClient::new(channel))
+}
+
+/// Connect to a Rustica server over mTLS. The channel can be shared by
+/// clients for any of the server's services.
+pub async fn get_rustica_channel(server: &RusticaServer) -> Result<Channel, RefreshError> {
     let client_identity = Identity::from_pem(&server.mtls_cert, &server.mtls_key);
 
     let channel = match Channel::from_shared(server.address.clone()) {
@@ -52,9 +61,7 @@ pub async fn get_rustica_client(
         .connect()
         .await?;
 
-    let client = RusticaClient::new(channel);
-
-    Ok(client)
+    Ok(channel)
 }
 
 pub async fn complete_rustica_challenge(
@@ -0,0 +1,8 @@
+DROP TABLE x509_authorizations;
+DROP TABLE registered_keys;
+DROP TABLE fingerprint_critical_options;
+DROP TABLE fingerprint_extensions;
+DROP TABLE fingerprint_permissions;
+DROP TABLE fingerprint_host_authorizations;
+DROP TABLE fingerprint_principal_authorizations;
+DROP TABLE hosts;
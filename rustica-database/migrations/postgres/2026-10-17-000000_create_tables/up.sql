@@ -0,0 +1,74 @@
+-- The PostgreSQL schema starts from the current SQLite schema. See
+-- migrations/sqlite/2021-01-14-051956_hosts/up.sql for how each table is used.
+CREATE TABLE hosts (
+	hostname TEXT PRIMARY KEY,
+	fingerprint TEXT NOT NULL
+);
+
+CREATE TABLE fingerprint_principal_authorizations (
+	fingerprint TEXT NOT NULL,
+	principal TEXT NOT NULL,
+	authority TEXT NOT NULL,
+	PRIMARY KEY (fingerprint, principal, authority)
+);
+
+CREATE TABLE fingerprint_host_authorizations (
+	fingerprint TEXT NOT NULL,
+	hostname TEXT NOT NULL,
+	authority TEXT NOT NULL,
+	PRIMARY KEY (fingerprint, hostname, authority)
+);
+
+CREATE TABLE fingerprint_permissions (
+	fingerprint TEXT NOT NULL,
+	host_unrestricted BOOLEAN DEFAULT FALSE NOT NULL,
+	principal_unrestricted BOOLEAN DEFAULT FALSE NOT NULL,
+	can_create_host_certs BOOLEAN DEFAULT FALSE NOT NULL,
+	can_create_user_certs BOOLEAN DEFAULT FALSE NOT NULL,
+	max_creation_time BIGINT DEFAULT 10 NOT NULL,
+	authority TEXT NOT NULL,
+	PRIMARY KEY (fingerprint, authority)
+);
+
+CREATE TABLE fingerprint_extensions (
+	fingerprint TEXT NOT NULL,
+	extension_name TEXT NOT NULL,
+	extension_value TEXT NULL,
+	authority TEXT NOT NULL,
+	PRIMARY KEY (fingerprint, authority, extension_name)
+);
+
+CREATE TABLE fingerprint_critical_options (
+	fingerprint TEXT NOT NULL,
+	critical_option_name TEXT NOT NULL,
+	critical_option_value TEXT NULL,
+	authority TEXT NOT NULL,
+	PRIMARY KEY (fingerprint, authority, critical_option_name)
+);
+
+-- user is a reserved word in PostgreSQL so it must be quoted
+CREATE TABLE registered_keys (
+	fingerprint TEXT PRIMARY KEY NOT NULL,
+	pubkey TEXT NOT NULL,
+	"user" TEXT NOT NULL,
+	pin_policy TEXT NULL,
+	touch_policy TEXT NULL,
+	hsm_serial TEXT NULL,
+	firmware TEXT NULL,
+	attestation_certificate TEXT NULL,
+	attestation_intermediate TEXT NULL,
+	auth_data TEXT NULL,
+	auth_data_signature TEXT NULL,
+	aaguid TEXT NULL,
+	challenge TEXT NULL,
+	alg INTEGER NULL,
+	application TEXT NULL
+);
+
+CREATE TABLE x509_authorizations (
+	"user" TEXT NOT NULL,
+	hsm_serial TEXT NOT NULL,
+	require_touch BOOLEAN NOT NULL,
+	authority TEXT NOT NULL,
+	PRIMARY KEY ("user", hsm_serial)
+);
@@ -54,7 +54,7 @@ CREATE TABLE fingerprint_critical_options (
 -- containing the "obelisk" and "mitchell" principals                         -
 -------------------------------------------------------------------------------
 -- 
-INSERT INTO fingerprint_principal_authorizations VALUES (0, "DSA0E4rquTwSZ9DFNY5gAM7g9tzveUGwhfoeN3ef7Xw", "testuser");
+-- INSERT INTO fingerprint_principal_authorizations VALUES (0, "DSA0E4rquTwSZ9DFNY5gAM7g9tzveUGwhfoeN3ef7Xw", "testuser");
 -- INSERT INTO fingerprint_principal_authorizations VALUES (1, "0ZUOTCC6OQ7kwHJ8lXx16pICBFErB48I4rGe4wVXfW8", "test");
 -- INSERT INTO fingerprint_principal_authorizations VALUES (2, "0ZUOTCC6OQ7kwHJ8lXx16pICBFErB48I4rGe4wVXfW8", "mitchell");
 
@@ -168,5 +168,5 @@ INSERT INTO fingerprint_principal_authorizations VALUES (0, "DSA0E4rquTwSZ9DFNY5
 
 -- User Fingerprint Permissions
 -- Fingerprint, HostUnrestricted, PrincipalUnrestricted, AllowHostCerts, AllowUserCerts, MaxCreationTime
-INSERT INTO fingerprint_permissions VALUES ("DSA0E4rquTwSZ9DFNY5gAM7g9tzveUGwhfoeN3ef7Xw", TRUE, FALSE, FALSE, TRUE, 10);
+-- INSERT INTO fingerprint_permissions VALUES ("DSA0E4rquTwSZ9DFNY5gAM7g9tzveUGwhfoeN3ef7Xw", TRUE, FALSE, FALSE, TRUE, 10);
 -- INSERT INTO fingerprint_permissions VALUES ("0ZUOTCC6OQ7kwHJ8lXx16pICBFErB48I4rGe4wVXfW8", TRUE, FALSE, FALSE, TRUE, 10);
\ No newline at end of file
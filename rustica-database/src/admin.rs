@@ -0,0 +1,65 @@
+/// Rustica's admin service and rustica-admin both describe grants,
+/// registered keys, and X509 authorizations with the messages of the
+/// RusticaAdmin service. Each of them compiles the protobuf definitions into
+/// its own types, so the conversions cannot be plain functions here.
+/// Instead each expands `admin_conversions!` with the message types it has
+/// in scope, which defines `to_grants`, `to_registered_key`, and
+/// `to_x509_authorization` so both always agree on what a row means.
+#[macro_export]
+macro_rules! admin_conversions {
+    ($grants:ident, $permissions:ident, $registered_key:ident, $x509_authorization:ident) => {
+        fn to_grants(grants: $crate::grants::Grants) -> $grants {
+            $grants {
+                fingerprint: grants.fingerprint,
+                authority: grants.authority,
+                permissions: grants.permissions.map(|p| $permissions {
+                    host_unrestricted: p.host_unrestricted,
+                    principal_unrestricted: p.principal_unrestricted,
+                    can_create_host_certs: p.can_create_host_certs,
+                    can_create_user_certs: p.can_create_user_certs,
+                    max_creation_time: p.max_creation_time.max(0) as u64,
+                }),
+                principals: grants.principals,
+                hosts: grants.hosts,
+                // Extensions without a value have an empty one in messages
+                extensions: grants
+                    .extensions
+                    .into_iter()
+                    .map(|(name, value)| (name, value.unwrap_or_default()))
+                    .collect(),
+            }
+        }
+
+        fn to_registered_key(key: $crate::models::RegisteredKey) -> $registered_key {
+            let attestation = if key.attestation_certificate.is_some() {
+                "piv"
+            } else if key.auth_data.is_some() {
+                "u2f"
+            } else {
+                ""
+            };
+
+            $registered_key {
+                fingerprint: key.fingerprint,
+                pubkey: key.pubkey,
+                user: key.user,
+                attestation: attestation.to_owned(),
+                firmware: key.firmware.unwrap_or_default(),
+                hsm_serial: key.hsm_serial.unwrap_or_default(),
+                touch_policy: key.touch_policy.unwrap_or_default(),
+                pin_policy: key.pin_policy.unwrap_or_default(),
+            }
+        }
+
+        fn to_x509_authorization(
+            authorization: $crate::models::X509Authorization,
+        ) -> $x509_authorization {
+            $x509_authorization {
+                user: authorization.user,
+                hsm_serial: authorization.hsm_serial,
+                require_touch: authorization.require_touch,
+                authority: authorization.authority,
+            }
+        }
+    };
+}
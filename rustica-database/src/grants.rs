@@ -0,0 +1,329 @@
+/// Changes made to the grants, registered keys, and X509 authorizations in
+/// the local database. Each change runs in a transaction and returns how many
+/// rows were added, replaced, or removed so the caller can tell a change
+/// from a no-op.
+use crate::{models, schema, DatabaseConnection};
+
+use diesel::prelude::*;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// Everything a key has been granted for one authority
+pub struct Grants {
+    pub fingerprint: String,
+    pub authority: String,
+    /// Keys without permissions cannot get certificates from the authority
+    pub permissions: Option<models::FingerprintPermission>,
+    pub principals: Vec<String>,
+    pub hosts: Vec<String>,
+    pub extensions: HashMap<String, Option<String>>,
+}
+
+/// Select every row of a grant table, limited to one key or authority when
+/// they are given
+macro_rules! grant_rows {
+    ($table:ident, $fingerprint:expr, $authority:expr) => {{
+        let mut query = schema::$table::table.into_boxed();
+        if let Some(fingerprint) = $fingerprint {
+            query = query.filter(schema::$table::fingerprint.eq(fingerprint.clone()));
+        }
+        if let Some(authority) = $authority {
+            query = query.filter(schema::$table::authority.eq(authority.clone()));
+        }
+        query
+    }};
+}
+
+fn grants_for<'a>(
+    grants: &'a mut BTreeMap<(String, String), Grants>,
+    fingerprint: &str,
+    authority: &str,
+) -> &'a mut Grants {
+    grants
+        .entry((fingerprint.to_owned(), authority.to_owned()))
+        .or_insert_with(|| Grants {
+            fingerprint: fingerprint.to_owned(),
+            authority: authority.to_owned(),
+            permissions: None,
+            principals: vec![],
+            hosts: vec![],
+            extensions: HashMap::new(),
+        })
+}
+
+pub fn list_grants(
+    conn: &mut DatabaseConnection,
+    fingerprint: Option<String>,
+    authority: Option<String>,
+) -> QueryResult<Vec<Grants>> {
+    let permissions = grant_rows!(fingerprint_permissions, &fingerprint, &authority)
+        .load::<models::FingerprintPermission>(conn)?;
+    let principals = grant_rows!(fingerprint_principal_authorizations, &fingerprint, &authority)
+        .load::<models::FingerprintPrincipalAuthorization>(conn)?;
+    let hosts = grant_rows!(fingerprint_host_authorizations, &fingerprint, &authority)
+        .load::<models::FingerprintHostAuthorization>(conn)?;
+    let extensions = grant_rows!(fingerprint_extensions, &fingerprint, &authority)
+        .load::<models::FingerprintExtension>(conn)?;
+
+    // A key can have principals or hosts for an authority without
+    // having permissions for it, so rows from every table are grouped
+    let mut grants = BTreeMap::new();
+    for permission in permissions {
+        let grant = grants_for(&mut grants, &permission.fingerprint, &permission.authority);
+        grant.permissions = Some(permission);
+    }
+    for principal in principals {
+        grants_for(&mut grants, &principal.fingerprint, &principal.authority).principals.push(principal.principal);
+    }
+    for host in hosts {
+        grants_for(&mut grants, &host.fingerprint, &host.authority).hosts.push(host.hostname);
+    }
+    for extension in extensions {
+        grants_for(&mut grants, &extension.fingerprint, &extension.authority)
+            .extensions
+            .insert(extension.extension_name, extension.extension_value);
+    }
+
+    Ok(grants.into_values().collect())
+}
+
+pub fn grant_principals(
+    conn: &mut DatabaseConnection,
+    fingerprint: &str,
+    authority: &str,
+    principals: Vec<String>,
+) -> QueryResult<u64> {
+    use schema::fingerprint_principal_authorizations::dsl;
+    conn.transaction(|conn| {
+        let existing: Vec<String> = dsl::fingerprint_principal_authorizations
+            .filter(dsl::fingerprint.eq(fingerprint).and(dsl::authority.eq(authority)))
+            .select(dsl::principal)
+            .load(conn)?;
+
+        let mut changed = 0;
+        for principal in BTreeSet::from_iter(principals) {
+            if existing.contains(&principal) {
+                continue;
+            }
+            changed += diesel::insert_into(dsl::fingerprint_principal_authorizations)
+                .values(models::FingerprintPrincipalAuthorization {
+                    fingerprint: fingerprint.to_owned(),
+                    principal,
+                    authority: authority.to_owned(),
+                })
+                .execute(conn)?;
+        }
+        Ok(changed as u64)
+    })
+}
+
+pub fn revoke_principals(
+    conn: &mut DatabaseConnection,
+    fingerprint: &str,
+    authority: &str,
+    principals: &[String],
+) -> QueryResult<u64> {
+    use schema::fingerprint_principal_authorizations::dsl;
+    diesel::delete(dsl::fingerprint_principal_authorizations.filter(
+        dsl::fingerprint.eq(fingerprint)
+            .and(dsl::authority.eq(authority))
+            .and(dsl::principal.eq_any(principals)),
+    ))
+    .execute(conn)
+    .map(|changed| changed as u64)
+}
+
+pub fn grant_hosts(
+    conn: &mut DatabaseConnection,
+    fingerprint: &str,
+    authority: &str,
+    hosts: Vec<String>,
+) -> QueryResult<u64> {
+    use schema::fingerprint_host_authorizations::dsl;
+    conn.transaction(|conn| {
+        let existing: Vec<String> = dsl::fingerprint_host_authorizations
+            .filter(dsl::fingerprint.eq(fingerprint).and(dsl::authority.eq(authority)))
+            .select(dsl::hostname)
+            .load(conn)?;
+
+        let mut changed = 0;
+        for hostname in BTreeSet::from_iter(hosts) {
+            if existing.contains(&hostname) {
+                continue;
+            }
+            changed += diesel::insert_into(dsl::fingerprint_host_authorizations)
+                .values(models::FingerprintHostAuthorization {
+                    fingerprint: fingerprint.to_owned(),
+                    hostname,
+                    authority: authority.to_owned(),
+                })
+                .execute(conn)?;
+        }
+        Ok(changed as u64)
+    })
+}
+
+pub fn revoke_hosts(
+    conn: &mut DatabaseConnection,
+    fingerprint: &str,
+    authority: &str,
+    hosts: &[String],
+) -> QueryResult<u64> {
+    use schema::fingerprint_host_authorizations::dsl;
+    diesel::delete(dsl::fingerprint_host_authorizations.filter(
+        dsl::fingerprint.eq(fingerprint)
+            .and(dsl::authority.eq(authority))
+            .and(dsl::hostname.eq_any(hosts)),
+    ))
+    .execute(conn)
+    .map(|changed| changed as u64)
+}
+
+/// Granting an extension the key already has replaces its value
+pub fn grant_extensions(
+    conn: &mut DatabaseConnection,
+    fingerprint: &str,
+    authority: &str,
+    extensions: HashMap<String, Option<String>>,
+) -> QueryResult<u64> {
+    use schema::fingerprint_extensions::dsl;
+    conn.transaction(|conn| {
+        let mut changed = 0;
+        for (extension_name, extension_value) in extensions {
+            diesel::delete(dsl::fingerprint_extensions.filter(
+                dsl::fingerprint.eq(fingerprint)
+                    .and(dsl::authority.eq(authority))
+                    .and(dsl::extension_name.eq(&extension_name)),
+            ))
+            .execute(conn)?;
+
+            changed += diesel::insert_into(dsl::fingerprint_extensions)
+                .values(models::FingerprintExtension {
+                    fingerprint: fingerprint.to_owned(),
+                    extension_name,
+                    extension_value,
+                    authority: authority.to_owned(),
+                })
+                .execute(conn)?;
+        }
+        Ok(changed as u64)
+    })
+}
+
+pub fn revoke_extensions(
+    conn: &mut DatabaseConnection,
+    fingerprint: &str,
+    authority: &str,
+    extensions: &[String],
+) -> QueryResult<u64> {
+    use schema::fingerprint_extensions::dsl;
+    diesel::delete(dsl::fingerprint_extensions.filter(
+        dsl::fingerprint.eq(fingerprint)
+            .and(dsl::authority.eq(authority))
+            .and(dsl::extension_name.eq_any(extensions)),
+    ))
+    .execute(conn)
+    .map(|changed| changed as u64)
+}
+
+/// Replaces the key's permissions for the authority
+pub fn set_permissions(
+    conn: &mut DatabaseConnection,
+    permissions: &models::FingerprintPermission,
+) -> QueryResult<u64> {
+    use schema::fingerprint_permissions::dsl;
+    conn.transaction(|conn| {
+        diesel::delete(dsl::fingerprint_permissions.filter(
+            dsl::fingerprint.eq(&permissions.fingerprint)
+                .and(dsl::authority.eq(&permissions.authority)),
+        ))
+        .execute(conn)?;
+
+        diesel::insert_into(dsl::fingerprint_permissions)
+            .values(permissions)
+            .execute(conn)
+            .map(|changed| changed as u64)
+    })
+}
+
+pub fn delete_permissions(
+    conn: &mut DatabaseConnection,
+    fingerprint: &str,
+    authority: &str,
+) -> QueryResult<u64> {
+    use schema::fingerprint_permissions::dsl;
+    diesel::delete(dsl::fingerprint_permissions.filter(
+        dsl::fingerprint.eq(fingerprint).and(dsl::authority.eq(authority)),
+    ))
+    .execute(conn)
+    .map(|changed| changed as u64)
+}
+
+/// Lists registered keys, optionally only those registered by one identity
+pub fn list_registered_keys(
+    conn: &mut DatabaseConnection,
+    identity: Option<&str>,
+) -> QueryResult<Vec<models::RegisteredKey>> {
+    let keys = schema::registered_keys::table.load::<models::RegisteredKey>(conn)?;
+
+    // Keys registered with several identities store them comma separated
+    Ok(keys
+        .into_iter()
+        .filter(|key| identity.is_none_or(|identity| key.user.split(',').any(|user| user == identity)))
+        .collect())
+}
+
+pub fn delete_registered_keys(
+    conn: &mut DatabaseConnection,
+    fingerprints: &[String],
+) -> QueryResult<u64> {
+    use schema::registered_keys::dsl;
+    diesel::delete(dsl::registered_keys.filter(dsl::fingerprint.eq_any(fingerprints)))
+        .execute(conn)
+        .map(|changed| changed as u64)
+}
+
+pub fn list_x509_authorizations(
+    conn: &mut DatabaseConnection,
+    user: Option<String>,
+) -> QueryResult<Vec<models::X509Authorization>> {
+    use schema::x509_authorizations::dsl;
+    let mut query = dsl::x509_authorizations.into_boxed();
+    if let Some(user) = user {
+        query = query.filter(dsl::user.eq(user));
+    }
+    query.load::<models::X509Authorization>(conn)
+}
+
+/// Adds the authorization, replacing any for the same user and serial
+pub fn set_x509_authorization(
+    conn: &mut DatabaseConnection,
+    authorization: &models::X509Authorization,
+) -> QueryResult<u64> {
+    use schema::x509_authorizations::dsl;
+    conn.transaction(|conn| {
+        diesel::delete(dsl::x509_authorizations.filter(
+            dsl::user.eq(&authorization.user)
+                .and(dsl::hsm_serial.eq(&authorization.hsm_serial)),
+        ))
+        .execute(conn)?;
+
+        diesel::insert_into(dsl::x509_authorizations)
+            .values(authorization)
+            .execute(conn)
+            .map(|changed| changed as u64)
+    })
+}
+
+pub fn delete_x509_authorization(
+    conn: &mut DatabaseConnection,
+    user: &str,
+    hsm_serial: &str,
+) -> QueryResult<u64> {
+    use schema::x509_authorizations::dsl;
+    diesel::delete(dsl::x509_authorizations.filter(
+        dsl::user.eq(user).and(dsl::hsm_serial.eq(hsm_serial)),
+    ))
+    .execute(conn)
+    .map(|changed| changed as u64)
+}
@@ -0,0 +1,99 @@
+//! The tables of the SQLite issuance ledger. Rustica creates them with
+//! `SCHEMA` when it starts if they do not already exist.
+table! {
+    issued_certificates (id) {
+        id -> BigInt,
+        certificate_type -> Text,
+        serial -> Text,
+        fingerprint -> Text,
+        signed_by -> Text,
+        authority -> Text,
+        principals -> Text,
+        extensions -> Text,
+        critical_options -> Text,
+        valid_after -> BigInt,
+        valid_before -> BigInt,
+        issued_at -> BigInt,
+        requester_ip -> Text,
+    }
+}
+
+table! {
+    issued_certificate_identities (certificate_id, identity) {
+        certificate_id -> BigInt,
+        identity -> Text,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(issued_certificates, issued_certificate_identities);
+
+pub const SCHEMA: &str = "
+PRAGMA journal_mode = WAL;
+
+CREATE TABLE IF NOT EXISTS issued_certificates (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    certificate_type TEXT NOT NULL,
+    serial TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    signed_by TEXT NOT NULL,
+    authority TEXT NOT NULL,
+    principals TEXT NOT NULL,
+    extensions TEXT NOT NULL,
+    critical_options TEXT NOT NULL,
+    valid_after BIGINT NOT NULL,
+    valid_before BIGINT NOT NULL,
+    issued_at BIGINT NOT NULL,
+    requester_ip TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS issued_certificates_serial ON issued_certificates (serial);
+CREATE INDEX IF NOT EXISTS issued_certificates_fingerprint ON issued_certificates (fingerprint);
+CREATE INDEX IF NOT EXISTS issued_certificates_issued_at ON issued_certificates (issued_at);
+
+CREATE TABLE IF NOT EXISTS issued_certificate_identities (
+    certificate_id INTEGER NOT NULL REFERENCES issued_certificates (id),
+    identity TEXT NOT NULL,
+    PRIMARY KEY (certificate_id, identity)
+);
+
+CREATE INDEX IF NOT EXISTS issued_certificate_identities_identity ON issued_certificate_identities (identity);
+";
+
+// Timestamps are stored as signed integers because that is all SQLite has.
+// Converting with `as` keeps the full u64 range (for example a valid_before
+// of u64::MAX for certificates that never expire) while timestamps in a
+// normal range still sort and compare correctly. Principals, extensions,
+// and critical options are stored as JSON.
+#[derive(Insertable)]
+#[diesel(table_name = issued_certificates)]
+pub struct NewIssuedCertificate {
+    pub certificate_type: String,
+    pub serial: String,
+    pub fingerprint: String,
+    pub signed_by: String,
+    pub authority: String,
+    pub principals: String,
+    pub extensions: String,
+    pub critical_options: String,
+    pub valid_after: i64,
+    pub valid_before: i64,
+    pub issued_at: i64,
+    pub requester_ip: String,
+}
+
+#[derive(Queryable)]
+pub struct IssuedCertificateRow {
+    pub id: i64,
+    pub certificate_type: String,
+    pub serial: String,
+    pub fingerprint: String,
+    pub signed_by: String,
+    pub authority: String,
+    pub principals: String,
+    pub extensions: String,
+    pub critical_options: String,
+    pub valid_after: i64,
+    pub valid_before: i64,
+    pub issued_at: i64,
+    pub requester_ip: String,
+}
@@ -0,0 +1,186 @@
+//! The local database Rustica authorizes requests against, and the tables of
+//! its SQLite issuance ledger. Rustica and rustica-admin both read and change
+//! these through this crate so they always agree on the schema.
+#[macro_use]
+extern crate diesel;
+
+mod admin;
+pub mod grants;
+pub mod ledger;
+pub mod models;
+pub mod schema;
+
+use diesel::backend::Backend;
+use diesel::migration::MigrationSource;
+#[cfg(feature = "postgres")]
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::sql_types::Text;
+use diesel::sqlite::SqliteConnection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+
+pub const SQLITE_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/sqlite");
+#[cfg(feature = "postgres")]
+pub const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations/postgres");
+
+/// A connection to whichever backend the configuration selected
+#[cfg(feature = "postgres")]
+#[derive(diesel::MultiConnection)]
+pub enum DatabaseConnection {
+    Sqlite(SqliteConnection),
+    Postgresql(PgConnection),
+}
+
+#[cfg(not(feature = "postgres"))]
+pub type DatabaseConnection = SqliteConnection;
+
+/// Lets a SQLite connection be used wherever either backend is accepted
+#[cfg(feature = "postgres")]
+pub fn sqlite_connection(conn: SqliteConnection) -> DatabaseConnection {
+    DatabaseConnection::Sqlite(conn)
+}
+
+#[cfg(not(feature = "postgres"))]
+pub fn sqlite_connection(conn: SqliteConnection) -> DatabaseConnection {
+    conn
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    /// The migrations could not be read or run
+    Migration(String),
+    /// The database has a migration this version does not know, so it was
+    /// written by a newer version of Rustica
+    TooNew { version: String, supported: String },
+    /// The database is missing migrations and was not upgraded
+    OutOfDate { version: String, required: String },
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Migration(e) => write!(f, "{e}"),
+            Self::TooNew { version, supported } => write!(
+                f,
+                "The database schema is at version {version}, which is newer than this version of Rustica supports ({supported})"
+            ),
+            Self::OutOfDate { version, required } => write!(
+                f,
+                "The database schema is at version {version} but version {required} is required. Run `rustica migrate` to upgrade it."
+            ),
+        }
+    }
+}
+
+fn migration_error<E: std::fmt::Display>(e: E) -> SchemaError {
+    SchemaError::Migration(e.to_string())
+}
+
+/// The SQLite migration that adds the U2F columns to `registered_keys`. The
+/// registered keys migration before it was later changed to create those
+/// columns itself, so on databases it created this one cannot run.
+const SQLITE_U2F_MIGRATION: &str = "20220223205236";
+
+#[derive(QueryableByName)]
+struct TableColumn {
+    #[diesel(sql_type = Text)]
+    name: String,
+}
+
+/// Records the U2F migration as applied without running it when the
+/// `registered_keys` table already has its columns. Returns whether the
+/// migration was handled here.
+fn record_existing_u2f_columns(conn: &mut SqliteConnection, version: &str) -> Result<bool, SchemaError> {
+    if version != SQLITE_U2F_MIGRATION {
+        return Ok(false);
+    }
+
+    let columns: Vec<TableColumn> = diesel::sql_query("PRAGMA table_info(registered_keys)")
+        .load(conn)
+        .map_err(migration_error)?;
+
+    if !columns.iter().any(|c| c.name == "auth_data") {
+        return Ok(false);
+    }
+
+    diesel::sql_query("INSERT INTO __diesel_schema_migrations (version) VALUES (?)")
+        .bind::<Text, _>(version)
+        .execute(conn)
+        .map_err(migration_error)?;
+    Ok(true)
+}
+
+/// Checks the schema against the migrations this version of Rustica knows
+/// and returns its version, which is the newest migration applied. When
+/// `upgrade` is set any missing migrations are run first, one at a time,
+/// skipping those `already_applied` reports were satisfied another way.
+fn check_migrations<DB, C>(
+    conn: &mut C,
+    migrations: EmbeddedMigrations,
+    upgrade: bool,
+    already_applied: fn(&mut C, &str) -> Result<bool, SchemaError>,
+) -> Result<String, SchemaError>
+where
+    DB: Backend,
+    C: MigrationHarness<DB>,
+    EmbeddedMigrations: MigrationSource<DB>,
+{
+    let known = MigrationSource::<DB>::migrations(&migrations).map_err(migration_error)?;
+    let known_versions: Vec<String> = known.iter().map(|m| m.name().version().to_string()).collect();
+    let latest = known_versions.iter().max().cloned().unwrap_or_default();
+    let applied: Vec<String> = conn
+        .applied_migrations()
+        .map_err(migration_error)?
+        .iter()
+        .map(|v| v.to_string())
+        .collect();
+
+    // A newer Rustica may have changed the schema in ways this one would
+    // misread, so do not use it at all
+    if let Some(unknown) = applied.iter().filter(|v| !known_versions.contains(v)).max() {
+        return Err(SchemaError::TooNew {
+            version: unknown.clone(),
+            supported: latest,
+        });
+    }
+
+    let pending: Vec<_> = known
+        .into_iter()
+        .filter(|m| !applied.contains(&m.name().version().to_string()))
+        .collect();
+
+    if pending.is_empty() {
+        return Ok(latest);
+    }
+
+    if !upgrade {
+        return Err(SchemaError::OutOfDate {
+            version: applied.iter().max().cloned().unwrap_or_else(|| "none".to_owned()),
+            required: latest,
+        });
+    }
+
+    for migration in &pending {
+        if !already_applied(conn, &migration.name().version().to_string())? {
+            conn.run_migration(migration.as_ref()).map_err(migration_error)?;
+        }
+    }
+    Ok(latest)
+}
+
+#[cfg(feature = "postgres")]
+pub fn prepare_schema(conn: &mut DatabaseConnection, upgrade: bool) -> Result<String, SchemaError> {
+    match conn {
+        DatabaseConnection::Sqlite(conn) => {
+            check_migrations(conn, SQLITE_MIGRATIONS, upgrade, record_existing_u2f_columns)
+        }
+        DatabaseConnection::Postgresql(conn) => {
+            check_migrations(conn, POSTGRES_MIGRATIONS, upgrade, |_, _| Ok(false))
+        }
+    }
+}
+
+#[cfg(not(feature = "postgres"))]
+pub fn prepare_schema(conn: &mut DatabaseConnection, upgrade: bool) -> Result<String, SchemaError> {
+    check_migrations(conn, SQLITE_MIGRATIONS, upgrade, record_existing_u2f_columns)
+}
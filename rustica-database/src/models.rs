@@ -1,4 +1,7 @@
-use super::schema::registered_keys;
+use crate::schema::{
+    fingerprint_extensions, fingerprint_host_authorizations, fingerprint_permissions,
+    fingerprint_principal_authorizations, registered_keys, x509_authorizations,
+};
 
 #[derive(Queryable)]
 pub struct Host {
@@ -6,21 +9,24 @@ pub struct Host {
     pub fingerprint: String,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = fingerprint_principal_authorizations)]
 pub struct FingerprintPrincipalAuthorization {
     pub fingerprint: String,
     pub principal: String,
     pub authority: String,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = fingerprint_host_authorizations)]
 pub struct FingerprintHostAuthorization {
     pub fingerprint: String,
     pub hostname: String,
     pub authority: String,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = fingerprint_extensions)]
 pub struct FingerprintExtension {
     pub fingerprint: String,
     pub extension_name: String,
@@ -28,7 +34,8 @@ pub struct FingerprintExtension {
     pub authority: String,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = fingerprint_permissions)]
 pub struct FingerprintPermission {
     pub fingerprint: String,
     pub host_unrestricted: bool,
@@ -39,7 +46,7 @@ pub struct FingerprintPermission {
     pub authority: String,
 }
 
-#[derive(Insertable)]
+#[derive(Queryable, Insertable)]
 #[diesel(table_name = registered_keys)]
 pub struct RegisteredKey {
     pub fingerprint: String,
@@ -59,7 +66,8 @@ pub struct RegisteredKey {
     pub application: Option<String>,
 }
 
-#[derive(Queryable)]
+#[derive(Queryable, Insertable)]
+#[diesel(table_name = x509_authorizations)]
 pub struct X509Authorization {
     pub user: String,
     pub hsm_serial: String,
@@ -0,0 +1,232 @@
+/// An AuthorizedPrincipalsCommand for sshd that enforces the host
+/// restrictions Rustica puts in user certificates. sshd ignores extensions
+/// it does not know about so without this, a certificate restricted to some
+/// hosts can be used on any host that trusts the CA.
+///
+/// When the certificate may be used on this host, the user being logged in
+/// as is printed which is the same principal sshd requires when no
+/// AuthorizedPrincipalsCommand is configured. Otherwise nothing is printed
+/// and sshd will reject the certificate.
+use clap::{Arg, Command};
+use sshcerts::{Certificate, PublicKey};
+
+use std::collections::HashMap;
+use std::process;
+
+/// The extension Rustica uses to list the hosts a certificate may be used on.
+/// The value is a comma separated list of hostnames or host key fingerprints.
+const HOSTS_EXTENSION: &str = "hosts@rustica";
+
+/// Where host keys are found if none are provided
+const DEFAULT_HOST_KEY_DIRECTORY: &str = "/etc/ssh";
+
+/// The longest hostname gethostname can return. One that fills the whole
+/// buffer may have been cut short.
+const MAX_HOSTNAME_LENGTH: usize = 255;
+
+/// The system hostname. A hostname that is not UTF-8 or may have been
+/// truncated is an error rather than a name that could match the wrong host.
+fn system_hostname() -> Result<String, String> {
+    let hostname =
+        nix::unistd::gethostname().map_err(|e| format!("Could not get the hostname: {e}"))?;
+
+    if hostname.len() >= MAX_HOSTNAME_LENGTH {
+        return Err("The hostname may have been truncated".to_owned());
+    }
+
+    hostname
+        .into_string()
+        .map_err(|name| format!("The hostname {name:?} is not valid UTF-8"))
+}
+
+/// The hosts a certificate is restricted to, or None if it may be used on
+/// any host
+fn restricted_hosts(extensions: &HashMap<String, String>) -> Option<Vec<String>> {
+    extensions.get(HOSTS_EXTENSION).map(|hosts| {
+        hosts
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(str::to_owned)
+            .collect()
+    })
+}
+
+/// Check if any of the hosts is one of this host's names or the fingerprint
+/// of one of its host keys. Names are expected to be lowercase.
+fn host_matches(hosts: &[String], names: &[String], fingerprints: &[String]) -> bool {
+    hosts.iter().any(|host| {
+        // Fingerprints may be written with or without the SHA256: prefix
+        let fingerprint = host.strip_prefix("SHA256:").unwrap_or(host);
+        names.contains(&host.to_lowercase()) || fingerprints.iter().any(|f| f == fingerprint)
+    })
+}
+
+/// Read the fingerprints of all host public keys in the default location
+fn default_host_key_fingerprints() -> Vec<String> {
+    let entries = match std::fs::read_dir(DEFAULT_HOST_KEY_DIRECTORY) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with("ssh_host_") && name.ends_with("_key.pub")
+        })
+        .filter_map(|path| PublicKey::from_path(path).ok())
+        .map(|key| key.fingerprint().hash)
+        .collect()
+}
+
+fn main() {
+    let matches = Command::new("rustica-principals")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Enforces Rustica host restrictions. Use as: AuthorizedPrincipalsCommand /path/to/rustica-principals %u %t %k")
+        .arg(
+            Arg::new("name")
+                .help("A name this host is known by. Defaults to the system hostname. Can be provided multiple times")
+                .long("name")
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("host-key")
+                .help("The path to one of this host's public keys. Defaults to all host keys in /etc/ssh. Can be provided multiple times")
+                .long("host-key")
+                .takes_value(true)
+                .multiple_occurrences(true),
+        )
+        .arg(Arg::new("user").help("The user being logged in as (%u)").required(true))
+        .arg(Arg::new("type").help("The certificate type (%t)").required(true))
+        .arg(Arg::new("certificate").help("The base64 encoded certificate (%k)").required(true))
+        .get_matches();
+
+    let user = matches.value_of("user").unwrap();
+    let certificate = format!(
+        "{} {}",
+        matches.value_of("type").unwrap(),
+        matches.value_of("certificate").unwrap()
+    );
+
+    let certificate = match Certificate::from_string(&certificate) {
+        Ok(certificate) => certificate,
+        Err(e) => {
+            eprintln!("Could not parse certificate: {e}");
+            process::exit(1);
+        }
+    };
+
+    let hosts = match restricted_hosts(&certificate.extensions) {
+        Some(hosts) => hosts,
+        // The certificate is not restricted to any hosts
+        None => {
+            println!("{user}");
+            return;
+        }
+    };
+
+    let names: Vec<String> = match matches.values_of("name") {
+        Some(names) => names.map(|name| name.to_lowercase()).collect(),
+        // Host keys can still match so this host is not given up on
+        None => match system_hostname() {
+            Ok(name) => vec![name.to_lowercase()],
+            Err(e) => {
+                eprintln!("{e}. Only host keys will be matched, pass --name to match a hostname");
+                vec![]
+            }
+        },
+    };
+
+    let fingerprints: Vec<String> = match matches.values_of("host-key") {
+        Some(paths) => {
+            let mut fingerprints = vec![];
+            for path in paths {
+                match PublicKey::from_path(path) {
+                    Ok(key) => fingerprints.push(key.fingerprint().hash),
+                    Err(e) => {
+                        eprintln!("Could not read host key {path}: {e}");
+                        process::exit(1);
+                    }
+                }
+            }
+            fingerprints
+        }
+        None => default_host_key_fingerprints(),
+    };
+
+    if host_matches(&hosts, &names, &fingerprints) {
+        println!("{user}");
+    } else {
+        eprintln!(
+            "Certificate {} is not valid on this host. It is restricted to: {}",
+            certificate.key_id,
+            hosts.join(","),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "o0zD6jV8fFOjBsmzzdmnUQ9tuMZfRgVhLOF2BDFF9SU";
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn extensions(hosts: &str) -> HashMap<String, String> {
+        [(HOSTS_EXTENSION.to_owned(), hosts.to_owned())]
+            .into_iter()
+            .collect()
+    }
+
+    #[test]
+    fn unrestricted_certificates_are_allowed_anywhere() {
+        let mut extensions = HashMap::new();
+        extensions.insert("permit-pty".to_owned(), String::new());
+        assert_eq!(restricted_hosts(&extensions), None);
+    }
+
+    #[test]
+    fn restricted_hosts_are_trimmed() {
+        assert_eq!(
+            restricted_hosts(&extensions("db1.example.com, SHA256:abc,,")),
+            Some(strings(&["db1.example.com", "SHA256:abc"]))
+        );
+        // An empty restriction allows no host rather than every host
+        assert_eq!(restricted_hosts(&extensions("")), Some(vec![]));
+    }
+
+    #[test]
+    fn hosts_match_by_name() {
+        let names = strings(&["db1.example.com"]);
+        assert!(host_matches(
+            &strings(&["web1", "DB1.example.com"]),
+            &names,
+            &[]
+        ));
+        assert!(!host_matches(&strings(&["db2.example.com"]), &names, &[]));
+        assert!(!host_matches(&[], &names, &[]));
+    }
+
+    #[test]
+    fn hosts_match_by_host_key_fingerprint() {
+        let fingerprints = strings(&[FINGERPRINT]);
+        assert!(host_matches(&strings(&[FINGERPRINT]), &[], &fingerprints));
+        assert!(host_matches(
+            &[format!("SHA256:{FINGERPRINT}")],
+            &[],
+            &fingerprints
+        ));
+        // Fingerprints are case sensitive
+        assert!(!host_matches(
+            &[FINGERPRINT.to_lowercase()],
+            &[],
+            &fingerprints
+        ));
+    }
+}
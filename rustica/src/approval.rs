@@ -0,0 +1,489 @@
+/// Some certificates, such as root on production hosts, should need a second
+/// person. When an authorizer marks a request as needing approval, the
+/// certificate is not issued right away. Instead the request is held here
+/// until enough of the listed approvers approve it or one of them denies it,
+/// and the requester polls for the certificate in the meantime.
+///
+/// Requests are only held in memory. They survive configuration reloads but
+/// are lost when Rustica restarts and are not shared between replicas.
+use crate::auth::ApprovalRequirement;
+use crate::server::SshCertificateIssuance;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+/// Approved certificates are valid for the duration they were authorized
+/// for, starting from when they were approved. Their validity window is moved
+/// later by the time the request waited but never made longer.
+#[derive(Deserialize)]
+pub struct ApprovalConfiguration {
+    /// How many seconds a request waits for approval. Once approved, the
+    /// requester has this long again to collect the certificate.
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: u64,
+    /// The most requests that can wait for approval at once
+    #[serde(default = "default_max_pending")]
+    pub max_pending: usize,
+}
+
+fn default_request_timeout() -> u64 {
+    3600
+}
+
+fn default_max_pending() -> usize {
+    1000
+}
+
+impl Default for ApprovalConfiguration {
+    fn default() -> Self {
+        Self {
+            request_timeout: default_request_timeout(),
+            max_pending: default_max_pending(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ApprovalError {
+    /// The authorizer asked for more approvals than there are approvers
+    Unsatisfiable,
+    /// Too many requests are already waiting for approval
+    TooManyPending,
+    /// A random request ID could not be generated
+    IdGenerationFailed,
+    /// The request does not exist, has expired, or is not visible to the
+    /// caller
+    UnknownRequest,
+    /// The caller is not one of the request's approvers
+    NotAnApprover,
+    /// Nobody may review their own request
+    OwnRequest,
+    /// The caller has already approved this request
+    AlreadyApproved,
+    /// The request has already been approved or denied
+    AlreadyDecided,
+}
+
+impl std::fmt::Display for ApprovalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsatisfiable => write!(f, "More approvals are required than there are approvers"),
+            Self::TooManyPending => write!(f, "Too many requests are waiting for approval"),
+            Self::IdGenerationFailed => write!(f, "Could not generate a request ID"),
+            Self::UnknownRequest => write!(f, "No such request"),
+            Self::NotAnApprover => write!(f, "Not an approver for this request"),
+            Self::OwnRequest => write!(f, "Requests cannot be reviewed by their requester"),
+            Self::AlreadyApproved => write!(f, "Request has already been approved by this approver"),
+            Self::AlreadyDecided => write!(f, "Request has already been decided"),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum ApprovalState {
+    /// Waiting for more approvals
+    Pending,
+    /// Enough approvals were given and the certificate is being signed
+    Issuing,
+    /// The certificate was issued and can be collected
+    Issued(String),
+    /// The request was denied or the certificate could not be issued. This
+    /// contains the reason.
+    Denied(String),
+}
+
+#[derive(Clone)]
+pub struct ApprovalRequest {
+    pub id: String,
+    /// The authorized certificate as it will be issued
+    pub issuance: SshCertificateIssuance,
+    pub requirement: ApprovalRequirement,
+    /// The approvers that have approved the request, in order
+    pub approved_by: Vec<String>,
+    pub requested_at: u64,
+    pub state: ApprovalState,
+    expires_at: u64,
+}
+
+/// The result of an approver's review
+pub enum ReviewOutcome {
+    /// More approvals are needed
+    Pending { approvals: usize, required: usize },
+    /// Enough approvals were given. The certificate must now be issued and
+    /// the result passed to `complete`.
+    Approved(Box<ApprovalRequest>),
+    /// The request was denied
+    Denied { approvals: usize, required: usize },
+}
+
+struct Requests {
+    request_timeout: u64,
+    max_pending: usize,
+    requests: HashMap<String, ApprovalRequest>,
+}
+
+impl Requests {
+    fn prune(&mut self, time: u64) {
+        self.requests.retain(|_, request| request.expires_at > time);
+    }
+}
+
+pub struct ApprovalStore {
+    requests: Mutex<Requests>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+impl ApprovalStore {
+    pub fn new(config: &ApprovalConfiguration) -> Self {
+        Self {
+            requests: Mutex::new(Requests {
+                request_timeout: config.request_timeout,
+                max_pending: config.max_pending,
+                requests: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Apply a reloaded configuration. Requests already waiting keep their
+    /// expiry.
+    pub fn configure(&self, config: &ApprovalConfiguration) {
+        let mut requests = self.lock();
+        requests.request_timeout = config.request_timeout;
+        requests.max_pending = config.max_pending;
+    }
+
+    // The lock is never held across an await so a poisoned lock can only
+    // come from a panic that left the map consistent
+    fn lock(&self) -> MutexGuard<'_, Requests> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Hold an authorized certificate until it is approved. Returns the ID
+    /// the requester and approvers refer to it by.
+    pub fn submit(
+        &self,
+        issuance: SshCertificateIssuance,
+        requirement: ApprovalRequirement,
+    ) -> Result<String, ApprovalError> {
+        if requirement.required > requirement.approvers.len() {
+            return Err(ApprovalError::Unsatisfiable);
+        }
+
+        let mut id = [0u8; 16];
+        SystemRandom::new()
+            .fill(&mut id)
+            .map_err(|_| ApprovalError::IdGenerationFailed)?;
+        let id = hex::encode(id);
+
+        let time = now();
+        let mut requests = self.lock();
+        requests.prune(time);
+
+        let pending = requests
+            .requests
+            .values()
+            .filter(|x| matches!(x.state, ApprovalState::Pending))
+            .count();
+        if pending >= requests.max_pending {
+            return Err(ApprovalError::TooManyPending);
+        }
+
+        let expires_at = time.saturating_add(requests.request_timeout);
+        requests.requests.insert(
+            id.clone(),
+            ApprovalRequest {
+                id: id.clone(),
+                issuance,
+                requirement,
+                approved_by: vec![],
+                requested_at: time,
+                state: ApprovalState::Pending,
+                expires_at,
+            },
+        );
+
+        Ok(id)
+    }
+
+    /// The requests waiting for approval that the caller can review, oldest
+    /// first. If `all` is set, every waiting request is returned.
+    pub fn list(&self, identities: &[String], all: bool) -> Vec<ApprovalRequest> {
+        let mut requests = self.lock();
+        requests.prune(now());
+
+        let mut pending: Vec<ApprovalRequest> = requests
+            .requests
+            .values()
+            .filter(|x| matches!(x.state, ApprovalState::Pending))
+            .filter(|x| all || reviewer(x, identities).is_ok())
+            .cloned()
+            .collect();
+        pending.sort_by_key(|x| x.requested_at);
+        pending
+    }
+
+    /// Approve or deny a request. Returns the identity the review was
+    /// recorded under along with the outcome.
+    pub fn review(
+        &self,
+        id: &str,
+        identities: &[String],
+        approve: bool,
+        reason: &str,
+    ) -> Result<(String, ReviewOutcome), ApprovalError> {
+        let mut requests = self.lock();
+        requests.prune(now());
+
+        let request = requests
+            .requests
+            .get_mut(id)
+            .ok_or(ApprovalError::UnknownRequest)?;
+        let approver = reviewer(request, identities)?;
+
+        if !matches!(request.state, ApprovalState::Pending) {
+            return Err(ApprovalError::AlreadyDecided);
+        }
+
+        if !approve {
+            request.state = ApprovalState::Denied(if reason.is_empty() {
+                format!("Denied by {approver}")
+            } else {
+                format!("Denied by {approver}: {reason}")
+            });
+            let outcome = ReviewOutcome::Denied {
+                approvals: request.approved_by.len(),
+                required: request.requirement.required,
+            };
+            return Ok((approver, outcome));
+        }
+
+        if request.approved_by.contains(&approver) {
+            return Err(ApprovalError::AlreadyApproved);
+        }
+        request.approved_by.push(approver.clone());
+
+        if request.approved_by.len() < request.requirement.required {
+            let outcome = ReviewOutcome::Pending {
+                approvals: request.approved_by.len(),
+                required: request.requirement.required,
+            };
+            return Ok((approver, outcome));
+        }
+
+        request.state = ApprovalState::Issuing;
+        Ok((approver, ReviewOutcome::Approved(Box::new(request.clone()))))
+    }
+
+    /// Record the result of issuing an approved certificate. The requester
+    /// then has the request timeout to collect it.
+    pub fn complete(&self, id: &str, result: Result<String, String>) {
+        let mut requests = self.lock();
+        let request_timeout = requests.request_timeout;
+
+        if let Some(request) = requests.requests.get_mut(id) {
+            request.state = match result {
+                Ok(certificate) => ApprovalState::Issued(certificate),
+                Err(e) => ApprovalState::Denied(e),
+            };
+            request.expires_at = now().saturating_add(request_timeout);
+        }
+    }
+
+    /// The state of a request. Only the requester can see their request.
+    pub fn status(&self, id: &str, identities: &[String]) -> Result<ApprovalState, ApprovalError> {
+        let mut requests = self.lock();
+        requests.prune(now());
+
+        match requests.requests.get(id) {
+            Some(request) if request.issuance.mtls_identities == identities => {
+                Ok(request.state.clone())
+            }
+            _ => Err(ApprovalError::UnknownRequest),
+        }
+    }
+}
+
+/// Find which of the caller's identities can review a request
+fn reviewer(request: &ApprovalRequest, identities: &[String]) -> Result<String, ApprovalError> {
+    if identities
+        .iter()
+        .any(|x| request.issuance.mtls_identities.contains(x))
+    {
+        return Err(ApprovalError::OwnRequest);
+    }
+
+    identities
+        .iter()
+        .find(|x| request.requirement.approvers.contains(x))
+        .cloned()
+        .ok_or(ApprovalError::NotAnApprover)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::SshAuthorization;
+
+    use sshcerts::ssh::{CertType, PublicKey};
+
+    const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIKNO7QPgbPauWmF8nfTV6fkYd2fUtxvajI96bjDXE0oM";
+
+    fn identities(names: &[&str]) -> Vec<String> {
+        names.iter().map(|x| x.to_string()).collect()
+    }
+
+    fn store(request_timeout: u64) -> ApprovalStore {
+        ApprovalStore::new(&ApprovalConfiguration {
+            request_timeout,
+            max_pending: 10,
+        })
+    }
+
+    /// Submit a request from dave that needs two of alice, bob, and carol
+    fn submit(store: &ApprovalStore) -> String {
+        let ssh_pubkey = PublicKey::from_string(KEY).unwrap();
+        let requirement = ApprovalRequirement {
+            approvers: identities(&["alice", "bob", "carol"]),
+            required: 2,
+        };
+        let issuance = SshCertificateIssuance {
+            fingerprint: ssh_pubkey.fingerprint().hash,
+            ssh_pubkey,
+            cert_type: CertType::User,
+            authority: String::new(),
+            authorization: SshAuthorization {
+                serial: 1,
+                valid_before: 600,
+                valid_after: 0,
+                principals: identities(&["root"]),
+                hosts: None,
+                extensions: HashMap::new(),
+                force_command: None,
+                force_source_ip: false,
+                authority: String::new(),
+                approval: Some(requirement.clone()),
+            },
+            requested_extensions: HashMap::new(),
+            requested_critical_options: HashMap::new(),
+            mtls_identities: identities(&["dave"]),
+            access_serial: None,
+            access_fingerprint: None,
+            remote_addr: "127.0.0.1:1000".parse().unwrap(),
+        };
+
+        store.submit(issuance, requirement).unwrap()
+    }
+
+    #[test]
+    fn approved_once_quorum_is_reached() {
+        let store = store(3600);
+        let id = submit(&store);
+
+        let (approver, outcome) = store.review(&id, &identities(&["alice"]), true, "").unwrap();
+        assert_eq!(approver, "alice");
+        assert!(matches!(
+            outcome,
+            ReviewOutcome::Pending {
+                approvals: 1,
+                required: 2
+            }
+        ));
+
+        let (_, outcome) = store.review(&id, &identities(&["bob"]), true, "").unwrap();
+        match outcome {
+            ReviewOutcome::Approved(request) => {
+                assert_eq!(request.approved_by, identities(&["alice", "bob"]))
+            }
+            _ => panic!("Request was not approved"),
+        }
+
+        assert!(matches!(
+            store.status(&id, &identities(&["dave"])),
+            Ok(ApprovalState::Issuing)
+        ));
+        assert!(matches!(
+            store.review(&id, &identities(&["carol"]), true, ""),
+            Err(ApprovalError::AlreadyDecided)
+        ));
+    }
+
+    #[test]
+    fn denied_by_a_single_approver() {
+        let store = store(3600);
+        let id = submit(&store);
+
+        store.review(&id, &identities(&["alice"]), true, "").unwrap();
+        let (_, outcome) = store
+            .review(&id, &identities(&["bob"]), false, "not on call")
+            .unwrap();
+        assert!(matches!(
+            outcome,
+            ReviewOutcome::Denied {
+                approvals: 1,
+                required: 2
+            }
+        ));
+
+        match store.status(&id, &identities(&["dave"])) {
+            Ok(ApprovalState::Denied(reason)) => assert_eq!(reason, "Denied by bob: not on call"),
+            _ => panic!("Request was not denied"),
+        }
+        assert!(matches!(
+            store.review(&id, &identities(&["carol"]), true, ""),
+            Err(ApprovalError::AlreadyDecided)
+        ));
+    }
+
+    #[test]
+    fn requester_cannot_review() {
+        let store = store(3600);
+        let id = submit(&store);
+
+        // Even when one of their other identities is an approver
+        assert!(matches!(
+            store.review(&id, &identities(&["dave", "alice"]), true, ""),
+            Err(ApprovalError::OwnRequest)
+        ));
+        assert!(matches!(
+            store.review(&id, &identities(&["erin"]), true, ""),
+            Err(ApprovalError::NotAnApprover)
+        ));
+    }
+
+    #[test]
+    fn approvals_are_counted_once() {
+        let store = store(3600);
+        let id = submit(&store);
+
+        store.review(&id, &identities(&["alice"]), true, "").unwrap();
+        assert!(matches!(
+            store.review(&id, &identities(&["alice"]), true, ""),
+            Err(ApprovalError::AlreadyApproved)
+        ));
+        assert!(matches!(
+            store.status(&id, &identities(&["dave"])),
+            Ok(ApprovalState::Pending)
+        ));
+    }
+
+    #[test]
+    fn expired_requests_cannot_be_reviewed() {
+        let store = store(0);
+        let id = submit(&store);
+
+        assert!(matches!(
+            store.review(&id, &identities(&["alice"]), true, ""),
+            Err(ApprovalError::UnknownRequest)
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+/// Changes made to the local database through the RusticaAdmin service. The
+/// changes themselves are shared with rustica-admin, this runs them on the
+/// connection pool.
+use super::{database_error, models, LocalDatabase};
+use crate::auth::AuthorizationError;
+
+use rustica_database::grants::{self, Grants};
+
+use std::collections::HashMap;
+
+impl LocalDatabase {
+    pub async fn list_grants(
+        &self,
+        fingerprint: Option<String>,
+        authority: Option<String>,
+    ) -> Result<Vec<Grants>, AuthorizationError> {
+        self.run(move |conn| {
+            grants::list_grants(conn, fingerprint, authority).map_err(database_error)
+        }).await
+    }
+
+    pub async fn grant_principals(
+        &self,
+        fingerprint: String,
+        authority: String,
+        principals: Vec<String>,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::grant_principals(conn, &fingerprint, &authority, principals).map_err(database_error)
+        }).await
+    }
+
+    pub async fn revoke_principals(
+        &self,
+        fingerprint: String,
+        authority: String,
+        principals: Vec<String>,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::revoke_principals(conn, &fingerprint, &authority, &principals).map_err(database_error)
+        }).await
+    }
+
+    pub async fn grant_hosts(
+        &self,
+        fingerprint: String,
+        authority: String,
+        hosts: Vec<String>,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::grant_hosts(conn, &fingerprint, &authority, hosts).map_err(database_error)
+        }).await
+    }
+
+    pub async fn revoke_hosts(
+        &self,
+        fingerprint: String,
+        authority: String,
+        hosts: Vec<String>,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::revoke_hosts(conn, &fingerprint, &authority, &hosts).map_err(database_error)
+        }).await
+    }
+
+    pub async fn grant_extensions(
+        &self,
+        fingerprint: String,
+        authority: String,
+        extensions: HashMap<String, Option<String>>,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::grant_extensions(conn, &fingerprint, &authority, extensions).map_err(database_error)
+        }).await
+    }
+
+    pub async fn revoke_extensions(
+        &self,
+        fingerprint: String,
+        authority: String,
+        extensions: Vec<String>,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::revoke_extensions(conn, &fingerprint, &authority, &extensions).map_err(database_error)
+        }).await
+    }
+
+    pub async fn set_permissions(
+        &self,
+        permissions: models::FingerprintPermission,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::set_permissions(conn, &permissions).map_err(database_error)
+        }).await
+    }
+
+    pub async fn delete_permissions(
+        &self,
+        fingerprint: String,
+        authority: String,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::delete_permissions(conn, &fingerprint, &authority).map_err(database_error)
+        }).await
+    }
+
+    pub async fn list_registered_keys(
+        &self,
+        identity: Option<String>,
+    ) -> Result<Vec<models::RegisteredKey>, AuthorizationError> {
+        self.run(move |conn| {
+            grants::list_registered_keys(conn, identity.as_deref()).map_err(database_error)
+        }).await
+    }
+
+    pub async fn delete_registered_keys(
+        &self,
+        fingerprints: Vec<String>,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::delete_registered_keys(conn, &fingerprints).map_err(database_error)
+        }).await
+    }
+
+    pub async fn list_x509_authorizations(
+        &self,
+        user: Option<String>,
+    ) -> Result<Vec<models::X509Authorization>, AuthorizationError> {
+        self.run(move |conn| {
+            grants::list_x509_authorizations(conn, user).map_err(database_error)
+        }).await
+    }
+
+    pub async fn set_x509_authorization(
+        &self,
+        authorization: models::X509Authorization,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::set_x509_authorization(conn, &authorization).map_err(database_error)
+        }).await
+    }
+
+    pub async fn delete_x509_authorization(
+        &self,
+        user: String,
+        hsm_serial: String,
+    ) -> Result<u64, AuthorizationError> {
+        self.run(move |conn| {
+            grants::delete_x509_authorization(conn, &user, &hsm_serial).map_err(database_error)
+        }).await
+    }
+}
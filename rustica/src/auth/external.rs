@@ -9,7 +9,7 @@ use x509_parser::oid_registry::Oid;
 use super::{
     AuthorizationError, KeyAttestation, RegisterKeyRequestProperties, SshAuthorization,
     SshAuthorizationRequestProperties, X509Authorization, X509AuthorizationRequestProperties,
-    AllowedSigners, AllowedSigner,
+    AllowedSigners, AllowedSigner, ApprovalRequirement,
 };
 use serde::Deserialize;
 use std::collections::HashMap;
@@ -29,12 +29,23 @@ pub struct AuthServer {
     pub mtls_key: String,
 }
 
+/// Start the identities sent to the authorization service with the
+/// requester's named identities. Values are joined the same way as the mTLS
+/// identities. The identities Rustica sets itself are added afterwards so a
+/// named identity can never replace one.
+fn named_identities(attributes: &HashMap<String, Vec<String>>) -> HashMap<String, String> {
+    attributes
+        .iter()
+        .map(|(name, values)| (name.clone(), values.join(",")))
+        .collect()
+}
+
 impl AuthServer {
     pub async fn authorize_ssh_cert(
         &self,
         auth_props: &SshAuthorizationRequestProperties,
     ) -> Result<SshAuthorization, AuthorizationError> {
-        let mut identities = HashMap::new();
+        let mut identities = named_identities(&auth_props.identity_attributes);
         identities.insert(
             String::from("requester_ip"),
             auth_props.requester_ip.clone(),
@@ -63,6 +74,15 @@ impl AuthServer {
         authorization_request.insert(String::from("cert_type"), auth_props.cert_type.to_string());
         authorization_request.insert(String::from("authority"), auth_props.authority.to_string());
 
+        // Requested extensions and critical options use the same prefixes as
+        // the approval response
+        for (name, value) in auth_props.extensions.iter() {
+            authorization_request.insert(format!("extension.{}", name), value.clone());
+        }
+        for (name, value) in auth_props.critical_options.iter() {
+            authorization_request.insert(format!("critical_option.{}", name), value.clone());
+        }
+
         let request = tonic::Request::new(AuthorizeRequest {
             identities,
             authorization_request,
@@ -142,6 +162,26 @@ impl AuthServer {
             None
         };
 
+        // The authorizer can require other people to approve the request by
+        // returning how many approvals are needed and who can give them
+        let approval = match (
+            approval_response.get("approval_required"),
+            approval_response.get("approvers"),
+        ) {
+            (Some(required), Some(approvers)) => Some(ApprovalRequirement {
+                required: required
+                    .parse::<usize>()
+                    .map_err(|_| AuthorizationError::AuthorizerError)?,
+                approvers: approvers
+                    .split(',')
+                    .filter(|x| !x.is_empty())
+                    .map(String::from)
+                    .collect(),
+            }),
+            (None, None) => None,
+            _ => return Err(AuthorizationError::AuthorizerError),
+        };
+
         let serial = approval_response["serial"]
             .parse::<u64>()
             .map_err(|_| AuthorizationError::AuthorizerError)?;
@@ -165,6 +205,7 @@ impl AuthServer {
             force_command,
             force_source_ip,
             authority: approval_response["authority"].to_string(),
+            approval,
         })
     }
 
@@ -172,7 +213,7 @@ impl AuthServer {
         &self,
         req: &RegisterKeyRequestProperties,
     ) -> Result<(), AuthorizationError> {
-        let mut identities = HashMap::new();
+        let mut identities = named_identities(&req.identity_attributes);
         identities.insert(String::from("requester_ip"), req.requester_ip.clone());
         identities.insert(String::from("key_fingerprint"), req.fingerprint.clone());
         identities.insert(
@@ -275,7 +316,7 @@ impl AuthServer {
         authorization_request.insert("authority".to_string(), auth_props.authority.clone());
 
         // Identities
-        let mut identities = HashMap::new();
+        let mut identities = named_identities(&auth_props.identity_attributes);
         identities.insert(
             String::from("mtls_identities"),
             auth_props.mtls_identities.join(","),
@@ -460,4 +501,26 @@ impl AuthServer {
 
         Ok(AllowedSigners{ allowed_signers })
     }
+
+    /// Check the authorization server can be reached. This only opens a
+    /// connection as the Author service has no call without side effects.
+    pub async fn check_health(&self) -> Result<(), AuthorizationError> {
+        let client_identity =
+            Identity::from_pem(self.mtls_cert.as_bytes(), self.mtls_key.as_bytes());
+
+        let tls = ClientTlsConfig::new()
+            .domain_name(&self.server)
+            .ca_certificate(Certificate::from_pem(self.ca.as_bytes()))
+            .identity(client_identity);
+
+        Channel::from_shared(format!("https://{}:{}", &self.server, &self.port))
+            .map_err(|_| AuthorizationError::ConnectionFailure)?
+            .timeout(Duration::from_secs(10))
+            .tls_config(tls)
+            .map_err(|_| AuthorizationError::ConnectionFailure)?
+            .connect()
+            .await
+            .map(|_| ())
+            .map_err(|_| AuthorizationError::ConnectionFailure)
+    }
 }
@@ -1,8 +1,10 @@
 #[cfg(feature = "local-db")]
 pub mod database;
 pub mod external;
+pub mod policy;
 
 use crate::key::Key;
+use crate::metrics::{self, AuthorizerOutcome};
 
 pub use super::key::KeyAttestation;
 
@@ -12,6 +14,7 @@ use sshcerts::ssh::CertType;
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::time::Instant;
 
 #[derive(Debug)]
 pub enum AuthorizationError {
@@ -43,11 +46,22 @@ impl std::fmt::Display for AuthorizationError {
 #[derive(Deserialize)]
 pub struct AuthorizationConfiguration {
     #[cfg(feature = "local-db")]
-    pub database: Option<database::LocalDatabase>,
+    pub database: Option<database::LocalDatabaseConfiguration>,
     pub external: Option<external::AuthServer>,
+    pub policy: Option<policy::PolicyConfiguration>,
 }
 
-#[derive(Debug)]
+/// Other people that must approve a request before the certificate is
+/// issued
+#[derive(Clone, Debug, Deserialize)]
+pub struct ApprovalRequirement {
+    /// The mTLS identities that may approve or deny the request
+    pub approvers: Vec<String>,
+    /// How many different approvers must approve the request
+    pub required: usize,
+}
+
+#[derive(Clone, Debug)]
 pub struct SshAuthorization {
     pub serial: u64,
     pub valid_before: u64,
@@ -58,12 +72,16 @@ pub struct SshAuthorization {
     pub force_command: Option<String>,
     pub force_source_ip: bool,
     pub authority: String,
+    /// If set, the certificate is only issued once the request is approved
+    pub approval: Option<ApprovalRequirement>,
 }
 
 #[derive(Debug)]
 pub struct SshAuthorizationRequestProperties {
     pub fingerprint: String,
     pub mtls_identities: Vec<String>,
+    /// Named identities of the requester such as a SPIFFE ID or team
+    pub identity_attributes: HashMap<String, Vec<String>>,
     pub requester_ip: String,
     pub principals: Vec<String>,
     pub servers: Vec<String>,
@@ -71,12 +89,19 @@ pub struct SshAuthorizationRequestProperties {
     pub valid_after: u64,
     pub cert_type: CertType,
     pub authority: String,
+    /// The extensions the client asked for. The issued certificate will only
+    /// contain the ones that are also authorized.
+    pub extensions: HashMap<String, String>,
+    /// The critical options the client asked for
+    pub critical_options: HashMap<String, String>,
 }
 
 #[derive(Debug)]
 pub struct X509AuthorizationRequestProperties {
     pub authority: String,
     pub mtls_identities: Vec<String>,
+    /// Named identities of the requester such as a SPIFFE ID or team
+    pub identity_attributes: HashMap<String, Vec<String>>,
     pub requester_ip: String,
     pub attestation: Vec<u8>,
     pub attestation_intermediate: Vec<u8>,
@@ -100,6 +125,8 @@ pub struct RegisterKeyRequestProperties {
     pub fingerprint: String,
     pub pubkey: String,
     pub mtls_identities: Vec<String>,
+    /// Named identities of the requester such as a SPIFFE ID or team
+    pub identity_attributes: HashMap<String, Vec<String>>,
     pub requester_ip: String,
     pub attestation: Option<KeyAttestation>,
 }
@@ -120,6 +147,7 @@ pub enum AuthorizationMechanism {
     #[cfg(feature = "local-db")]
     Local(database::LocalDatabase),
     External(external::AuthServer),
+    Policy(policy::PolicyAuthorizer),
 }
 
 impl AuthorizationMechanism {
@@ -127,63 +155,138 @@ impl AuthorizationMechanism {
         &self,
         auth_props: &SshAuthorizationRequestProperties,
     ) -> Result<SshAuthorization, AuthorizationError> {
-        match &self {
+        let start = Instant::now();
+        let result = match &self {
             #[cfg(feature = "local-db")]
-            AuthorizationMechanism::Local(local) => local.authorize_ssh_cert(auth_props),
+            AuthorizationMechanism::Local(local) => local.authorize_ssh_cert(auth_props).await,
             AuthorizationMechanism::External(external) => {
                 external.authorize_ssh_cert(auth_props).await
             }
-        }
+            AuthorizationMechanism::Policy(policy) => {
+                policy.authorize_ssh_cert(auth_props).await
+            }
+        };
+
+        self.record_metrics("authorize_ssh_cert", start, &result);
+        result
     }
 
     pub async fn authorize_attested_x509_cert(
         &self,
         auth_props: &X509AuthorizationRequestProperties,
     ) -> Result<X509Authorization, AuthorizationError> {
-        match &self {
+        let start = Instant::now();
+        let result = match &self {
             #[cfg(feature = "local-db")]
-            AuthorizationMechanism::Local(local) => local.authorize_attested_x509_cert(auth_props),
+            AuthorizationMechanism::Local(local) => {
+                local.authorize_attested_x509_cert(auth_props).await
+            }
             AuthorizationMechanism::External(external) => {
                 external.authorize_attested_x509_cert(auth_props).await
             }
-        }
+            AuthorizationMechanism::Policy(policy) => {
+                policy.authorize_attested_x509_cert(auth_props).await
+            }
+        };
+
+        self.record_metrics("authorize_attested_x509_cert", start, &result);
+        result
     }
 
     pub async fn register_key(
         &self,
         register_properties: &RegisterKeyRequestProperties,
     ) -> Result<(), AuthorizationError> {
-        match &self {
+        let start = Instant::now();
+        let result = match &self {
             #[cfg(feature = "local-db")]
-            AuthorizationMechanism::Local(local) => local.register_key(register_properties),
+            AuthorizationMechanism::Local(local) => local.register_key(register_properties).await,
             AuthorizationMechanism::External(external) => {
                 external.register_key(register_properties).await
             }
-        }
+            AuthorizationMechanism::Policy(policy) => {
+                policy.register_key(register_properties).await
+            }
+        };
+
+        self.record_metrics("register_key", start, &result);
+        result
     }
 
     pub async fn get_allowed_signers(
         &self,
     ) -> Result<AllowedSigners, AuthorizationError> {
-        match &self {
+        let start = Instant::now();
+        let result = match &self {
             #[cfg(feature = "local-db")]
-            AuthorizationMechanism::Local(local) => local.get_allowed_signers(),
+            AuthorizationMechanism::Local(local) => local.get_allowed_signers().await,
             AuthorizationMechanism::External(external) => {
                 external.get_allowed_signers().await
             }
+            AuthorizationMechanism::Policy(policy) => {
+                policy.get_allowed_signers().await
+            }
+        };
+
+        self.record_metrics("get_allowed_signers", start, &result);
+        result
+    }
+
+    /// Check the authorizer is reachable. This is used to drive the gRPC
+    /// health service and is not recorded in the authorizer metrics.
+    pub async fn check_health(&self) -> Result<(), AuthorizationError> {
+        match &self {
+            #[cfg(feature = "local-db")]
+            AuthorizationMechanism::Local(local) => local.check_health().await,
+            AuthorizationMechanism::External(external) => external.check_health().await,
+            AuthorizationMechanism::Policy(policy) => policy.check_health().await,
+        }
+    }
+
+    /// A short name for the mechanism used to label metrics
+    fn name(&self) -> &'static str {
+        match &self {
+            #[cfg(feature = "local-db")]
+            AuthorizationMechanism::Local(_) => "local",
+            AuthorizationMechanism::External(_) => "external",
+            AuthorizationMechanism::Policy(_) => "policy",
         }
     }
 
+    /// Record how long a call to the authorizer took and what it returned
+    fn record_metrics<T>(
+        &self,
+        operation: &str,
+        start: Instant,
+        result: &Result<T, AuthorizationError>,
+    ) {
+        let outcome = match result {
+            Ok(_) => AuthorizerOutcome::Authorized,
+            Err(AuthorizationError::NotAuthorized) | Err(AuthorizationError::CertType) => {
+                AuthorizerOutcome::Denied
+            }
+            Err(_) => AuthorizerOutcome::Error,
+        };
+
+        metrics::authorizer_request(self.name(), operation, start.elapsed(), outcome);
+    }
+
     pub fn info(&self) -> String {
         match &self {
             #[cfg(feature = "local-db")]
             AuthorizationMechanism::Local(local) => {
-                format!("Configured authorizer: Local DB at {}", &local.path)
+                format!(
+                    "Configured authorizer: Local DB at {} (schema version {})",
+                    &local.path, &local.schema_version
+                )
             }
             AuthorizationMechanism::External(external) => format!(
                 "Configured authorizer: Remote Service at {}",
                 &external.server
             ),
+            AuthorizationMechanism::Policy(policy) => {
+                format!("Configured authorizer: Policy file at {}", &policy.path)
+            }
         }
     }
 }
@@ -191,18 +294,34 @@ impl AuthorizationMechanism {
 impl TryInto<AuthorizationMechanism> for AuthorizationConfiguration {
     type Error = ();
     fn try_into(self) -> Result<AuthorizationMechanism, ()> {
+        let policy = match self.policy {
+            Some(policy) => Some(policy::PolicyAuthorizer::try_from(policy).map_err(|e| {
+                error!("Could not load authorization policy: {e}");
+            })?),
+            None => None,
+        };
+
         #[cfg(feature = "local-db")]
-        match (self.database, self.external) {
-            (Some(database), None) => Ok(AuthorizationMechanism::Local(database)),
-            (None, Some(external)) => Ok(AuthorizationMechanism::External(external)),
+        let database = match self.database {
+            Some(database) => Some(database::LocalDatabase::try_from(database).map_err(|e| {
+                error!("Could not open local database: {e}");
+            })?),
+            None => None,
+        };
+
+        #[cfg(feature = "local-db")]
+        match (database, self.external, policy) {
+            (Some(database), None, None) => Ok(AuthorizationMechanism::Local(database)),
+            (None, Some(external), None) => Ok(AuthorizationMechanism::External(external)),
+            (None, None, Some(policy)) => Ok(AuthorizationMechanism::Policy(policy)),
             _ => Err(()),
         }
 
         #[cfg(not(feature = "local-db"))]
-        if let Some(external) = self.external {
-            Ok(AuthorizationMechanism::External(external))
-        } else {
-            Err(())
+        match (self.external, policy) {
+            (Some(external), None) => Ok(AuthorizationMechanism::External(external)),
+            (None, Some(policy)) => Ok(AuthorizationMechanism::Policy(policy)),
+            _ => Err(()),
         }
     }
 }
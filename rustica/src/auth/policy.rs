@@ -0,0 +1,825 @@
+/// The policy authorizer makes authorization decisions from a declarative
+/// rules file instead of a database or remote service. It is designed for
+/// small deployments where the full set of permissions comfortably fits in
+/// a single file that can be kept in version control.
+///
+/// The policy file may be TOML or YAML (chosen by the file extension) and
+/// is reloaded whenever it changes on disk. If a changed file cannot be
+/// parsed, the previously loaded policy remains in effect.
+///
+/// Rules are evaluated in order and the first rule that matches a request
+/// is used. Every condition set on a rule must match for the rule to match,
+/// and conditions that are not set match everything.
+use super::{
+    AllowedSigner, AllowedSigners, ApprovalRequirement, AuthorizationError, KeyAttestation,
+    RegisterKeyRequestProperties, SshAuthorization, SshAuthorizationRequestProperties,
+    X509Authorization, X509AuthorizationRequestProperties,
+};
+use crate::key::TouchPolicy;
+
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use sshcerts::ssh::CertType;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::RwLock;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::SystemTime;
+
+#[derive(Deserialize)]
+pub struct PolicyConfiguration {
+    /// The path to the policy file. Files ending in `.yaml` or `.yml` are
+    /// parsed as YAML, everything else is parsed as TOML.
+    pub path: String,
+    /// The path registered keys are appended to. If not set, this is the
+    /// policy path with `.registered_keys.toml` appended.
+    pub registered_keys_path: Option<String>,
+}
+
+/// Conditions shared by SSH and X509 rules
+#[derive(Debug, Default, Deserialize)]
+struct RuleConditions {
+    /// Match if any of the requester's mTLS identities is in this list
+    mtls_identities: Option<Vec<String>>,
+    /// Match if, for every named identity listed, any of the requester's
+    /// values for it is in the list
+    identities: Option<HashMap<String, Vec<String>>>,
+    /// Match if the requester's IP is in any of these CIDR ranges
+    source_ips: Option<Vec<IpNet>>,
+    /// Match if the request is for one of these authorities
+    authorities: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PolicyCertType {
+    User,
+    Host,
+}
+
+#[derive(Debug, Deserialize)]
+struct SshRule {
+    /// Used in logs when the rule matches
+    name: String,
+    #[serde(flatten)]
+    conditions: RuleConditions,
+    /// Match if the key's SHA256 fingerprint is in this list
+    fingerprints: Option<Vec<String>>,
+    /// Match if the requested certificate is one of these types
+    cert_types: Option<Vec<PolicyCertType>>,
+    /// The principals the certificate will be issued with
+    #[serde(default)]
+    principals: Vec<String>,
+    /// The hosts the certificate is valid for. If not set, all hosts.
+    hosts: Option<Vec<String>>,
+    /// The extensions the certificate will be issued with
+    #[serde(default)]
+    extensions: HashMap<String, String>,
+    /// A command the certificate will be restricted to
+    force_command: Option<String>,
+    /// Restrict the certificate to the IP it was requested from
+    #[serde(default)]
+    force_source_ip: bool,
+    /// The longest a certificate may be valid for, in seconds
+    max_validity: u64,
+    /// Other people that must approve the request before the certificate
+    /// is issued
+    approval: Option<ApprovalRequirement>,
+}
+
+#[derive(Debug, Deserialize)]
+struct X509Rule {
+    /// Used in logs when the rule matches
+    name: String,
+    #[serde(flatten)]
+    conditions: RuleConditions,
+    /// Match only if the key requires touch for every operation or uses a
+    /// cached touch
+    #[serde(default)]
+    require_touch: bool,
+    /// The subject alternative names of the certificate. If not set, the
+    /// requester's first mTLS identity is used.
+    sans: Option<Vec<String>>,
+    /// How long the certificate will be valid for, in seconds
+    validity: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Policy {
+    #[serde(default)]
+    ssh: Vec<SshRule>,
+    #[serde(default)]
+    x509: Vec<X509Rule>,
+}
+
+/// A key registered through the policy authorizer
+#[derive(Deserialize, Serialize)]
+struct RegisteredKey {
+    fingerprint: String,
+    pubkey: String,
+    user: String,
+    firmware: Option<String>,
+    hsm_serial: Option<String>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct RegisteredKeys {
+    #[serde(default)]
+    keys: Vec<RegisteredKey>,
+}
+
+struct LoadedPolicy {
+    /// The modification time of the file when it was loaded
+    modified: Option<SystemTime>,
+    policy: Policy,
+}
+
+pub struct PolicyAuthorizer {
+    pub path: String,
+    registered_keys_path: String,
+    policy: RwLock<LoadedPolicy>,
+}
+
+fn parse_policy(path: &str, contents: &[u8]) -> Result<Policy, String> {
+    if path.ends_with(".yaml") || path.ends_with(".yml") {
+        serde_yaml::from_slice(contents).map_err(|e| e.to_string())
+    } else {
+        toml::from_slice(contents).map_err(|e| e.to_string())
+    }
+}
+
+fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+/// The requester IP is provided as a socket address but may also be a bare
+/// IP address
+fn parse_requester_ip(requester_ip: &str) -> Option<IpAddr> {
+    requester_ip
+        .parse::<SocketAddr>()
+        .map(|x| x.ip())
+        .or_else(|_| requester_ip.parse::<IpAddr>())
+        .ok()
+}
+
+impl RuleConditions {
+    fn matches(
+        &self,
+        mtls_identities: &[String],
+        identity_attributes: &HashMap<String, Vec<String>>,
+        requester_ip: &str,
+        authority: &str,
+    ) -> bool {
+        if let Some(identities) = &self.mtls_identities {
+            if !mtls_identities.iter().any(|x| identities.contains(x)) {
+                return false;
+            }
+        }
+
+        if let Some(identities) = &self.identities {
+            let all_match = identities.iter().all(|(name, allowed)| {
+                identity_attributes
+                    .get(name)
+                    .is_some_and(|values| values.iter().any(|x| allowed.contains(x)))
+            });
+
+            if !all_match {
+                return false;
+            }
+        }
+
+        if let Some(source_ips) = &self.source_ips {
+            match parse_requester_ip(requester_ip) {
+                Some(ip) if source_ips.iter().any(|x| x.contains(&ip)) => (),
+                _ => return false,
+            }
+        }
+
+        if let Some(authorities) = &self.authorities {
+            if !authorities.iter().any(|x| x == authority) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl SshRule {
+    fn matches(&self, req: &SshAuthorizationRequestProperties) -> bool {
+        if !self.conditions.matches(
+            &req.mtls_identities,
+            &req.identity_attributes,
+            &req.requester_ip,
+            &req.authority,
+        ) {
+            return false;
+        }
+
+        if let Some(fingerprints) = &self.fingerprints {
+            if !fingerprints
+                .iter()
+                .any(|x| x.strip_prefix("SHA256:").unwrap_or(x) == req.fingerprint)
+            {
+                return false;
+            }
+        }
+
+        if let Some(cert_types) = &self.cert_types {
+            if !cert_types.iter().any(|x| {
+                matches!(
+                    (x, req.cert_type),
+                    (PolicyCertType::User, CertType::User) | (PolicyCertType::Host, CertType::Host)
+                )
+            }) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl TryFrom<PolicyConfiguration> for PolicyAuthorizer {
+    type Error = String;
+
+    fn try_from(config: PolicyConfiguration) -> Result<Self, Self::Error> {
+        let contents = std::fs::read(&config.path)
+            .map_err(|e| format!("Could not read policy file {}: {e}", config.path))?;
+        let modified = std::fs::metadata(&config.path)
+            .and_then(|x| x.modified())
+            .ok();
+        let policy = parse_policy(&config.path, &contents)?;
+
+        let registered_keys_path = config
+            .registered_keys_path
+            .unwrap_or_else(|| format!("{}.registered_keys.toml", config.path));
+
+        Ok(Self {
+            path: config.path,
+            registered_keys_path,
+            policy: RwLock::new(LoadedPolicy { modified, policy }),
+        })
+    }
+}
+
+impl PolicyAuthorizer {
+    /// Reload the policy if the file has changed since it was last loaded
+    async fn reload_if_changed(&self) {
+        let modified = match tokio::fs::metadata(&self.path)
+            .await
+            .and_then(|x| x.modified())
+        {
+            Ok(modified) => modified,
+            Err(e) => {
+                error!("Could not check policy file {} for changes: {e}", self.path);
+                return;
+            }
+        };
+
+        if self.policy.read().await.modified == Some(modified) {
+            return;
+        }
+
+        let mut loaded = self.policy.write().await;
+        // Another request may have reloaded while we waited for the lock
+        if loaded.modified == Some(modified) {
+            return;
+        }
+
+        // Record the modification time even on failure so a broken file is
+        // not reparsed on every request
+        loaded.modified = Some(modified);
+
+        let contents = match tokio::fs::read(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) => {
+                error!("Could not read policy file {}: {e}", self.path);
+                return;
+            }
+        };
+
+        match parse_policy(&self.path, &contents) {
+            Ok(policy) => {
+                info!("Reloaded policy from {}", self.path);
+                loaded.policy = policy;
+            }
+            Err(e) => error!(
+                "Could not parse updated policy file {}, keeping previous policy: {e}",
+                self.path
+            ),
+        }
+    }
+
+    pub async fn authorize_ssh_cert(
+        &self,
+        req: &SshAuthorizationRequestProperties,
+    ) -> Result<SshAuthorization, AuthorizationError> {
+        self.reload_if_changed().await;
+        let loaded = self.policy.read().await;
+
+        let rule = loaded
+            .policy
+            .ssh
+            .iter()
+            .find(|rule| rule.matches(req))
+            .ok_or(AuthorizationError::NotAuthorized)?;
+
+        debug!(
+            "Policy rule [{}] matched SSH request for key [{}]",
+            rule.name, req.fingerprint
+        );
+
+        let valid_before = req
+            .valid_before
+            .min(current_timestamp().saturating_add(rule.max_validity));
+
+        Ok(SshAuthorization {
+            serial: 0x000000000000000,
+            valid_before,
+            valid_after: req.valid_after,
+            principals: rule.principals.clone(),
+            hosts: rule.hosts.clone(),
+            extensions: rule.extensions.clone(),
+            force_command: rule.force_command.clone(),
+            force_source_ip: rule.force_source_ip,
+            authority: req.authority.clone(),
+            approval: rule.approval.clone(),
+        })
+    }
+
+    pub async fn authorize_attested_x509_cert(
+        &self,
+        req: &X509AuthorizationRequestProperties,
+    ) -> Result<X509Authorization, AuthorizationError> {
+        let touch_policy = match &req.key.attestation {
+            Some(KeyAttestation::Piv(att)) => &att.touch_policy,
+            _ => return Err(AuthorizationError::AuthorizerError),
+        };
+
+        let mtls_user = req
+            .mtls_identities
+            .first()
+            .ok_or(AuthorizationError::AuthorizerError)?;
+
+        self.reload_if_changed().await;
+        let loaded = self.policy.read().await;
+
+        let rule = loaded
+            .policy
+            .x509
+            .iter()
+            .find(|rule| {
+                rule.conditions.matches(
+                    &req.mtls_identities,
+                    &req.identity_attributes,
+                    &req.requester_ip,
+                    &req.authority,
+                ) && !(rule.require_touch && *touch_policy == TouchPolicy::Never)
+            })
+            .ok_or(AuthorizationError::NotAuthorized)?;
+
+        debug!(
+            "Policy rule [{}] matched X509 request for [{}]",
+            rule.name, mtls_user
+        );
+
+        let current_time = current_timestamp();
+
+        Ok(X509Authorization {
+            authority: req.authority.clone(),
+            issuer: "Rustica".to_owned(),
+            common_name: mtls_user.clone(),
+            sans: rule.sans.clone().unwrap_or_else(|| vec![mtls_user.clone()]),
+            extensions: vec![],
+            serial: 0xFEFEFEFEFE,
+            valid_before: current_time + rule.validity,
+            valid_after: current_time,
+        })
+    }
+
+    pub async fn register_key(
+        &self,
+        req: &RegisterKeyRequestProperties,
+    ) -> Result<(), AuthorizationError> {
+        let (firmware, hsm_serial) = match &req.attestation {
+            Some(KeyAttestation::Piv(attestation)) => (
+                Some(attestation.firmware.clone()),
+                Some(attestation.serial.to_string()),
+            ),
+            Some(KeyAttestation::U2f(attestation)) => (Some(attestation.firmware.clone()), None),
+            None => (None, None),
+        };
+
+        let registered_key = RegisteredKeys {
+            keys: vec![RegisteredKey {
+                fingerprint: req.fingerprint.clone(),
+                pubkey: req.pubkey.clone(),
+                user: req.mtls_identities.join(","),
+                firmware,
+                hsm_serial,
+            }],
+        };
+
+        // Each key is serialized as its own array of tables entry so it can
+        // be appended without rewriting the file
+        let entry = toml::to_string(&registered_key)
+            .map_err(|e| AuthorizationError::DatabaseError(e.to_string()))?;
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.registered_keys_path)
+            .await
+            .map_err(|e| AuthorizationError::DatabaseError(e.to_string()))?;
+
+        file.write_all(format!("\n{entry}").as_bytes())
+            .await
+            .map_err(|e| AuthorizationError::DatabaseError(e.to_string()))
+    }
+
+    pub async fn get_allowed_signers(&self) -> Result<AllowedSigners, AuthorizationError> {
+        let registered_keys: RegisteredKeys = match tokio::fs::read(&self.registered_keys_path).await
+        {
+            Ok(contents) => toml::from_slice(&contents)
+                .map_err(|e| AuthorizationError::DatabaseError(e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RegisteredKeys::default(),
+            Err(e) => return Err(AuthorizationError::DatabaseError(e.to_string())),
+        };
+
+        let allowed_signers = registered_keys
+            .keys
+            .into_iter()
+            .map(|key| AllowedSigner {
+                identity: key.user,
+                pubkey: key.pubkey,
+            })
+            .collect();
+
+        Ok(AllowedSigners { allowed_signers })
+    }
+
+    /// The policy is served from memory so the only thing that can go wrong
+    /// is the file disappearing, which would stop future changes loading
+    pub async fn check_health(&self) -> Result<(), AuthorizationError> {
+        tokio::fs::metadata(&self.path)
+            .await
+            .map(|_| ())
+            .map_err(|e| AuthorizationError::DatabaseError(format!("{}: {e}", self.path)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key::{Key, PIVAttestation, PinPolicy};
+
+    use std::path::Path;
+    use std::time::Duration;
+
+    const POLICY: &str = r#"
+[[ssh]]
+name = "breakglass"
+mtls_identities = ["admin"]
+fingerprints = ["SHA256:BreakglassFingerprint"]
+principals = ["root"]
+max_validity = 60
+
+[[ssh]]
+name = "office"
+source_ips = ["10.0.0.0/8"]
+authorities = ["example"]
+cert_types = ["user"]
+principals = ["office"]
+max_validity = 3600
+
+[[ssh]]
+name = "alice"
+mtls_identities = ["alice"]
+fingerprints = ["AliceFingerprint"]
+principals = ["alice"]
+max_validity = 600
+
+[[x509]]
+name = "touch"
+mtls_identities = ["alice"]
+require_touch = true
+validity = 100
+
+[[x509]]
+name = "infra"
+identities = { team = ["infra"] }
+sans = ["infra.example.com"]
+validity = 10
+"#;
+
+    /// Write the policy and give it a modification time that differs from
+    /// any earlier write so it is always picked up
+    fn write_policy(path: &Path, contents: &str, age: u64) {
+        std::fs::write(path, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(age))
+            .unwrap();
+    }
+
+    fn authorizer(directory: &tempfile::TempDir) -> PolicyAuthorizer {
+        let path = directory.path().join("policy.toml");
+        write_policy(&path, POLICY, 100);
+
+        PolicyAuthorizer::try_from(PolicyConfiguration {
+            path: path.to_string_lossy().into_owned(),
+            registered_keys_path: None,
+        })
+        .unwrap()
+    }
+
+    fn ssh_request(
+        identity: &str,
+        fingerprint: &str,
+        requester_ip: &str,
+        authority: &str,
+        cert_type: CertType,
+    ) -> SshAuthorizationRequestProperties {
+        let now = current_timestamp();
+        SshAuthorizationRequestProperties {
+            fingerprint: fingerprint.to_owned(),
+            mtls_identities: vec![identity.to_owned()],
+            identity_attributes: HashMap::new(),
+            requester_ip: requester_ip.to_owned(),
+            principals: vec![],
+            servers: vec![],
+            valid_before: now + 86400,
+            valid_after: now,
+            cert_type,
+            authority: authority.to_owned(),
+            extensions: HashMap::new(),
+            critical_options: HashMap::new(),
+        }
+    }
+
+    async fn matched_principals(
+        authorizer: &PolicyAuthorizer,
+        req: &SshAuthorizationRequestProperties,
+    ) -> Option<Vec<String>> {
+        authorizer
+            .authorize_ssh_cert(req)
+            .await
+            .ok()
+            .map(|x| x.principals)
+    }
+
+    fn x509_request(
+        identity: &str,
+        team: Option<&str>,
+        touch_policy: TouchPolicy,
+    ) -> X509AuthorizationRequestProperties {
+        let identity_attributes = team
+            .map(|team| HashMap::from([("team".to_owned(), vec![team.to_owned()])]))
+            .unwrap_or_default();
+
+        X509AuthorizationRequestProperties {
+            authority: "example".to_owned(),
+            mtls_identities: vec![identity.to_owned()],
+            identity_attributes,
+            requester_ip: "127.0.0.1:4000".to_owned(),
+            attestation: vec![],
+            attestation_intermediate: vec![],
+            key: Key {
+                fingerprint: "X509Fingerprint".to_owned(),
+                attestation: Some(KeyAttestation::Piv(PIVAttestation {
+                    pin_policy: PinPolicy::Once,
+                    touch_policy,
+                    serial: 1,
+                    firmware: "5.4.3".to_owned(),
+                    certificate: vec![],
+                    intermediate: vec![],
+                })),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn first_matching_rule_is_used() {
+        let directory = tempfile::tempdir().unwrap();
+        let authorizer = authorizer(&directory);
+
+        // Both breakglass and office match, breakglass comes first
+        let req = ssh_request(
+            "admin",
+            "BreakglassFingerprint",
+            "10.0.0.1:22",
+            "example",
+            CertType::User,
+        );
+        assert_eq!(
+            matched_principals(&authorizer, &req).await,
+            Some(vec!["root".to_owned()])
+        );
+
+        let req = ssh_request(
+            "admin",
+            "OtherFingerprint",
+            "10.0.0.1:22",
+            "example",
+            CertType::User,
+        );
+        assert_eq!(
+            matched_principals(&authorizer, &req).await,
+            Some(vec!["office".to_owned()])
+        );
+    }
+
+    #[tokio::test]
+    async fn identities_and_fingerprints_must_match() {
+        let directory = tempfile::tempdir().unwrap();
+        let authorizer = authorizer(&directory);
+
+        // Rules may list fingerprints with or without the SHA256: prefix
+        let req = ssh_request(
+            "alice",
+            "AliceFingerprint",
+            "192.168.0.1:22",
+            "example",
+            CertType::User,
+        );
+        assert_eq!(
+            matched_principals(&authorizer, &req).await,
+            Some(vec!["alice".to_owned()])
+        );
+        let req = ssh_request(
+            "admin",
+            "BreakglassFingerprint",
+            "192.168.0.1:22",
+            "example",
+            CertType::User,
+        );
+        assert_eq!(
+            matched_principals(&authorizer, &req).await,
+            Some(vec!["root".to_owned()])
+        );
+
+        let req = ssh_request(
+            "bob",
+            "AliceFingerprint",
+            "192.168.0.1:22",
+            "example",
+            CertType::User,
+        );
+        assert_eq!(matched_principals(&authorizer, &req).await, None);
+        let req = ssh_request(
+            "alice",
+            "BreakglassFingerprint",
+            "192.168.0.1:22",
+            "example",
+            CertType::User,
+        );
+        assert_eq!(matched_principals(&authorizer, &req).await, None);
+    }
+
+    #[tokio::test]
+    async fn source_ip_authority_and_cert_type_must_match() {
+        let directory = tempfile::tempdir().unwrap();
+        let authorizer = authorizer(&directory);
+        let office = Some(vec!["office".to_owned()]);
+
+        // The requester IP may or may not include a port
+        let req = ssh_request(
+            "bob",
+            "BobFingerprint",
+            "10.1.2.3:4000",
+            "example",
+            CertType::User,
+        );
+        assert_eq!(matched_principals(&authorizer, &req).await, office);
+        let req = ssh_request(
+            "bob",
+            "BobFingerprint",
+            "10.1.2.3",
+            "example",
+            CertType::User,
+        );
+        assert_eq!(matched_principals(&authorizer, &req).await, office);
+
+        let req = ssh_request(
+            "bob",
+            "BobFingerprint",
+            "192.168.0.1:4000",
+            "example",
+            CertType::User,
+        );
+        assert_eq!(matched_principals(&authorizer, &req).await, None);
+        let req = ssh_request(
+            "bob",
+            "BobFingerprint",
+            "not an address",
+            "example",
+            CertType::User,
+        );
+        assert_eq!(matched_principals(&authorizer, &req).await, None);
+        let req = ssh_request(
+            "bob",
+            "BobFingerprint",
+            "10.1.2.3:4000",
+            "other",
+            CertType::User,
+        );
+        assert_eq!(matched_principals(&authorizer, &req).await, None);
+        let req = ssh_request(
+            "bob",
+            "BobFingerprint",
+            "10.1.2.3:4000",
+            "example",
+            CertType::Host,
+        );
+        assert_eq!(matched_principals(&authorizer, &req).await, None);
+    }
+
+    #[tokio::test]
+    async fn validity_is_capped_at_max_validity() {
+        let directory = tempfile::tempdir().unwrap();
+        let authorizer = authorizer(&directory);
+
+        let req = ssh_request(
+            "bob",
+            "BobFingerprint",
+            "10.1.2.3:4000",
+            "example",
+            CertType::User,
+        );
+        let authorization = authorizer.authorize_ssh_cert(&req).await.unwrap();
+        assert!(authorization.valid_before <= current_timestamp() + 3600);
+        assert!(authorization.valid_before + 60 >= current_timestamp() + 3600);
+        assert_eq!(authorization.valid_after, req.valid_after);
+
+        // A request for less than the cap is left alone
+        let mut req = req;
+        req.valid_before = req.valid_after + 120;
+        let authorization = authorizer.authorize_ssh_cert(&req).await.unwrap();
+        assert_eq!(authorization.valid_before, req.valid_before);
+    }
+
+    #[tokio::test]
+    async fn touch_is_required_when_configured() {
+        let directory = tempfile::tempdir().unwrap();
+        let authorizer = authorizer(&directory);
+
+        let req = x509_request("alice", None, TouchPolicy::Never);
+        assert!(matches!(
+            authorizer.authorize_attested_x509_cert(&req).await,
+            Err(AuthorizationError::NotAuthorized)
+        ));
+
+        for touch_policy in [TouchPolicy::Always, TouchPolicy::Cached] {
+            let req = x509_request("alice", None, touch_policy);
+            let authorization = authorizer.authorize_attested_x509_cert(&req).await.unwrap();
+            assert_eq!(authorization.sans, vec!["alice".to_owned()]);
+            assert_eq!(authorization.valid_before - authorization.valid_after, 100);
+        }
+
+        // Rules that do not require touch accept any touch policy
+        let req = x509_request("alice", Some("infra"), TouchPolicy::Never);
+        let authorization = authorizer.authorize_attested_x509_cert(&req).await.unwrap();
+        assert_eq!(authorization.sans, vec!["infra.example.com".to_owned()]);
+        assert_eq!(authorization.common_name, "alice");
+    }
+
+    #[tokio::test]
+    async fn previous_policy_is_kept_when_the_file_cannot_be_parsed() {
+        let directory = tempfile::tempdir().unwrap();
+        let authorizer = authorizer(&directory);
+        let path = directory.path().join("policy.toml");
+        let req = ssh_request(
+            "alice",
+            "AliceFingerprint",
+            "192.168.0.1:22",
+            "example",
+            CertType::User,
+        );
+
+        write_policy(&path, "[[ssh]]\nname = ", 50);
+        assert_eq!(
+            matched_principals(&authorizer, &req).await,
+            Some(vec!["alice".to_owned()])
+        );
+
+        let updated = r#"
+[[ssh]]
+name = "alice"
+mtls_identities = ["alice"]
+principals = ["alice", "deploy"]
+max_validity = 600
+"#;
+        write_policy(&path, updated, 0);
+        assert_eq!(
+            matched_principals(&authorizer, &req).await,
+            Some(vec!["alice".to_owned(), "deploy".to_owned()])
+        );
+    }
+}
@@ -0,0 +1,333 @@
+/// The challenge keys are used to prove that a challenge given out by
+/// `Challenge` was issued by Rustica when it is returned in a later call.
+/// When running a single instance these can be generated on start, but when
+/// running multiple replicas behind a load balancer every replica must share
+/// the same keys. To allow these keys to be rotated without failing requests
+/// that are in flight, a previous set of keys can also be configured which
+/// will be accepted but never used to issue new challenges.
+use ring::{hmac, rand};
+use serde::Deserialize;
+use sshcerts::{ssh::KeyTypeKind, PrivateKey, PublicKey};
+
+use std::num::NonZeroUsize;
+
+#[cfg(feature = "amazon-kms")]
+use aws_credential_types::provider::{future, ProvideCredentials};
+#[cfg(feature = "amazon-kms")]
+use aws_sdk_kms::{
+    config::{Credentials, Region},
+    primitives::Blob,
+    Client,
+};
+
+/// The minimum size of a configured HMAC key in bytes
+const MINIMUM_HMAC_KEY_LENGTH: usize = 32;
+
+/// Where the contents of a secret should be loaded from
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretSource {
+    /// The secret is provided directly in the configuration
+    Value(String),
+    /// The secret is read from the file at this path
+    File(String),
+    /// The secret is encrypted with a KMS key and will be decrypted on start
+    #[cfg(feature = "amazon-kms")]
+    AmazonKms(KmsWrappedSecret),
+}
+
+/// A secret that has been encrypted with a KMS key. The ciphertext is
+/// the base64 encoded output of the KMS Encrypt call.
+#[cfg(feature = "amazon-kms")]
+#[derive(Debug, Deserialize)]
+pub struct KmsWrappedSecret {
+    /// Base64 encoded ciphertext blob
+    ciphertext: String,
+    /// The AWS access key that can decrypt the secret
+    aws_access_key_id: String,
+    /// The secret corresponding to the AWS access key
+    aws_secret_access_key: String,
+    /// The region to be used
+    aws_region: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChallengeKeyConfiguration {
+    /// A hex encoded key used to HMAC challenges. Must be at least 32 bytes.
+    pub hmac_key: SecretSource,
+    /// An OpenSSH private key used to sign challenge certificates
+    pub challenge_key: SecretSource,
+}
+
+#[derive(Deserialize)]
+pub struct ChallengeConfiguration {
+    /// The keys used to issue and validate challenges
+    pub current: ChallengeKeyConfiguration,
+    /// Keys that were previously current. Challenges issued with these are
+    /// still accepted so replicas can be rotated one at a time.
+    pub previous: Option<ChallengeKeyConfiguration>,
+}
+
+/// Controls how long a challenge can be used for after it is issued. A
+/// challenge can only be redeemed once, so every redeemed challenge is
+/// remembered until it expires.
+#[derive(Clone, Deserialize)]
+pub struct ChallengeWindowConfiguration {
+    /// How many seconds a challenge is valid for after it is issued
+    #[serde(default = "default_validity")]
+    pub validity: u64,
+    /// How many seconds the clocks of replicas may differ by. This is allowed
+    /// in both directions so a challenge may have been issued slightly in
+    /// the future or be slightly past its validity.
+    #[serde(default)]
+    pub clock_skew: u64,
+    /// How many redeemed challenges to remember. If this many unexpired
+    /// challenges have been redeemed, the oldest is forgotten to make room.
+    #[serde(default = "default_replay_cache_size")]
+    pub replay_cache_size: NonZeroUsize,
+}
+
+fn default_validity() -> u64 {
+    5
+}
+
+fn default_replay_cache_size() -> NonZeroUsize {
+    NonZeroUsize::new(65536).unwrap()
+}
+
+impl Default for ChallengeWindowConfiguration {
+    fn default() -> Self {
+        Self {
+            validity: default_validity(),
+            clock_skew: 0,
+            replay_cache_size: default_replay_cache_size(),
+        }
+    }
+}
+
+/// A single HMAC key and challenge signing key
+#[derive(Clone)]
+pub struct ChallengeKeyPair {
+    pub hmac_key: hmac::Key,
+    pub challenge_key: PrivateKey,
+}
+
+/// The challenge keys used by a running Rustica server
+#[derive(Clone)]
+pub struct ChallengeKeys {
+    /// Used to issue new challenges and to validate returned ones
+    pub current: ChallengeKeyPair,
+    /// Only used to validate returned challenges
+    pub previous: Option<ChallengeKeyPair>,
+}
+
+#[cfg(feature = "amazon-kms")]
+impl ProvideCredentials for KmsWrappedSecret {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::ready(Ok(Credentials::new(
+            self.aws_access_key_id.clone(),
+            self.aws_secret_access_key.clone(),
+            None,
+            None,
+            "RusticaChallengeKey",
+        )))
+    }
+}
+
+#[cfg(feature = "amazon-kms")]
+impl KmsWrappedSecret {
+    async fn decrypt(self) -> Result<String, String> {
+        let ciphertext = base64::decode(&self.ciphertext)
+            .map_err(|_| "KMS ciphertext is not valid base64".to_owned())?;
+
+        let aws_config = aws_config::from_env()
+            .region(Region::new(self.aws_region.clone()))
+            .credentials_provider(self)
+            .load()
+            .await;
+        let client = Client::new(&aws_config);
+
+        let plaintext = client
+            .decrypt()
+            .ciphertext_blob(Blob::new(ciphertext))
+            .send()
+            .await
+            .map_err(|e| format!("Could not decrypt secret with KMS: {e}"))?
+            .plaintext
+            .ok_or("KMS did not return a plaintext".to_owned())?;
+
+        String::from_utf8(plaintext.into_inner())
+            .map_err(|_| "Decrypted secret is not valid UTF-8".to_owned())
+    }
+}
+
+impl SecretSource {
+    /// Load the secret from wherever it is stored
+    async fn load(self) -> Result<String, String> {
+        match self {
+            Self::Value(secret) => Ok(secret),
+            Self::File(path) => tokio::fs::read_to_string(&path)
+                .await
+                .map_err(|e| format!("Could not read secret from {path}: {e}")),
+            #[cfg(feature = "amazon-kms")]
+            Self::AmazonKms(secret) => secret.decrypt().await,
+        }
+    }
+}
+
+impl ChallengeKeyPair {
+    /// Generate a new random key pair. This is only suitable when a single
+    /// instance of Rustica is running.
+    pub fn generate() -> Self {
+        let rng = rand::SystemRandom::new();
+        let hmac_key = hmac::Key::generate(hmac::HMAC_SHA256, &rng).unwrap();
+        let challenge_key = PrivateKey::new(KeyTypeKind::Ed25519, "RusticaChallengeKey").unwrap();
+
+        Self {
+            hmac_key,
+            challenge_key,
+        }
+    }
+
+    async fn new(config: ChallengeKeyConfiguration) -> Result<Self, String> {
+        let hmac_key = hex::decode(config.hmac_key.load().await?.trim())
+            .map_err(|_| "HMAC key is not valid hex".to_owned())?;
+
+        if hmac_key.len() < MINIMUM_HMAC_KEY_LENGTH {
+            return Err(format!(
+                "HMAC key must be at least {MINIMUM_HMAC_KEY_LENGTH} bytes"
+            ));
+        }
+
+        let challenge_key = PrivateKey::from_string(&config.challenge_key.load().await?)
+            .map_err(|e| format!("Could not parse challenge key: {e}"))?;
+
+        Ok(Self {
+            hmac_key: hmac::Key::new(hmac::HMAC_SHA256, &hmac_key),
+            challenge_key,
+        })
+    }
+}
+
+impl ChallengeKeys {
+    /// Generate a new random set of challenge keys with no previous keys
+    pub fn generate() -> Self {
+        Self {
+            current: ChallengeKeyPair::generate(),
+            previous: None,
+        }
+    }
+
+    /// Load the configured challenge keys
+    pub async fn new(config: ChallengeConfiguration) -> Result<Self, String> {
+        let current = ChallengeKeyPair::new(config.current).await?;
+        let previous = match config.previous {
+            Some(previous) => Some(ChallengeKeyPair::new(previous).await?),
+            None => None,
+        };
+
+        Ok(Self { current, previous })
+    }
+
+    /// Check an HMAC tag was created by either the current or previous key
+    pub fn verify_hmac(&self, data: &[u8], tag: &[u8]) -> bool {
+        std::iter::once(&self.current)
+            .chain(self.previous.as_ref())
+            .any(|keys| hmac::verify(&keys.hmac_key, data, tag).is_ok())
+    }
+
+    /// Check if a public key belongs to the current or previous challenge key
+    pub fn is_challenge_key(&self, key: &PublicKey) -> bool {
+        let fingerprint = key.fingerprint().hash;
+        std::iter::once(&self.current)
+            .chain(self.previous.as_ref())
+            .any(|keys| keys.challenge_key.pubkey.fingerprint().hash == fingerprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHALLENGE_KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../tests/test_ed25519");
+
+    fn key_configuration(hmac_key: &str) -> ChallengeKeyConfiguration {
+        ChallengeKeyConfiguration {
+            hmac_key: SecretSource::Value(hmac_key.to_owned()),
+            challenge_key: SecretSource::File(CHALLENGE_KEY.to_owned()),
+        }
+    }
+
+    fn tag(keys: &ChallengeKeyPair, data: &[u8]) -> Vec<u8> {
+        hmac::sign(&keys.hmac_key, data).as_ref().to_vec()
+    }
+
+    #[test]
+    fn previous_keys_are_accepted() {
+        let previous = ChallengeKeyPair::generate();
+        let keys = ChallengeKeys {
+            current: ChallengeKeyPair::generate(),
+            previous: Some(previous.clone()),
+        };
+        let unrelated = ChallengeKeyPair::generate();
+
+        assert!(keys.verify_hmac(b"challenge", &tag(&keys.current, b"challenge")));
+        assert!(keys.verify_hmac(b"challenge", &tag(&previous, b"challenge")));
+        assert!(!keys.verify_hmac(b"challenge", &tag(&unrelated, b"challenge")));
+        assert!(!keys.verify_hmac(b"other", &tag(&keys.current, b"challenge")));
+
+        assert!(keys.is_challenge_key(&keys.current.challenge_key.pubkey));
+        assert!(keys.is_challenge_key(&previous.challenge_key.pubkey));
+        assert!(!keys.is_challenge_key(&unrelated.challenge_key.pubkey));
+    }
+
+    #[test]
+    fn previous_keys_are_optional() {
+        let keys = ChallengeKeys::generate();
+        let unrelated = ChallengeKeyPair::generate();
+
+        assert!(keys.verify_hmac(b"challenge", &tag(&keys.current, b"challenge")));
+        assert!(!keys.verify_hmac(b"challenge", &tag(&unrelated, b"challenge")));
+        assert!(!keys.is_challenge_key(&unrelated.challenge_key.pubkey));
+    }
+
+    #[tokio::test]
+    async fn configured_keys_are_loaded() {
+        let hmac_key = "ab".repeat(MINIMUM_HMAC_KEY_LENGTH);
+        let keys = ChallengeKeys::new(ChallengeConfiguration {
+            current: key_configuration(&format!("{hmac_key}\n")),
+            previous: Some(key_configuration(&"cd".repeat(MINIMUM_HMAC_KEY_LENGTH))),
+        })
+        .await
+        .unwrap();
+
+        let expected = hmac::Key::new(hmac::HMAC_SHA256, &hex::decode(&hmac_key).unwrap());
+        assert!(keys.verify_hmac(b"challenge", hmac::sign(&expected, b"challenge").as_ref()));
+        assert!(keys.previous.is_some());
+    }
+
+    #[tokio::test]
+    async fn short_hmac_keys_are_rejected() {
+        let short = "ab".repeat(MINIMUM_HMAC_KEY_LENGTH - 1);
+        assert!(ChallengeKeyPair::new(key_configuration(&short)).await.is_err());
+
+        // A short previous key is rejected even if the current one is fine
+        let config = ChallengeConfiguration {
+            current: key_configuration(&"ab".repeat(MINIMUM_HMAC_KEY_LENGTH)),
+            previous: Some(key_configuration(&short)),
+        };
+        assert!(ChallengeKeys::new(config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn non_hex_hmac_keys_are_rejected() {
+        let not_hex = "zz".repeat(MINIMUM_HMAC_KEY_LENGTH);
+        assert!(ChallengeKeyPair::new(key_configuration(&not_hex)).await.is_err());
+
+        let odd_length = "a".repeat(MINIMUM_HMAC_KEY_LENGTH * 2 + 1);
+        assert!(ChallengeKeyPair::new(key_configuration(&odd_length)).await.is_err());
+    }
+}
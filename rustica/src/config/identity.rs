@@ -0,0 +1,82 @@
+/// Client certificates often carry more than a common name. Workload
+/// certificates may identify themselves with a SPIFFE ID in a URI SAN and
+/// teams may be encoded as organizational units. Each configured attribute
+/// pulls values out of client certificates and passes them to authorizers
+/// under its own name, so rules can match on them directly.
+use serde::Deserialize;
+
+/// Names the external authorizer already uses for request identities
+const RESERVED_NAMES: [&str; 5] = [
+    "mtls_identities",
+    "requester_ip",
+    "key_fingerprint",
+    "leaf",
+    "intermediate",
+];
+
+/// Where in a client certificate the values of an attribute come from
+#[derive(Clone, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum IdentitySource {
+    /// Every common name in the subject
+    CommonName,
+    /// Every URI subject alternative name
+    SanUri,
+    /// Every email (RFC 822) subject alternative name
+    SanEmail,
+    /// Every DNS subject alternative name
+    SanDns,
+    /// Every subject attribute with this OID in dotted form, for example
+    /// 2.5.4.11 for organizational units
+    Dn { oid: String },
+}
+
+#[derive(Clone, Deserialize)]
+pub struct IdentityAttributeConfiguration {
+    /// The name authorizers see this attribute as
+    pub name: String,
+    #[serde(flatten)]
+    pub source: IdentitySource,
+    /// If set, only values starting with this are used
+    pub prefix: Option<String>,
+}
+
+/// The named attributes extracted from client certificates. The common
+/// names are always used as the mTLS identities whether or not they are also
+/// configured as an attribute.
+#[derive(Clone, Default, Deserialize)]
+pub struct ClientIdentityConfiguration {
+    #[serde(default)]
+    pub attributes: Vec<IdentityAttributeConfiguration>,
+}
+
+impl ClientIdentityConfiguration {
+    /// Check attribute names are unique and OIDs are well formed
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, attribute) in self.attributes.iter().enumerate() {
+            if attribute.name.is_empty() {
+                return Err("Identity attributes must have a name".to_owned());
+            }
+
+            if RESERVED_NAMES.contains(&attribute.name.as_str()) {
+                return Err(format!("{} is a reserved identity name", attribute.name));
+            }
+
+            if self.attributes[..i].iter().any(|x| x.name == attribute.name) {
+                return Err(format!("Identity attribute {} is configured twice", attribute.name));
+            }
+
+            if let IdentitySource::Dn { oid } = &attribute.source {
+                let valid = oid.split('.').count() > 1
+                    && oid
+                        .split('.')
+                        .all(|arc| !arc.is_empty() && arc.chars().all(|c| c.is_ascii_digit()));
+                if !valid {
+                    return Err(format!("{oid} is not a valid OID"));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
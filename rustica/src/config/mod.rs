@@ -1,23 +1,39 @@
+mod challenge;
+mod identity;
+
+pub use challenge::{ChallengeConfiguration, ChallengeKeys, ChallengeWindowConfiguration};
+pub use identity::{ClientIdentityConfiguration, IdentitySource};
+
+use crate::approval::{ApprovalConfiguration, ApprovalStore};
 use crate::auth::AuthorizationConfiguration;
+use crate::health::HealthConfiguration;
+use crate::ledger::{Ledger, LedgerConfiguration, LedgerError};
 use crate::logging::{Log, LoggingConfiguration};
+#[cfg(feature = "prometheus")]
+use crate::metrics::MetricsConfiguration;
+#[cfg(feature = "oidc")]
+use crate::oidc::{OidcConfiguration, OidcError, OidcVerifier};
+use crate::revocation::{
+    RevocationConfiguration, RevocationError, RevocationSettings, RevocationStore,
+};
 use crate::server::{AllowedSignersCache, RusticaServer};
 use crate::signing::{SigningConfiguration, SigningError};
 
 use clap::{Arg, Command};
 
-use crossbeam_channel::{unbounded, Receiver};
+use crossbeam_channel::{unbounded, Receiver, Sender};
 use lru::LruCache;
-use ring::{hmac, rand};
 use serde::Deserialize;
 
 use std::convert::TryInto;
 use std::net::SocketAddr;
 use std::time::Duration;
 use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex as StdMutex};
 
 use tokio::sync::{RwLock, Mutex};
 
-use sshcerts::{ssh::KeyTypeKind, CertType, PrivateKey};
+use sshcerts::CertType;
 
 #[derive(Deserialize)]
 pub struct ClientAuthorityConfiguration {
@@ -33,11 +49,20 @@ pub struct AllowedSignersConfiguration {
     pub rate_limit_cooldown: Duration,
 }
 
+#[derive(Default, Deserialize)]
+pub struct AdminConfiguration {
+    /// The mTLS identities allowed to call administrative endpoints such as
+    /// revoking certificates
+    pub identities: Vec<String>,
+}
+
 #[derive(Deserialize)]
 pub struct Configuration {
     pub server_cert: String,
     pub server_key: String,
     pub client_authority: ClientAuthorityConfiguration,
+    #[serde(default)]
+    pub client_identity: ClientIdentityConfiguration,
     pub listen_address: String,
     pub authorization: AuthorizationConfiguration,
     pub signing: SigningConfiguration,
@@ -45,6 +70,21 @@ pub struct Configuration {
     pub require_attestation_chain: bool,
     pub logging: LoggingConfiguration,
     pub allowed_signers: AllowedSignersConfiguration,
+    #[serde(default)]
+    pub admin: AdminConfiguration,
+    pub revocation: Option<RevocationConfiguration>,
+    #[serde(default)]
+    pub approval: ApprovalConfiguration,
+    pub challenge: Option<ChallengeConfiguration>,
+    #[serde(default)]
+    pub challenge_window: ChallengeWindowConfiguration,
+    pub ledger: Option<LedgerConfiguration>,
+    #[serde(default)]
+    pub health: HealthConfiguration,
+    #[cfg(feature = "prometheus")]
+    pub metrics: Option<MetricsConfiguration>,
+    #[cfg(feature = "oidc")]
+    pub oidc: Option<OidcConfiguration>,
 }
 
 pub struct RusticaSettings {
@@ -52,9 +92,46 @@ pub struct RusticaSettings {
     pub client_ca_cert: String,
     pub server_cert: String,
     pub server_key: String,
+    /// Clients must present an mTLS certificate unless they can identify
+    /// with an OIDC token instead
+    pub require_client_certificate: bool,
     pub address: SocketAddr,
-    pub log_receiver: Receiver<Log>,
+    pub config_path: String,
     pub logging_configuration: LoggingConfiguration,
+    pub health: HealthConfiguration,
+    #[cfg(feature = "prometheus")]
+    pub metrics_address: Option<SocketAddr>,
+}
+
+/// Settings from a reloaded configuration for state the new server shares
+/// with the running one
+pub struct SharedStateUpdate {
+    revocation: Option<RevocationSettings>,
+    replay_cache_size: NonZeroUsize,
+    approval: ApprovalConfiguration,
+    allowed_signers_rate_limiter_size: NonZeroUsize,
+}
+
+impl SharedStateUpdate {
+    /// Apply the new settings to the state `server` shares with the
+    /// previous configuration
+    pub async fn apply(self, server: &RusticaServer) {
+        if let (Some(settings), Some(store)) = (self.revocation, &server.revocation) {
+            store.configure(settings).await;
+        }
+
+        server
+            .redeemed_challenges
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .resize(self.replay_cache_size);
+        server.approvals.configure(&self.approval);
+        server
+            .allowed_signers_rate_limiter
+            .lock()
+            .await
+            .resize(self.allowed_signers_rate_limiter_size);
+    }
 }
 
 pub enum ConfigurationError {
@@ -67,6 +144,17 @@ pub enum ConfigurationError {
     ValidateOnly,
     DefaultAuthorityDoesNotHaveSSHKeys,
     NoSuchSigningMechanismForClientCa(String, Vec<String>),
+    RevocationError(RevocationError),
+    ChallengeKeyError(String),
+    LedgerError(LedgerError),
+    ClientIdentityError(String),
+    InvalidHealthCheckInterval,
+    #[cfg(feature = "oidc")]
+    OidcError(OidcError),
+    #[cfg(feature = "local-db")]
+    Migrated(String),
+    #[cfg(feature = "local-db")]
+    MigrationError(String),
 }
 
 impl From<sshcerts::error::Error> for ConfigurationError {
@@ -99,6 +187,17 @@ impl std::fmt::Display for ConfigurationError {
                 f,
                 "The requested signing mechanism to issue client certificates ({chosen}) is not configured. Options are: {}", options.join(", ")
             ),
+            Self::RevocationError(ref e) => write!(f, "{}", e),
+            Self::ChallengeKeyError(ref e) => write!(f, "Could not load challenge keys: {}", e),
+            Self::LedgerError(ref e) => write!(f, "{}", e),
+            Self::ClientIdentityError(ref e) => write!(f, "Invalid client identity configuration: {}", e),
+            Self::InvalidHealthCheckInterval => write!(f, "The health check interval must be at least 1 second"),
+            #[cfg(feature = "oidc")]
+            Self::OidcError(ref e) => write!(f, "{}", e),
+            #[cfg(feature = "local-db")]
+            Self::Migrated(ref version) => write!(f, "The local database is at schema version {}", version),
+            #[cfg(feature = "local-db")]
+            Self::MigrationError(ref e) => write!(f, "Could not migrate the local database: {}", e),
         }
     }
 }
@@ -109,8 +208,11 @@ impl std::fmt::Debug for ConfigurationError {
     }
 }
 
-pub async fn configure() -> Result<RusticaSettings, ConfigurationError> {
-    let matches = Command::new("Rustica")
+/// Parse the command line and load the configuration file it points to.
+/// This also creates the channel used to send logs so the receiving end is
+/// returned alongside the settings.
+pub async fn configure() -> Result<(RusticaSettings, Receiver<Log>), ConfigurationError> {
+    let command = Command::new("Rustica")
         .version(env!("CARGO_PKG_VERSION"))
         .author("Mitchell Grenier <mitchell@confurious.io>")
         .about("Rustica is a Yubikey backed SSHCA")
@@ -119,7 +221,8 @@ pub async fn configure() -> Result<RusticaSettings, ConfigurationError> {
                 .help("Path to Rustica configuration toml file")
                 .long("config")
                 .default_value("/etc/rustica/rustica.toml")
-                .takes_value(true),
+                .takes_value(true)
+                .global(true),
         )
         .arg(
             Arg::new("validate")
@@ -128,11 +231,77 @@ pub async fn configure() -> Result<RusticaSettings, ConfigurationError> {
                 .short('v')
                 .action(clap::ArgAction::Count)
                 .takes_value(false),
-        )
-        .get_matches();
+        );
+
+    #[cfg(feature = "local-db")]
+    let command = command.subcommand(
+        Command::new("migrate")
+            .about("Create the local database if it does not exist, bring its schema up to date, and then quit"),
+    );
+
+    let matches = command.get_matches();
+
+    let config_path = matches.value_of("config").unwrap();
+    let config = read_configuration(config_path).await?;
+
+    #[cfg(feature = "local-db")]
+    if matches.subcommand_matches("migrate").is_some() {
+        return Err(migrate(&config));
+    }
+
+    // Only validate that the configuration parses correctly
+    // Do not check that we could access keys and build certificates.
+    if matches.get_count("validate") == 1 {
+        return Err(ConfigurationError::ValidateOnly);
+    }
+
+    let (log_sender, log_receiver) = unbounded();
+    // Nothing is shared with a previous server so there is nothing to update
+    let (settings, _) = build_settings(config, config_path, log_sender, None).await?;
+
+    // We're only validating that we can use this configuration so do not start
+    // This happens after we've parsed the config but also confirmed access to
+    // keys and created certificates.
+    if matches.get_count("validate") > 1 {
+        return Err(ConfigurationError::ValidateOnly);
+    }
+
+    Ok((settings, log_receiver))
+}
+
+/// Load the configuration file again for a running server. The new
+/// configuration goes through the same validation as `--validate-config`
+/// which includes accessing all keys. The state a restart would lose (the
+/// generated challenge keys, the allowed signers cache, and requests waiting
+/// for approval) is carried over from the running server. That state is not
+/// changed until the returned update is applied, which should only happen
+/// once the new configuration is serving.
+pub async fn reload(
+    config_path: &str,
+    previous: &RusticaServer,
+) -> Result<(RusticaSettings, SharedStateUpdate), ConfigurationError> {
+    let config = read_configuration(config_path).await?;
+    build_settings(config, config_path, previous.log_sender.clone(), Some(previous)).await
+}
+
+/// Run the local database migrations without starting the server. The
+/// outcome is always returned as an error so the caller quits.
+#[cfg(feature = "local-db")]
+fn migrate(config: &Configuration) -> ConfigurationError {
+    let database = match &config.authorization.database {
+        Some(database) => database,
+        None => return ConfigurationError::MigrationError("No local database is configured".to_owned()),
+    };
+
+    match database.migrate() {
+        Ok(version) => ConfigurationError::Migrated(version),
+        Err(e) => ConfigurationError::MigrationError(e.to_string()),
+    }
+}
 
+async fn read_configuration(config_path: &str) -> Result<Configuration, ConfigurationError> {
     // Read the configuration file
-    let config = match tokio::fs::read(matches.value_of("config").unwrap()).await {
+    let config = match tokio::fs::read(config_path).await {
         Ok(config) => config,
         Err(_) => return Err(ConfigurationError::FileError),
     };
@@ -146,25 +315,45 @@ pub async fn configure() -> Result<RusticaSettings, ConfigurationError> {
         }
     };
 
-    // Only validate that the configuration parses correctly
-    // Do not check that we could access keys and build certificates.
-    if matches.get_count("validate") == 1 {
-        return Err(ConfigurationError::ValidateOnly);
+    // The health checks run on a tokio interval which cannot be zero
+    if config.health.check_interval == 0 {
+        return Err(ConfigurationError::InvalidHealthCheckInterval);
     }
 
+    Ok(config)
+}
+
+async fn build_settings(
+    config: Configuration,
+    config_path: &str,
+    log_sender: Sender<Log>,
+    previous: Option<&RusticaServer>,
+) -> Result<(RusticaSettings, SharedStateUpdate), ConfigurationError> {
     let address = match config.listen_address.parse() {
         Ok(addr) => addr,
         Err(_) => return Err(ConfigurationError::InvalidListenAddress),
     };
 
-    let (log_sender, log_receiver) = unbounded();
+    #[cfg(feature = "prometheus")]
+    let metrics_address = match config.metrics {
+        Some(metrics) => match metrics.listen_address.parse() {
+            Ok(addr) => Some(addr),
+            Err(_) => return Err(ConfigurationError::InvalidListenAddress),
+        },
+        None => None,
+    };
+
+    config
+        .client_identity
+        .validate()
+        .map_err(ConfigurationError::ClientIdentityError)?;
 
     let authorizer = match config.authorization.try_into() {
         Ok(authorizer) => authorizer,
         _ => return Err(ConfigurationError::AuthorizerError),
     };
 
-    let signer = match config.signing.convert_to_signing_mechanism().await {
+    let signer = match config.signing.convert_to_signing_mechanism(&log_sender).await {
         Ok(signer) => signer,
         Err(e) => return Err(ConfigurationError::SigningMechanismError(e)),
     };
@@ -176,56 +365,169 @@ pub async fn configure() -> Result<RusticaSettings, ConfigurationError> {
         return Err(ConfigurationError::DefaultAuthorityDoesNotHaveSSHKeys);
     }
 
-    let rng = rand::SystemRandom::new();
-    let hmac_key = hmac::Key::generate(hmac::HMAC_SHA256, &rng).unwrap();
-    let challenge_key = PrivateKey::new(KeyTypeKind::Ed25519, "RusticaChallengeKey").unwrap();
+    let trust_material = signer
+        .get_trust_material()
+        .map_err(ConfigurationError::SigningMechanismError)?;
+
+    // If no challenge keys are configured, generate them. This only works
+    // when a single instance of Rustica is running because challenges issued
+    // by one instance cannot be validated by another. Generated keys are kept
+    // across reloads so outstanding challenges stay valid.
+    let challenge_keys = match (config.challenge, previous) {
+        (Some(challenge), _) => ChallengeKeys::new(challenge)
+            .await
+            .map_err(ConfigurationError::ChallengeKeyError)?,
+        (None, Some(previous)) => previous.challenge_keys.clone(),
+        (None, None) => ChallengeKeys::generate(),
+    };
+
+    // While the client certificate authority is being rotated, access
+    // certificates signed by any of its published keys are accepted
+    let client_cas = signer
+        .get_trusted_client_certificate_authorities(&config.client_authority.authority)
+        .map_err(|e| ConfigurationError::SigningMechanismError(e))?;
+    if client_cas.is_empty() {
+        return Err(ConfigurationError::NoSuchSigningMechanismForClientCa(config.client_authority.authority.clone(), signer.get_authorities()));
+    }
 
-    let client_ca_cert = signer
-        .get_client_certificate_authority(&config.client_authority.authority)
-        .map_err(|e| ConfigurationError::SigningMechanismError(e))?
-        .ok_or(ConfigurationError::NoSuchSigningMechanismForClientCa(config.client_authority.authority.clone(), signer.get_authorities()))?
-        .serialize_pem()
+    let client_ca_cert = client_cas
+        .iter()
+        .map(|ca| ca.serialize_pem())
+        .collect::<Result<Vec<_>, _>>()
         .map_err(|e| {
             ConfigurationError::SigningMechanismError(SigningError::AccessError(format!(
                 "Could not create a PEM from the requested signing system: {e}"
             )))
-        })?;
+        })?
+        .concat();
 
-    let allowed_signers_rate_limiter = LruCache::new(config.allowed_signers.lru_rate_limiter_size);
+    let ledger = match config.ledger {
+        Some(ledger) => Some(
+            Ledger::new(ledger)
+                .await
+                .map_err(ConfigurationError::LedgerError)?,
+        ),
+        None => None,
+    };
 
-    let allowed_signers_cache = AllowedSignersCache {
-        compressed_allowed_signers: vec![],
-        expiry_timestamp: Duration::ZERO,
+    #[cfg(feature = "oidc")]
+    let oidc = match config.oidc {
+        Some(oidc) => Some(
+            OidcVerifier::new(oidc)
+                .await
+                .map_err(ConfigurationError::OidcError)?,
+        ),
+        None => None,
+    };
+
+    // The store is kept so revocations handled by the previous configuration
+    // while it drains are not overwritten by a second copy of the file. It is
+    // only read again if it now points somewhere else.
+    let previous_revocation = previous.and_then(|previous| previous.revocation.as_ref());
+    let mut revocation_settings = None;
+    let revocation = match (config.revocation, previous_revocation) {
+        (Some(revocation), Some(store)) if store.path() == revocation.path => {
+            revocation_settings = Some(
+                revocation
+                    .settings()
+                    .map_err(ConfigurationError::RevocationError)?,
+            );
+            Some(store.clone())
+        }
+        (Some(revocation), _) => Some(Arc::new(
+            RevocationStore::new(revocation)
+                .await
+                .map_err(ConfigurationError::RevocationError)?,
+        )),
+        (None, _) => None,
+    };
+
+    #[cfg(feature = "oidc")]
+    let require_client_certificate = oidc.is_none();
+    #[cfg(not(feature = "oidc"))]
+    let require_client_certificate = true;
+
+    // Challenges redeemed before a reload must still not be accepted again
+    let redeemed_challenges = match previous {
+        Some(previous) => previous.redeemed_challenges.clone(),
+        None => Arc::new(StdMutex::new(LruCache::new(
+            config.challenge_window.replay_cache_size,
+        ))),
+    };
+
+    // Requests waiting for approval are kept so approvers can still act on
+    // them after a reload
+    let approvals = match previous {
+        Some(previous) => previous.approvals.clone(),
+        None => Arc::new(ApprovalStore::new(&config.approval)),
+    };
+
+    let (allowed_signers_rate_limiter, allowed_signers_cache) = match previous {
+        Some(previous) => (
+            previous.allowed_signers_rate_limiter.clone(),
+            previous.allowed_signers_cache.clone(),
+        ),
+        None => {
+            let allowed_signers_rate_limiter =
+                LruCache::new(config.allowed_signers.lru_rate_limiter_size);
+
+            let allowed_signers_cache = AllowedSignersCache {
+                compressed_allowed_signers: vec![],
+                expiry_timestamp: Duration::ZERO,
+            };
+
+            (
+                Mutex::new(allowed_signers_rate_limiter).into(),
+                RwLock::new(allowed_signers_cache).into(),
+            )
+        }
+    };
+
+    // The state above that is shared with the running server is only
+    // changed once the new configuration has started serving
+    let shared_state = SharedStateUpdate {
+        revocation: revocation_settings,
+        replay_cache_size: config.challenge_window.replay_cache_size,
+        approval: config.approval,
+        allowed_signers_rate_limiter_size: config.allowed_signers.lru_rate_limiter_size,
     };
-    
-    // We're only validating that we can use this configuration so do not start
-    // This happens after we've parsed the config but also confirmed access to
-    // keys and created certificates.
-    if matches.get_count("validate") > 1 {
-        return Err(ConfigurationError::ValidateOnly);
-    }
 
     let server = RusticaServer {
         log_sender,
-        hmac_key,
-        challenge_key,
+        challenge_keys,
+        challenge_window: config.challenge_window,
+        redeemed_challenges,
         authorizer,
         signer,
+        trust_material,
         require_rustica_proof: config.require_rustica_proof,
         require_attestation_chain: config.require_attestation_chain,
         client_authority: config.client_authority,
+        client_identity: config.client_identity,
         allowed_signers: config.allowed_signers,
-        allowed_signers_rate_limiter: Mutex::new(allowed_signers_rate_limiter).into(),
-        allowed_signers_cache: RwLock::new(allowed_signers_cache).into(),
+        allowed_signers_rate_limiter,
+        allowed_signers_cache,
+        admin: config.admin,
+        revocation,
+        approvals,
+        ledger,
+        #[cfg(feature = "oidc")]
+        oidc,
     };
 
-    Ok(RusticaSettings {
+    let settings = RusticaSettings {
         server,
         client_ca_cert,
         server_cert: config.server_cert,
         server_key: config.server_key,
+        require_client_certificate,
         address,
-        log_receiver,
+        config_path: config_path.to_owned(),
         logging_configuration: config.logging,
-    })
+        health: config.health,
+        #[cfg(feature = "prometheus")]
+        metrics_address,
+    };
+
+    Ok((settings, shared_state))
 }
@@ -0,0 +1,116 @@
+/// The health module drives the standard `grpc.health.v1` service that is
+/// served alongside Rustica. Every `check_interval` seconds the signing and
+/// authorization backends are checked and the status of the Rustica service
+/// is updated so load balancers and orchestrators can stop sending requests
+/// to an instance that cannot fulfill them.
+use crate::logging::{InternalMessage, Log, Severity};
+use crate::rustica::rustica_server::RusticaServer as GRPCRusticaServer;
+use crate::server::RusticaServer;
+
+use serde::Deserialize;
+use tonic_health::server::HealthReporter;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+pub struct HealthConfiguration {
+    /// How often, in seconds, to check the signers and authorizer are
+    /// reachable. This must be at least 1.
+    #[serde(default = "default_check_interval")]
+    pub check_interval: u64,
+    /// How long, in seconds, to wait for in-flight requests and logs to
+    /// finish after receiving SIGTERM before exiting anyway
+    #[serde(default = "default_shutdown_grace_period")]
+    pub shutdown_grace_period: u64,
+}
+
+fn default_check_interval() -> u64 {
+    10
+}
+
+fn default_shutdown_grace_period() -> u64 {
+    30
+}
+
+impl Default for HealthConfiguration {
+    fn default() -> Self {
+        Self {
+            check_interval: default_check_interval(),
+            shutdown_grace_period: default_shutdown_grace_period(),
+        }
+    }
+}
+
+/// Run the configured health checks and return why Rustica is unhealthy if
+/// any of them fail
+async fn check(server: &RusticaServer) -> Result<(), String> {
+    server
+        .signer
+        .check_health()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    server
+        .authorizer
+        .check_health()
+        .await
+        .map_err(|e| format!("Authorizer is unhealthy: {e}"))
+}
+
+/// Mark Rustica as serving or not serving. The empty service name is the
+/// overall server status which is what most health checkers query.
+async fn set_serving(reporter: &mut HealthReporter, serving: bool) {
+    let status = if serving {
+        tonic_health::ServingStatus::Serving
+    } else {
+        tonic_health::ServingStatus::NotServing
+    };
+
+    reporter.set_service_status("", status).await;
+    if serving {
+        reporter
+            .set_serving::<GRPCRusticaServer<RusticaServer>>()
+            .await;
+    } else {
+        reporter
+            .set_not_serving::<GRPCRusticaServer<RusticaServer>>()
+            .await;
+    }
+}
+
+/// Periodically check the health of the server's backends and update the
+/// health service. Changes in health are sent to the logging system so they
+/// are visible without polling the health service. This never returns.
+pub async fn monitor(server: Arc<RusticaServer>, mut reporter: HealthReporter, interval: Duration) {
+    let mut healthy = None;
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+        let result = check(&server).await;
+
+        if healthy != Some(result.is_ok()) {
+            let log = match &result {
+                Ok(_) => InternalMessage {
+                    severity: Severity::Info,
+                    message: "Rustica is healthy".to_owned(),
+                },
+                Err(e) => InternalMessage {
+                    severity: Severity::Error,
+                    message: format!("Rustica is unhealthy: {e}"),
+                },
+            };
+            let _ = server.log_sender.send(Log::InternalMessage(log));
+            set_serving(&mut reporter, result.is_ok()).await;
+        }
+
+        healthy = Some(result.is_ok());
+    }
+}
+
+/// Mark Rustica as not serving so health checkers stop sending new requests
+/// while in-flight ones are drained
+pub async fn shutting_down(mut reporter: HealthReporter) {
+    set_serving(&mut reporter, false).await;
+}
@@ -0,0 +1,140 @@
+/// The ledger module keeps a durable record of every certificate Rustica
+/// issues so questions like "what did this person get in the last day" can
+/// be answered without searching through logs. Certificates are recorded
+/// before they are returned, so a certificate that could not be recorded is
+/// never handed out.
+#[cfg(feature = "local-db")]
+mod sqlite;
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+/// The most results a single query may return
+#[cfg_attr(not(feature = "local-db"), allow(dead_code))]
+pub const MAX_QUERY_LIMIT: u32 = 1000;
+
+/// How many results are returned if a query does not set a limit
+#[cfg_attr(not(feature = "local-db"), allow(dead_code))]
+const DEFAULT_QUERY_LIMIT: u32 = 100;
+
+#[derive(Deserialize)]
+pub struct LedgerConfiguration {
+    /// Store the ledger in a local SQLite database
+    #[cfg(feature = "local-db")]
+    pub sqlite: Option<sqlite::Config>,
+}
+
+#[derive(Debug)]
+pub enum LedgerError {
+    /// The ledger section does not configure a backend
+    NoBackendConfigured,
+    /// The ledger could not be read or written
+    #[cfg_attr(not(feature = "local-db"), allow(dead_code))]
+    StoreError(String),
+    /// A stored entry could not be parsed
+    #[cfg_attr(not(feature = "local-db"), allow(dead_code))]
+    ParsingError(String),
+}
+
+impl std::fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoBackendConfigured => write!(f, "The ledger is enabled but no backend is configured"),
+            Self::StoreError(e) => write!(f, "Could not access the ledger: {e}"),
+            Self::ParsingError(e) => write!(f, "Could not parse a ledger entry: {e}"),
+        }
+    }
+}
+
+/// A single issued certificate
+#[derive(Debug)]
+pub struct IssuedCertificate {
+    /// Either user, host, or x509
+    pub certificate_type: String,
+    /// SSH serials are unsigned while X509 serials are signed so both are
+    /// stored as decimal strings
+    pub serial: String,
+    /// The fingerprint of the key the certificate was issued for
+    pub fingerprint: String,
+    /// The fingerprint of the SSH CA key, or the issuer of X509 certificates
+    pub signed_by: String,
+    /// The configured authority name for the signer
+    pub authority: String,
+    /// The mTLS identities of the requester
+    pub mtls_identities: Vec<String>,
+    /// Principals for SSH certificates or SANs for X509 certificates
+    pub principals: Vec<String>,
+    pub extensions: HashMap<String, String>,
+    pub critical_options: HashMap<String, String>,
+    pub valid_after: u64,
+    pub valid_before: u64,
+    /// When the certificate was issued as a unix timestamp
+    pub issued_at: u64,
+    pub requester_ip: String,
+}
+
+/// Filters for searching the ledger. Filters that are not set match every
+/// certificate. Results are returned newest first.
+#[derive(Debug, Default)]
+#[cfg_attr(not(feature = "local-db"), allow(dead_code))]
+pub struct LedgerQuery {
+    pub identity: Option<String>,
+    pub fingerprint: Option<String>,
+    pub serial: Option<String>,
+    pub authority: Option<String>,
+    /// Only certificates issued at or after this unix timestamp
+    pub issued_after: Option<u64>,
+    /// Only certificates issued before this unix timestamp
+    pub issued_before: Option<u64>,
+    pub limit: u32,
+}
+
+impl LedgerQuery {
+    /// The number of results to return, applying the default and maximum
+    #[cfg_attr(not(feature = "local-db"), allow(dead_code))]
+    fn limit(&self) -> i64 {
+        match self.limit {
+            0 => DEFAULT_QUERY_LIMIT.into(),
+            limit => limit.min(MAX_QUERY_LIMIT).into(),
+        }
+    }
+}
+
+pub enum Ledger {
+    #[cfg(feature = "local-db")]
+    Sqlite(sqlite::SqliteLedger),
+}
+
+impl Ledger {
+    /// Open the configured ledger backend, creating it if it does not exist
+    pub async fn new(config: LedgerConfiguration) -> Result<Self, LedgerError> {
+        #[cfg(feature = "local-db")]
+        if let Some(sqlite) = config.sqlite {
+            return Ok(Ledger::Sqlite(sqlite::SqliteLedger::new(sqlite).await?));
+        }
+
+        #[cfg(not(feature = "local-db"))]
+        let _ = config;
+
+        Err(LedgerError::NoBackendConfigured)
+    }
+
+    /// Durably record a newly issued certificate
+    #[cfg_attr(not(feature = "local-db"), allow(unused_variables))]
+    pub async fn record(&self, certificate: IssuedCertificate) -> Result<(), LedgerError> {
+        match *self {
+            #[cfg(feature = "local-db")]
+            Ledger::Sqlite(ref sqlite) => sqlite.record(certificate).await,
+        }
+    }
+
+    /// Find issued certificates matching the query
+    #[cfg_attr(not(feature = "local-db"), allow(unused_variables))]
+    pub async fn search(&self, query: LedgerQuery) -> Result<Vec<IssuedCertificate>, LedgerError> {
+        match *self {
+            #[cfg(feature = "local-db")]
+            Ledger::Sqlite(ref sqlite) => sqlite.search(query).await,
+        }
+    }
+}
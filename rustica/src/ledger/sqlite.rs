@@ -0,0 +1,284 @@
+/// Stores the issuance ledger in a SQLite database. The tables are created
+/// when Rustica starts if they do not already exist. Diesel is synchronous so
+/// all database work happens on a blocking thread.
+use super::{IssuedCertificate, LedgerError, LedgerQuery};
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::sqlite::SqliteConnection;
+
+use serde::Deserialize;
+
+use rustica_database::ledger::{
+    issued_certificate_identities, issued_certificates, IssuedCertificateRow,
+    NewIssuedCertificate, SCHEMA,
+};
+
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// The path to the SQLite database. It is created if it does not exist.
+    pub path: String,
+}
+
+#[derive(Clone)]
+pub struct SqliteLedger {
+    path: String,
+}
+
+fn store_error<E: std::fmt::Display>(e: E) -> LedgerError {
+    LedgerError::StoreError(e.to_string())
+}
+
+fn parsing_error<E: std::fmt::Display>(e: E) -> LedgerError {
+    LedgerError::ParsingError(e.to_string())
+}
+
+fn establish_connection(path: &str) -> Result<SqliteConnection, LedgerError> {
+    let mut conn = SqliteConnection::establish(path).map_err(store_error)?;
+    // Wait for other writers instead of failing immediately
+    conn.batch_execute("PRAGMA busy_timeout = 5000;")
+        .map_err(store_error)?;
+    Ok(conn)
+}
+
+fn issued_certificate(
+    row: IssuedCertificateRow,
+    mtls_identities: Vec<String>,
+) -> Result<IssuedCertificate, LedgerError> {
+    Ok(IssuedCertificate {
+        certificate_type: row.certificate_type,
+        serial: row.serial,
+        fingerprint: row.fingerprint,
+        signed_by: row.signed_by,
+        authority: row.authority,
+        mtls_identities,
+        principals: serde_json::from_str(&row.principals).map_err(parsing_error)?,
+        extensions: serde_json::from_str(&row.extensions).map_err(parsing_error)?,
+        critical_options: serde_json::from_str(&row.critical_options).map_err(parsing_error)?,
+        valid_after: row.valid_after as u64,
+        valid_before: row.valid_before as u64,
+        issued_at: row.issued_at as u64,
+        requester_ip: row.requester_ip,
+    })
+}
+
+impl SqliteLedger {
+    pub async fn new(config: Config) -> Result<Self, LedgerError> {
+        let ledger = SqliteLedger { path: config.path };
+        let path = ledger.path.clone();
+
+        tokio::task::spawn_blocking(move || {
+            establish_connection(&path)?
+                .batch_execute(SCHEMA)
+                .map_err(store_error)
+        })
+        .await
+        .map_err(store_error)??;
+
+        Ok(ledger)
+    }
+
+    pub async fn record(&self, certificate: IssuedCertificate) -> Result<(), LedgerError> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || record(&path, certificate))
+            .await
+            .map_err(store_error)?
+    }
+
+    pub async fn search(&self, query: LedgerQuery) -> Result<Vec<IssuedCertificate>, LedgerError> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || search(&path, query))
+            .await
+            .map_err(store_error)?
+    }
+}
+
+fn record(path: &str, certificate: IssuedCertificate) -> Result<(), LedgerError> {
+    let row = NewIssuedCertificate {
+        certificate_type: certificate.certificate_type,
+        serial: certificate.serial,
+        fingerprint: certificate.fingerprint,
+        signed_by: certificate.signed_by,
+        authority: certificate.authority,
+        principals: serde_json::to_string(&certificate.principals).map_err(parsing_error)?,
+        extensions: serde_json::to_string(&certificate.extensions).map_err(parsing_error)?,
+        critical_options: serde_json::to_string(&certificate.critical_options)
+            .map_err(parsing_error)?,
+        valid_after: certificate.valid_after as i64,
+        valid_before: certificate.valid_before as i64,
+        issued_at: certificate.issued_at as i64,
+        requester_ip: certificate.requester_ip,
+    };
+
+    // The same identity should not be stored twice for one certificate
+    let mtls_identities: BTreeSet<String> = certificate.mtls_identities.into_iter().collect();
+
+    let mut conn = establish_connection(path)?;
+    conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        let certificate_id: i64 = diesel::insert_into(issued_certificates::table)
+            .values(&row)
+            .returning(issued_certificates::id)
+            .get_result(conn)?;
+
+        let identities: Vec<_> = mtls_identities
+            .iter()
+            .map(|identity| {
+                (
+                    issued_certificate_identities::certificate_id.eq(certificate_id),
+                    issued_certificate_identities::identity.eq(identity),
+                )
+            })
+            .collect();
+
+        if !identities.is_empty() {
+            diesel::insert_into(issued_certificate_identities::table)
+                .values(&identities)
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })
+    .map_err(store_error)
+}
+
+fn search(path: &str, query: LedgerQuery) -> Result<Vec<IssuedCertificate>, LedgerError> {
+    let mut conn = establish_connection(path)?;
+    let limit = query.limit();
+
+    let mut statement = issued_certificates::table.into_boxed();
+
+    if let Some(identity) = query.identity {
+        statement = statement.filter(
+            issued_certificates::id.eq_any(
+                issued_certificate_identities::table
+                    .filter(issued_certificate_identities::identity.eq(identity))
+                    .select(issued_certificate_identities::certificate_id),
+            ),
+        );
+    }
+
+    if let Some(fingerprint) = query.fingerprint {
+        statement = statement.filter(issued_certificates::fingerprint.eq(fingerprint));
+    }
+
+    if let Some(serial) = query.serial {
+        statement = statement.filter(issued_certificates::serial.eq(serial));
+    }
+
+    if let Some(authority) = query.authority {
+        statement = statement.filter(issued_certificates::authority.eq(authority));
+    }
+
+    if let Some(issued_after) = query.issued_after {
+        statement = statement.filter(issued_certificates::issued_at.ge(issued_after as i64));
+    }
+
+    if let Some(issued_before) = query.issued_before {
+        statement = statement.filter(issued_certificates::issued_at.lt(issued_before as i64));
+    }
+
+    let rows: Vec<IssuedCertificateRow> = statement
+        .order((
+            issued_certificates::issued_at.desc(),
+            issued_certificates::id.desc(),
+        ))
+        .limit(limit)
+        .load(&mut conn)
+        .map_err(store_error)?;
+
+    let ids: Vec<i64> = rows.iter().map(|row| row.id).collect();
+    let identities: Vec<(i64, String)> = issued_certificate_identities::table
+        .filter(issued_certificate_identities::certificate_id.eq_any(&ids))
+        .select((
+            issued_certificate_identities::certificate_id,
+            issued_certificate_identities::identity,
+        ))
+        .load(&mut conn)
+        .map_err(store_error)?;
+
+    let mut identities_by_certificate: HashMap<i64, Vec<String>> = HashMap::new();
+    for (certificate_id, identity) in identities {
+        identities_by_certificate
+            .entry(certificate_id)
+            .or_default()
+            .push(identity);
+    }
+
+    rows.into_iter()
+        .map(|row| {
+            let identities = identities_by_certificate.remove(&row.id).unwrap_or_default();
+            issued_certificate(row, identities)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn ledger(directory: &tempfile::TempDir) -> SqliteLedger {
+        SqliteLedger::new(Config {
+            path: directory.path().join("ledger.db").to_string_lossy().into_owned(),
+        })
+        .await
+        .unwrap()
+    }
+
+    fn certificate(serial: &str, mtls_identities: &[&str]) -> IssuedCertificate {
+        IssuedCertificate {
+            certificate_type: String::from("user"),
+            serial: serial.to_string(),
+            fingerprint: String::from("SHA256:d/Ofi1fZuphAvf7DXj85VnSbz0CSI6RFTfqLb1SBSYA"),
+            signed_by: String::from("SHA256:zhVx9bUzSMu4PSeRiDkTiVYVyiRsDHeVlVmE1KT3nCo"),
+            authority: String::from("example"),
+            mtls_identities: mtls_identities.iter().map(|i| i.to_string()).collect(),
+            principals: vec![String::from("alice")],
+            extensions: HashMap::new(),
+            critical_options: HashMap::new(),
+            valid_after: 100,
+            valid_before: 200,
+            issued_at: 100,
+            requester_ip: String::from("127.0.0.1"),
+        }
+    }
+
+    #[tokio::test]
+    async fn certificates_are_found_by_any_of_their_identities() {
+        let directory = tempfile::tempdir().unwrap();
+        let ledger = ledger(&directory).await;
+
+        ledger
+            .record(certificate("1", &["alice", "infra", "alice"]))
+            .await
+            .unwrap();
+        ledger.record(certificate("2", &["bob"])).await.unwrap();
+
+        let found = ledger
+            .search(LedgerQuery {
+                identity: Some(String::from("infra")),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].serial, "1");
+
+        // Every identity is returned, without duplicates
+        let mut identities = found[0].mtls_identities.clone();
+        identities.sort();
+        assert_eq!(identities, vec!["alice", "infra"]);
+
+        let found = ledger
+            .search(LedgerQuery {
+                identity: Some(String::from("bob")),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].serial, "2");
+        assert_eq!(found[0].mtls_identities, vec!["bob"]);
+    }
+}
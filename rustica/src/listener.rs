@@ -0,0 +1,161 @@
+/// Rustica binds its listening socket once and hands accepted connections to
+/// the tonic server that is currently running. Reloading the configuration
+/// starts a new server (a new `Generation`) with the new TLS identity and
+/// `RusticaServer`, switches new connections over to it, and then lets the
+/// previous generation finish the requests it already has. This way a reload
+/// never refuses connections or interrupts requests, and every request is
+/// handled entirely by either the old or the new configuration.
+use crate::health;
+#[cfg(feature = "local-db")]
+use crate::rustica::rustica_admin_server::RusticaAdminServer as GRPCRusticaAdminServer;
+use crate::rustica::rustica_server::RusticaServer as GRPCRusticaServer;
+use crate::server::RusticaServer;
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tonic::transport::{Server, ServerTlsConfig};
+use tonic_health::server::HealthReporter;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The listening socket shared by every generation
+pub struct Listener {
+    /// Where newly accepted connections are sent
+    route: watch::Sender<mpsc::Sender<TcpStream>>,
+    accept_task: JoinHandle<()>,
+}
+
+/// A running tonic server with its own configuration
+pub struct Generation {
+    pub server: Arc<RusticaServer>,
+    /// Resolves if the tonic server exits on its own, which only happens if
+    /// it fails
+    pub task: JoinHandle<Result<(), tonic::transport::Error>>,
+    connections: mpsc::Sender<TcpStream>,
+    health_reporter: HealthReporter,
+    health_task: JoinHandle<()>,
+    drain: oneshot::Sender<()>,
+}
+
+async fn accept_connections(
+    listener: TcpListener,
+    route: watch::Receiver<mpsc::Sender<TcpStream>>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let connections = route.borrow().clone();
+                // This only fails if the generation has stopped which means
+                // Rustica is shutting down
+                let _ = connections.send(stream).await;
+            }
+            Err(e) => {
+                // This is usually from running out of file descriptors so
+                // back off instead of spinning
+                error!("Could not accept connection: {e}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+impl Listener {
+    /// Bind the listening socket and send connections to the first
+    /// generation
+    pub async fn bind(address: SocketAddr, first: &Generation) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(address).await?;
+        let (route, route_receiver) = watch::channel(first.connections.clone());
+        let accept_task = tokio::spawn(accept_connections(listener, route_receiver));
+
+        Ok(Self { route, accept_task })
+    }
+
+    /// Send all new connections to this generation
+    pub fn route_to(&self, generation: &Generation) {
+        self.route.send_replace(generation.connections.clone());
+    }
+
+    /// Stop accepting new connections
+    pub fn close(self) {
+        self.accept_task.abort();
+    }
+}
+
+impl Generation {
+    /// Start serving Rustica with the provided configuration. This fails if
+    /// the TLS configuration is invalid. The health of this generation's
+    /// backends is checked every `check_interval`.
+    pub fn start(
+        server: RusticaServer,
+        tls: ServerTlsConfig,
+        check_interval: Duration,
+    ) -> Result<Self, tonic::transport::Error> {
+        let server = Arc::new(server);
+        let (health_reporter, health_service) = tonic_health::server::health_reporter();
+
+        // This is the only part of starting that can fail so it comes before
+        // anything is spawned
+        let router = Server::builder()
+            .tls_config(tls)?
+            .max_frame_size(1024 * 1024 * 4) // 4 MiB
+            .add_service(health_service)
+            .add_service(GRPCRusticaServer::from_arc(server.clone()));
+
+        // Admin calls need the local database authorizer but the service is
+        // always served so callers get a clear error without it
+        #[cfg(feature = "local-db")]
+        let router = router.add_service(GRPCRusticaAdminServer::from_arc(server.clone()));
+
+        let health_task = tokio::spawn(health::monitor(
+            server.clone(),
+            health_reporter.clone(),
+            check_interval,
+        ));
+
+        let (connections, connection_receiver) = mpsc::channel(128);
+        let incoming = ReceiverStream::new(connection_receiver).map(Ok::<_, std::io::Error>);
+        let (drain, drain_receiver) = oneshot::channel::<()>();
+        let task = tokio::spawn(router.serve_with_incoming_shutdown(incoming, async {
+            let _ = drain_receiver.await;
+        }));
+
+        Ok(Self {
+            server,
+            task,
+            connections,
+            health_reporter,
+            health_task,
+            drain,
+        })
+    }
+
+    /// Stop health checking and report that this generation is not serving
+    /// so health checkers stop sending new requests here
+    pub async fn stop_serving(&mut self) {
+        self.health_task.abort();
+        health::shutting_down(self.health_reporter.clone()).await;
+    }
+
+    /// Stop accepting requests and wait until `deadline` for in-flight ones
+    /// to finish
+    pub async fn drain(mut self, deadline: Instant) {
+        self.health_task.abort();
+        let _ = self.drain.send(());
+
+        match tokio::time::timeout_at(deadline, &mut self.task).await {
+            Ok(Ok(Ok(()))) => (),
+            Ok(Ok(Err(e))) => error!("Rustica server exited with an error: {e}"),
+            Ok(Err(e)) => error!("Rustica server task failed: {e}"),
+            Err(_) => {
+                error!("In-flight requests did not finish within the shutdown grace period");
+                self.task.abort();
+            }
+        }
+    }
+}
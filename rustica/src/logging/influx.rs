@@ -1,10 +1,8 @@
-use super::{Log, LoggingError, RusticaLogger, WrappedLog};
+use super::{Log, LogRuntime, LoggingError, RusticaLogger, WrappedLog};
 
 use influxdb::InfluxDbWriteable;
 use influxdb::{Client, Timestamp};
 
-use tokio::runtime::Handle;
-
 use serde::Deserialize;
 
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -20,17 +18,17 @@ pub struct Config {
 
 pub struct InfluxLogger {
     client: Client,
-    runtime: Handle,
+    runtime: LogRuntime,
     dataset: String,
 }
 
 impl InfluxLogger {
     /// Create a new InfluxDB logger from the provided configuration
-    pub fn new(config: Config, handle: Handle) -> Self {
+    pub fn new(config: Config, runtime: LogRuntime) -> Self {
         Self {
             client: Client::new(config.address, config.database)
                 .with_auth(config.user, config.password),
-            runtime: handle,
+            runtime,
             dataset: config.dataset,
         }
     }
@@ -67,6 +65,13 @@ impl RusticaLogger for InfluxLogger {
             }
             Log::KeyRegistered(_kr) => (),
             Log::KeyRegistrationFailure(_krf) => (),
+            Log::CertificatesRevoked(_) => (),
+            Log::AccessCredentialsRevoked(_) => (),
+            Log::AccessCredentialRenewed(_) => (),
+            Log::ApprovalRequested(_) => (),
+            Log::ApprovalReviewed(_) => (),
+            #[cfg(feature = "local-db")]
+            Log::LocalDatabaseChanged(_) => (),
             Log::InternalMessage(_im) => (),
             Log::Heartbeat(_) => (),
             Log::X509CertificateIssued(_) => (),
@@ -7,13 +7,20 @@ mod webhook;
 
 mod stdout;
 
-use crossbeam_channel::{Receiver, RecvTimeoutError};
+use crossbeam_channel::{never, select, Receiver};
 
 use serde::{Deserialize, Serialize};
 #[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
-use tokio::runtime::Runtime;
+use tokio::runtime::{Handle, Runtime};
 
 use std::collections::HashMap;
+#[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+#[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+use std::time::Instant;
 use std::time::Duration;
 
 /// A severity scale to measure how critical a log is when sent
@@ -55,12 +62,19 @@ pub struct CertificateIssued {
     pub extensions: HashMap<String, String>,
     /// Critical Options present in the issued certificate
     pub critical_options: HashMap<String, String>,
+    /// Extensions the authorizer allowed that were left out because the
+    /// client did not request them
+    pub declined_extensions: Vec<String>,
+    /// Critical options that were added because the client requested them
+    pub added_critical_options: Vec<String>,
     /// Validity period starts
     pub valid_after: u64,
     /// Validity period ends
     pub valid_before: u64,
     /// Was a new access certificate returned with this request
     pub new_access_certificate_issued: bool,
+    /// The approvers that approved the request, if it needed approval
+    pub approved_by: Vec<String>,
 }
 
 /// Issued when a certificate request is granted to a user or host
@@ -96,6 +110,111 @@ pub struct KeyRegistrationFailure {
     pub message: String,
 }
 
+/// Issued when an admin revokes certificates or keys for an authority
+#[derive(Serialize)]
+pub struct CertificatesRevoked {
+    /// The configured authority name the revocations apply to
+    pub authority: String,
+    /// The MTLS identities of the admin that made the request
+    pub mtls_identities: Vec<String>,
+    /// Serials of the revoked certificates
+    pub serials: Vec<u64>,
+    /// Fingerprints of the revoked keys
+    pub fingerprints: Vec<String>,
+    /// Key IDs of the revoked certificates
+    pub key_ids: Vec<String>,
+    /// The version of the KRL after the revocations were applied
+    pub krl_version: u64,
+}
+
+/// Issued when an admin revokes mTLS access certificates
+#[derive(Serialize)]
+pub struct AccessCredentialsRevoked {
+    /// The MTLS identities of the admin that made the request
+    pub mtls_identities: Vec<String>,
+    /// Serials of the revoked access certificates
+    pub serials: Vec<String>,
+    /// Fingerprints of the revoked access certificates
+    pub fingerprints: Vec<String>,
+    /// Identities that may no longer use any access certificate
+    pub identities: Vec<String>,
+    /// The number of the access certificate CRL after the revocations were
+    /// applied
+    pub crl_number: u64,
+}
+
+/// Issued when a client renews its mTLS access certificate without
+/// requesting an SSH certificate
+#[derive(Serialize)]
+pub struct AccessCredentialRenewed {
+    /// The fingerprint of the key used to answer the challenge
+    pub fingerprint: String,
+    /// The MTLS identities of the new access certificate
+    pub mtls_identities: Vec<String>,
+    /// Validity period starts
+    pub valid_after: u64,
+    /// Validity period ends
+    pub valid_before: u64,
+}
+
+/// Issued when a certificate request is held until other people approve it
+#[derive(Serialize)]
+pub struct ApprovalRequested {
+    /// The ID approvers refer to the request by
+    pub request_id: String,
+    /// The fingerprint of the key the certificate is for
+    pub fingerprint: String,
+    /// The configured authority name for the signer
+    pub authority: String,
+    /// Certificate type, either User or Host
+    pub certificate_type: String,
+    /// The MTLS identities of the requester
+    pub mtls_identities: Vec<String>,
+    /// The principals authorized for the request
+    pub principals: Vec<String>,
+    /// The identities that may approve or deny the request
+    pub approvers: Vec<String>,
+    /// How many approvals are needed
+    pub required_approvals: usize,
+}
+
+/// Issued when an approver approves or denies a request
+#[derive(Serialize)]
+pub struct ApprovalReviewed {
+    /// The ID of the reviewed request
+    pub request_id: String,
+    /// The identity of the approver that reviewed the request
+    pub approver: String,
+    /// Whether the request was approved or denied
+    pub approved: bool,
+    /// The reason given for a denial
+    pub reason: String,
+    /// How many approvals the request has after this review
+    pub approvals: usize,
+    /// How many approvals are needed
+    pub required_approvals: usize,
+}
+
+/// Issued when an admin changes the grants, registered keys, or X509
+/// authorizations in the local database
+#[cfg(feature = "local-db")]
+#[derive(Serialize)]
+pub struct LocalDatabaseChanged {
+    /// The MTLS identities of the admin that made the change
+    pub mtls_identities: Vec<String>,
+    /// The call that made the change, such as GrantPrincipals
+    pub action: String,
+    /// The configured authority name the change applies to. Empty for
+    /// changes that do not belong to an authority.
+    pub authority: String,
+    /// The key fingerprints or users the change applies to
+    pub subjects: Vec<String>,
+    /// The principals, hosts, extensions, or permissions that were changed
+    pub values: Vec<String>,
+    /// How many rows were added, replaced, or removed
+    pub changed: u64,
+}
+
 /// Issued when errors or notable events occur within the system
 #[derive(Serialize)]
 pub struct InternalMessage {
@@ -124,6 +243,23 @@ pub enum Log {
     /// This could be due to an external authorizor denying (again for any reason
     /// it sees fit) or attestation/database errors.
     KeyRegistrationFailure(KeyRegistrationFailure),
+    /// An admin has revoked certificates or keys. Hosts will stop trusting
+    /// them once they fetch the new KRL.
+    CertificatesRevoked(CertificatesRevoked),
+    /// An admin has revoked access certificates. Rustica refuses them
+    /// immediately and other services once they fetch the new CRL.
+    AccessCredentialsRevoked(AccessCredentialsRevoked),
+    /// A client has renewed its access certificate using the
+    /// RenewAccessCredential call
+    AccessCredentialRenewed(AccessCredentialRenewed),
+    /// A certificate request needs approval before it is issued. A
+    /// CertificateIssued log follows once it is approved.
+    ApprovalRequested(ApprovalRequested),
+    /// An approver has approved or denied a request waiting for approval
+    ApprovalReviewed(ApprovalReviewed),
+    /// An admin has changed the grants or keys in the local database
+    #[cfg(feature = "local-db")]
+    LocalDatabaseChanged(LocalDatabaseChanged),
     /// Used for relaying status messages to a logging backend. Rustica errors
     /// or failures send messages of this type.
     InternalMessage(InternalMessage),
@@ -158,6 +294,10 @@ pub struct LoggingConfiguration {
     /// empty heartbeat log to the logging systems to signal it is still up
     /// and healthy.
     heartbeat_interval: Option<u64>,
+    /// When Rustica is shutting down, wait up to this many seconds for logs
+    /// that are still being sent to remote backends before giving up.
+    #[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+    flush_timeout: Option<u64>,
     /// Configures the stdout logger. This is powered by env_logger and is a
     /// thin wrapper around it, however it lets us log to stdout the same way
     /// we log to other more complex systems.
@@ -189,102 +329,245 @@ pub enum LoggingError {
     CommunicationError(String),
 }
 
+/// Loggers that talk to remote backends send logs asynchronously so they do
+/// not hold up other loggers. This wraps the handle to the runtime they send
+/// on and keeps count of the sends still in flight so they can be waited on
+/// when Rustica shuts down.
+#[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+#[derive(Clone)]
+pub struct LogRuntime {
+    handle: Handle,
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// Decrements the in flight count when a send finishes, even if it panics
+#[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+struct InFlightGuard(Arc<AtomicUsize>);
+
+#[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+impl LogRuntime {
+    fn new(handle: Handle) -> Self {
+        Self {
+            handle,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Spawn sending a log onto the logging runtime
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightGuard(self.in_flight.clone());
+        self.handle.spawn(async move {
+            let _guard = guard;
+            future.await;
+        });
+    }
+
+    /// Block until every spawned send has finished or the timeout passes.
+    /// Returns how many sends were still in flight.
+    fn flush(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let in_flight = self.in_flight.load(Ordering::SeqCst);
+            if in_flight == 0 || Instant::now() >= deadline {
+                return in_flight;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
 /// To implement a new logger, it must implement the `send_log` function
 /// and return success or failure.
 trait RusticaLogger {
     fn send_log(&self, log: &WrappedLog) -> Result<(), LoggingError>;
 }
 
-/// This is the entry point of our logging thread started from main. This
-/// should be running in its own thread waiting for logs to come in from
-/// the tonic server. If it does not receive a message in 300 seconds it
-/// will send a heartbeat message instead. For stdout, and influx, this is
-/// a noop and will not actually be sent to the backend (or logged to the
-/// screen).
-pub fn start_logging_thread(config: LoggingConfiguration, log_receiver: Receiver<Log>) {
+/// The loggers configured from a `LoggingConfiguration`. These are rebuilt
+/// when the configuration is reloaded.
+struct Loggers {
+    identifier: String,
+    heartbeat_interval: u64,
     #[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
-    let runtime = Runtime::new().unwrap();
-    let heartbeat_interval = config.heartbeat_interval.unwrap_or(300);
-    // Configure the different loggers
-    let stdout_logger = match config.stdout {
-        Some(config) => {
-            println!("Configured logger: stdout");
-            Some(stdout::StdoutLogger::new(config))
-        }
-        None => {
-            println!("stdout logger is not enabled. This is not recommended!");
-            None
-        }
-    };
-
+    flush_timeout: u64,
+    stdout: Option<stdout::StdoutLogger>,
     #[cfg(feature = "influx")]
-    let influx_logger = match config.influx {
-        Some(config) => {
-            println!("Configured logger: influx");
-            Some(influx::InfluxLogger::new(config, runtime.handle().clone()))
-        }
-        None => None,
-    };
-
+    influx: Option<influx::InfluxLogger>,
     #[cfg(feature = "splunk")]
-    let splunk_logger = match config.splunk {
-        Some(config) => {
-            println!("Configured logger: splunk");
-            Some(splunk::SplunkLogger::new(config, runtime.handle().clone()))
-        }
-        None => None,
-    };
-
+    splunk: Option<splunk::SplunkLogger>,
     #[cfg(feature = "webhook")]
-    let webhook_logger = match config.webhook {
-        Some(config) => {
-            println!("Configured logger: webhook");
-            Some(webhook::WebhookLogger::new(
-                config,
-                runtime.handle().clone(),
-            ))
-        }
-        None => None,
-    };
+    webhook: Option<webhook::WebhookLogger>,
+}
 
-    // Main logging loop
-    loop {
-        let log = match log_receiver.recv_timeout(Duration::from_secs(heartbeat_interval)) {
-            Ok(l) => l,
-            Err(RecvTimeoutError::Timeout) => Log::Heartbeat(Heartbeat {}),
-            _ => break,
+impl Loggers {
+    fn new(
+        config: LoggingConfiguration,
+        #[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+        log_runtime: &LogRuntime,
+    ) -> Self {
+        // Configure the different loggers
+        let stdout = match config.stdout {
+            Some(config) => {
+                println!("Configured logger: stdout");
+                Some(stdout::StdoutLogger::new(config))
+            }
+            None => {
+                println!("stdout logger is not enabled. This is not recommended!");
+                None
+            }
+        };
+
+        #[cfg(feature = "influx")]
+        let influx = match config.influx {
+            Some(config) => {
+                println!("Configured logger: influx");
+                Some(influx::InfluxLogger::new(config, log_runtime.clone()))
+            }
+            None => None,
+        };
+
+        #[cfg(feature = "splunk")]
+        let splunk = match config.splunk {
+            Some(config) => {
+                println!("Configured logger: splunk");
+                Some(splunk::SplunkLogger::new(config, log_runtime.clone()))
+            }
+            None => None,
         };
 
+        #[cfg(feature = "webhook")]
+        let webhook = match config.webhook {
+            Some(config) => {
+                println!("Configured logger: webhook");
+                Some(webhook::WebhookLogger::new(
+                    config,
+                    log_runtime.clone(),
+                ))
+            }
+            None => None,
+        };
+
+        Self {
+            identifier: config.identifier.unwrap_or_default(),
+            heartbeat_interval: config.heartbeat_interval.unwrap_or(300),
+            #[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+            flush_timeout: config.flush_timeout.unwrap_or(10),
+            stdout,
+            #[cfg(feature = "influx")]
+            influx,
+            #[cfg(feature = "splunk")]
+            splunk,
+            #[cfg(feature = "webhook")]
+            webhook,
+        }
+    }
+
+    fn send_log(&self, log: Log) {
         let log = WrappedLog {
             log,
-            identifier: config.identifier.clone().unwrap_or_default(),
+            identifier: self.identifier.clone(),
         };
 
-        if let Some(logger) = &stdout_logger {
+        if let Some(logger) = &self.stdout {
             logger.send_log(&log).unwrap();
         }
 
         #[cfg(feature = "influx")]
-        if let Some(logger) = &influx_logger {
+        if let Some(logger) = &self.influx {
             if let Err(_) = logger.send_log(&log) {
                 error!("Could not send logs to InfluxDB");
             }
         }
 
         #[cfg(feature = "splunk")]
-        if let Some(logger) = &splunk_logger {
+        if let Some(logger) = &self.splunk {
             if let Err(_) = logger.send_log(&log) {
                 error!("Could not send logs to Splunk");
             }
         }
 
         #[cfg(feature = "webhook")]
-        if let Some(logger) = &webhook_logger {
+        if let Some(logger) = &self.webhook {
             if let Err(_) = logger.send_log(&log) {
                 error!("Could not send logs to webhook");
             }
         }
     }
+}
+
+/// This is the entry point of our logging thread started from main. This
+/// should be running in its own thread waiting for logs to come in from
+/// the tonic server. If it does not receive a message in 300 seconds it
+/// will send a heartbeat message instead. For stdout, and influx, this is
+/// a noop and will not actually be sent to the backend (or logged to the
+/// screen).
+///
+/// When the configuration is reloaded, the new logging configuration is sent
+/// on `config_receiver` and the loggers are rebuilt from it. Logs that are
+/// already being sent by the previous loggers are not affected.
+pub fn start_logging_thread(
+    config: LoggingConfiguration,
+    log_receiver: Receiver<Log>,
+    mut config_receiver: Receiver<LoggingConfiguration>,
+) {
+    #[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+    let runtime = Runtime::new().unwrap();
+    #[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+    let log_runtime = LogRuntime::new(runtime.handle().clone());
+
+    let mut loggers = Loggers::new(
+        config,
+        #[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+        &log_runtime,
+    );
+
+    // Main logging loop
+    loop {
+        let log = select! {
+            recv(log_receiver) -> log => match log {
+                Ok(l) => l,
+                Err(_) => break,
+            },
+            recv(config_receiver) -> config => {
+                match config {
+                    Ok(config) => {
+                        loggers = Loggers::new(
+                            config,
+                            #[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+                            &log_runtime,
+                        );
+                    }
+                    // Nothing can reload the configuration anymore
+                    Err(_) => config_receiver = never(),
+                }
+                continue;
+            },
+            default(Duration::from_secs(loggers.heartbeat_interval)) => Log::Heartbeat(Heartbeat {}),
+        };
+
+        loggers.send_log(log);
+    }
+
+    // Every sender has been dropped which means Rustica is shutting down.
+    // Give logs that are still being sent a chance to make it out.
+    #[cfg(any(feature = "influx", feature = "splunk", feature = "webhook"))]
+    {
+        let flush_timeout = Duration::from_secs(loggers.flush_timeout);
+        let remaining = log_runtime.flush(flush_timeout);
+        if remaining > 0 {
+            error!("Gave up waiting for {remaining} logs to be sent to remote backends");
+        }
+    }
 
     error!("Logging thread has gone away.");
 }
@@ -1,12 +1,10 @@
-use super::{LoggingError, RusticaLogger, WrappedLog};
+use super::{LogRuntime, LoggingError, RusticaLogger, WrappedLog};
 
 use reqwest;
 
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
-use tokio::runtime::Handle;
-
 /// The struct that defines the Splunk specific configuration of the logging
 /// service.
 #[derive(Deserialize)]
@@ -20,7 +18,7 @@ pub struct Config {
 /// `Config` struct.
 pub struct SplunkLogger {
     /// A tokio runtime to send logs on
-    runtime: Handle,
+    runtime: LogRuntime,
     /// A reqwest client configured with the Splunk endpoint and authentication
     client: reqwest::Client,
     /// An API token to send with our logs for authentication
@@ -45,7 +43,7 @@ impl SplunkLogger {
     /// Implement the new function for the Splunk logger. This converts
     /// the configuration struct into a type that can handle sending
     /// logs directly to a Splunk HEC endpoint.
-    pub fn new(config: Config, handle: Handle) -> Self {
+    pub fn new(config: Config, runtime: LogRuntime) -> Self {
         // I don't think this can fail with our settings so we do an unwrap
         let client = reqwest::Client::builder()
             .danger_accept_invalid_certs(true)
@@ -53,7 +51,7 @@ impl SplunkLogger {
             .build().unwrap();
 
         Self {
-            runtime: handle,
+            runtime,
             client,
             token: config.token.clone(),
             url: config.url.clone(),
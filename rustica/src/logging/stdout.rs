@@ -18,7 +18,7 @@ impl RusticaLogger for StdoutLogger {
         match &log.log {
             Log::CertificateIssued(ci) => {
                 info!(
-                    "[{}] Certificate issued for: [{}] Authority: [{}] Identified by: [{}] Principals granted: [{}] Extensions: [{:?}] CriticalOptions: [{:?}] Valid After: [{}] Valid Before: [{}] Serial Number: [{}]",
+                    "[{}] Certificate issued for: [{}] Authority: [{}] Identified by: [{}] Principals granted: [{}] Extensions: [{:?}] CriticalOptions: [{:?}] Declined Extensions: [{}] Added CriticalOptions: [{}] Valid After: [{}] Valid Before: [{}] Serial Number: [{}] Approved By: [{}]",
                     ci.certificate_type,
                     ci.fingerprint,
                     ci.authority,
@@ -26,13 +26,70 @@ impl RusticaLogger for StdoutLogger {
                     ci.principals.join(", "),
                     ci.extensions,
                     ci.critical_options,
+                    ci.declined_extensions.join(", "),
+                    ci.added_critical_options.join(", "),
                     ci.valid_after,
                     ci.valid_before,
                     ci.serial,
+                    ci.approved_by.join(", "),
                 )
             }
             Log::KeyRegistered(kr) => info!("Key registered: [{}] Identified by: [{}]", kr.fingerprint, kr.mtls_identities.join(", ")),
             Log::KeyRegistrationFailure(krf) => info!("Failed to register key: [{}] Identified by: [{}]", krf.key_info.fingerprint, krf.key_info.mtls_identities.join(", ")),
+            Log::CertificatesRevoked(cr) => info!(
+                "Revocations added. Authority: [{}] Identified by: [{}] Serials: [{:?}] Fingerprints: [{}] Key IDs: [{}] KRL Version: [{}]",
+                cr.authority,
+                cr.mtls_identities.join(", "),
+                cr.serials,
+                cr.fingerprints.join(", "),
+                cr.key_ids.join(", "),
+                cr.krl_version,
+            ),
+            Log::AccessCredentialsRevoked(acr) => info!(
+                "Access credentials revoked. Identified by: [{}] Serials: [{}] Fingerprints: [{}] Identities: [{}] CRL Number: [{}]",
+                acr.mtls_identities.join(", "),
+                acr.serials.join(", "),
+                acr.fingerprints.join(", "),
+                acr.identities.join(", "),
+                acr.crl_number,
+            ),
+            Log::AccessCredentialRenewed(acr) => info!(
+                "Access credential renewed. Identified by: [{}] Key: [{}] Valid After: [{}] Valid Before: [{}]",
+                acr.mtls_identities.join(", "),
+                acr.fingerprint,
+                acr.valid_after,
+                acr.valid_before,
+            ),
+            Log::ApprovalRequested(ar) => info!(
+                "Approval requested. Request ID: [{}] [{}] Certificate for: [{}] Authority: [{}] Identified by: [{}] Principals: [{}] Approvers: [{}] Required Approvals: [{}]",
+                ar.request_id,
+                ar.certificate_type,
+                ar.fingerprint,
+                ar.authority,
+                ar.mtls_identities.join(", "),
+                ar.principals.join(", "),
+                ar.approvers.join(", "),
+                ar.required_approvals,
+            ),
+            Log::ApprovalReviewed(ar) => info!(
+                "Approval reviewed. Request ID: [{}] Approver: [{}] Approved: [{}] Reason: [{}] Approvals: [{}/{}]",
+                ar.request_id,
+                ar.approver,
+                ar.approved,
+                ar.reason,
+                ar.approvals,
+                ar.required_approvals,
+            ),
+            #[cfg(feature = "local-db")]
+            Log::LocalDatabaseChanged(ldc) => info!(
+                "Local database changed. Action: [{}] Authority: [{}] Subjects: [{}] Values: [{}] Changed: [{}] Identified by: [{}]",
+                ldc.action,
+                ldc.authority,
+                ldc.subjects.join(", "),
+                ldc.values.join(", "),
+                ldc.changed,
+                ldc.mtls_identities.join(", "),
+            ),
             Log::InternalMessage(im) => match im.severity {
                 Severity::Error => error!("{}", im.message),
                 Severity::Warning => warn!("{}", im.message),
@@ -1,12 +1,10 @@
-use super::{LoggingError, RusticaLogger, WrappedLog};
+use super::{LogRuntime, LoggingError, RusticaLogger, WrappedLog};
 
 use reqwest;
 
 use serde::Deserialize;
 use std::time::Duration;
 
-use tokio::runtime::Handle;
-
 /// The struct that defines the Webhook specific configuration of the logging
 /// service.
 #[derive(Deserialize)]
@@ -19,7 +17,7 @@ pub struct Config {
 /// The specific logger that is configured from the `Config` struct.
 pub struct WebhookLogger {
     /// A tokio runtime to send logs on
-    runtime: Handle,
+    runtime: LogRuntime,
     /// A reqwest client configured with the Splunk endpoint and authentication
     client: reqwest::Client,
     /// The configuration struct
@@ -31,14 +29,14 @@ impl WebhookLogger {
     /// Implement the new function for the Splunk logger. This converts
     /// the configuration struct into a type that can handle sending
     /// logs directly to a Splunk HEC endpoint.
-    pub fn new(config: Config, handle: Handle) -> Self {
+    pub fn new(config: Config, runtime: LogRuntime) -> Self {
         // I don't think this can fail with our settings so we do an unwrap
         let client = reqwest::Client::builder()
             .timeout(Duration::from_secs(config.timeout.into()))
             .build().unwrap();
 
         Self {
-            runtime: handle,
+            runtime,
             client,
             config,
         }
@@ -1,66 +1,188 @@
 #[macro_use]
 extern crate log;
 
-#[cfg(feature = "local-db")]
-#[macro_use]
-extern crate diesel;
-
+mod approval;
 mod auth;
 mod config;
 mod error;
+mod health;
 mod key;
+mod ledger;
+mod listener;
 mod logging;
+mod metrics;
+#[cfg(feature = "oidc")]
+mod oidc;
+mod revocation;
 mod server;
 mod signing;
 mod verification;
 
-use rustica::rustica_server::RusticaServer as GRPCRusticaServer;
-use tonic::transport::{Certificate as TonicCertificate, Identity, Server, ServerTlsConfig};
+use tonic::transport::{Certificate as TonicCertificate, Identity, ServerTlsConfig};
 
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::Instant;
+
+use std::net::SocketAddr;
 use std::thread;
+use std::time::Duration;
 
-use config::ConfigurationError;
+use config::{ConfigurationError, RusticaSettings};
+use listener::{Generation, Listener};
+use logging::{InternalMessage, Log, LoggingConfiguration, Severity};
 
 pub mod rustica {
     tonic::include_proto!("rustica");
 }
 
+fn tls_config(settings: &RusticaSettings) -> ServerTlsConfig {
+    let identity = Identity::from_pem(&settings.server_cert, &settings.server_key);
+    let client_ca_cert = TonicCertificate::from_pem(&settings.client_ca_cert);
+
+    // When clients can identify with an OIDC token instead, a client
+    // certificate is still verified if one is presented
+    ServerTlsConfig::new()
+        .identity(identity)
+        .client_ca_root(client_ca_cert)
+        .client_auth_optional(!settings.require_client_certificate)
+}
+
+/// Load the configuration file again and start a new generation serving it.
+/// If anything about the new configuration is invalid, an error is returned
+/// and the running generation is left untouched. State shared between the
+/// generations is only updated once the new one has started.
+async fn reload(
+    config_path: &str,
+    current: &Generation,
+    address: SocketAddr,
+    check_interval: Duration,
+) -> Result<(Generation, LoggingConfiguration), String> {
+    let (settings, shared_state) = config::reload(config_path, &current.server)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if settings.address != address {
+        warn!("listen_address cannot be changed by a reload and requires a restart");
+    }
+
+    let tls = tls_config(&settings);
+    println!("{}", settings.server.signer);
+    println!("{}", settings.server.authorizer.info());
+
+    let generation = Generation::start(settings.server, tls, check_interval)
+        .map_err(|e| format!("Invalid TLS configuration: {e}"))?;
+    shared_state.apply(&generation.server).await;
+
+    Ok((generation, settings.logging_configuration))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
-    let settings = match config::configure().await {
+    let (settings, log_receiver) = match config::configure().await {
         Ok(settings) => settings,
         Err(ConfigurationError::ValidateOnly) => {
             println!("Configuration successfully validated");
             return Ok(());
         }
+        #[cfg(feature = "local-db")]
+        Err(ConfigurationError::Migrated(version)) => {
+            println!("Local database is at schema version {version}");
+            return Ok(());
+        }
         Err(e) => return Err(e)?,
     };
 
-    let identity = Identity::from_pem(settings.server_cert, settings.server_key);
-    let client_ca_cert = TonicCertificate::from_pem(settings.client_ca_cert);
-
     println!("Starting Rustica on: {}", settings.address);
     println!("{}", settings.server.signer);
     println!("{}", settings.server.authorizer.info());
 
+    let tls = tls_config(&settings);
+    let config_path = settings.config_path;
+    let address = settings.address;
+    // Health settings are only read on start
+    let check_interval = Duration::from_secs(settings.health.check_interval);
+    let shutdown_grace_period = Duration::from_secs(settings.health.shutdown_grace_period);
+
     let logging_configuration = settings.logging_configuration;
-    let log_receiver = settings.log_receiver;
+    let (logging_config_sender, logging_config_receiver) = crossbeam_channel::unbounded();
+
+    let logging_thread = thread::spawn(|| {
+        logging::start_logging_thread(
+            logging_configuration,
+            log_receiver,
+            logging_config_receiver,
+        );
+    });
+
+    // Register for signals before serving so one arriving during start up
+    // is not missed
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
 
-    thread::spawn(|| {
-        logging::start_logging_thread(logging_configuration, log_receiver);
+    #[cfg(feature = "prometheus")]
+    let metrics_task = settings.metrics_address.map(|metrics_address| {
+        println!("Serving metrics on: {}", metrics_address);
+        tokio::spawn(metrics::serve(
+            metrics_address,
+            settings.server.log_sender.clone(),
+        ))
     });
 
-    Server::builder()
-        .tls_config(
-            ServerTlsConfig::new()
-                .identity(identity)
-                .client_ca_root(client_ca_cert),
-        )?
-        .max_frame_size(1024 * 1024 * 4) // 4 MiB
-        .add_service(GRPCRusticaServer::new(settings.server))
-        .serve(settings.address)
-        .await?;
+    let mut generation = Generation::start(settings.server, tls, check_interval)?;
+    let listener = Listener::bind(address, &generation).await?;
+
+    loop {
+        tokio::select! {
+            result = &mut generation.task => return Ok(result??),
+            _ = sighup.recv() => (),
+            _ = sigterm.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+
+        println!("Reloading configuration from {config_path}");
+        match reload(&config_path, &generation, address, check_interval).await {
+            Ok((next, logging_configuration)) => {
+                listener.route_to(&next);
+                let previous = std::mem::replace(&mut generation, next);
+                tokio::spawn(previous.drain(Instant::now() + shutdown_grace_period));
+                let _ = logging_config_sender.send(logging_configuration);
+
+                let _ = generation.server.log_sender.send(Log::InternalMessage(InternalMessage {
+                    severity: Severity::Info,
+                    message: format!("Reloaded configuration from {config_path}"),
+                }));
+            }
+            Err(e) => {
+                let _ = generation.server.log_sender.send(Log::InternalMessage(InternalMessage {
+                    severity: Severity::Error,
+                    message: format!("Could not reload configuration, keeping the current one: {e}"),
+                }));
+            }
+        }
+    }
+
+    println!("Shutting down, draining in-flight requests");
+    let deadline = Instant::now() + shutdown_grace_period;
+
+    // Report that we are going away so no new requests are sent here, then
+    // stop accepting connections and wait for in-flight requests to finish.
+    generation.stop_serving().await;
+    listener.close();
+    generation.drain(deadline).await;
+
+    #[cfg(feature = "prometheus")]
+    if let Some(metrics_task) = metrics_task {
+        metrics_task.abort();
+        let _ = metrics_task.await;
+    }
+
+    // Everything holding a log sender has now been dropped so the logging
+    // thread will send what is left in its queue and exit
+    let flush = tokio::task::spawn_blocking(move || logging_thread.join());
+    if tokio::time::timeout_at(deadline, flush).await.is_err() {
+        error!("Logs were not flushed within the shutdown grace period");
+    }
 
     Ok(())
 }
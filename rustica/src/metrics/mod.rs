@@ -0,0 +1,35 @@
+/// The metrics module records operational metrics about Rustica and, when
+/// the `prometheus` feature is enabled, serves them on an HTTP `/metrics`
+/// endpoint in the Prometheus text format. When the feature is disabled
+/// every recording function is a no-op so call sites do not need to care
+/// whether metrics are compiled in.
+#[cfg(not(feature = "prometheus"))]
+mod noop;
+#[cfg(feature = "prometheus")]
+mod registry;
+
+#[cfg(not(feature = "prometheus"))]
+pub use noop::*;
+#[cfg(feature = "prometheus")]
+pub use registry::*;
+
+/// The result of a call to the authorization backend
+pub enum AuthorizerOutcome {
+    /// The request was authorized
+    Authorized,
+    /// The authorizer ran successfully but denied the request
+    Denied,
+    /// The authorizer could not make a decision
+    Error,
+}
+
+impl AuthorizerOutcome {
+    #[allow(dead_code)]
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Authorized => "authorized",
+            Self::Denied => "denied",
+            Self::Error => "error",
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use super::AuthorizerOutcome;
+use crate::error::RusticaServerError;
+
+use std::time::Duration;
+
+pub fn certificate_issued(_authority: &str, _cert_type: &str) {}
+
+pub fn server_error(_error: &RusticaServerError) {}
+
+pub fn authorizer_request(
+    _mechanism: &str,
+    _operation: &str,
+    _duration: Duration,
+    _outcome: AuthorizerOutcome,
+) {
+}
+
+pub fn signer_request(_authority: &str, _duration: Duration, _success: bool) {}
+
+pub fn allowed_signers_cache(_hit: bool) {}
+
+pub fn allowed_signers_rate_limited() {}
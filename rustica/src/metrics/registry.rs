@@ -0,0 +1,288 @@
+use super::AuthorizerOutcome;
+use crate::error::RusticaServerError;
+use crate::logging::Log;
+
+use crossbeam_channel::Sender;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, Request, Response, Server, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use serde::Deserialize;
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+pub struct MetricsConfiguration {
+    /// The address and port to serve `/metrics` on. This endpoint is not
+    /// authenticated so it should not be exposed publicly.
+    pub listen_address: String,
+}
+
+struct Metrics {
+    registry: Registry,
+    certificates_issued: IntCounterVec,
+    server_errors: IntCounterVec,
+    authorizer_requests: IntCounterVec,
+    authorizer_duration: HistogramVec,
+    signer_requests: IntCounterVec,
+    signer_duration: HistogramVec,
+    allowed_signers_cache: IntCounterVec,
+    allowed_signers_rate_limited: IntCounter,
+    logging_channel_depth: IntGauge,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+
+        let certificates_issued = IntCounterVec::new(
+            Opts::new(
+                "rustica_certificates_issued_total",
+                "Certificates issued by authority and certificate type",
+            ),
+            &["authority", "cert_type"],
+        )?;
+        let server_errors = IntCounterVec::new(
+            Opts::new(
+                "rustica_server_errors_total",
+                "Errors returned to clients by error code",
+            ),
+            &["error"],
+        )?;
+        let authorizer_requests = IntCounterVec::new(
+            Opts::new(
+                "rustica_authorizer_requests_total",
+                "Calls to the authorization backend by outcome",
+            ),
+            &["mechanism", "operation", "outcome"],
+        )?;
+        let authorizer_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "rustica_authorizer_duration_seconds",
+                "Time taken by the authorization backend",
+            ),
+            &["mechanism", "operation"],
+        )?;
+        let signer_requests = IntCounterVec::new(
+            Opts::new(
+                "rustica_signer_requests_total",
+                "Signing operations by authority and outcome",
+            ),
+            &["authority", "outcome"],
+        )?;
+        let signer_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "rustica_signer_duration_seconds",
+                "Time taken to sign certificates by authority",
+            ),
+            &["authority"],
+        )?;
+        let allowed_signers_cache = IntCounterVec::new(
+            Opts::new(
+                "rustica_allowed_signers_cache_total",
+                "Allowed signers requests served from the cache or the authorizer",
+            ),
+            &["result"],
+        )?;
+        let allowed_signers_rate_limited = IntCounter::new(
+            "rustica_allowed_signers_rate_limited_total",
+            "Allowed signers requests rejected by the rate limiter",
+        )?;
+        let logging_channel_depth = IntGauge::new(
+            "rustica_logging_channel_depth",
+            "Logs waiting to be processed by the logging thread",
+        )?;
+
+        registry.register(Box::new(certificates_issued.clone()))?;
+        registry.register(Box::new(server_errors.clone()))?;
+        registry.register(Box::new(authorizer_requests.clone()))?;
+        registry.register(Box::new(authorizer_duration.clone()))?;
+        registry.register(Box::new(signer_requests.clone()))?;
+        registry.register(Box::new(signer_duration.clone()))?;
+        registry.register(Box::new(allowed_signers_cache.clone()))?;
+        registry.register(Box::new(allowed_signers_rate_limited.clone()))?;
+        registry.register(Box::new(logging_channel_depth.clone()))?;
+
+        Ok(Self {
+            registry,
+            certificates_issued,
+            server_errors,
+            authorizer_requests,
+            authorizer_duration,
+            signer_requests,
+            signer_duration,
+            allowed_signers_cache,
+            allowed_signers_rate_limited,
+            logging_channel_depth,
+        })
+    }
+}
+
+fn metrics() -> &'static Metrics {
+    // Registration can only fail on duplicate or malformed metric names
+    // which are all static above
+    METRICS.get_or_init(|| Metrics::new().expect("Could not register metrics"))
+}
+
+pub fn certificate_issued(authority: &str, cert_type: &str) {
+    metrics()
+        .certificates_issued
+        .with_label_values(&[authority, cert_type])
+        .inc();
+}
+
+pub fn server_error(error: &RusticaServerError) {
+    metrics()
+        .server_errors
+        .with_label_values(&[&format!("{:?}", error)])
+        .inc();
+}
+
+pub fn authorizer_request(
+    mechanism: &str,
+    operation: &str,
+    duration: Duration,
+    outcome: AuthorizerOutcome,
+) {
+    let metrics = metrics();
+    metrics
+        .authorizer_requests
+        .with_label_values(&[mechanism, operation, outcome.as_str()])
+        .inc();
+    metrics
+        .authorizer_duration
+        .with_label_values(&[mechanism, operation])
+        .observe(duration.as_secs_f64());
+}
+
+pub fn signer_request(authority: &str, duration: Duration, success: bool) {
+    let metrics = metrics();
+    let outcome = if success { "success" } else { "failure" };
+    metrics
+        .signer_requests
+        .with_label_values(&[authority, outcome])
+        .inc();
+    metrics
+        .signer_duration
+        .with_label_values(&[authority])
+        .observe(duration.as_secs_f64());
+}
+
+pub fn allowed_signers_cache(hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    metrics()
+        .allowed_signers_cache
+        .with_label_values(&[result])
+        .inc();
+}
+
+pub fn allowed_signers_rate_limited() {
+    metrics().allowed_signers_rate_limited.inc();
+}
+
+/// Render all metrics in the Prometheus text format
+fn render(log_sender: &Sender<Log>) -> Response<Body> {
+    let metrics = metrics();
+    // The channel depth is sampled when scraped rather than tracked on
+    // every send
+    metrics
+        .logging_channel_depth
+        .set(log_sender.len() as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    if let Err(e) = encoder.encode(&metrics.registry.gather(), &mut buffer) {
+        error!("Could not encode metrics: {e}");
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(Body::empty())
+            .unwrap();
+    }
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, encoder.format_type())
+        .body(Body::from(buffer))
+        .unwrap()
+}
+
+/// Serve metrics over HTTP until the process exits
+pub async fn serve(address: SocketAddr, log_sender: Sender<Log>) {
+    let make_service = make_service_fn(move |_| {
+        let log_sender = log_sender.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let response = if request.uri().path() == "/metrics" {
+                    render(&log_sender)
+                } else {
+                    Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::empty())
+                        .unwrap()
+                };
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    if let Err(e) = Server::bind(&address).serve(make_service).await {
+        error!("Metrics server stopped: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logging::Heartbeat;
+
+    async fn rendered(log_sender: &Sender<Log>) -> String {
+        let body = hyper::body::to_bytes(render(log_sender).into_body())
+            .await
+            .unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    // Metrics are global so other tests could record under the same labels,
+    // and rendering sets the logging channel depth, so everything is checked
+    // in a single test with labels nothing else uses
+    #[tokio::test]
+    async fn recorded_metrics_are_rendered() {
+        let (log_sender, log_receiver) = crossbeam_channel::unbounded();
+        certificate_issued("metrics-test", "user");
+        certificate_issued("metrics-test", "user");
+        certificate_issued("metrics-test", "host");
+        signer_request("metrics-test", Duration::from_millis(5), false);
+        authorizer_request(
+            "metrics-test",
+            "authorize",
+            Duration::from_millis(5),
+            AuthorizerOutcome::Denied,
+        );
+        log_sender.send(Log::Heartbeat(Heartbeat {})).unwrap();
+        log_sender.send(Log::Heartbeat(Heartbeat {})).unwrap();
+
+        let rendered_metrics = rendered(&log_sender).await;
+        for line in [
+            "rustica_certificates_issued_total{authority=\"metrics-test\",cert_type=\"user\"} 2",
+            "rustica_certificates_issued_total{authority=\"metrics-test\",cert_type=\"host\"} 1",
+            "rustica_signer_requests_total{authority=\"metrics-test\",outcome=\"failure\"} 1",
+            "rustica_signer_duration_seconds_count{authority=\"metrics-test\"} 1",
+            "rustica_authorizer_requests_total{mechanism=\"metrics-test\",operation=\"authorize\",outcome=\"denied\"} 1",
+            "rustica_logging_channel_depth 2",
+        ] {
+            assert!(rendered_metrics.contains(line), "{line} was not rendered");
+        }
+
+        // The depth is sampled again on every render
+        log_receiver.recv().unwrap();
+        assert!(rendered(&log_sender)
+            .await
+            .contains("rustica_logging_channel_depth 1"));
+    }
+}
@@ -0,0 +1,156 @@
+/// Parsing JSON Web Key Sets and verifying JWT signatures with the keys they
+/// contain. Only the algorithms OIDC providers commonly sign ID tokens with
+/// are supported.
+use ring::signature::{self, RsaPublicKeyComponents, UnparsedPublicKey};
+use serde::Deserialize;
+
+/// A single key from a JWKS document as published by the issuer
+#[derive(Deserialize)]
+struct RawJwk {
+    kty: String,
+    kid: Option<String>,
+    #[serde(rename = "use")]
+    key_use: Option<String>,
+    // RSA
+    n: Option<String>,
+    e: Option<String>,
+    // EC
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawJwks {
+    keys: Vec<RawJwk>,
+}
+
+enum KeyMaterial {
+    Rsa { n: Vec<u8>, e: Vec<u8> },
+    /// An uncompressed SEC1 point and the curve it is on
+    Ec { point: Vec<u8>, curve: String },
+}
+
+/// A verification key from a JWKS
+pub struct Jwk {
+    pub kid: Option<String>,
+    material: KeyMaterial,
+}
+
+fn decode(value: &Option<String>, field: &str) -> Result<Vec<u8>, String> {
+    let value = value.as_ref().ok_or(format!("Key is missing {field}"))?;
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| format!("Key has an invalid {field}"))
+}
+
+impl Jwk {
+    fn from_raw(raw: RawJwk) -> Result<Self, String> {
+        let material = match raw.kty.as_str() {
+            "RSA" => KeyMaterial::Rsa {
+                n: decode(&raw.n, "n")?,
+                e: decode(&raw.e, "e")?,
+            },
+            "EC" => {
+                let curve = raw.crv.clone().ok_or("Key is missing crv")?;
+                let mut point = vec![0x04];
+                point.extend(decode(&raw.x, "x")?);
+                point.extend(decode(&raw.y, "y")?);
+                KeyMaterial::Ec { point, curve }
+            }
+            kty => return Err(format!("Unsupported key type {kty}")),
+        };
+
+        Ok(Self {
+            kid: raw.kid,
+            material,
+        })
+    }
+
+    /// Verify a JWT signature made with the given algorithm. Returns false if
+    /// this key cannot be used with the algorithm.
+    pub fn verify(&self, alg: &str, message: &[u8], sig: &[u8]) -> bool {
+        match (&self.material, alg) {
+            (KeyMaterial::Rsa { n, e }, _) => {
+                let params = match alg {
+                    "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+                    "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+                    "RS512" => &signature::RSA_PKCS1_2048_8192_SHA512,
+                    _ => return false,
+                };
+                RsaPublicKeyComponents { n, e }
+                    .verify(params, message, sig)
+                    .is_ok()
+            }
+            (KeyMaterial::Ec { point, curve }, "ES256") if curve == "P-256" => {
+                UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                    .verify(message, sig)
+                    .is_ok()
+            }
+            (KeyMaterial::Ec { point, curve }, "ES384") if curve == "P-384" => {
+                UnparsedPublicKey::new(&signature::ECDSA_P384_SHA384_FIXED, point)
+                    .verify(message, sig)
+                    .is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parse a JWKS document. Keys that are not for signing or that use an
+/// unsupported type are skipped so an issuer adding a new kind of key does
+/// not break verification with the ones we understand.
+pub fn parse(document: &[u8]) -> Result<Vec<Jwk>, String> {
+    let raw: RawJwks =
+        serde_json::from_slice(document).map_err(|e| format!("Invalid JWKS: {e}"))?;
+
+    let keys: Vec<Jwk> = raw
+        .keys
+        .into_iter()
+        .filter(|key| key.key_use.as_deref().unwrap_or("sig") == "sig")
+        .filter_map(|key| match Jwk::from_raw(key) {
+            Ok(key) => Some(key),
+            Err(e) => {
+                warn!("Skipping JWKS key: {e}");
+                None
+            }
+        })
+        .collect();
+
+    if keys.is_empty() {
+        return Err("JWKS does not contain any usable signing keys".to_owned());
+    }
+
+    Ok(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unusable_keys_are_skipped() {
+        let document = br#"{"keys": [
+            {"kty": "OKP", "kid": "ed25519", "crv": "Ed25519", "x": "AAAA"},
+            {"kty": "RSA", "kid": "encryption", "use": "enc", "n": "AQAB", "e": "AQAB"},
+            {"kty": "RSA", "kid": "signing", "n": "AQAB", "e": "AQAB"}
+        ]}"#;
+
+        let keys = parse(document).unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].kid.as_deref(), Some("signing"));
+    }
+
+    #[test]
+    fn a_jwks_without_signing_keys_is_rejected() {
+        let document = br#"{"keys": [{"kty": "EC", "kid": "no-curve", "x": "AA", "y": "AA"}]}"#;
+        assert!(parse(document).is_err());
+    }
+
+    #[test]
+    fn keys_only_verify_asymmetric_algorithms() {
+        let keys = parse(br#"{"keys": [{"kty": "RSA", "n": "AQAB", "e": "AQAB"}]}"#).unwrap();
+        for alg in ["none", "HS256", "ES256", ""] {
+            assert!(!keys[0].verify(alg, b"message", b""));
+        }
+    }
+}
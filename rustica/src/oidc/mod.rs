@@ -0,0 +1,588 @@
+/// The OIDC module lets clients that cannot hold a long lived mTLS
+/// certificate, such as CI jobs, identify themselves with an ID token from a
+/// trusted issuer instead. Tokens are sent as a bearer token in the
+/// `authorization` metadata of each request. After the token is verified,
+/// the configured claims become the identities passed to the authorizer in
+/// place of the mTLS identities. Each claim is also passed as a named
+/// identity.
+mod jwks;
+
+use jwks::Jwk;
+use serde::Deserialize;
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::RwLock;
+
+/// The largest token that will be parsed
+const MAX_TOKEN_LENGTH: usize = 16384;
+
+/// When a token is signed by a key we don't know, the JWKS is fetched again
+/// in case the issuer rotated keys. This limits how often that can happen.
+const MINIMUM_JWKS_REFETCH_INTERVAL: u64 = 60;
+
+/// Where the issuer's signing keys are loaded from
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JwksSource {
+    /// The JWKS is read from the file at this path when the configuration
+    /// is loaded
+    File(String),
+    /// The JWKS is fetched from this URL and refreshed periodically
+    Url(String),
+}
+
+#[derive(Deserialize)]
+pub struct OidcConfiguration {
+    /// The iss claim tokens must have
+    pub issuer: String,
+    /// A value the aud claim of tokens must contain
+    pub audience: String,
+    pub jwks: JwksSource,
+    /// How many seconds a fetched JWKS is used before fetching it again
+    #[serde(default = "default_jwks_refresh_interval")]
+    pub jwks_refresh_interval: u64,
+    /// The claims that become identities. Each one is passed to the
+    /// authorizer as `claim:value`.
+    pub identity_claims: Vec<String>,
+    /// Claims that must be present with exactly these values
+    #[serde(default)]
+    pub required_claims: HashMap<String, String>,
+    /// How many seconds of clock difference with the issuer to allow when
+    /// checking exp and nbf
+    #[serde(default = "default_leeway")]
+    pub leeway: u64,
+}
+
+fn default_jwks_refresh_interval() -> u64 {
+    3600
+}
+
+fn default_leeway() -> u64 {
+    30
+}
+
+#[derive(Debug)]
+pub enum OidcError {
+    /// The signing keys could not be loaded
+    KeyError(String),
+    /// The token was malformed, not signed by the issuer, or did not meet
+    /// the configured requirements
+    InvalidToken(String),
+}
+
+impl std::fmt::Display for OidcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::KeyError(e) => write!(f, "Could not load OIDC signing keys: {e}"),
+            Self::InvalidToken(e) => write!(f, "Invalid OIDC token: {e}"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    kid: Option<String>,
+}
+
+/// The issuer's keys and when they were loaded
+struct KeySet {
+    keys: Vec<Jwk>,
+    fetched_at: u64,
+}
+
+impl KeySet {
+    fn contains(&self, kid: &Option<String>) -> bool {
+        kid.is_none() || self.keys.iter().any(|key| &key.kid == kid)
+    }
+}
+
+/// Verifies ID tokens against the configured issuer
+pub struct OidcVerifier {
+    config: OidcConfiguration,
+    client: reqwest::Client,
+    keys: RwLock<KeySet>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0)
+}
+
+fn decode_segment(segment: &str) -> Result<Vec<u8>, OidcError> {
+    base64::decode_config(segment, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| OidcError::InvalidToken("Token is not valid base64".to_owned()))
+}
+
+/// Claims are compared and turned into identities as strings. Only scalar
+/// values can be used.
+fn claim_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+impl OidcVerifier {
+    /// Load the issuer's signing keys. This fails if they cannot be loaded
+    /// so a bad configuration is caught on start.
+    pub async fn new(config: OidcConfiguration) -> Result<Self, OidcError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| OidcError::KeyError(e.to_string()))?;
+
+        if config.identity_claims.is_empty() {
+            return Err(OidcError::KeyError(
+                "At least one identity claim must be configured".to_owned(),
+            ));
+        }
+
+        let mut verifier = Self {
+            config,
+            client,
+            keys: RwLock::new(KeySet {
+                keys: vec![],
+                fetched_at: 0,
+            }),
+        };
+
+        let keys = verifier.load_keys().await?;
+        verifier.keys = RwLock::new(KeySet {
+            keys,
+            fetched_at: now(),
+        });
+
+        Ok(verifier)
+    }
+
+    async fn load_keys(&self) -> Result<Vec<Jwk>, OidcError> {
+        let document = match &self.config.jwks {
+            JwksSource::File(path) => tokio::fs::read(path)
+                .await
+                .map_err(|e| OidcError::KeyError(format!("Could not read {path}: {e}")))?,
+            JwksSource::Url(url) => self
+                .client
+                .get(url)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map_err(|e| OidcError::KeyError(e.to_string()))?
+                .bytes()
+                .await
+                .map_err(|e| OidcError::KeyError(e.to_string()))?
+                .to_vec(),
+        };
+
+        jwks::parse(&document).map_err(OidcError::KeyError)
+    }
+
+    /// Fetch the JWKS again if it was loaded from a URL and is either due
+    /// for a refresh or does not contain the key a token was signed with.
+    /// If fetching fails the keys we already have continue to be used.
+    async fn refresh_keys(&self, kid: &Option<String>) {
+        if matches!(self.config.jwks, JwksSource::File(_)) {
+            return;
+        }
+
+        let needs_refresh = |keys: &KeySet, time: u64| {
+            let age = time.saturating_sub(keys.fetched_at);
+            age >= self.config.jwks_refresh_interval
+                || (!keys.contains(kid) && age >= MINIMUM_JWKS_REFETCH_INTERVAL)
+        };
+
+        if !needs_refresh(&*self.keys.read().await, now()) {
+            return;
+        }
+
+        // Check again once we hold the write lock in case another request
+        // already refreshed the keys
+        let mut keys = self.keys.write().await;
+        let time = now();
+        if !needs_refresh(&keys, time) {
+            return;
+        }
+
+        match self.load_keys().await {
+            Ok(new_keys) => keys.keys = new_keys,
+            Err(e) => warn!("Could not refresh OIDC signing keys: {e}"),
+        }
+        keys.fetched_at = time;
+    }
+
+    /// Verify an ID token and return the configured identity claims that it
+    /// contains along with their values
+    pub async fn verify(&self, token: &str) -> Result<Vec<(String, String)>, OidcError> {
+        if token.len() > MAX_TOKEN_LENGTH {
+            return Err(OidcError::InvalidToken("Token is too large".to_owned()));
+        }
+
+        // The signature covers the encoded header and payload
+        let (signed, sig) = token
+            .rsplit_once('.')
+            .ok_or(OidcError::InvalidToken("Token is not a JWT".to_owned()))?;
+        let (header, payload) = match signed.split_once('.') {
+            Some((header, payload)) if !payload.contains('.') => (header, payload),
+            _ => return Err(OidcError::InvalidToken("Token is not a JWT".to_owned())),
+        };
+        let sig = decode_segment(sig)?;
+
+        let header: Header = serde_json::from_slice(&decode_segment(header)?)
+            .map_err(|_| OidcError::InvalidToken("Token has an invalid header".to_owned()))?;
+
+        self.refresh_keys(&header.kid).await;
+
+        let verified = self
+            .keys
+            .read()
+            .await
+            .keys
+            .iter()
+            .filter(|key| header.kid.is_none() || key.kid == header.kid)
+            .any(|key| key.verify(&header.alg, signed.as_bytes(), &sig));
+
+        if !verified {
+            return Err(OidcError::InvalidToken(format!(
+                "Token is not signed by a known key (kid: {}, alg: {})",
+                header.kid.as_deref().unwrap_or("none"),
+                header.alg
+            )));
+        }
+
+        let claims: HashMap<String, Value> = serde_json::from_slice(&decode_segment(payload)?)
+            .map_err(|_| OidcError::InvalidToken("Token has invalid claims".to_owned()))?;
+
+        self.check_claims(&claims)?;
+
+        let identities: Vec<(String, String)> = self
+            .config
+            .identity_claims
+            .iter()
+            .filter_map(|claim| {
+                claims
+                    .get(claim)
+                    .and_then(claim_string)
+                    .map(|value| (claim.clone(), value))
+            })
+            .collect();
+
+        if identities.is_empty() {
+            return Err(OidcError::InvalidToken(
+                "Token does not contain any identity claims".to_owned(),
+            ));
+        }
+
+        Ok(identities)
+    }
+
+    /// Check the issuer, audience, validity period, and required claims
+    fn check_claims(&self, claims: &HashMap<String, Value>) -> Result<(), OidcError> {
+        let invalid = |e: &str| Err(OidcError::InvalidToken(e.to_owned()));
+
+        if claims.get("iss").and_then(Value::as_str) != Some(self.config.issuer.as_str()) {
+            return invalid("Token was not issued by the configured issuer");
+        }
+
+        let audience_matches = match claims.get("aud") {
+            Some(Value::String(aud)) => aud == &self.config.audience,
+            Some(Value::Array(auds)) => auds
+                .iter()
+                .any(|aud| aud.as_str() == Some(self.config.audience.as_str())),
+            _ => false,
+        };
+        if !audience_matches {
+            return invalid("Token is not for the configured audience");
+        }
+
+        let time = now();
+        match claims.get("exp").and_then(Value::as_u64) {
+            Some(exp) if time <= exp.saturating_add(self.config.leeway) => (),
+            Some(_) => return invalid("Token has expired"),
+            None => return invalid("Token does not have an expiry"),
+        }
+
+        if let Some(nbf) = claims.get("nbf").and_then(Value::as_u64) {
+            if time.saturating_add(self.config.leeway) < nbf {
+                return invalid("Token is not yet valid");
+            }
+        }
+
+        for (claim, expected) in &self.config.required_claims {
+            if claims.get(claim).and_then(claim_string).as_ref() != Some(expected) {
+                return Err(OidcError::InvalidToken(format!(
+                    "Token does not have the required value for {claim}"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::hmac;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use serde_json::json;
+
+    const ISSUER: &str = "https://token.example.com";
+    const AUDIENCE: &str = "rustica";
+    const KID: &str = "test-key";
+
+    fn encode(value: &[u8]) -> String {
+        base64::encode_config(value, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn generate_key() -> EcdsaKeyPair {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap()
+    }
+
+    /// A verifier trusting `key`, with its JWKS written to a file in
+    /// `directory`
+    async fn verifier(
+        key: &EcdsaKeyPair,
+        directory: &tempfile::TempDir,
+        required_claims: HashMap<String, String>,
+    ) -> OidcVerifier {
+        // The public key is an uncompressed point: 0x04 || x || y
+        let point = key.public_key().as_ref();
+        let jwks = json!({
+            "keys": [
+                {"kty": "EC", "use": "enc", "kid": "encryption", "crv": "P-256", "x": encode(&point[1..33]), "y": encode(&point[33..])},
+                {"kty": "EC", "use": "sig", "kid": KID, "crv": "P-256", "x": encode(&point[1..33]), "y": encode(&point[33..])},
+            ]
+        });
+        let path = directory.path().join("jwks.json");
+        std::fs::write(&path, jwks.to_string()).unwrap();
+
+        OidcVerifier::new(OidcConfiguration {
+            issuer: ISSUER.to_owned(),
+            audience: AUDIENCE.to_owned(),
+            jwks: JwksSource::File(path.to_string_lossy().into_owned()),
+            jwks_refresh_interval: default_jwks_refresh_interval(),
+            identity_claims: vec!["sub".to_owned(), "repository_id".to_owned()],
+            required_claims,
+            leeway: 30,
+        })
+        .await
+        .unwrap()
+    }
+
+    fn sign(key: &EcdsaKeyPair, header: Value, claims: Value) -> String {
+        let signed = format!(
+            "{}.{}",
+            encode(header.to_string().as_bytes()),
+            encode(claims.to_string().as_bytes())
+        );
+        let sig = key.sign(&SystemRandom::new(), signed.as_bytes()).unwrap();
+        format!("{signed}.{}", encode(sig.as_ref()))
+    }
+
+    fn claims() -> Value {
+        json!({
+            "iss": ISSUER,
+            "aud": AUDIENCE,
+            "exp": now() + 300,
+            "iat": now(),
+            "sub": "repo:example/service:ref:refs/heads/main",
+            "repository_id": 1234,
+            "environment": "production",
+        })
+    }
+
+    fn with(claim: &str, value: Value) -> Value {
+        let mut claims = claims();
+        claims[claim] = value;
+        claims
+    }
+
+    fn header() -> Value {
+        json!({"alg": "ES256", "kid": KID, "typ": "JWT"})
+    }
+
+    async fn rejects(verifier: &OidcVerifier, token: &str) {
+        assert!(matches!(
+            verifier.verify(token).await,
+            Err(OidcError::InvalidToken(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn identities_come_from_the_configured_claims() {
+        let key = generate_key();
+        let directory = tempfile::tempdir().unwrap();
+        let verifier = verifier(&key, &directory, HashMap::new()).await;
+
+        let identities = verifier
+            .verify(&sign(&key, header(), claims()))
+            .await
+            .unwrap();
+        assert_eq!(
+            identities,
+            vec![
+                (
+                    "sub".to_owned(),
+                    "repo:example/service:ref:refs/heads/main".to_owned()
+                ),
+                ("repository_id".to_owned(), "1234".to_owned()),
+            ]
+        );
+
+        // Configured claims that are missing are left out, but at least one
+        // must be present
+        let mut partial = claims();
+        partial.as_object_mut().unwrap().remove("repository_id");
+        let identities = verifier
+            .verify(&sign(&key, header(), partial))
+            .await
+            .unwrap();
+        assert_eq!(identities.len(), 1);
+
+        let mut anonymous = claims();
+        anonymous.as_object_mut().unwrap().remove("sub");
+        anonymous.as_object_mut().unwrap().remove("repository_id");
+        rejects(&verifier, &sign(&key, header(), anonymous)).await;
+    }
+
+    #[tokio::test]
+    async fn issuer_and_audience_must_match() {
+        let key = generate_key();
+        let directory = tempfile::tempdir().unwrap();
+        let verifier = verifier(&key, &directory, HashMap::new()).await;
+
+        rejects(
+            &verifier,
+            &sign(
+                &key,
+                header(),
+                with("iss", json!("https://other.example.com")),
+            ),
+        )
+        .await;
+        rejects(
+            &verifier,
+            &sign(&key, header(), with("aud", json!("other"))),
+        )
+        .await;
+        rejects(
+            &verifier,
+            &sign(&key, header(), with("aud", json!(["other", "another"]))),
+        )
+        .await;
+
+        let token = sign(&key, header(), with("aud", json!(["other", AUDIENCE])));
+        assert!(verifier.verify(&token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn expiry_is_checked_with_leeway() {
+        let key = generate_key();
+        let directory = tempfile::tempdir().unwrap();
+        let verifier = verifier(&key, &directory, HashMap::new()).await;
+
+        rejects(
+            &verifier,
+            &sign(&key, header(), with("exp", json!(now() - 60))),
+        )
+        .await;
+
+        let token = sign(&key, header(), with("exp", json!(now() - 10)));
+        assert!(verifier.verify(&token).await.is_ok());
+
+        let mut no_expiry = claims();
+        no_expiry.as_object_mut().unwrap().remove("exp");
+        rejects(&verifier, &sign(&key, header(), no_expiry)).await;
+    }
+
+    #[tokio::test]
+    async fn not_before_is_checked_with_leeway() {
+        let key = generate_key();
+        let directory = tempfile::tempdir().unwrap();
+        let verifier = verifier(&key, &directory, HashMap::new()).await;
+
+        rejects(
+            &verifier,
+            &sign(&key, header(), with("nbf", json!(now() + 60))),
+        )
+        .await;
+
+        let token = sign(&key, header(), with("nbf", json!(now() + 10)));
+        assert!(verifier.verify(&token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn unsigned_and_symmetric_tokens_are_rejected() {
+        let key = generate_key();
+        let directory = tempfile::tempdir().unwrap();
+        let verifier = verifier(&key, &directory, HashMap::new()).await;
+
+        let payload = encode(claims().to_string().as_bytes());
+
+        let none_header = encode(json!({"alg": "none", "kid": KID}).to_string().as_bytes());
+        rejects(&verifier, &format!("{none_header}.{payload}.")).await;
+
+        // An HS256 token keyed with the public key must not be accepted as
+        // if the public key were a shared secret
+        let hs256_header = encode(json!({"alg": "HS256", "kid": KID}).to_string().as_bytes());
+        let signed = format!("{hs256_header}.{payload}");
+        let secret = hmac::Key::new(hmac::HMAC_SHA256, key.public_key().as_ref());
+        let tag = hmac::sign(&secret, signed.as_bytes());
+        rejects(&verifier, &format!("{signed}.{}", encode(tag.as_ref()))).await;
+
+        // An ES256 signature presented as another algorithm is not accepted
+        let token = sign(&key, json!({"alg": "ES384", "kid": KID}), claims());
+        rejects(&verifier, &token).await;
+    }
+
+    #[tokio::test]
+    async fn unknown_keys_are_rejected() {
+        let key = generate_key();
+        let directory = tempfile::tempdir().unwrap();
+        let verifier = verifier(&key, &directory, HashMap::new()).await;
+
+        let token = sign(&key, json!({"alg": "ES256", "kid": "unknown"}), claims());
+        rejects(&verifier, &token).await;
+
+        // Keys that are not for signing are never used to verify
+        let token = sign(&key, json!({"alg": "ES256", "kid": "encryption"}), claims());
+        rejects(&verifier, &token).await;
+
+        // A token signed by a different key with the trusted kid
+        let token = sign(&generate_key(), header(), claims());
+        rejects(&verifier, &token).await;
+    }
+
+    #[tokio::test]
+    async fn required_claims_must_match() {
+        let key = generate_key();
+        let directory = tempfile::tempdir().unwrap();
+        let required = HashMap::from([("environment".to_owned(), "production".to_owned())]);
+        let verifier = verifier(&key, &directory, required).await;
+
+        assert!(verifier
+            .verify(&sign(&key, header(), claims()))
+            .await
+            .is_ok());
+        rejects(
+            &verifier,
+            &sign(&key, header(), with("environment", json!("staging"))),
+        )
+        .await;
+
+        let mut missing = claims();
+        missing.as_object_mut().unwrap().remove("environment");
+        rejects(&verifier, &sign(&key, header(), missing)).await;
+    }
+}
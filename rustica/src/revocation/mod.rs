@@ -0,0 +1,455 @@
+/// The revocation module records certificates and keys that should no
+/// longer be trusted and turns them into OpenSSH KRLs that hosts can load
+/// with the `RevokedKeys` sshd option. Revocations are tracked per authority
+/// and persisted to a TOML file so they survive restarts.
+///
+/// mTLS access certificates can also be revoked. These are refused by every
+/// Rustica call and published as a CRL signed by the client certificate
+/// authority so other services that trust it can refuse them too.
+mod krl;
+
+use serde::{Deserialize, Serialize};
+use sshcerts::ssh::PublicKey;
+
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+
+use tokio::sync::RwLock;
+
+#[derive(Deserialize)]
+pub struct RevocationConfiguration {
+    /// The path revocations are stored in. If it does not exist it will be
+    /// created the first time something is revoked.
+    pub path: String,
+    /// Access certificates revoked in the configuration rather than through
+    /// the RevokeAccessCredentials call
+    #[serde(default)]
+    pub access: AccessRevocations,
+    /// How many seconds the access certificate CRL is valid for
+    #[serde(default = "default_crl_validity")]
+    pub crl_validity: u64,
+}
+
+fn default_crl_validity() -> u64 {
+    86400
+}
+
+#[derive(Debug)]
+pub enum RevocationError {
+    /// The revocation store could not be read or written
+    StoreError(String),
+    /// The revocation store exists but could not be parsed
+    ParsingError(String),
+    /// A provided fingerprint was not a SHA256 fingerprint
+    InvalidFingerprint(String),
+    /// OpenSSH cannot revoke a certificate with a serial of zero
+    InvalidSerial,
+    /// A provided access certificate serial was not hex
+    InvalidAccessSerial(String),
+    /// A provided access certificate fingerprint was not a hex SHA256 hash
+    InvalidAccessFingerprint(String),
+}
+
+impl std::fmt::Display for RevocationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::StoreError(e) => write!(f, "Could not access the revocation store: {e}"),
+            Self::ParsingError(e) => write!(f, "Could not parse the revocation store: {e}"),
+            Self::InvalidFingerprint(fp) => write!(f, "{fp} is not a valid SHA256 fingerprint"),
+            Self::InvalidSerial => write!(f, "Certificates with a serial of 0 cannot be revoked"),
+            Self::InvalidAccessSerial(serial) => write!(f, "{serial} is not a valid hex serial"),
+            Self::InvalidAccessFingerprint(fp) => {
+                write!(f, "{fp} is not a valid hex SHA256 fingerprint")
+            }
+        }
+    }
+}
+
+/// The parts of a reloaded configuration that are applied to a store that
+/// is already running, validated ahead of time so applying them cannot fail
+pub struct RevocationSettings {
+    access: AccessRevocations,
+    crl_validity: u64,
+}
+
+impl RevocationConfiguration {
+    /// Validate the settings a reload would apply to an existing store
+    pub fn settings(&self) -> Result<RevocationSettings, RevocationError> {
+        Ok(RevocationSettings {
+            access: self.access.normalized()?,
+            crl_validity: self.crl_validity,
+        })
+    }
+}
+
+/// Everything revoked for a single authority
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct AuthorityRevocations {
+    /// Serials of revoked certificates. These are stored as strings because
+    /// TOML integers cannot represent the full range of a u64.
+    #[serde(default, with = "serial_strings")]
+    pub serials: BTreeSet<u64>,
+    /// SHA256 fingerprints of revoked keys
+    #[serde(default)]
+    pub fingerprints: BTreeSet<String>,
+    /// Key IDs of revoked certificates
+    #[serde(default)]
+    pub key_ids: BTreeSet<String>,
+}
+
+/// Revoked mTLS access certificates. A certificate is refused if its
+/// serial or fingerprint is listed or if any of its identities are.
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct AccessRevocations {
+    /// Hex encoded serials. Colons and leading zeros are ignored.
+    #[serde(default)]
+    pub serials: BTreeSet<String>,
+    /// Hex encoded SHA256 fingerprints of the DER encoded certificates.
+    /// Colons are ignored.
+    #[serde(default)]
+    pub fingerprints: BTreeSet<String>,
+    /// mTLS identities that are no longer allowed to use Rustica
+    #[serde(default)]
+    pub identities: BTreeSet<String>,
+}
+
+/// The on disk representation of all revocations
+#[derive(Clone, Default, Deserialize, Serialize)]
+struct RevocationList {
+    /// Incremented every time the list changes. This is used as the KRL
+    /// version so hosts can tell when they have a newer list, and as the CRL
+    /// number of the access certificate CRL.
+    #[serde(default)]
+    version: u64,
+    #[serde(default)]
+    authorities: HashMap<String, AuthorityRevocations>,
+    #[serde(default)]
+    access: AccessRevocations,
+}
+
+/// Holds the current revocations in memory and writes them back to disk
+/// every time they change. A single store is shared by every generation of
+/// the server that uses the same path so revocations made while a previous
+/// configuration drains are never overwritten.
+pub struct RevocationStore {
+    path: String,
+    revocations: RwLock<RevocationList>,
+    /// Access revocations from the configuration. These are never written
+    /// to the store.
+    configured_access: RwLock<AccessRevocations>,
+    crl_validity: AtomicU64,
+}
+
+/// SSH key fingerprints are stored and compared as unpadded base64 without
+/// the SHA256: prefix, the same form the KRL encoder reads
+fn normalize_key_fingerprint(fingerprint: &str) -> &str {
+    fingerprint
+        .strip_prefix("SHA256:")
+        .unwrap_or(fingerprint)
+        .trim_end_matches('=')
+}
+
+/// Serials are compared as lowercase hex without separators or leading
+/// zeros so the forms printed by different tools all match
+pub fn normalize_access_serial(serial: &str) -> Result<String, RevocationError> {
+    let normalized = serial.replace(':', "").to_lowercase();
+    if normalized.is_empty() || !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(RevocationError::InvalidAccessSerial(serial.to_owned()));
+    }
+
+    match normalized.trim_start_matches('0') {
+        "" => Ok("0".to_owned()),
+        trimmed => Ok(trimmed.to_owned()),
+    }
+}
+
+/// Fingerprints are compared as lowercase hex without separators
+pub fn normalize_access_fingerprint(fingerprint: &str) -> Result<String, RevocationError> {
+    let normalized = fingerprint.replace(':', "").to_lowercase();
+    if normalized.len() != 64 || !normalized.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(RevocationError::InvalidAccessFingerprint(
+            fingerprint.to_owned(),
+        ));
+    }
+
+    Ok(normalized)
+}
+
+impl AccessRevocations {
+    /// Normalize every serial and fingerprint, failing if any are invalid
+    fn normalized(&self) -> Result<Self, RevocationError> {
+        Ok(Self {
+            serials: self
+                .serials
+                .iter()
+                .map(|x| normalize_access_serial(x))
+                .collect::<Result<_, _>>()?,
+            fingerprints: self
+                .fingerprints
+                .iter()
+                .map(|x| normalize_access_fingerprint(x))
+                .collect::<Result<_, _>>()?,
+            identities: self.identities.clone(),
+        })
+    }
+
+    fn contains(
+        &self,
+        serial: Option<&str>,
+        fingerprint: Option<&str>,
+        identities: &[String],
+    ) -> bool {
+        serial.is_some_and(|x| self.serials.contains(x))
+            || fingerprint.is_some_and(|x| self.fingerprints.contains(x))
+            || identities.iter().any(|x| self.identities.contains(x))
+    }
+}
+
+mod serial_strings {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::BTreeSet;
+
+    pub fn serialize<S: Serializer>(serials: &BTreeSet<u64>, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(serials.iter().map(|x| x.to_string()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<BTreeSet<u64>, D::Error> {
+        Vec::<String>::deserialize(d)?
+            .iter()
+            .map(|x| x.parse::<u64>().map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+impl RevocationStore {
+    /// Load the revocation store from the configured path. A missing file is
+    /// treated as an empty store.
+    pub async fn new(config: RevocationConfiguration) -> Result<Self, RevocationError> {
+        let revocations = match tokio::fs::read(&config.path).await {
+            Ok(contents) => toml::from_slice(&contents)
+                .map_err(|e| RevocationError::ParsingError(e.to_string()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RevocationList::default(),
+            Err(e) => return Err(RevocationError::StoreError(e.to_string())),
+        };
+
+        Ok(Self {
+            path: config.path,
+            revocations: RwLock::new(revocations),
+            configured_access: RwLock::new(config.access.normalized()?),
+            crl_validity: AtomicU64::new(config.crl_validity),
+        })
+    }
+
+    /// The path revocations are stored in
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// How many seconds the access certificate CRL is valid for
+    pub fn crl_validity(&self) -> u64 {
+        self.crl_validity.load(Ordering::Relaxed)
+    }
+
+    /// Apply a reloaded configuration with the same path. Revocations already
+    /// in the store are kept.
+    pub async fn configure(&self, settings: RevocationSettings) {
+        *self.configured_access.write().await = settings.access;
+        self.crl_validity.store(settings.crl_validity, Ordering::Relaxed);
+    }
+
+    /// Write the revocation list to disk. Changes are made to a copy of the
+    /// list and only replace the one in memory once this succeeds, so what
+    /// is enforced and served never gets ahead of what is stored.
+    async fn persist(&self, revocations: &RevocationList) -> Result<(), RevocationError> {
+        let serialized = toml::to_string(revocations)
+            .map_err(|e| RevocationError::StoreError(e.to_string()))?;
+
+        // Write to a temporary file first so a crash can never leave us with a
+        // truncated revocation list
+        let temporary_path = format!("{}.tmp", self.path);
+        tokio::fs::write(&temporary_path, serialized)
+            .await
+            .map_err(|e| RevocationError::StoreError(e.to_string()))?;
+        tokio::fs::rename(&temporary_path, &self.path)
+            .await
+            .map_err(|e| RevocationError::StoreError(e.to_string()))
+    }
+
+    /// Record new revocations for an authority and persist them. Returns the
+    /// new version of the revocation list.
+    pub async fn revoke(
+        &self,
+        authority: &str,
+        serials: &[u64],
+        fingerprints: &[String],
+        key_ids: &[String],
+    ) -> Result<u64, RevocationError> {
+        if serials.contains(&0) {
+            return Err(RevocationError::InvalidSerial);
+        }
+
+        for fingerprint in fingerprints {
+            krl::decode_sha256_fingerprint(fingerprint)?;
+        }
+
+        let mut revocations = self.revocations.write().await;
+        let mut updated = revocations.clone();
+        let authority_revocations = updated
+            .authorities
+            .entry(authority.to_owned())
+            .or_default();
+
+        authority_revocations.serials.extend(serials);
+        authority_revocations.fingerprints.extend(
+            fingerprints
+                .iter()
+                .map(|fp| normalize_key_fingerprint(fp).to_owned()),
+        );
+        authority_revocations
+            .key_ids
+            .extend(key_ids.iter().cloned());
+        updated.version += 1;
+        self.persist(&updated).await?;
+
+        *revocations = updated;
+        Ok(revocations.version)
+    }
+
+    /// Record revoked access certificates and persist them. Returns the new
+    /// version of the revocation list.
+    pub async fn revoke_access(&self, access: &AccessRevocations) -> Result<u64, RevocationError> {
+        let access = access.normalized()?;
+
+        let mut revocations = self.revocations.write().await;
+        let mut updated = revocations.clone();
+        updated.access.serials.extend(access.serials);
+        updated.access.fingerprints.extend(access.fingerprints);
+        updated.access.identities.extend(access.identities);
+        updated.version += 1;
+        self.persist(&updated).await?;
+
+        *revocations = updated;
+        Ok(revocations.version)
+    }
+
+    /// Check if an access certificate has been revoked, either in the store
+    /// or in the configuration. Clients that did not identify with a
+    /// certificate only have identities to check.
+    pub async fn is_access_revoked(
+        &self,
+        serial: Option<&str>,
+        fingerprint: Option<&str>,
+        identities: &[String],
+    ) -> bool {
+        self.configured_access
+            .read()
+            .await
+            .contains(serial, fingerprint, identities)
+            || self
+                .revocations
+                .read()
+                .await
+                .access
+                .contains(serial, fingerprint, identities)
+    }
+
+    /// Every revoked access certificate serial along with the current
+    /// version of the revocation list
+    pub async fn revoked_access_serials(&self) -> (BTreeSet<String>, u64) {
+        let revocations = self.revocations.read().await;
+        let configured_access = self.configured_access.read().await;
+        let serials = revocations
+            .access
+            .serials
+            .union(&configured_access.serials)
+            .cloned()
+            .collect();
+
+        (serials, revocations.version)
+    }
+
+    /// Check if a key has been revoked for the given authority
+    pub async fn is_key_revoked(&self, authority: &str, fingerprint: &str) -> bool {
+        self.revocations
+            .read()
+            .await
+            .authorities
+            .get(authority)
+            .map(|x| x.fingerprints.contains(normalize_key_fingerprint(fingerprint)))
+            .unwrap_or(false)
+    }
+
+    /// Generate a KRL for all revocations recorded against an authority. The
+    /// CA keys should be every SSH public key the authority signs with.
+    pub async fn generate_krl(
+        &self,
+        authority: &str,
+        ca_keys: &[PublicKey],
+    ) -> Result<(Vec<u8>, u64), RevocationError> {
+        let revocations = self.revocations.read().await;
+        let empty = AuthorityRevocations::default();
+        let authority_revocations = revocations.authorities.get(authority).unwrap_or(&empty);
+
+        let generated_date = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|x| x.as_secs())
+            .unwrap_or_default();
+
+        let krl = krl::encode_krl(
+            revocations.version,
+            generated_date,
+            &format!("Rustica KRL for {authority}"),
+            ca_keys,
+            authority_revocations,
+        )?;
+
+        Ok((krl, revocations.version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "d/Ofi1fZuphAvf7DXj85VnSbz0CSI6RFTfqLb1SBSYA";
+
+    async fn store(directory: &tempfile::TempDir) -> RevocationStore {
+        RevocationStore::new(RevocationConfiguration {
+            path: directory.path().join("revocations.toml").to_string_lossy().into_owned(),
+            access: AccessRevocations::default(),
+            crl_validity: default_crl_validity(),
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn padded_fingerprints_are_revoked() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = store(&directory).await;
+
+        store
+            .revoke("example", &[], &[format!("SHA256:{FINGERPRINT}=")], &[])
+            .await
+            .unwrap();
+
+        assert!(store.is_key_revoked("example", FINGERPRINT).await);
+        assert!(store.is_key_revoked("example", &format!("SHA256:{FINGERPRINT}")).await);
+        assert!(store.is_key_revoked("example", &format!("{FINGERPRINT}=")).await);
+        assert!(!store.is_key_revoked("other", FINGERPRINT).await);
+    }
+
+    #[tokio::test]
+    async fn revocations_are_persisted() {
+        let directory = tempfile::tempdir().unwrap();
+        store(&directory)
+            .await
+            .revoke("example", &[5], &[FINGERPRINT.to_owned()], &[])
+            .await
+            .unwrap();
+
+        let reloaded = store(&directory).await;
+        assert!(reloaded.is_key_revoked("example", FINGERPRINT).await);
+    }
+}
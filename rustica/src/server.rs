@@ -1,31 +1,66 @@
+use crate::approval::{self, ApprovalError, ApprovalRequest, ApprovalStore, ReviewOutcome};
 use crate::auth::{
-    AuthorizationMechanism, RegisterKeyRequestProperties, SshAuthorizationRequestProperties,
-    X509AuthorizationRequestProperties,
+    AuthorizationMechanism, RegisterKeyRequestProperties, SshAuthorization,
+    SshAuthorizationRequestProperties, X509AuthorizationRequestProperties,
+};
+use crate::config::{
+    AdminConfiguration, AllowedSignersConfiguration, ChallengeKeys, ChallengeWindowConfiguration,
+    ClientAuthorityConfiguration, ClientIdentityConfiguration, IdentitySource,
 };
-use crate::config::{AllowedSignersConfiguration, ClientAuthorityConfiguration};
 use crate::error::RusticaServerError;
+use crate::ledger::{self, Ledger, LedgerQuery};
 use crate::logging::{
-    CertificateIssued, InternalMessage, KeyInfo, KeyRegistrationFailure, Log, Severity,
-    X509CertificateIssued,
+    AccessCredentialRenewed, AccessCredentialsRevoked, ApprovalRequested, ApprovalReviewed,
+    CertificateIssued, CertificatesRevoked, InternalMessage, KeyInfo,
+    KeyRegistrationFailure, Log, Severity, X509CertificateIssued,
 };
+use crate::metrics;
+#[cfg(feature = "oidc")]
+use crate::oidc::OidcVerifier;
 use crate::rustica::{
     rustica_server::Rustica, CertificateRequest, CertificateResponse, Challenge, ChallengeRequest,
     ChallengeResponse, RegisterKeyRequest, RegisterKeyResponse, RegisterU2fKeyRequest,
     RegisterU2fKeyResponse, AllowedSignersRequest, AllowedSignersResponse,
 };
 use crate::rustica::{AttestedX509CertificateRequest, AttestedX509CertificateResponse};
-use crate::signing::SigningMechanism;
+use crate::rustica::{
+    RevokeCertificatesRequest, RevokeCertificatesResponse, RevokedKeysRequest,
+    RevokedKeysResponse,
+};
+use crate::rustica::{
+    AccessCrlRequest, AccessCrlResponse, RevokeAccessCredentialsRequest,
+    RevokeAccessCredentialsResponse,
+};
+use crate::rustica::{Authority, AuthorityKey, AuthoritiesRequest, AuthoritiesResponse};
+use crate::rustica::{
+    ApprovalState, ApprovedCertificateRequest, ApprovedCertificateResponse,
+    ListPendingApprovalsRequest, ListPendingApprovalsResponse, PendingApproval,
+    ReviewPendingApprovalRequest, ReviewPendingApprovalResponse,
+};
+use crate::rustica::{RenewAccessCredentialRequest, RenewAccessCredentialResponse};
+use crate::rustica::{
+    IssuedCertificate, IssuedCertificatesResponse, ListIssuedCertificatesRequest,
+    SearchIssuedCertificatesRequest,
+};
+use crate::revocation::{normalize_access_serial, AccessRevocations, RevocationStore};
+use crate::signing::{AuthorityTrustMaterial, KeyState, SigningMechanism};
 use crate::verification::{verify_piv_certificate_chain, verify_u2f_certificate_chain};
 
 use crossbeam_channel::Sender;
 
-use rcgen::{DistinguishedName, DnType, SanType};
-use sshcerts::ssh::{CertType, Certificate, PrivateKey, PublicKey};
+use rcgen::{
+    CertificateRevocationList, CertificateRevocationListParams, DistinguishedName, DnType,
+    KeyIdMethod, RevokedCertParams, SanType, SerialNumber,
+};
+use sshcerts::ssh::{CertType, Certificate, PublicKey};
 
-use ring::hmac;
-use std::collections::HashMap;
+use ring::{digest, hmac};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::collections::{BTreeSet, HashMap};
 use std::io::Write;
+use std::net::SocketAddr;
 use std::time::{Duration, UNIX_EPOCH};
+use std::sync::Mutex as StdMutex;
 use std::{sync::Arc, time::SystemTime};
 use tonic::transport::Certificate as TonicCertificate;
 use tonic::{Request, Response, Status};
@@ -45,23 +80,84 @@ pub struct AllowedSignersCache {
 
 pub struct RusticaServer {
     pub log_sender: Sender<Log>,
-    pub hmac_key: hmac::Key,
-    pub challenge_key: PrivateKey,
+    pub challenge_keys: ChallengeKeys,
+    pub challenge_window: ChallengeWindowConfiguration,
+    // HMAC tags of redeemed challenges and when they expire. This uses a
+    // standard mutex because it is held very briefly and validate_request is
+    // not async.
+    pub redeemed_challenges: Arc<StdMutex<LruCache<Vec<u8>, u64>>>,
     pub authorizer: AuthorizationMechanism,
     pub signer: SigningMechanism,
+    /// Served by GetAuthorities. Collected when the configuration is loaded
+    /// because serializing the X509 certificates requires signing them.
+    pub trust_material: Vec<AuthorityTrustMaterial>,
     pub require_rustica_proof: bool,
     pub require_attestation_chain: bool,
     pub client_authority: ClientAuthorityConfiguration,
+    pub client_identity: ClientIdentityConfiguration,
     pub allowed_signers: AllowedSignersConfiguration,
     // Identity-based rate limiter using LRU cache is needed for the allowed_signers endpoint since the allowed_signers
     // payload might be heavy even when compressed
     pub allowed_signers_rate_limiter: Arc<Mutex<LruCache<String, Duration>>>,
     pub allowed_signers_cache: Arc<RwLock<AllowedSignersCache>>,
+    pub admin: AdminConfiguration,
+    pub revocation: Option<Arc<RevocationStore>>,
+    /// Certificate requests waiting for approval. These are kept across
+    /// reloads.
+    pub approvals: Arc<ApprovalStore>,
+    pub ledger: Option<Ledger>,
+    /// Verifies OIDC tokens for clients that do not present an mTLS
+    /// certificate
+    #[cfg(feature = "oidc")]
+    pub oidc: Option<OidcVerifier>,
+}
+
+/// Everything needed to issue an SSH certificate once it has been
+/// authorized. Requests that need approval are held in this form until they
+/// are approved.
+#[derive(Clone)]
+pub struct SshCertificateIssuance {
+    pub ssh_pubkey: PublicKey,
+    pub fingerprint: String,
+    pub cert_type: CertType,
+    /// The authority the client requested
+    pub authority: String,
+    pub authorization: SshAuthorization,
+    /// The extensions and critical options the client asked for
+    pub requested_extensions: HashMap<String, String>,
+    pub requested_critical_options: HashMap<String, String>,
+    pub mtls_identities: Vec<String>,
+    /// The serial and fingerprint of the requester's mTLS certificate so it
+    /// can be checked against revocations again if issuing is delayed
+    pub access_serial: Option<String>,
+    pub access_fingerprint: Option<String>,
+    pub remote_addr: SocketAddr,
 }
 
 struct MtlsCertificateInfo {
     identities: Vec<String>,
+    attributes: HashMap<String, Vec<String>>,
     expiry_timestamp: i64,
+    /// Lowercase hex without leading zeros, as compared against revocations
+    serial: String,
+    /// Lowercase hex SHA256 of the DER encoded certificate
+    fingerprint: String,
+}
+
+/// The identities of the client making a request
+struct ClientIdentity {
+    identities: Vec<String>,
+    /// Named identities such as a SPIFFE ID, a team, or an OIDC claim
+    attributes: HashMap<String, Vec<String>>,
+    /// When the client's mTLS certificate expires. Clients that identified
+    /// with an OIDC token have no access certificate to renew.
+    expiry_timestamp: Option<i64>,
+    /// The serial and fingerprint of the client's mTLS certificate
+    serial: Option<String>,
+    fingerprint: Option<String>,
+    /// The DER encoded mTLS certificate the client presented. Its subject
+    /// and SANs are carried into the certificate it is renewed with.
+    certificate: Option<Vec<u8>>,
 }
 
 struct CertificateRefreshSettings {
@@ -69,6 +165,25 @@ struct CertificateRefreshSettings {
     not_before: u64,
 }
 
+/// The extension listing the hosts a user certificate may be used on. It is
+/// enforced by rustica-principals running as the AuthorizedPrincipalsCommand.
+const HOSTS_EXTENSION: &str = "hosts@rustica";
+
+/// Critical options a client may add to its own certificate. Each of these
+/// can only restrict how the certificate is used.
+const CLIENT_CRITICAL_OPTIONS: [&str; 3] = ["force-command", "source-address", "verify-required"];
+
+/// The extensions and critical options for a certificate once the client's
+/// request has been applied to what the authorizer allowed
+struct CertificateOptions {
+    extensions: HashMap<String, String>,
+    critical_options: HashMap<String, String>,
+    /// Extensions the authorizer allowed that the client did not request
+    declined_extensions: Vec<String>,
+    /// Critical options added because the client requested them
+    added_critical_options: Vec<String>,
+}
+
 /// Macro for simplifying sending error logs to the Rustica logging system.
 macro_rules! rustica_error {
     ($self:ident, $message:expr) => {
@@ -89,28 +204,94 @@ macro_rules! rustica_warning {
     };
 }
 
+#[cfg(feature = "local-db")]
+mod admin;
+
 fn create_response<T>(e: T) -> Response<CertificateResponse>
 where
     T: Into<RusticaServerError>,
 {
     let e = e.into();
+    metrics::server_error(&e);
     Response::new(CertificateResponse {
         certificate: String::new(),
         error: format!("{:?}", e),
         error_code: e as i64,
         new_client_certificate: String::new(),
         new_client_key: String::new(),
+        approval_request_id: String::new(),
     })
 }
 
-/// Extract the identities (CNs) from the presented mTLS certificates.
+/// Collect the values of a subject attribute from a client certificate
+fn subject_values(cert: &X509Certificate, oid: &str) -> Vec<String> {
+    cert.subject()
+        .iter_attributes()
+        .filter(|attr| attr.attr_type().to_id_string() == oid)
+        .filter_map(|attr| attr.attr_value().as_str().ok().map(String::from))
+        .collect()
+}
+
+/// Collect the values of every configured identity attribute from a client
+/// certificate. Attributes without any values are left out.
+fn extract_identity_attributes(
+    config: &ClientIdentityConfiguration,
+    cert: &X509Certificate,
+) -> HashMap<String, Vec<String>> {
+    // A certificate with a malformed SAN extension simply has no SAN values.
+    // Attributes can only ever add identities so this cannot grant access.
+    let sans = match cert.subject_alternative_name() {
+        Ok(Some(sans)) => sans.value.general_names.clone(),
+        _ => vec![],
+    };
+    let san_values = |select: fn(&GeneralName) -> Option<String>| -> Vec<String> {
+        sans.iter().filter_map(select).collect()
+    };
+
+    let mut attributes = HashMap::new();
+    for attribute in &config.attributes {
+        let mut values = match &attribute.source {
+            IdentitySource::CommonName => subject_values(cert, "2.5.4.3"),
+            IdentitySource::Dn { oid } => subject_values(cert, oid),
+            IdentitySource::SanUri => san_values(|name| match name {
+                GeneralName::URI(uri) => Some(uri.to_string()),
+                _ => None,
+            }),
+            IdentitySource::SanEmail => san_values(|name| match name {
+                GeneralName::RFC822Name(email) => Some(email.to_string()),
+                _ => None,
+            }),
+            IdentitySource::SanDns => san_values(|name| match name {
+                GeneralName::DNSName(dns) => Some(dns.to_string()),
+                _ => None,
+            }),
+        };
+
+        if let Some(prefix) = &attribute.prefix {
+            values.retain(|value| value.starts_with(prefix));
+        }
+
+        if !values.is_empty() {
+            attributes.insert(attribute.name.clone(), values);
+        }
+    }
+
+    attributes
+}
+
+/// Extract the identities (CNs) from the presented mTLS certificates along
+/// with any configured identity attributes.
 /// This should almost always be exactly 1. If it is 0, this is an error.
 fn extract_certificate_information(
+    config: &ClientIdentityConfiguration,
     peer: &TonicCertificate,
 ) -> Result<MtlsCertificateInfo, RusticaServerError> {
     let mut cert_info = MtlsCertificateInfo {
         identities: vec![],
+        attributes: HashMap::new(),
         expiry_timestamp: 0x7FFFFFFFFFFFFFFF,
+        serial: String::new(),
+        fingerprint: hex::encode(digest::digest(&digest::SHA256, peer.as_ref())),
     };
 
     match x509_parser::parse_x509_certificate(peer.as_ref()) {
@@ -120,6 +301,8 @@ fn extract_certificate_information(
             // This is used to automatically refresh the certificate if it's
             // going to expire within a given window
             cert_info.expiry_timestamp = cert.validity().not_after.timestamp();
+            cert_info.serial = normalize_access_serial(&hex::encode(cert.raw_serial()))
+                .map_err(|_| RusticaServerError::NotAuthorized)?;
 
             // Loop through all the DNs to find the common name as identified by the OID
             for ident in cert.tbs_certificate.subject.iter_rdn() {
@@ -134,35 +317,190 @@ fn extract_certificate_information(
                     }
                 }
             }
+
+            cert_info.attributes = extract_identity_attributes(config, &cert);
         }
     };
     Ok(cert_info)
 }
 
+/// Get the OIDC token from the authorization metadata of a request
+#[cfg(feature = "oidc")]
+fn bearer_token<T>(request: &Request<T>) -> Option<String> {
+    request
+        .metadata()
+        .get("authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_owned())
+}
+
+/// Refuse clients whose access certificate or identities have been revoked
+async fn check_access_revocation<T>(
+    srv: &RusticaServer,
+    request: &Request<T>,
+    serial: Option<&str>,
+    fingerprint: Option<&str>,
+    identities: &[String],
+) -> Result<(), RusticaServerError> {
+    let revocation = match &srv.revocation {
+        Some(revocation) => revocation,
+        None => return Ok(()),
+    };
+
+    if revocation
+        .is_access_revoked(serial, fingerprint, identities)
+        .await
+    {
+        let remote_addr = request
+            .remote_addr()
+            .map(|x| x.to_string())
+            .unwrap_or_default();
+        rustica_warning!(
+            srv,
+            format!(
+                "Rejected revoked access credential for [{}] from [{remote_addr}] (serial: {})",
+                identities.join(", "),
+                serial.unwrap_or("none"),
+            )
+        );
+        return Err(RusticaServerError::NotAuthorized);
+    }
+
+    Ok(())
+}
+
+/// Check that neither the key nor the requester of a held certificate has
+/// been revoked since it was requested
+async fn check_issuance_revocation(
+    srv: &RusticaServer,
+    issuance: &SshCertificateIssuance,
+) -> Result<(), &'static str> {
+    let revocation = match &srv.revocation {
+        Some(revocation) => revocation,
+        None => return Ok(()),
+    };
+
+    if revocation
+        .is_key_revoked(&issuance.authority, &issuance.fingerprint)
+        .await
+    {
+        rustica_warning!(
+            srv,
+            format!(
+                "Refused to issue an approved certificate for revoked key [{}] from authority [{}]",
+                issuance.fingerprint, issuance.authority
+            )
+        );
+        return Err("The key was revoked while the request waited for approval");
+    }
+
+    if revocation
+        .is_access_revoked(
+            issuance.access_serial.as_deref(),
+            issuance.access_fingerprint.as_deref(),
+            &issuance.mtls_identities,
+        )
+        .await
+    {
+        rustica_warning!(
+            srv,
+            format!(
+                "Refused to issue an approved certificate for revoked access credential [{}] (serial: {})",
+                issuance.mtls_identities.join(", "),
+                issuance.access_serial.as_deref().unwrap_or("none"),
+            )
+        );
+        return Err("The requester's access was revoked while the request waited for approval");
+    }
+
+    Ok(())
+}
+
+/// Identify the client making a request. If the client presented an mTLS
+/// certificate its identities are always used. Otherwise, if OIDC is
+/// configured, the client may identify with a token instead.
+async fn identify<T>(
+    srv: &RusticaServer,
+    request: &Request<T>,
+) -> Result<ClientIdentity, RusticaServerError> {
+    // Only support the presenting of a single client certificate
+    // I've never seen anyone handle multiple ones and since we don't
+    // need to here, trying to support it will only lead to validation
+    // issues or inconsistencies.
+    if let Some(cert) = request.peer_certs().and_then(|certs| certs.first().cloned()) {
+        let cert_info = extract_certificate_information(&srv.client_identity, &cert)?;
+        check_access_revocation(
+            srv,
+            request,
+            Some(&cert_info.serial),
+            Some(&cert_info.fingerprint),
+            &cert_info.identities,
+        )
+        .await?;
+
+        return Ok(ClientIdentity {
+            identities: cert_info.identities,
+            attributes: cert_info.attributes,
+            expiry_timestamp: Some(cert_info.expiry_timestamp),
+            serial: Some(cert_info.serial),
+            fingerprint: Some(cert_info.fingerprint),
+            certificate: Some(cert.as_ref().to_vec()),
+        });
+    }
+
+    #[cfg(feature = "oidc")]
+    if let (Some(oidc), Some(token)) = (&srv.oidc, bearer_token(request)) {
+        return match oidc.verify(&token).await {
+            Ok(claims) => {
+                let mut attributes: HashMap<String, Vec<String>> = HashMap::new();
+                for (claim, value) in &claims {
+                    attributes.entry(claim.clone()).or_default().push(value.clone());
+                }
+
+                let identities: Vec<String> = claims
+                    .iter()
+                    .map(|(claim, value)| format!("{claim}:{value}"))
+                    .collect();
+                check_access_revocation(srv, request, None, None, &identities).await?;
+
+                Ok(ClientIdentity {
+                    identities,
+                    attributes,
+                    expiry_timestamp: None,
+                    serial: None,
+                    fingerprint: None,
+                    certificate: None,
+                })
+            }
+            Err(e) => {
+                let remote_addr = request
+                    .remote_addr()
+                    .map(|x| x.to_string())
+                    .unwrap_or_default();
+                rustica_warning!(srv, format!("Rejected token from [{remote_addr}]: {e}"));
+                Err(RusticaServerError::NotAuthorized)
+            }
+        };
+    }
+
+    #[cfg(not(feature = "oidc"))]
+    let _ = srv;
+
+    Err(RusticaServerError::NotAuthorized)
+}
+
 /// Validates a request passes all the following checks in this order:
-/// - Validate the peer certs are the way we expect
 /// - Validate Time is not expired
 /// - Validate Signature
 /// - Validate HMAC
 /// - Validate certificate parameters
 fn validate_request(
     srv: &RusticaServer,
-    hmac_key: &ring::hmac::Key,
-    peer_certs: &Arc<Vec<TonicCertificate>>,
+    cert_info: &ClientIdentity,
     challenge: &Challenge,
-) -> Result<(PublicKey, Vec<String>, Option<CertificateRefreshSettings>), RusticaServerError> {
-    // Only support the presenting of a single client certificate
-    // I've never seen anyone handle multiple ones and since we don't
-    // need to here, trying to support it will only lead to validation
-    // issues or inconsistencies.
-    let cert = if let Some(cert) = peer_certs.get(0) {
-        cert
-    } else {
-        return Err(RusticaServerError::BadRequest);
-    };
-
-    let cert_info = extract_certificate_information(cert)?;
-
+) -> Result<(PublicKey, Option<CertificateRefreshSettings>), RusticaServerError> {
     // Get request time, and current time. Any issue causes request to fail
     let (request_time, time) = match (
         challenge.challenge_time.parse::<u64>(),
@@ -174,15 +512,10 @@ fn validate_request(
 
     // This is our operational window. A user must confirm they control the
     // the private key within this window or else we will kick out and make
-    // them start again. This is so short because we don't want people to
+    // them start again. This is kept short because we don't want people to
     // be able to "buffer" requests, where they presign them and then use
-    // them later. Admittedly, the period set here is exceedingly short but in
-    // practice it has not been too much of an issue.
-    //
-    // Also request_time is not trusted at this point so in theory could cause
-    // an underflow with the subtraction from time but this would result in a
-    // a large number greater than five so would stop anyway.
-    if (time - request_time) > 5 {
+    // them later.
+    if !challenge_in_window(&srv.challenge_window, request_time, time) {
         rustica_warning!(
             srv,
             format!(
@@ -225,15 +558,21 @@ fn validate_request(
 
     let hmac_challenge = &parsed_certificate.key_id;
     let hmac_verification = format!(
-        "{}-{}-{}",
+        "{}-{}-{}-{}",
         request_time,
+        parsed_certificate.serial,
         challenge.pubkey,
         cert_info.identities.join(",")
     );
     let decoded_challenge =
         hex::decode(&hmac_challenge).map_err(|_| RusticaServerError::BadChallenge)?;
 
-    if hmac::verify(hmac_key, hmac_verification.as_bytes(), &decoded_challenge).is_err() {
+    // During a key rotation this will accept challenges issued with either
+    // the current or previous HMAC key
+    if !srv
+        .challenge_keys
+        .verify_hmac(hmac_verification.as_bytes(), &decoded_challenge)
+    {
         rustica_warning!(
             srv,
             format!(
@@ -263,16 +602,21 @@ fn validate_request(
     // certificate is expiring, it's possible that tonic accepts the request because it
     // hasn't expired (expiry == time), then when we get here another second has passed
     // so time is now larger
-    let certificate_refresh_settings = if (time > cert_info.expiry_timestamp as u64)
-        || (cert_info.expiry_timestamp as u64 - time)
-            < srv.client_authority.expiration_renewal_period
-    {
-        Some(CertificateRefreshSettings {
-            not_after: time + srv.client_authority.validity_length,
-            not_before: time,
-        })
-    } else {
-        None
+    //
+    // Clients that identified with an OIDC token have no access certificate
+    // so are never given one.
+    let certificate_refresh_settings = match cert_info.expiry_timestamp {
+        Some(expiry_timestamp)
+            if (time > expiry_timestamp as u64)
+                || (expiry_timestamp as u64 - time)
+                    < srv.client_authority.expiration_renewal_period =>
+        {
+            Some(CertificateRefreshSettings {
+                not_after: time + srv.client_authority.validity_length,
+                not_before: time,
+            })
+        }
+        _ => None,
     };
 
     // This functionality exists because when user certificates are FIDO or
@@ -288,8 +632,9 @@ fn validate_request(
     // waiting for a user to initiate a connection themselves.
     if !srv.require_rustica_proof {
         // Do an extra sanity check here that the certificate we received was signed by us
-        if parsed_certificate.signature_key.fingerprint().hash
-            != srv.challenge_key.pubkey.fingerprint().hash
+        if !srv
+            .challenge_keys
+            .is_challenge_key(&parsed_certificate.signature_key)
         {
             rustica_warning!(
                 srv,
@@ -300,11 +645,9 @@ fn validate_request(
             );
             return Err(RusticaServerError::BadChallenge);
         }
-        return Ok((
-            hmac_ssh_pubkey,
-            cert_info.identities,
-            certificate_refresh_settings,
-        ));
+
+        redeem_challenge(srv, decoded_challenge, request_time, time, &cert_info.identities)?;
+        return Ok((hmac_ssh_pubkey, certificate_refresh_settings));
     }
 
     // We now know the request has not been replayed significantly in time.
@@ -345,11 +688,598 @@ fn validate_request(
     // this point the user must have received our challenge certificate
     // containing our HMAC challenge, resigned it with their key, and
     // sent it back for which it passed all checks.
-    Ok((
-        hmac_ssh_pubkey,
-        cert_info.identities,
-        certificate_refresh_settings,
-    ))
+    redeem_challenge(srv, decoded_challenge, request_time, time, &cert_info.identities)?;
+    Ok((hmac_ssh_pubkey, certificate_refresh_settings))
+}
+
+/// Check a challenge issued at `request_time` can still be used at `time`.
+/// The clock skew allowance covers challenges issued by a replica whose
+/// clock is ahead of or behind ours.
+fn challenge_in_window(
+    window: &ChallengeWindowConfiguration,
+    request_time: u64,
+    time: u64,
+) -> bool {
+    // request_time is not trusted at this point so the arithmetic is done in
+    // a type that cannot overflow.
+    let age = i128::from(time) - i128::from(request_time);
+    age >= -i128::from(window.clock_skew)
+        && age <= i128::from(window.validity) + i128::from(window.clock_skew)
+}
+
+/// The outcome of remembering a redeemed challenge
+#[derive(Debug, PartialEq)]
+enum Redemption {
+    Redeemed,
+    /// The challenge had already been redeemed
+    Replayed,
+    /// The cache was full so the oldest challenge was forgotten before it
+    /// expired
+    EvictedUnexpired,
+}
+
+/// Remember a redeemed challenge until `expires_at`. When the cache is full
+/// the least recently redeemed challenge is forgotten. Challenges are
+/// redeemed in roughly the order they expire so this is the one closest to
+/// failing the time check anyway.
+fn remember_redeemed(
+    redeemed: &mut LruCache<Vec<u8>, u64>,
+    tag: Vec<u8>,
+    expires_at: u64,
+    time: u64,
+) -> Redemption {
+    if redeemed.contains(&tag) {
+        return Redemption::Replayed;
+    }
+
+    match redeemed.push(tag, expires_at) {
+        Some((_, expiry)) if expiry >= time => Redemption::EvictedUnexpired,
+        _ => Redemption::Redeemed,
+    }
+}
+
+/// Mark a challenge as used so it cannot be redeemed again. Challenges are
+/// only remembered until they expire because after that they fail the time
+/// check anyway. A full cache forgets its oldest challenge rather than
+/// refusing requests, so one client cannot lock out everyone else by
+/// redeeming challenges quickly.
+fn redeem_challenge(
+    srv: &RusticaServer,
+    tag: Vec<u8>,
+    request_time: u64,
+    time: u64,
+    identities: &[String],
+) -> Result<(), RusticaServerError> {
+    let expires_at = request_time
+        .saturating_add(srv.challenge_window.validity)
+        .saturating_add(srv.challenge_window.clock_skew);
+
+    // A panic while holding the lock cannot leave the cache in a bad state
+    let mut redeemed = srv
+        .redeemed_challenges
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+
+    match remember_redeemed(&mut redeemed, tag, expires_at, time) {
+        Redemption::Redeemed => Ok(()),
+        Redemption::Replayed => {
+            rustica_warning!(
+                srv,
+                format!("Replayed challenge received from: {}", identities.join(","))
+            );
+            Err(RusticaServerError::BadChallenge)
+        }
+        Redemption::EvictedUnexpired => {
+            rustica_warning!(
+                srv,
+                String::from("The replay cache is full so an unexpired challenge was forgotten. Consider increasing replay_cache_size.")
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Check if any of the presented mTLS identities is a configured admin
+fn is_admin(admin: &AdminConfiguration, identities: &[String]) -> bool {
+    identities
+        .iter()
+        .any(|identity| admin.identities.contains(identity))
+}
+
+/// Read the subject and SANs of a presented access certificate so they can
+/// be issued again. Identity attributes can come from any of them, so
+/// renewing must reproduce them exactly and names that cannot be carried
+/// over are an error rather than being dropped.
+fn presented_names(presented: &[u8]) -> Result<(DistinguishedName, Vec<SanType>), String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(presented)
+        .map_err(|e| format!("Could not parse the presented certificate: {e}"))?;
+
+    let mut distinguished_name = DistinguishedName::new();
+    for attr in cert.subject().iter_attributes() {
+        let oid: Vec<u64> = attr
+            .attr_type()
+            .iter()
+            .ok_or_else(|| format!("Subject attribute {} cannot be reproduced", attr.attr_type()))?
+            .collect();
+        let dn_type = DnType::from_oid(&oid);
+        let value = attr
+            .attr_value()
+            .as_str()
+            .map_err(|_| format!("Subject attribute {} is not a string", attr.attr_type()))?;
+
+        // A distinguished name can only hold each attribute once when it is
+        // issued so a repeated one, such as a second OU, would be lost
+        if distinguished_name.get(&dn_type).is_some() {
+            return Err(format!(
+                "Subject attribute {} appears more than once",
+                attr.attr_type()
+            ));
+        }
+        distinguished_name.push(dn_type, value);
+    }
+
+    let general_names = match cert.subject_alternative_name() {
+        Ok(Some(sans)) => sans.value.general_names.clone(),
+        Ok(None) => vec![],
+        Err(e) => return Err(format!("Could not parse the SANs: {e}")),
+    };
+
+    let mut subject_alt_names = vec![];
+    for name in general_names {
+        subject_alt_names.push(match name {
+            GeneralName::DNSName(dns) => SanType::DnsName(dns.to_owned()),
+            GeneralName::RFC822Name(email) => SanType::Rfc822Name(email.to_owned()),
+            GeneralName::URI(uri) => SanType::URI(uri.to_owned()),
+            GeneralName::IPAddress(octets) => {
+                let ip: std::net::IpAddr = if let Ok(v4) = <[u8; 4]>::try_from(octets) {
+                    v4.into()
+                } else if let Ok(v6) = <[u8; 16]>::try_from(octets) {
+                    v6.into()
+                } else {
+                    return Err("An IP address SAN has an invalid length".to_owned());
+                };
+                SanType::IpAddress(ip)
+            }
+            other => return Err(format!("SAN {other} cannot be reproduced")),
+        });
+    }
+
+    Ok((distinguished_name, subject_alt_names))
+}
+
+/// Renew an mTLS access certificate. The new certificate has the same
+/// subject and SANs as the presented one so every identity and identity
+/// attribute is kept. If the client provided a CSR, the certificate is
+/// issued for its key and no private key is returned. Otherwise the key is
+/// generated here, which is only done for older clients that do not send a
+/// CSR.
+///
+/// Returns the PEM encoded certificate and private key.
+fn issue_access_certificate(
+    srv: &RusticaServer,
+    csr: &[u8],
+    presented: &[u8],
+    settings: &CertificateRefreshSettings,
+) -> Result<(String, String), String> {
+    let ca = srv
+        .signer
+        .get_client_certificate_authority(&srv.client_authority.authority)
+        .map_err(|e| e.to_string())?
+        .ok_or("The client certificate authority is not configured")?;
+
+    let params = access_certificate_params(presented, settings)?;
+
+    if csr.is_empty() {
+        let new_certificate =
+            rcgen::Certificate::from_params(params).map_err(|e| e.to_string())?;
+        let certificate = new_certificate
+            .serialize_pem_with_signer(ca)
+            .map_err(|e| e.to_string())?;
+
+        return Ok((certificate, new_certificate.serialize_private_key_pem()));
+    }
+
+    // This also verifies the CSR is signed by the key it contains
+    let mut csr = rcgen::CertificateSigningRequest::from_der(csr)
+        .map_err(|e| format!("Invalid CSR: {e}"))?;
+    csr.params = params;
+
+    let certificate = csr
+        .serialize_pem_with_signer(ca)
+        .map_err(|e| e.to_string())?;
+
+    Ok((certificate, String::new()))
+}
+
+/// Only the public key is taken from a CSR. Everything else comes from the
+/// presented access certificate and the refresh settings.
+fn access_certificate_params(
+    presented: &[u8],
+    settings: &CertificateRefreshSettings,
+) -> Result<rcgen::CertificateParams, String> {
+    let (distinguished_name, subject_alt_names) = presented_names(presented)?;
+
+    // CertificateParams is non_exhaustive so it cannot be built with a
+    // struct literal
+    let mut params = rcgen::CertificateParams::default();
+    params.distinguished_name = distinguished_name;
+    params.subject_alt_names = subject_alt_names;
+    params.not_before = (UNIX_EPOCH + Duration::from_secs(settings.not_before)).into();
+    params.not_after = (UNIX_EPOCH + Duration::from_secs(settings.not_after)).into();
+    Ok(params)
+}
+
+/// Work out the window an approved certificate is valid for. It starts from
+/// when the request was approved rather than when it was made, but never ends
+/// later than the authorizer allowed, so time spent waiting comes out of the
+/// certificate's validity. Returns None once the authorized window has run
+/// out.
+fn approved_window(
+    valid_after: u64,
+    valid_before: u64,
+    requested_at: u64,
+    now: u64,
+) -> Option<(u64, u64)> {
+    let valid_after = valid_after.saturating_add(now.saturating_sub(requested_at));
+    if valid_after >= valid_before || now >= valid_before {
+        return None;
+    }
+
+    Some((valid_after, valid_before))
+}
+
+/// Sign an authorized SSH certificate, record it in the ledger, and log it.
+/// This is used both for certificates issued right away and for ones issued
+/// once they have been approved.
+///
+/// Returns the serialized certificate.
+async fn issue_ssh_certificate(
+    srv: &RusticaServer,
+    issuance: &SshCertificateIssuance,
+    approved_by: &[String],
+    new_access_certificate_issued: bool,
+) -> Result<String, RusticaServerError> {
+    let authorization = &issuance.authorization;
+    let fingerprint = &issuance.fingerprint;
+
+    let ca_cert = srv
+        .signer
+        .get_signer_public_key(&issuance.authority, issuance.cert_type)
+        .map_err(|_| RusticaServerError::NotAuthorized)?;
+
+    let current_timestamp = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(ts) => ts.as_secs(),
+        Err(_e) => 0xFFFFFFFFFFFFFFFF,
+    };
+
+    let mut critical_options = HashMap::new();
+    if let Some(cmd) = &authorization.force_command {
+        critical_options.insert(String::from("force-command"), cmd.clone());
+    }
+
+    if authorization.force_source_ip {
+        critical_options.insert(
+            String::from("source-address"),
+            issuance.remote_addr.ip().to_string(),
+        );
+    }
+
+    let mut options = apply_requested_options(
+        authorization.extensions.clone(),
+        critical_options,
+        &issuance.requested_extensions,
+        &issuance.requested_critical_options,
+    );
+
+    // This is added after the requested extensions are applied because
+    // a client must not be able to remove its host restrictions
+    if let (CertType::User, Some(hosts)) = (issuance.cert_type, &authorization.hosts) {
+        options
+            .extensions
+            .insert(String::from(HOSTS_EXTENSION), hosts.join(","));
+    }
+
+    let cert = Certificate::builder(&issuance.ssh_pubkey, issuance.cert_type, &ca_cert)
+        .map_err(|_| RusticaServerError::NotAuthorized)?
+        .serial(authorization.serial)
+        .key_id(format!("Rustica-JITC-for-{}", fingerprint))
+        .set_principals(&authorization.principals)
+        .valid_after(authorization.valid_after)
+        .valid_before(authorization.valid_before)
+        .set_critical_options(options.critical_options.clone())
+        .set_extensions(options.extensions.clone());
+
+    let cert = srv.signer.sign(&authorization.authority, cert).await;
+
+    let serialized_cert = match cert {
+        Ok(cert) => {
+            let serialized = format!("{}", cert);
+
+            // Sanity check that we can parse the cert we just generated
+            if let Err(e) = Certificate::from_string(&serialized) {
+                debug!("Offending Public Key: {}", issuance.ssh_pubkey);
+                debug!("Offending certificate: {}", serialized);
+                rustica_error!(srv, format!("Couldn't deserialize certificate: {}", e));
+                return Err(RusticaServerError::BadCertOptions);
+            }
+            serialized
+        }
+        Err(e) => {
+            rustica_error!(srv, format!("Creating certificate failed: {}", e));
+            return Err(RusticaServerError::BadChallenge);
+        }
+    };
+
+    // The certificate must be in the ledger before it is returned so
+    // every certificate in use can be found there
+    if let Some(ledger) = &srv.ledger {
+        let issued = ledger::IssuedCertificate {
+            certificate_type: issuance.cert_type.to_string(),
+            serial: authorization.serial.to_string(),
+            fingerprint: fingerprint.clone(),
+            signed_by: ca_cert.fingerprint().hash,
+            authority: issuance.authority.clone(),
+            mtls_identities: issuance.mtls_identities.clone(),
+            principals: authorization.principals.clone(),
+            extensions: options.extensions.clone(),
+            critical_options: options.critical_options.clone(),
+            valid_after: authorization.valid_after,
+            valid_before: authorization.valid_before,
+            issued_at: current_timestamp,
+            requester_ip: issuance.remote_addr.ip().to_string(),
+        };
+
+        if let Err(e) = ledger.record(issued).await {
+            rustica_error!(
+                srv,
+                format!("Could not record certificate for key [{fingerprint}] in the ledger: {e}")
+            );
+            return Err(RusticaServerError::Unknown);
+        }
+    }
+
+    metrics::certificate_issued(
+        &issuance.authority,
+        &issuance.cert_type.to_string().to_lowercase(),
+    );
+
+    let _ = srv.log_sender.send(Log::CertificateIssued(certificate_issued(
+        issuance,
+        ca_cert.fingerprint().hash,
+        options,
+        approved_by,
+        new_access_certificate_issued,
+    )));
+
+    Ok(serialized_cert)
+}
+
+/// The log entry for an SSH certificate that has just been signed
+fn certificate_issued(
+    issuance: &SshCertificateIssuance,
+    signed_by: String,
+    options: CertificateOptions,
+    approved_by: &[String],
+    new_access_certificate_issued: bool,
+) -> CertificateIssued {
+    let authorization = &issuance.authorization;
+    CertificateIssued {
+        fingerprint: issuance.fingerprint.clone(),
+        signed_by,
+        authority: issuance.authority.clone(),
+        serial: authorization.serial,
+        certificate_type: issuance.cert_type.to_string(),
+        mtls_identities: issuance.mtls_identities.clone(),
+        principals: authorization.principals.clone(),
+        extensions: options.extensions,
+        critical_options: options.critical_options,
+        declined_extensions: options.declined_extensions,
+        added_critical_options: options.added_critical_options,
+        valid_after: authorization.valid_after,
+        valid_before: authorization.valid_before,
+        new_access_certificate_issued,
+        approved_by: approved_by.to_vec(),
+    }
+}
+
+/// Describe a request waiting for approval to the people reviewing it
+fn pending_approval(request: ApprovalRequest) -> PendingApproval {
+    let authorization = request.issuance.authorization;
+    PendingApproval {
+        request_id: request.id,
+        mtls_identities: request.issuance.mtls_identities,
+        requester_ip: request.issuance.remote_addr.ip().to_string(),
+        fingerprint: request.issuance.fingerprint,
+        authority: request.issuance.authority,
+        certificate_type: request.issuance.cert_type.to_string(),
+        principals: authorization.principals,
+        hosts: authorization.hosts.unwrap_or_default(),
+        extensions: authorization.extensions,
+        force_command: authorization.force_command.unwrap_or_default(),
+        validity: authorization.valid_before.saturating_sub(request.requested_at),
+        requested_at: request.requested_at,
+        approvers: request.requirement.approvers,
+        required_approvals: request.requirement.required as u32,
+        approved_by: request.approved_by,
+    }
+}
+
+/// Build a CRL of revoked access certificates signed by the client
+/// certificate authority. Only serials can be published in a CRL, so
+/// certificates revoked by fingerprint or identity are only refused by
+/// Rustica itself. When each serial was revoked is not tracked so the
+/// revocation time is reported as when the CRL was generated.
+///
+/// Returns the PEM encoded CRL.
+fn generate_access_crl(
+    srv: &RusticaServer,
+    serials: &BTreeSet<String>,
+    crl_number: u64,
+    validity: u64,
+) -> Result<String, String> {
+    let ca = srv
+        .signer
+        .get_client_certificate_authority(&srv.client_authority.authority)
+        .map_err(|e| e.to_string())?
+        .ok_or("The client certificate authority is not configured")?;
+
+    let now = SystemTime::now();
+    let revoked_certs = serials
+        .iter()
+        .map(|serial| {
+            // Serials are stored without leading zeros so may need one
+            // added back before being decoded
+            let serial = if serial.len() % 2 == 1 {
+                format!("0{serial}")
+            } else {
+                serial.to_owned()
+            };
+            hex::decode(serial)
+                .map(|serial| RevokedCertParams {
+                    serial_number: SerialNumber::from(serial),
+                    revocation_time: now.into(),
+                    reason_code: None,
+                    invalidity_date: None,
+                })
+                .map_err(|e| e.to_string())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let params = CertificateRevocationListParams {
+        this_update: now.into(),
+        next_update: (now + Duration::from_secs(validity)).into(),
+        crl_number: SerialNumber::from(crl_number),
+        issuing_distribution_point: None,
+        revoked_certs,
+        alg: ca.get_params().alg,
+        key_identifier_method: KeyIdMethod::Sha256,
+    };
+
+    CertificateRevocationList::from_params(params)
+        .and_then(|crl| crl.serialize_pem_with_signer(ca))
+        .map_err(|e| e.to_string())
+}
+
+/// A client can only narrow what the authorizer allows. Extensions are
+/// limited to the ones that were requested, unless none were requested in
+/// which case every allowed extension is kept so older clients see no change.
+/// Critical options from the authorizer are always kept and requested ones
+/// are only added if they are restrictions the authorizer did not already set.
+fn apply_requested_options(
+    allowed_extensions: HashMap<String, String>,
+    mut critical_options: HashMap<String, String>,
+    requested_extensions: &HashMap<String, String>,
+    requested_critical_options: &HashMap<String, String>,
+) -> CertificateOptions {
+    let (extensions, declined_extensions): (HashMap<_, _>, HashMap<_, _>) =
+        if requested_extensions.is_empty() {
+            (allowed_extensions, HashMap::new())
+        } else {
+            allowed_extensions
+                .into_iter()
+                .partition(|(name, _)| requested_extensions.contains_key(name))
+        };
+
+    let mut added_critical_options = vec![];
+    for (name, value) in requested_critical_options {
+        if critical_options.contains_key(name) || !CLIENT_CRITICAL_OPTIONS.contains(&name.as_str()) {
+            debug!("Ignoring requested critical option [{name}]");
+            continue;
+        }
+
+        critical_options.insert(name.clone(), value.clone());
+        added_critical_options.push(name.clone());
+    }
+
+    let mut declined_extensions: Vec<String> = declined_extensions.into_keys().collect();
+    declined_extensions.sort();
+    added_critical_options.sort();
+
+    CertificateOptions {
+        extensions,
+        critical_options,
+        declined_extensions,
+        added_critical_options,
+    }
+}
+
+/// Empty strings in requests mean the filter is not set
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Zero timestamps in requests mean the filter is not set
+fn non_zero(value: u64) -> Option<u64> {
+    if value == 0 {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Run a query against the issuance ledger on behalf of an admin
+async fn search_ledger(
+    srv: &RusticaServer,
+    mtls_identities: &[String],
+    remote_addr: std::net::SocketAddr,
+    query: LedgerQuery,
+) -> Result<Response<IssuedCertificatesResponse>, Status> {
+    if !is_admin(&srv.admin, mtls_identities) {
+        rustica_warning!(
+            srv,
+            format!(
+                "[{}] from [{}] tried to query the issuance ledger but is not an admin",
+                mtls_identities.join(","),
+                remote_addr,
+            )
+        );
+        return Err(Status::permission_denied(""));
+    }
+
+    let ledger = srv
+        .ledger
+        .as_ref()
+        .ok_or(Status::failed_precondition("The issuance ledger is not configured"))?;
+
+    debug!(
+        "[{}] from [{}] queried the issuance ledger: {:?}",
+        mtls_identities.join(","),
+        remote_addr,
+        query,
+    );
+
+    let certificates = match ledger.search(query).await {
+        Ok(certificates) => certificates,
+        Err(e) => {
+            rustica_error!(srv, format!("Could not query the issuance ledger: {e}"));
+            return Err(Status::internal(""));
+        }
+    };
+
+    let certificates = certificates
+        .into_iter()
+        .map(|c| IssuedCertificate {
+            certificate_type: c.certificate_type,
+            serial: c.serial,
+            fingerprint: c.fingerprint,
+            signed_by: c.signed_by,
+            authority: c.authority,
+            mtls_identities: c.mtls_identities,
+            principals: c.principals,
+            extensions: c.extensions,
+            critical_options: c.critical_options,
+            valid_after: c.valid_after,
+            valid_before: c.valid_before,
+            issued_at: c.issued_at,
+            requester_ip: c.requester_ip,
+        })
+        .collect();
+
+    Ok(Response::new(IssuedCertificatesResponse { certificates }))
 }
 
 /// Check that mTLS identity is not rate limited for allowed_signers endpoint
@@ -394,21 +1324,11 @@ impl Rustica for RusticaServer {
         // as we may have guarantees on this information upstream.
         let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
 
-        let peer = if let Some(cert) = request
-            .peer_certs()
-            .ok_or(Status::permission_denied(""))?
-            .get(0)
-        {
-            cert.clone()
-        } else {
-            return Err(Status::permission_denied(""));
-        };
-
-        let request = request.into_inner();
-        let mtls_identities = match extract_certificate_information(&peer) {
-            Ok(ci) => ci.identities,
+        let mtls_identities = match identify(self, &request).await {
+            Ok(client) => client.identities,
             Err(_) => return Err(Status::permission_denied("")),
         };
+        let request = request.into_inner();
 
         // Limit the size of the public key to mitigate DoS
         // ED25519 public key strings are 127 chars in length.
@@ -443,22 +1363,31 @@ impl Rustica for RusticaServer {
             .expect("Could not get time from the system")
             .as_secs()
             .to_string();
+        // The nonce makes every challenge unique, even ones for the same key
+        // in the same second, so each one can only be redeemed once
+        let mut nonce = [0u8; 8];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| Status::unavailable(""))?;
+        let nonce = u64::from_be_bytes(nonce);
+
         let pubkey = &request.pubkey;
-        let challenge = format!("{}-{}-{}", timestamp, pubkey, mtls_identities.join(","));
-        let tag = hmac::sign(&self.hmac_key, challenge.as_bytes());
+        let challenge = format!("{}-{}-{}-{}", timestamp, nonce, pubkey, mtls_identities.join(","));
+        let challenge_keys = &self.challenge_keys.current;
+        let tag = hmac::sign(&challenge_keys.hmac_key, challenge.as_bytes());
 
         // Build an SSHCertificate as a challenge
         //
         // Generating certificates here should never fail. We map_err as a guard
         // in case there is some SSH pubkey that causes some failure condition
         // preventing us from crashing and resulting in a DOS.
-        let cert = Certificate::builder(&ssh_pubkey, CertType::Host, &self.challenge_key.pubkey)
+        let cert = Certificate::builder(&ssh_pubkey, CertType::Host, &challenge_keys.challenge_key.pubkey)
             .map_err(|_| Status::permission_denied(""))?
-            .serial(0xFEFEFEFEFEFEFEFE)
+            .serial(nonce)
             .key_id(hex::encode(tag))
             .valid_after(0)
             .valid_before(0)
-            .sign(&self.challenge_key)
+            .sign(&challenge_keys.challenge_key)
             .map_err(|_| Status::permission_denied(""))?;
 
         let reply = ChallengeResponse {
@@ -476,19 +1405,19 @@ impl Rustica for RusticaServer {
         request: Request<CertificateRequest>,
     ) -> Result<Response<CertificateResponse>, Status> {
         let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
-        let peer = request.peer_certs();
+        let client = identify(self, &request).await;
         let request = request.into_inner();
 
-        let (challenge, peer) = match (&request.challenge, peer) {
-            (Some(challenge), Some(peer)) => (challenge, peer),
+        let (challenge, client) = match (&request.challenge, client) {
+            (Some(challenge), Ok(client)) => (challenge, client),
             _ => return Ok(create_response(RusticaServerError::BadRequest)),
         };
 
-        let (ssh_pubkey, mtls_identities, mtls_refresh) =
-            match validate_request(self, &self.hmac_key, &peer, challenge) {
-                Ok(x) => x,
-                Err(e) => return Ok(create_response(e)),
-            };
+        let (ssh_pubkey, mtls_refresh) = match validate_request(self, &client, challenge) {
+            Ok(x) => x,
+            Err(e) => return Ok(create_response(e)),
+        };
+        let mtls_identities = client.identities;
 
         let current_timestamp = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
             Ok(ts) => ts.as_secs(),
@@ -519,6 +1448,7 @@ impl Rustica for RusticaServer {
         let auth_props = SshAuthorizationRequestProperties {
             fingerprint: fingerprint.clone(),
             mtls_identities: mtls_identities.clone(),
+            identity_attributes: client.attributes,
             requester_ip: remote_addr.to_string(),
             principals: request.principals.clone(),
             servers: request.servers.clone(),
@@ -526,6 +1456,8 @@ impl Rustica for RusticaServer {
             valid_after: request.valid_after,
             valid_before: request.valid_before,
             authority: authority.clone(),
+            extensions: request.extensions.clone(),
+            critical_options: request.critical_options.clone(),
         };
 
         debug!(
@@ -536,18 +1468,39 @@ impl Rustica for RusticaServer {
             authority
         );
 
-        // I'm unsure if it's a good move to have this before or after the authorization call.
-        // Before means if a key is requested we don't know about, we can prevent extraneous calls to
-        // the authorization backend.
+        // A revoked key must never receive a new certificate, regardless of
+        // what the authorizer says
+        if let Some(revocation) = &self.revocation {
+            if revocation.is_key_revoked(authority, &fingerprint).await {
+                rustica_warning!(
+                    self,
+                    format!(
+                        "[{}] from [{}] requested a certificate for revoked key [{}] from authority [{}]",
+                        mtls_identities.join(","),
+                        remote_addr,
+                        fingerprint,
+                        authority
+                    )
+                );
+                return Ok(create_response(RusticaServerError::NotAuthorized));
+            }
+        }
+
+        // I'm unsure if it's a good move to have this before or after the authorization call.
+        // Before means if a key is requested we don't know about, we can prevent extraneous calls to
+        // the authorization backend.
         //
         // Having it after means that it's easier to flood the backend service but brutefocing key_ids
         // is much less achievable.
-        let ca_cert = match self.signer.get_signer_public_key(authority, req_cert_type) {
-            Ok(public_key) => public_key,
+        if self
+            .signer
+            .get_signer_public_key(authority, req_cert_type)
+            .is_err()
+        {
             // Since all PublicKeys are cached, this can only happen if a public key
             // we don't have is requested.
-            Err(_) => return Ok(create_response(RusticaServerError::NotAuthorized)),
-        };
+            return Ok(create_response(RusticaServerError::NotAuthorized));
+        }
 
         let authorization = self.authorizer.authorize_ssh_cert(&auth_props).await;
 
@@ -558,94 +1511,186 @@ impl Rustica for RusticaServer {
 
         debug!("[{}] from [{}] is granted the following authorization on key [{}] for authority [{}]: {:?}", mtls_identities.join(","), remote_addr, fingerprint, authority, authorization);
 
-        let mut critical_options = HashMap::new();
-        if let Some(cmd) = authorization.force_command {
-            critical_options.insert(String::from("force-command"), cmd);
-        }
-
-        if authorization.force_source_ip {
-            critical_options.insert(String::from("source-address"), remote_addr.ip().to_string());
-        }
-
-        let cert = Certificate::builder(&ssh_pubkey, req_cert_type, &ca_cert)
-            .map_err(|_| Status::permission_denied(""))?
-            .serial(authorization.serial)
-            .key_id(format!("Rustica-JITC-for-{}", &fingerprint))
-            .set_principals(&authorization.principals)
-            .valid_after(authorization.valid_after)
-            .valid_before(authorization.valid_before)
-            .set_critical_options(critical_options.clone())
-            .set_extensions(authorization.extensions.clone());
-
-        let cert = self.signer.sign(&authorization.authority, cert).await;
-
-        let serialized_cert = match cert {
-            Ok(cert) => {
-                let serialized = format!("{}", cert);
-
-                // Sanity check that we can parse the cert we just generated
-                if let Err(e) = Certificate::from_string(&serialized) {
-                    debug!("Offending Public Key: {}", ssh_pubkey);
-                    debug!("Offending certificate: {}", serialized);
-                    rustica_error!(self, format!("Couldn't deserialize certificate: {}", e));
-                    return Ok(create_response(RusticaServerError::BadCertOptions));
-                }
-                serialized
-            }
-            Err(e) => {
-                rustica_error!(self, format!("Creating certificate failed: {}", e));
-                return Ok(create_response(RusticaServerError::BadChallenge));
-            }
+        let issuance = SshCertificateIssuance {
+            ssh_pubkey,
+            fingerprint: fingerprint.clone(),
+            cert_type: req_cert_type,
+            authority: authority.clone(),
+            authorization,
+            requested_extensions: request.extensions.clone(),
+            requested_critical_options: request.critical_options.clone(),
+            mtls_identities: mtls_identities.clone(),
+            access_serial: client.serial,
+            access_fingerprint: client.fingerprint,
+            remote_addr,
         };
 
         let mut reply = CertificateResponse {
-            certificate: serialized_cert,
+            certificate: String::new(),
             error: String::new(),
             error_code: RusticaServerError::Success as i64,
             new_client_certificate: String::new(),
             new_client_key: String::new(),
+            approval_request_id: String::new(),
         };
 
-        if let (Some(settings), Ok(Some(ca))) = (
-            &mtls_refresh,
-            self.signer
-                .get_client_certificate_authority(&self.client_authority.authority),
-        ) {
-            let mut params = rcgen::CertificateParams::new(mtls_identities.clone());
-            params.not_before = (UNIX_EPOCH + Duration::from_secs(settings.not_before)).into();
-            params.not_after = (UNIX_EPOCH + Duration::from_secs(settings.not_after)).into();
-            params.distinguished_name.push(
-                DnType::CommonName,
-                mtls_identities
-                    .get(0)
-                    .map(|x| x.to_owned())
-                    .unwrap_or_default(),
-            );
+        // A problem renewing the access certificate does not stop the SSH
+        // certificate from being returned. The client will try again on its
+        // next request.
+        if let (Some(settings), Some(presented)) = (&mtls_refresh, &client.certificate) {
+            match issue_access_certificate(self, &request.access_csr, presented, settings) {
+                Ok((certificate, key)) => {
+                    reply.new_client_certificate = certificate;
+                    reply.new_client_key = key;
+                }
+                Err(e) => {
+                    rustica_warning!(
+                        self,
+                        format!(
+                            "Could not renew the access certificate for [{}]: {e}",
+                            mtls_identities.join(","),
+                        )
+                    );
+                }
+            }
+        }
+
+        // The certificate is held until enough approvers approve it. The
+        // client collects it with ApprovedCertificate.
+        if let Some(requirement) = issuance
+            .authorization
+            .approval
+            .clone()
+            .filter(|x| x.required > 0)
+        {
+            let principals = issuance.authorization.principals.clone();
+            let request_id = match self.approvals.submit(issuance, requirement.clone()) {
+                Ok(request_id) => request_id,
+                Err(e) => {
+                    rustica_error!(
+                        self,
+                        format!(
+                            "Could not hold the request from [{}] for key [{fingerprint}] for approval: {e}",
+                            mtls_identities.join(","),
+                        )
+                    );
+                    return Ok(create_response(RusticaServerError::Unknown));
+                }
+            };
+
+            let _ = self
+                .log_sender
+                .send(Log::ApprovalRequested(ApprovalRequested {
+                    request_id: request_id.clone(),
+                    fingerprint,
+                    authority: authority.to_string(),
+                    certificate_type: req_cert_type.to_string(),
+                    mtls_identities,
+                    principals,
+                    approvers: requirement.approvers,
+                    required_approvals: requirement.required,
+                }));
+
+            reply.error = format!("{:?}", RusticaServerError::PendingApproval);
+            reply.error_code = RusticaServerError::PendingApproval as i64;
+            reply.approval_request_id = request_id;
+            return Ok(Response::new(reply));
+        }
+
+        let new_access_certificate_issued = !reply.new_client_certificate.is_empty();
+        match issue_ssh_certificate(self, &issuance, &[], new_access_certificate_issued).await {
+            Ok(certificate) => reply.certificate = certificate,
+            Err(e) => return Ok(create_response(e)),
+        }
 
-            let new_certificate = rcgen::Certificate::from_params(params).unwrap();
+        Ok(Response::new(reply))
+    }
+
+    /// Handler used when a client renews its access certificate without
+    /// requesting an SSH certificate
+    async fn renew_access_credential(
+        &self,
+        request: Request<RenewAccessCredentialRequest>,
+    ) -> Result<Response<RenewAccessCredentialResponse>, Status> {
+        let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
+        let client = identify(self, &request).await;
+        let request = request.into_inner();
 
-            reply.new_client_key = new_certificate.serialize_private_key_pem();
-            reply.new_client_certificate = new_certificate.serialize_pem_with_signer(ca).unwrap();
+        let (challenge, client) = match (&request.challenge, client) {
+            (Some(challenge), Ok(client)) => (challenge, client),
+            _ => return Err(Status::permission_denied("")),
         };
 
+        let (ssh_pubkey, mtls_refresh) =
+            validate_request(self, &client, challenge).map_err(|e| {
+                metrics::server_error(&e);
+                Status::permission_denied("")
+            })?;
+        let mtls_identities = client.identities;
+
+        // Only keys generated by the client are accepted here
+        if request.csr.is_empty() {
+            return Err(Status::invalid_argument("A CSR is required"));
+        }
+
+        let settings = match mtls_refresh {
+            Some(settings) => settings,
+            None => {
+                return Ok(Response::new(RenewAccessCredentialResponse {
+                    certificate: String::new(),
+                }))
+            }
+        };
+
+        // A key revoked in any authority cannot be used to keep access
+        let fingerprint = ssh_pubkey.fingerprint().hash;
+        if let Some(revocation) = &self.revocation {
+            for authority in self.signer.get_authorities() {
+                if revocation.is_key_revoked(&authority, &fingerprint).await {
+                    rustica_warning!(
+                        self,
+                        format!(
+                            "[{}] from [{}] tried to renew their access certificate with revoked key [{}]",
+                            mtls_identities.join(","),
+                            remote_addr,
+                            fingerprint,
+                        )
+                    );
+                    return Err(Status::permission_denied(""));
+                }
+            }
+        }
+
+        // Only clients with an access certificate are ever asked to renew it
+        let presented = client
+            .certificate
+            .as_deref()
+            .ok_or(Status::permission_denied(""))?;
+
+        let (certificate, _) =
+            issue_access_certificate(self, &request.csr, presented, &settings).map_err(
+                |e| {
+                    rustica_warning!(
+                        self,
+                        format!(
+                            "Could not renew the access certificate for [{}]: {e}",
+                            mtls_identities.join(","),
+                        )
+                    );
+                    Status::invalid_argument("")
+                },
+            )?;
+
         let _ = self
             .log_sender
-            .send(Log::CertificateIssued(CertificateIssued {
+            .send(Log::AccessCredentialRenewed(AccessCredentialRenewed {
                 fingerprint,
-                signed_by: ca_cert.fingerprint().hash,
-                authority: authority.to_string(),
-                serial: authorization.serial,
-                certificate_type: req_cert_type.to_string(),
                 mtls_identities,
-                principals: authorization.principals,
-                extensions: authorization.extensions,
-                critical_options,
-                valid_after: authorization.valid_after,
-                valid_before: authorization.valid_before,
-                new_access_certificate_issued: mtls_refresh.is_some(),
+                valid_after: settings.not_before,
+                valid_before: settings.not_after,
             }));
 
-        Ok(Response::new(reply))
+        Ok(Response::new(RenewAccessCredentialResponse { certificate }))
     }
 
     async fn register_key(
@@ -657,22 +1702,22 @@ impl Rustica for RusticaServer {
             None => String::new(),
         };
 
-        let peer = request.peer_certs();
+        let client = identify(self, &request).await;
         let request = request.into_inner();
 
-        let (challenge, peer) = match (&request.challenge, peer) {
-            (Some(challenge), Some(peer)) => (challenge, peer),
+        let (challenge, client) = match (&request.challenge, client) {
+            (Some(challenge), Ok(client)) => (challenge, client),
             _ => return Err(Status::permission_denied("")),
         };
 
-        let (ssh_pubkey, mtls_identities, _) =
-            match validate_request(self, &self.hmac_key, &peer, challenge) {
-                Ok(x) => x,
-                Err(e) => {
-                    rustica_error!(self, format!("Could not validate request: {:?}", e));
-                    return Err(Status::cancelled(""));
-                }
-            };
+        let (ssh_pubkey, _) = match validate_request(self, &client, challenge) {
+            Ok(x) => x,
+            Err(e) => {
+                rustica_error!(self, format!("Could not validate request: {:?}", e));
+                return Err(Status::cancelled(""));
+            }
+        };
+        let mtls_identities = client.identities;
 
         let (fingerprint, attestation) = match verify_piv_certificate_chain(
             &request.certificate,
@@ -721,6 +1766,7 @@ impl Rustica for RusticaServer {
             fingerprint: fingerprint.clone(),
             pubkey: ssh_pubkey.to_string(),
             mtls_identities: mtls_identities.clone(),
+            identity_attributes: client.attributes,
             requester_ip,
             attestation,
         };
@@ -761,19 +1807,19 @@ impl Rustica for RusticaServer {
             None => String::new(),
         };
 
-        let peer = request.peer_certs();
+        let client = identify(self, &request).await;
         let request = request.into_inner();
 
-        let (challenge, peer) = match (&request.challenge, peer) {
-            (Some(challenge), Some(peer)) => (challenge, peer),
+        let (challenge, client) = match (&request.challenge, client) {
+            (Some(challenge), Ok(client)) => (challenge, client),
             _ => return Err(Status::permission_denied("")),
         };
 
-        let (ssh_pubkey, mtls_identities, _) =
-            match validate_request(self, &self.hmac_key, &peer, challenge) {
-                Ok(x) => x,
-                Err(e) => return Err(Status::cancelled(format!("{:?}", e))),
-            };
+        let (ssh_pubkey, _) = match validate_request(self, &client, challenge) {
+            Ok(x) => x,
+            Err(e) => return Err(Status::cancelled(format!("{:?}", e))),
+        };
+        let mtls_identities = client.identities;
 
         let (fingerprint, attestation) = match verify_u2f_certificate_chain(
             &request.auth_data,
@@ -827,6 +1873,7 @@ impl Rustica for RusticaServer {
             fingerprint: fingerprint.clone(),
             pubkey: ssh_pubkey.to_string(),
             mtls_identities: mtls_identities.clone(),
+            identity_attributes: client.attributes,
             requester_ip,
             attestation,
         };
@@ -867,27 +1914,17 @@ impl Rustica for RusticaServer {
     ) -> Result<Response<AttestedX509CertificateResponse>, Status> {
         let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
 
-        let peer_certs = request.peer_certs().ok_or(Status::permission_denied(""))?;
-
-        let peer = if let Some(peer) = peer_certs.get(0) {
-            if peer_certs.len() != 1 {
-                rustica_warning!(
-                    self,
-                    format!("Received request with multiple peer identities from {remote_addr}")
-                );
-                return Err(Status::permission_denied(""));
-            }
-            peer
-        } else {
-            rustica_error!(
+        if request.peer_certs().is_some_and(|certs| certs.len() > 1) {
+            rustica_warning!(
                 self,
-                format!("No peer certificate was presented. Tonic issue?")
+                format!("Received request with multiple peer identities from {remote_addr}")
             );
             return Err(Status::permission_denied(""));
-        };
+        }
 
-        let cert_info =
-            extract_certificate_information(&peer).map_err(|_| Status::permission_denied(""))?;
+        let cert_info = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?;
         let request = request.into_inner();
 
         let key =
@@ -904,6 +1941,7 @@ impl Rustica for RusticaServer {
         let auth_props = X509AuthorizationRequestProperties {
             authority: authority.to_owned(),
             mtls_identities: cert_info.identities.clone(),
+            identity_attributes: cert_info.attributes,
             requester_ip: remote_addr.to_string(),
             attestation: request.attestation.to_vec(),
             attestation_intermediate: request.attestation_intermediate.to_vec(),
@@ -943,7 +1981,11 @@ impl Rustica for RusticaServer {
             }
         };
 
-        csr.params.subject_alt_names = vec![SanType::Rfc822Name(authorization.common_name.clone())];
+        csr.params.subject_alt_names = authorization
+            .sans
+            .iter()
+            .map(|san| SanType::Rfc822Name(san.clone()))
+            .collect();
         csr.params.serial_number = Some(rcgen::SerialNumber::from_slice(
             &authorization.serial.to_le_bytes(),
         ));
@@ -1040,27 +2082,67 @@ impl Rustica for RusticaServer {
             return Err(Status::permission_denied(""));
         }
 
+        let extensions: HashMap<String, String> = authorization
+            .extensions
+            .iter()
+            .map(|e| {
+                (
+                    format!(
+                        "{}",
+                        e.oid_components()
+                            .map(|x| x.to_string())
+                            .collect::<Vec<String>>()
+                            .join(".")
+                    ),
+                    format!("{}", hex::encode(e.content())),
+                )
+            })
+            .collect();
+
+        if let Some(ledger) = &self.ledger {
+            let issued_at = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|ts| ts.as_secs())
+                .unwrap_or_default();
+
+            let issued = ledger::IssuedCertificate {
+                certificate_type: String::from("X509"),
+                serial: authorization.serial.to_string(),
+                fingerprint: auth_props.key.fingerprint.clone(),
+                signed_by: authorization.issuer.clone(),
+                // The authority that signed, which the authorizer may have
+                // chosen instead of the one requested
+                authority: authorization.authority.clone(),
+                mtls_identities: cert_info.identities.clone(),
+                principals: authorization.sans.clone(),
+                extensions: extensions.clone(),
+                critical_options: HashMap::new(),
+                valid_after: authorization.valid_after,
+                valid_before: authorization.valid_before,
+                issued_at,
+                requester_ip: remote_addr.ip().to_string(),
+            };
+
+            if let Err(e) = ledger.record(issued).await {
+                rustica_error!(
+                    self,
+                    format!(
+                        "Could not record X509 certificate for [{}] in the ledger: {e}",
+                        cert_info.identities.join(","),
+                    )
+                );
+                return Err(Status::internal(""));
+            }
+        }
+
+        metrics::certificate_issued(authority, "x509");
+
         let _ = self
             .log_sender
             .send(Log::X509CertificateIssued(X509CertificateIssued {
                 authority: authority.to_string(),
                 mtls_identities: cert_info.identities,
-                extensions: authorization
-                    .extensions
-                    .iter()
-                    .map(|e| {
-                        (
-                            format!(
-                                "{}",
-                                e.oid_components()
-                                    .map(|x| x.to_string())
-                                    .collect::<Vec<String>>()
-                                    .join(".")
-                            ),
-                            format!("{}", hex::encode(e.content())),
-                        )
-                    })
-                    .collect(),
+                extensions,
                 valid_after: authorization.valid_after,
                 valid_before: authorization.valid_before,
                 serial: authorization.serial,
@@ -1081,27 +2163,9 @@ impl Rustica for RusticaServer {
     ) -> Result<Response<AllowedSignersResponse>, Status> {
         let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
 
-        let peer = request.peer_certs();
-
-        let peer = peer.ok_or(Status::permission_denied(""))?;
-
-        // Only support the presenting of a single client certificate
-        // I've never seen anyone handle multiple ones and since we don't
-        // need to here, trying to support it will only lead to validation
-        // issues or inconsistencies.
-        let cert = if let Some(cert) = peer.get(0) {
-            cert
-        } else {
-            return Err(Status::permission_denied(""));
-        };
-
-        let cert_info = match extract_certificate_information(cert) {
-            Ok(cert_info) => cert_info,
-            Err(e) => {
-                rustica_error!(self, format!("Could not validate request: {:?}", e));
-                return Err(Status::cancelled(""));
-            }
-        };
+        let cert_info = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?;
 
         let mtls_identities = cert_info.identities.join(",");
 
@@ -1121,6 +2185,7 @@ impl Rustica for RusticaServer {
         };
 
         if is_rate_limited(self, mtls_identities.clone(), current_time).await {
+            metrics::allowed_signers_rate_limited();
             info!(
                 "[{}] from [{}] is rate limited for allowed_signers call",
                 mtls_identities,
@@ -1136,6 +2201,7 @@ impl Rustica for RusticaServer {
 
             // Cache still valid
             if current_time <= cache.expiry_timestamp {
+                metrics::allowed_signers_cache(true);
                 let reply = AllowedSignersResponse {
                     compressed_allowed_signers: cache.compressed_allowed_signers.clone(),
                 };
@@ -1151,12 +2217,15 @@ impl Rustica for RusticaServer {
 
         // Cache has been refreshed while we waited on the write lock
         if current_time <= cache.expiry_timestamp {
+            metrics::allowed_signers_cache(true);
             let reply = AllowedSignersResponse {
                 compressed_allowed_signers: cache.compressed_allowed_signers.clone(),
             };
             return Ok(Response::new(reply));
         }
 
+        metrics::allowed_signers_cache(false);
+
         // Refresh the cache by fetching a new list of signers from the authorizer
         let response = match self.authorizer.get_allowed_signers().await {
             Ok(response) => response,
@@ -1212,4 +2281,847 @@ impl Rustica for RusticaServer {
 
         Ok(Response::new(reply))
     }
+
+    /// Handler used by admins to revoke previously issued certificates or keys
+    async fn revoke_certificates(
+        &self,
+        request: Request<RevokeCertificatesRequest>,
+    ) -> Result<Response<RevokeCertificatesResponse>, Status> {
+        let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
+        let mtls_identities = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?
+            .identities;
+
+        if !is_admin(&self.admin, &mtls_identities) {
+            rustica_warning!(
+                self,
+                format!(
+                    "[{}] from [{}] tried to revoke certificates but is not an admin",
+                    mtls_identities.join(","),
+                    remote_addr,
+                )
+            );
+            return Err(Status::permission_denied(""));
+        }
+
+        let revocation = self
+            .revocation
+            .as_ref()
+            .ok_or(Status::failed_precondition("Revocation is not configured"))?;
+
+        let request = request.into_inner();
+        let authority = if request.authority.is_empty() {
+            &self.signer.default_authority
+        } else {
+            &request.authority
+        };
+
+        if !self.signer.get_authorities().contains(authority) {
+            return Err(Status::invalid_argument("Unknown authority"));
+        }
+
+        let krl_version = match revocation
+            .revoke(
+                authority,
+                &request.serials,
+                &request.fingerprints,
+                &request.certificate_key_ids,
+            )
+            .await
+        {
+            Ok(version) => version,
+            Err(e) => {
+                rustica_error!(
+                    self,
+                    format!(
+                        "Could not revoke certificates for [{}] from [{}]: {e}",
+                        mtls_identities.join(","),
+                        remote_addr,
+                    )
+                );
+                return Err(Status::invalid_argument(e.to_string()));
+            }
+        };
+
+        let _ = self
+            .log_sender
+            .send(Log::CertificatesRevoked(CertificatesRevoked {
+                authority: authority.to_string(),
+                mtls_identities,
+                serials: request.serials,
+                fingerprints: request.fingerprints,
+                key_ids: request.certificate_key_ids,
+                krl_version,
+            }));
+
+        Ok(Response::new(RevokeCertificatesResponse { krl_version }))
+    }
+
+    /// Handler used by hosts to fetch the KRL for an authority
+    async fn revoked_keys(
+        &self,
+        request: Request<RevokedKeysRequest>,
+    ) -> Result<Response<RevokedKeysResponse>, Status> {
+        let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
+        let mtls_identities = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?
+            .identities;
+
+        let revocation = self
+            .revocation
+            .as_ref()
+            .ok_or(Status::failed_precondition("Revocation is not configured"))?;
+
+        let request = request.into_inner();
+        let authority = if request.authority.is_empty() {
+            &self.signer.default_authority
+        } else {
+            &request.authority
+        };
+
+        if !self.signer.get_authorities().contains(authority) {
+            return Err(Status::invalid_argument("Unknown authority"));
+        }
+
+        debug!(
+            "[{}] from [{}] requested the KRL for authority [{}]",
+            mtls_identities.join(","),
+            remote_addr,
+            authority,
+        );
+
+        // Include every SSH key the authority publishes so both user and
+        // host certificates are covered by the same KRL, including ones
+        // signed by keys that are being rotated out
+        let ca_keys: Vec<PublicKey> = [CertType::User, CertType::Host]
+            .into_iter()
+            .filter_map(|cert_type| self.signer.get_trusted_public_keys(authority, cert_type).ok())
+            .flatten()
+            .collect();
+
+        let (krl, krl_version) = match revocation.generate_krl(authority, &ca_keys).await {
+            Ok(krl) => krl,
+            Err(e) => {
+                rustica_error!(self, format!("Could not generate KRL for [{authority}]: {e}"));
+                return Err(Status::internal(""));
+            }
+        };
+
+        Ok(Response::new(RevokedKeysResponse { krl, krl_version }))
+    }
+
+    /// Handler used by admins to revoke mTLS access certificates
+    async fn revoke_access_credentials(
+        &self,
+        request: Request<RevokeAccessCredentialsRequest>,
+    ) -> Result<Response<RevokeAccessCredentialsResponse>, Status> {
+        let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
+        let mtls_identities = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?
+            .identities;
+
+        if !is_admin(&self.admin, &mtls_identities) {
+            rustica_warning!(
+                self,
+                format!(
+                    "[{}] from [{}] tried to revoke access credentials but is not an admin",
+                    mtls_identities.join(","),
+                    remote_addr,
+                )
+            );
+            return Err(Status::permission_denied(""));
+        }
+
+        let revocation = self
+            .revocation
+            .as_ref()
+            .ok_or(Status::failed_precondition("Revocation is not configured"))?;
+
+        let request = request.into_inner();
+        let access = AccessRevocations {
+            serials: request.serials.iter().cloned().collect(),
+            fingerprints: request.fingerprints.iter().cloned().collect(),
+            identities: request.identities.iter().cloned().collect(),
+        };
+
+        let crl_number = match revocation.revoke_access(&access).await {
+            Ok(version) => version,
+            Err(e) => {
+                rustica_error!(
+                    self,
+                    format!(
+                        "Could not revoke access credentials for [{}] from [{}]: {e}",
+                        mtls_identities.join(","),
+                        remote_addr,
+                    )
+                );
+                return Err(Status::invalid_argument(e.to_string()));
+            }
+        };
+
+        let _ = self
+            .log_sender
+            .send(Log::AccessCredentialsRevoked(AccessCredentialsRevoked {
+                mtls_identities,
+                serials: request.serials,
+                fingerprints: request.fingerprints,
+                identities: request.identities,
+                crl_number,
+            }));
+
+        Ok(Response::new(RevokeAccessCredentialsResponse { crl_number }))
+    }
+
+    /// Handler used by services that trust the client certificate authority
+    /// to fetch the CRL of revoked access certificates
+    async fn access_crl(
+        &self,
+        request: Request<AccessCrlRequest>,
+    ) -> Result<Response<AccessCrlResponse>, Status> {
+        let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
+        let mtls_identities = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?
+            .identities;
+
+        let revocation = self
+            .revocation
+            .as_ref()
+            .ok_or(Status::failed_precondition("Revocation is not configured"))?;
+
+        debug!(
+            "[{}] from [{}] requested the access certificate CRL",
+            mtls_identities.join(","),
+            remote_addr,
+        );
+
+        let (serials, crl_number) = revocation.revoked_access_serials().await;
+        let crl = match generate_access_crl(self, &serials, crl_number, revocation.crl_validity()) {
+            Ok(crl) => crl,
+            Err(e) => {
+                rustica_error!(self, format!("Could not generate access certificate CRL: {e}"));
+                return Err(Status::internal(""));
+            }
+        };
+
+        Ok(Response::new(AccessCrlResponse { crl, crl_number }))
+    }
+
+    /// Handler used by approvers to find the requests waiting for them
+    async fn list_pending_approvals(
+        &self,
+        request: Request<ListPendingApprovalsRequest>,
+    ) -> Result<Response<ListPendingApprovalsResponse>, Status> {
+        let mtls_identities = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?
+            .identities;
+
+        let approvals = self
+            .approvals
+            .list(&mtls_identities, is_admin(&self.admin, &mtls_identities))
+            .into_iter()
+            .map(pending_approval)
+            .collect();
+
+        Ok(Response::new(ListPendingApprovalsResponse { approvals }))
+    }
+
+    /// Handler used by approvers to approve or deny a request. The
+    /// certificate is issued by the review that completes the quorum.
+    async fn review_pending_approval(
+        &self,
+        request: Request<ReviewPendingApprovalRequest>,
+    ) -> Result<Response<ReviewPendingApprovalResponse>, Status> {
+        let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
+        let mtls_identities = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?
+            .identities;
+        let request = request.into_inner();
+
+        let (approver, outcome) = match self.approvals.review(
+            &request.request_id,
+            &mtls_identities,
+            request.approve,
+            &request.reason,
+        ) {
+            Ok(review) => review,
+            Err(e) => {
+                rustica_warning!(
+                    self,
+                    format!(
+                        "[{}] from [{}] could not review request [{}]: {e}",
+                        mtls_identities.join(","),
+                        remote_addr,
+                        request.request_id,
+                    )
+                );
+                return Err(match e {
+                    ApprovalError::UnknownRequest => Status::not_found(""),
+                    ApprovalError::NotAnApprover | ApprovalError::OwnRequest => {
+                        Status::permission_denied(e.to_string())
+                    }
+                    _ => Status::failed_precondition(e.to_string()),
+                });
+            }
+        };
+
+        let (state, approvals, required) = match &outcome {
+            ReviewOutcome::Pending {
+                approvals,
+                required,
+            } => (ApprovalState::Pending, *approvals, *required),
+            ReviewOutcome::Denied {
+                approvals,
+                required,
+            } => (ApprovalState::Denied, *approvals, *required),
+            ReviewOutcome::Approved(approved) => (
+                ApprovalState::Approved,
+                approved.approved_by.len(),
+                approved.requirement.required,
+            ),
+        };
+
+        let _ = self
+            .log_sender
+            .send(Log::ApprovalReviewed(ApprovalReviewed {
+                request_id: request.request_id.clone(),
+                approver,
+                approved: request.approve,
+                reason: request.reason,
+                approvals,
+                required_approvals: required,
+            }));
+
+        if let ReviewOutcome::Approved(approved) = outcome {
+            let mut issuance = approved.issuance;
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0);
+            match approved_window(
+                issuance.authorization.valid_after,
+                issuance.authorization.valid_before,
+                approved.requested_at,
+                now,
+            ) {
+                Some((valid_after, valid_before)) => {
+                    issuance.authorization.valid_after = valid_after;
+                    issuance.authorization.valid_before = valid_before;
+                }
+                None => {
+                    let e = "The authorized validity ran out before the request was approved";
+                    self.approvals.complete(&approved.id, Err(e.to_owned()));
+                    return Err(Status::failed_precondition(e));
+                }
+            }
+
+            // The key or the requester's access may have been revoked while
+            // the request waited
+            if let Err(e) = check_issuance_revocation(self, &issuance).await {
+                self.approvals.complete(&approved.id, Err(e.to_owned()));
+                return Err(Status::failed_precondition(e));
+            }
+
+            match issue_ssh_certificate(self, &issuance, &approved.approved_by, false).await {
+                Ok(certificate) => self.approvals.complete(&approved.id, Ok(certificate)),
+                Err(e) => {
+                    rustica_error!(
+                        self,
+                        format!("Could not issue the approved certificate for request [{}]: {:?}", approved.id, e)
+                    );
+                    self.approvals.complete(
+                        &approved.id,
+                        Err(String::from("The certificate could not be issued")),
+                    );
+                    return Err(Status::internal(""));
+                }
+            }
+        }
+
+        Ok(Response::new(ReviewPendingApprovalResponse {
+            state: state as i32,
+            approvals: approvals as u32,
+        }))
+    }
+
+    /// Handler polled by a requester to collect their certificate once it
+    /// has been approved
+    async fn approved_certificate(
+        &self,
+        request: Request<ApprovedCertificateRequest>,
+    ) -> Result<Response<ApprovedCertificateResponse>, Status> {
+        let mtls_identities = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?
+            .identities;
+        let request = request.into_inner();
+
+        let state = self
+            .approvals
+            .status(&request.request_id, &mtls_identities)
+            .map_err(|_| Status::not_found(""))?;
+
+        let mut reply = ApprovedCertificateResponse {
+            state: ApprovalState::Pending as i32,
+            certificate: String::new(),
+            reason: String::new(),
+        };
+
+        match state {
+            approval::ApprovalState::Pending | approval::ApprovalState::Issuing => (),
+            approval::ApprovalState::Issued(certificate) => {
+                reply.state = ApprovalState::Approved as i32;
+                reply.certificate = certificate;
+            }
+            approval::ApprovalState::Denied(reason) => {
+                reply.state = ApprovalState::Denied as i32;
+                reply.reason = reason;
+            }
+        }
+
+        Ok(Response::new(reply))
+    }
+
+    /// Handler used by hosts and clients to fetch what they need to trust
+    /// certificates issued by each authority
+    async fn get_authorities(
+        &self,
+        request: Request<AuthoritiesRequest>,
+    ) -> Result<Response<AuthoritiesResponse>, Status> {
+        // Clients must identify like they do for every other call, so a
+        // revoked access certificate cannot be used here either
+        identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?;
+
+        let request = request.into_inner();
+        let known_hosts_pattern = if request.known_hosts_pattern.is_empty() {
+            "*"
+        } else {
+            &request.known_hosts_pattern
+        };
+
+        // The pattern ends up in a known_hosts file so it must not be able
+        // to add more lines or fields to it
+        if known_hosts_pattern.contains(char::is_whitespace) {
+            return Err(Status::invalid_argument("Invalid known_hosts pattern"));
+        }
+
+        let authorities = self
+            .trust_material
+            .iter()
+            .map(|material| {
+                // Comment the keys with their authority so they can be told
+                // apart once they are installed
+                let comment = |key: &PublicKey| {
+                    PublicKey {
+                        comment: Some(format!("rustica-{}", material.authority)),
+                        ..key.clone()
+                    }
+                    .to_string()
+                };
+
+                let active = |keys: &[(KeyState, PublicKey)]| {
+                    keys.iter()
+                        .find(|(state, _)| *state == KeyState::Active)
+                        .map(|(_, key)| key.to_string())
+                        .unwrap_or_default()
+                };
+
+                let authority_keys = |keys: &[(KeyState, PublicKey)]| {
+                    keys.iter()
+                        .map(|(state, key)| AuthorityKey {
+                            public_key: key.to_string(),
+                            state: state.to_string(),
+                        })
+                        .collect()
+                };
+
+                Authority {
+                    name: material.authority.clone(),
+                    is_default: material.authority == self.signer.default_authority,
+                    user_ca_public_key: active(&material.user_cas),
+                    host_ca_public_key: active(&material.host_cas),
+                    attested_x509_certificate_authority: material
+                        .attested_x509_certificate_authority
+                        .clone()
+                        .unwrap_or_default(),
+                    client_certificate_authority: material
+                        .client_certificate_authority
+                        .clone()
+                        .unwrap_or_default(),
+                    trusted_user_ca_keys_line: material
+                        .user_cas
+                        .iter()
+                        .map(|(_, key)| comment(key))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    known_hosts_line: material
+                        .host_cas
+                        .iter()
+                        .map(|(_, key)| format!("@cert-authority {known_hosts_pattern} {}", comment(key)))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    user_ca_keys: authority_keys(&material.user_cas),
+                    host_ca_keys: authority_keys(&material.host_cas),
+                }
+            })
+            .collect();
+
+        Ok(Response::new(AuthoritiesResponse { authorities }))
+    }
+
+    /// Handler used by admins to list recently issued certificates
+    async fn list_issued_certificates(
+        &self,
+        request: Request<ListIssuedCertificatesRequest>,
+    ) -> Result<Response<IssuedCertificatesResponse>, Status> {
+        let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
+        let mtls_identities = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?
+            .identities;
+
+        let request = request.into_inner();
+        let query = LedgerQuery {
+            issued_after: non_zero(request.issued_after),
+            issued_before: non_zero(request.issued_before),
+            limit: request.limit,
+            ..Default::default()
+        };
+
+        search_ledger(self, &mtls_identities, remote_addr, query).await
+    }
+
+    /// Handler used by admins to search issued certificates
+    async fn search_issued_certificates(
+        &self,
+        request: Request<SearchIssuedCertificatesRequest>,
+    ) -> Result<Response<IssuedCertificatesResponse>, Status> {
+        let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
+        let mtls_identities = identify(self, &request)
+            .await
+            .map_err(|_| Status::permission_denied(""))?
+            .identities;
+
+        let request = request.into_inner();
+        let query = LedgerQuery {
+            identity: non_empty(request.identity),
+            fingerprint: non_empty(request.fingerprint),
+            serial: non_empty(request.serial),
+            authority: non_empty(request.authority),
+            issued_after: non_zero(request.issued_after),
+            issued_before: non_zero(request.issued_before),
+            limit: request.limit,
+        };
+
+        search_ledger(self, &mtls_identities, remote_addr, query).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::num::NonZeroUsize;
+
+    const KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIKNO7QPgbPauWmF8nfTV6fkYd2fUtxvajI96bjDXE0oM";
+
+    fn map(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// The extensions a policy typically allows
+    fn allowed_extensions() -> HashMap<String, String> {
+        map(&[
+            ("permit-pty", ""),
+            ("permit-port-forwarding", ""),
+            ("permit-agent-forwarding", ""),
+        ])
+    }
+
+    fn window(validity: u64, clock_skew: u64) -> ChallengeWindowConfiguration {
+        ChallengeWindowConfiguration {
+            validity,
+            clock_skew,
+            replay_cache_size: NonZeroUsize::new(2).unwrap(),
+        }
+    }
+
+    #[test]
+    fn challenges_are_accepted_within_the_window() {
+        let window = window(5, 0);
+        assert!(challenge_in_window(&window, 1000, 1000));
+        assert!(challenge_in_window(&window, 1000, 1005));
+        assert!(!challenge_in_window(&window, 1000, 1006));
+        // A challenge from the future is never accepted without skew
+        assert!(!challenge_in_window(&window, 1001, 1000));
+        assert!(!challenge_in_window(&window, u64::MAX, 1000));
+        assert!(!challenge_in_window(&window, 0, u64::MAX));
+    }
+
+    #[test]
+    fn clock_skew_is_allowed_in_both_directions() {
+        let window = window(5, 3);
+        // Issued by a replica whose clock is ahead of ours
+        assert!(challenge_in_window(&window, 1003, 1000));
+        assert!(!challenge_in_window(&window, 1004, 1000));
+        // Issued by a replica whose clock is behind ours
+        assert!(challenge_in_window(&window, 1000, 1008));
+        assert!(!challenge_in_window(&window, 1000, 1009));
+    }
+
+    #[test]
+    fn challenges_can_only_be_redeemed_once() {
+        let mut redeemed = LruCache::new(NonZeroUsize::new(2).unwrap());
+        assert_eq!(remember_redeemed(&mut redeemed, vec![1], 1005, 1000), Redemption::Redeemed);
+        assert_eq!(remember_redeemed(&mut redeemed, vec![1], 1005, 1001), Redemption::Replayed);
+        assert_eq!(remember_redeemed(&mut redeemed, vec![2], 1005, 1001), Redemption::Redeemed);
+        assert_eq!(remember_redeemed(&mut redeemed, vec![2], 1005, 1002), Redemption::Replayed);
+    }
+
+    #[test]
+    fn a_full_cache_forgets_the_oldest_challenge() {
+        let mut redeemed = LruCache::new(NonZeroUsize::new(2).unwrap());
+        remember_redeemed(&mut redeemed, vec![1], 1005, 1000);
+        remember_redeemed(&mut redeemed, vec![2], 1006, 1001);
+
+        // Nothing has expired yet but new challenges are still accepted
+        assert_eq!(
+            remember_redeemed(&mut redeemed, vec![3], 1007, 1002),
+            Redemption::EvictedUnexpired
+        );
+        let forgotten = vec![1];
+        assert!(!redeemed.contains(&forgotten));
+        assert_eq!(remember_redeemed(&mut redeemed, vec![2], 1006, 1002), Redemption::Replayed);
+        assert_eq!(remember_redeemed(&mut redeemed, vec![3], 1007, 1002), Redemption::Replayed);
+
+        // Forgetting an expired challenge is not worth reporting
+        assert_eq!(remember_redeemed(&mut redeemed, vec![4], 1020, 1010), Redemption::Redeemed);
+    }
+
+    #[test]
+    fn requested_extensions_are_cut_to_the_authorized_set() {
+        let options = apply_requested_options(
+            allowed_extensions(),
+            HashMap::new(),
+            &map(&[("permit-pty", ""), ("permit-X11-forwarding", "")]),
+            &HashMap::new(),
+        );
+
+        // Extensions the authorizer did not allow are never added
+        assert_eq!(options.extensions, map(&[("permit-pty", "")]));
+        assert_eq!(
+            options.declined_extensions,
+            vec!["permit-agent-forwarding", "permit-port-forwarding"]
+        );
+    }
+
+    #[test]
+    fn empty_request_keeps_the_authorized_options() {
+        let options = apply_requested_options(
+            allowed_extensions(),
+            map(&[("force-command", "ls")]),
+            &HashMap::new(),
+            &HashMap::new(),
+        );
+
+        assert_eq!(options.extensions, allowed_extensions());
+        assert_eq!(options.critical_options, map(&[("force-command", "ls")]));
+        assert!(options.declined_extensions.is_empty());
+        assert!(options.added_critical_options.is_empty());
+    }
+
+    #[test]
+    fn clients_can_only_add_restricting_critical_options() {
+        let options = apply_requested_options(
+            allowed_extensions(),
+            map(&[("force-command", "ls")]),
+            &HashMap::new(),
+            &map(&[
+                ("force-command", "bash"),
+                ("source-address", "10.0.0.1"),
+                ("no-touch-required", ""),
+            ]),
+        );
+
+        // The authorizer's force-command cannot be replaced and only known
+        // restrictions are added
+        assert_eq!(
+            options.critical_options,
+            map(&[("force-command", "ls"), ("source-address", "10.0.0.1")])
+        );
+        assert_eq!(options.added_critical_options, vec!["source-address"]);
+        assert_eq!(options.extensions, allowed_extensions());
+    }
+
+    #[test]
+    fn reductions_are_logged_when_a_certificate_is_issued() {
+        let ssh_pubkey = PublicKey::from_string(KEY).unwrap();
+        let issuance = SshCertificateIssuance {
+            fingerprint: ssh_pubkey.fingerprint().hash,
+            ssh_pubkey,
+            cert_type: CertType::User,
+            authority: "example".to_owned(),
+            authorization: SshAuthorization {
+                serial: 1,
+                valid_before: 600,
+                valid_after: 0,
+                principals: vec!["root".to_owned()],
+                hosts: None,
+                extensions: allowed_extensions(),
+                force_command: None,
+                force_source_ip: false,
+                authority: "example".to_owned(),
+                approval: None,
+            },
+            requested_extensions: map(&[("permit-pty", "")]),
+            requested_critical_options: map(&[("verify-required", "")]),
+            mtls_identities: vec!["alice".to_owned()],
+            access_serial: None,
+            access_fingerprint: None,
+            remote_addr: "127.0.0.1:1000".parse().unwrap(),
+        };
+
+        let options = apply_requested_options(
+            issuance.authorization.extensions.clone(),
+            HashMap::new(),
+            &issuance.requested_extensions,
+            &issuance.requested_critical_options,
+        );
+        let log = certificate_issued(&issuance, String::new(), options, &[], false);
+
+        assert_eq!(log.extensions, map(&[("permit-pty", "")]));
+        assert_eq!(
+            log.declined_extensions,
+            vec!["permit-agent-forwarding", "permit-port-forwarding"]
+        );
+        assert_eq!(log.critical_options, map(&[("verify-required", "")]));
+        assert_eq!(log.added_critical_options, vec!["verify-required"]);
+    }
+
+    /// An access certificate with identities in its subject and SANs
+    fn presented_certificate() -> Vec<u8> {
+        let mut params = rcgen::CertificateParams::new(vec!["host.example.com".to_owned()]);
+        params.distinguished_name = DistinguishedName::new();
+        params.distinguished_name.push(DnType::CommonName, "alice");
+        params
+            .distinguished_name
+            .push(DnType::OrganizationalUnitName, "infra");
+        params
+            .subject_alt_names
+            .push(SanType::URI("spiffe://example.com/alice".to_owned()));
+        params
+            .subject_alt_names
+            .push(SanType::Rfc822Name("alice@example.com".to_owned()));
+        rcgen::Certificate::from_params(params)
+            .unwrap()
+            .serialize_der()
+            .unwrap()
+    }
+
+    fn identity_configuration() -> ClientIdentityConfiguration {
+        toml::from_str(
+            r#"
+            [[attributes]]
+            name = "team"
+            source = "dn"
+            oid = "2.5.4.11"
+
+            [[attributes]]
+            name = "spiffe_id"
+            source = "san_uri"
+
+            [[attributes]]
+            name = "email"
+            source = "san_email"
+
+            [[attributes]]
+            name = "host"
+            source = "san_dns"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn renewed_access_certificates_keep_every_identity() {
+        let mut ca_params = rcgen::CertificateParams::new(vec![]);
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+
+        // The CSR asks for its own subject and SANs, none of which may end up
+        // in the renewed certificate
+        let mut csr_params = rcgen::CertificateParams::new(vec!["mallory.example.com".to_owned()]);
+        csr_params.distinguished_name = DistinguishedName::new();
+        csr_params.distinguished_name.push(DnType::CommonName, "mallory");
+        csr_params
+            .subject_alt_names
+            .push(SanType::URI("spiffe://example.com/mallory".to_owned()));
+        let client_key = rcgen::Certificate::from_params(csr_params).unwrap();
+        let csr = client_key.serialize_request_der().unwrap();
+
+        let presented = presented_certificate();
+        let settings = CertificateRefreshSettings {
+            not_after: 2000,
+            not_before: 1000,
+        };
+        let mut csr = rcgen::CertificateSigningRequest::from_der(&csr).unwrap();
+        csr.params = access_certificate_params(&presented, &settings).unwrap();
+        let renewed = csr.serialize_der_with_signer(&ca).unwrap();
+
+        let (_, presented) = x509_parser::parse_x509_certificate(&presented).unwrap();
+        let (_, renewed) = x509_parser::parse_x509_certificate(&renewed).unwrap();
+        let config = identity_configuration();
+        let attributes = extract_identity_attributes(&config, &renewed);
+        assert_eq!(attributes.len(), 4);
+        assert_eq!(attributes, extract_identity_attributes(&config, &presented));
+        assert_eq!(subject_values(&renewed, "2.5.4.3"), vec!["alice"]);
+        assert_eq!(
+            renewed.subject_alternative_name().unwrap().unwrap().value,
+            presented.subject_alternative_name().unwrap().unwrap().value
+        );
+
+        // Only the key and validity come from the CSR and settings
+        assert_eq!(
+            renewed.public_key().subject_public_key.data.as_ref(),
+            client_key.get_key_pair().public_key_raw()
+        );
+        assert_eq!(renewed.validity().not_before.timestamp(), 1000);
+        assert_eq!(renewed.validity().not_after.timestamp(), 2000);
+    }
+
+    #[test]
+    fn approved_windows_start_at_approval() {
+        // Requested at 100 for 100..700 and approved 60 seconds later
+        assert_eq!(approved_window(100, 700, 100, 160), Some((160, 700)));
+        assert_eq!(approved_window(100, 700, 100, 100), Some((100, 700)));
+    }
+
+    #[test]
+    fn approved_windows_never_outlast_the_authorization() {
+        // Waiting would have pushed the end to 1200, past what was authorized
+        assert_eq!(approved_window(100, 700, 100, 600), Some((600, 700)));
+        // The window ran out while the request waited
+        assert_eq!(approved_window(100, 700, 100, 700), None);
+        assert_eq!(approved_window(100, 700, 100, 900), None);
+    }
+
+    #[test]
+    fn unparseable_access_certificates_are_not_renewed() {
+        let settings = CertificateRefreshSettings {
+            not_after: 2000,
+            not_before: 1000,
+        };
+        assert!(access_certificate_params(b"not a certificate", &settings).is_err());
+    }
 }
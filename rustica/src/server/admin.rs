@@ -0,0 +1,637 @@
+/// The RusticaAdmin service manages the grants, registered keys, and X509
+/// authorizations kept by the local database authorizer so admins do not
+/// have to write SQL against it. It is served next to the Rustica service
+/// and every call is limited to the configured admin identities. Every
+/// change is sent to the logging system.
+use super::{identify, is_admin, RusticaServer};
+use crate::auth::database::{models, LocalDatabase};
+use crate::auth::{AuthorizationError, AuthorizationMechanism};
+use crate::logging::{InternalMessage, LocalDatabaseChanged, Log, Severity};
+use crate::rustica::rustica_admin_server::RusticaAdmin;
+use crate::rustica::{
+    AdminChangeResponse, DeletePermissionsRequest, DeleteRegisteredKeysRequest,
+    DeleteX509AuthorizationRequest, GrantExtensionsRequest, Grants, HostsRequest,
+    ListGrantsRequest, ListGrantsResponse, ListRegisteredKeysRequest, ListRegisteredKeysResponse,
+    ListX509AuthorizationsRequest, ListX509AuthorizationsResponse, Permissions, PrincipalsRequest,
+    RegisteredKey, RevokeExtensionsRequest, SetPermissionsRequest, SetX509AuthorizationRequest,
+    X509Authorization,
+};
+
+use tonic::{Request, Response, Status};
+
+/// Check the caller is an admin and return the local database along with
+/// the caller's identities
+async fn admin_database<'a, T>(
+    srv: &'a RusticaServer,
+    request: &Request<T>,
+    action: &str,
+) -> Result<(&'a LocalDatabase, Vec<String>), Status> {
+    let remote_addr = request.remote_addr().ok_or(Status::permission_denied(""))?;
+    let mtls_identities = identify(srv, request)
+        .await
+        .map_err(|_| Status::permission_denied(""))?
+        .identities;
+
+    if !is_admin(&srv.admin, &mtls_identities) {
+        rustica_warning!(
+            srv,
+            format!(
+                "[{}] from [{}] tried to call {action} but is not an admin",
+                mtls_identities.join(","),
+                remote_addr,
+            )
+        );
+        return Err(Status::permission_denied(""));
+    }
+
+    Ok((local_database(&srv.authorizer)?, mtls_identities))
+}
+
+/// Admin calls change the local database so they need it to be the
+/// configured authorizer
+fn local_database(authorizer: &AuthorizationMechanism) -> Result<&LocalDatabase, Status> {
+    match authorizer {
+        AuthorizationMechanism::Local(database) => Ok(database),
+        _ => Err(Status::failed_precondition(
+            "The local database authorizer is not configured",
+        )),
+    }
+}
+
+/// Empty authorities mean the default one. Grants for authorities Rustica
+/// does not have would never be used so they are refused.
+fn resolve_authority(srv: &RusticaServer, authority: String) -> Result<String, &'static str> {
+    let authority = if authority.is_empty() {
+        srv.signer.default_authority.clone()
+    } else {
+        authority
+    };
+
+    if !srv.signer.get_authorities().contains(&authority) {
+        return Err("Unknown authority");
+    }
+    Ok(authority)
+}
+
+fn require_fingerprint(fingerprint: &str) -> Result<(), &'static str> {
+    if fingerprint.is_empty() {
+        return Err("A fingerprint is required");
+    }
+    Ok(())
+}
+
+/// Describes one change made to the local database
+struct Change {
+    mtls_identities: Vec<String>,
+    action: &'static str,
+    authority: String,
+    subjects: Vec<String>,
+    values: Vec<String>,
+    /// How many rows were added, replaced, or removed
+    changed: u64,
+}
+
+fn log_change(srv: &RusticaServer, change: Change) {
+    let _ = srv
+        .log_sender
+        .send(Log::LocalDatabaseChanged(LocalDatabaseChanged {
+            mtls_identities: change.mtls_identities,
+            action: change.action.to_owned(),
+            authority: change.authority,
+            subjects: change.subjects,
+            values: change.values,
+            changed: change.changed,
+        }));
+}
+
+fn database_failure(srv: &RusticaServer, action: &str, e: AuthorizationError) -> Status {
+    rustica_error!(srv, format!("Could not complete {action}: {e}"));
+    Status::internal("")
+}
+
+fn describe_permissions(permissions: &Permissions) -> Vec<String> {
+    vec![
+        format!("host_unrestricted={}", permissions.host_unrestricted),
+        format!(
+            "principal_unrestricted={}",
+            permissions.principal_unrestricted
+        ),
+        format!(
+            "can_create_host_certs={}",
+            permissions.can_create_host_certs
+        ),
+        format!(
+            "can_create_user_certs={}",
+            permissions.can_create_user_certs
+        ),
+        format!("max_creation_time={}", permissions.max_creation_time),
+    ]
+}
+
+rustica_database::admin_conversions!(Grants, Permissions, RegisteredKey, X509Authorization);
+
+#[tonic::async_trait]
+impl RusticaAdmin for RusticaServer {
+    async fn list_grants(
+        &self,
+        request: Request<ListGrantsRequest>,
+    ) -> Result<Response<ListGrantsResponse>, Status> {
+        let (database, _) = admin_database(self, &request, "ListGrants").await?;
+        let request = request.into_inner();
+
+        let non_empty = |value: String| if value.is_empty() { None } else { Some(value) };
+        let grants = database
+            .list_grants(non_empty(request.fingerprint), non_empty(request.authority))
+            .await
+            .map_err(|e| database_failure(self, "ListGrants", e))?;
+
+        Ok(Response::new(ListGrantsResponse {
+            grants: grants.into_iter().map(to_grants).collect(),
+        }))
+    }
+
+    async fn grant_principals(
+        &self,
+        request: Request<PrincipalsRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) = admin_database(self, &request, "GrantPrincipals").await?;
+        let request = request.into_inner();
+        require_fingerprint(&request.fingerprint).map_err(Status::invalid_argument)?;
+        let authority =
+            resolve_authority(self, request.authority).map_err(Status::invalid_argument)?;
+
+        let changed = database
+            .grant_principals(
+                request.fingerprint.clone(),
+                authority.clone(),
+                request.principals.clone(),
+            )
+            .await
+            .map_err(|e| database_failure(self, "GrantPrincipals", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "GrantPrincipals",
+                authority,
+                subjects: vec![request.fingerprint],
+                values: request.principals,
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+
+    async fn revoke_principals(
+        &self,
+        request: Request<PrincipalsRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) =
+            admin_database(self, &request, "RevokePrincipals").await?;
+        let request = request.into_inner();
+        require_fingerprint(&request.fingerprint).map_err(Status::invalid_argument)?;
+        let authority =
+            resolve_authority(self, request.authority).map_err(Status::invalid_argument)?;
+
+        let changed = database
+            .revoke_principals(
+                request.fingerprint.clone(),
+                authority.clone(),
+                request.principals.clone(),
+            )
+            .await
+            .map_err(|e| database_failure(self, "RevokePrincipals", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "RevokePrincipals",
+                authority,
+                subjects: vec![request.fingerprint],
+                values: request.principals,
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+
+    async fn grant_hosts(
+        &self,
+        request: Request<HostsRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) = admin_database(self, &request, "GrantHosts").await?;
+        let request = request.into_inner();
+        require_fingerprint(&request.fingerprint).map_err(Status::invalid_argument)?;
+        let authority =
+            resolve_authority(self, request.authority).map_err(Status::invalid_argument)?;
+
+        let changed = database
+            .grant_hosts(
+                request.fingerprint.clone(),
+                authority.clone(),
+                request.hosts.clone(),
+            )
+            .await
+            .map_err(|e| database_failure(self, "GrantHosts", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "GrantHosts",
+                authority,
+                subjects: vec![request.fingerprint],
+                values: request.hosts,
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+
+    async fn revoke_hosts(
+        &self,
+        request: Request<HostsRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) = admin_database(self, &request, "RevokeHosts").await?;
+        let request = request.into_inner();
+        require_fingerprint(&request.fingerprint).map_err(Status::invalid_argument)?;
+        let authority =
+            resolve_authority(self, request.authority).map_err(Status::invalid_argument)?;
+
+        let changed = database
+            .revoke_hosts(
+                request.fingerprint.clone(),
+                authority.clone(),
+                request.hosts.clone(),
+            )
+            .await
+            .map_err(|e| database_failure(self, "RevokeHosts", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "RevokeHosts",
+                authority,
+                subjects: vec![request.fingerprint],
+                values: request.hosts,
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+
+    async fn grant_extensions(
+        &self,
+        request: Request<GrantExtensionsRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) = admin_database(self, &request, "GrantExtensions").await?;
+        let request = request.into_inner();
+        require_fingerprint(&request.fingerprint).map_err(Status::invalid_argument)?;
+        let authority =
+            resolve_authority(self, request.authority).map_err(Status::invalid_argument)?;
+
+        let mut values: Vec<String> = request
+            .extensions
+            .iter()
+            .map(|(name, value)| {
+                if value.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{name}={value}")
+                }
+            })
+            .collect();
+        values.sort();
+
+        let extensions = request
+            .extensions
+            .into_iter()
+            .map(|(name, value)| (name, if value.is_empty() { None } else { Some(value) }))
+            .collect();
+
+        let changed = database
+            .grant_extensions(request.fingerprint.clone(), authority.clone(), extensions)
+            .await
+            .map_err(|e| database_failure(self, "GrantExtensions", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "GrantExtensions",
+                authority,
+                subjects: vec![request.fingerprint],
+                values,
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+
+    async fn revoke_extensions(
+        &self,
+        request: Request<RevokeExtensionsRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) =
+            admin_database(self, &request, "RevokeExtensions").await?;
+        let request = request.into_inner();
+        require_fingerprint(&request.fingerprint).map_err(Status::invalid_argument)?;
+        let authority =
+            resolve_authority(self, request.authority).map_err(Status::invalid_argument)?;
+
+        let changed = database
+            .revoke_extensions(
+                request.fingerprint.clone(),
+                authority.clone(),
+                request.extensions.clone(),
+            )
+            .await
+            .map_err(|e| database_failure(self, "RevokeExtensions", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "RevokeExtensions",
+                authority,
+                subjects: vec![request.fingerprint],
+                values: request.extensions,
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+
+    async fn set_permissions(
+        &self,
+        request: Request<SetPermissionsRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) = admin_database(self, &request, "SetPermissions").await?;
+        let request = request.into_inner();
+        require_fingerprint(&request.fingerprint).map_err(Status::invalid_argument)?;
+        let authority =
+            resolve_authority(self, request.authority).map_err(Status::invalid_argument)?;
+        let permissions = request
+            .permissions
+            .ok_or(Status::invalid_argument("Permissions are required"))?;
+        let max_creation_time = i64::try_from(permissions.max_creation_time)
+            .map_err(|_| Status::invalid_argument("max_creation_time is too large"))?;
+
+        let changed = database
+            .set_permissions(models::FingerprintPermission {
+                fingerprint: request.fingerprint.clone(),
+                host_unrestricted: permissions.host_unrestricted,
+                principal_unrestricted: permissions.principal_unrestricted,
+                can_create_host_certs: permissions.can_create_host_certs,
+                can_create_user_certs: permissions.can_create_user_certs,
+                max_creation_time,
+                authority: authority.clone(),
+            })
+            .await
+            .map_err(|e| database_failure(self, "SetPermissions", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "SetPermissions",
+                authority,
+                subjects: vec![request.fingerprint],
+                values: describe_permissions(&permissions),
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+
+    async fn delete_permissions(
+        &self,
+        request: Request<DeletePermissionsRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) =
+            admin_database(self, &request, "DeletePermissions").await?;
+        let request = request.into_inner();
+        require_fingerprint(&request.fingerprint).map_err(Status::invalid_argument)?;
+        let authority =
+            resolve_authority(self, request.authority).map_err(Status::invalid_argument)?;
+
+        let changed = database
+            .delete_permissions(request.fingerprint.clone(), authority.clone())
+            .await
+            .map_err(|e| database_failure(self, "DeletePermissions", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "DeletePermissions",
+                authority,
+                subjects: vec![request.fingerprint],
+                values: vec![],
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+
+    async fn list_registered_keys(
+        &self,
+        request: Request<ListRegisteredKeysRequest>,
+    ) -> Result<Response<ListRegisteredKeysResponse>, Status> {
+        let (database, _) = admin_database(self, &request, "ListRegisteredKeys").await?;
+        let request = request.into_inner();
+
+        let user = if request.user.is_empty() {
+            None
+        } else {
+            Some(request.user)
+        };
+        let keys = database
+            .list_registered_keys(user)
+            .await
+            .map_err(|e| database_failure(self, "ListRegisteredKeys", e))?;
+
+        Ok(Response::new(ListRegisteredKeysResponse {
+            keys: keys.into_iter().map(to_registered_key).collect(),
+        }))
+    }
+
+    async fn delete_registered_keys(
+        &self,
+        request: Request<DeleteRegisteredKeysRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) =
+            admin_database(self, &request, "DeleteRegisteredKeys").await?;
+        let request = request.into_inner();
+
+        let changed = database
+            .delete_registered_keys(request.fingerprints.clone())
+            .await
+            .map_err(|e| database_failure(self, "DeleteRegisteredKeys", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "DeleteRegisteredKeys",
+                authority: String::new(),
+                subjects: request.fingerprints,
+                values: vec![],
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+
+    async fn list_x509_authorizations(
+        &self,
+        request: Request<ListX509AuthorizationsRequest>,
+    ) -> Result<Response<ListX509AuthorizationsResponse>, Status> {
+        let (database, _) = admin_database(self, &request, "ListX509Authorizations").await?;
+        let request = request.into_inner();
+
+        let user = if request.user.is_empty() {
+            None
+        } else {
+            Some(request.user)
+        };
+        let authorizations = database
+            .list_x509_authorizations(user)
+            .await
+            .map_err(|e| database_failure(self, "ListX509Authorizations", e))?;
+
+        Ok(Response::new(ListX509AuthorizationsResponse {
+            authorizations: authorizations
+                .into_iter()
+                .map(to_x509_authorization)
+                .collect(),
+        }))
+    }
+
+    async fn set_x509_authorization(
+        &self,
+        request: Request<SetX509AuthorizationRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) =
+            admin_database(self, &request, "SetX509Authorization").await?;
+        let authorization = request
+            .into_inner()
+            .authorization
+            .ok_or(Status::invalid_argument("An authorization is required"))?;
+
+        if authorization.user.is_empty() || authorization.hsm_serial.is_empty() {
+            return Err(Status::invalid_argument("A user and serial are required"));
+        }
+        let authority =
+            resolve_authority(self, authorization.authority).map_err(Status::invalid_argument)?;
+
+        let changed = database
+            .set_x509_authorization(models::X509Authorization {
+                user: authorization.user.clone(),
+                hsm_serial: authorization.hsm_serial.clone(),
+                require_touch: authorization.require_touch,
+                authority: authority.clone(),
+            })
+            .await
+            .map_err(|e| database_failure(self, "SetX509Authorization", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "SetX509Authorization",
+                authority,
+                subjects: vec![authorization.user],
+                values: vec![
+                    format!("hsm_serial={}", authorization.hsm_serial),
+                    format!("require_touch={}", authorization.require_touch),
+                ],
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+
+    async fn delete_x509_authorization(
+        &self,
+        request: Request<DeleteX509AuthorizationRequest>,
+    ) -> Result<Response<AdminChangeResponse>, Status> {
+        let (database, mtls_identities) =
+            admin_database(self, &request, "DeleteX509Authorization").await?;
+        let request = request.into_inner();
+
+        let changed = database
+            .delete_x509_authorization(request.user.clone(), request.hsm_serial.clone())
+            .await
+            .map_err(|e| database_failure(self, "DeleteX509Authorization", e))?;
+
+        log_change(
+            self,
+            Change {
+                mtls_identities,
+                action: "DeleteX509Authorization",
+                authority: String::new(),
+                subjects: vec![request.user],
+                values: vec![format!("hsm_serial={}", request.hsm_serial)],
+                changed,
+            },
+        );
+        Ok(Response::new(AdminChangeResponse { changed }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::database::LocalDatabaseConfiguration;
+    use crate::auth::policy::{PolicyAuthorizer, PolicyConfiguration};
+    use crate::config::AdminConfiguration;
+
+    fn identities(identities: &[&str]) -> Vec<String> {
+        identities.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn only_configured_identities_are_admins() {
+        let admin = AdminConfiguration {
+            identities: identities(&["security-team"]),
+        };
+
+        assert!(is_admin(&admin, &identities(&["alice", "security-team"])));
+        assert!(!is_admin(&admin, &identities(&["alice"])));
+        assert!(!is_admin(&admin, &identities(&["Security-Team", "security"])));
+        assert!(!is_admin(&admin, &[]));
+
+        let nobody = AdminConfiguration { identities: vec![] };
+        assert!(!is_admin(&nobody, &identities(&["security-team"])));
+    }
+
+    #[test]
+    fn admin_calls_need_the_local_database() {
+        let directory = tempfile::tempdir().unwrap();
+
+        let path = directory.path().join("rustica.db").to_string_lossy().into_owned();
+        let config: LocalDatabaseConfiguration =
+            toml::from_str(&format!("path = {path:?}")).unwrap();
+        config.migrate().unwrap();
+        let local = AuthorizationMechanism::Local(LocalDatabase::try_from(config).unwrap());
+        assert!(local_database(&local).is_ok());
+
+        let policy_path = directory.path().join("policy.toml");
+        std::fs::write(&policy_path, "").unwrap();
+        let policy = AuthorizationMechanism::Policy(
+            PolicyAuthorizer::try_from(PolicyConfiguration {
+                path: policy_path.to_string_lossy().into_owned(),
+                registered_keys_path: None,
+            })
+            .unwrap(),
+        );
+        match local_database(&policy) {
+            Err(status) => assert_eq!(status.code(), tonic::Code::FailedPrecondition),
+            Ok(_) => panic!("Admin calls must not be served by the policy authorizer"),
+        }
+    }
+}
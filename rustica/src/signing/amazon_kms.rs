@@ -402,4 +402,22 @@ impl Signer for AmazonKMSSigner {
     fn get_client_certificate_authority(&self) -> Option<&X509Certificate> {
         return self.client_certificate_authority.as_ref();
     }
+
+    async fn check_health(&self) -> Result<(), SigningError> {
+        let ssh_keys = match &self.ssh_keys {
+            Some(ssh_keys) => ssh_keys,
+            None => return Ok(()),
+        };
+
+        for key_id in [&ssh_keys.user.key_id, &ssh_keys.host.key_id] {
+            self.client
+                .get_public_key()
+                .key_id(key_id)
+                .send()
+                .await
+                .map_err(|_| SigningError::AccessError(format!("Could not access key {key_id}")))?;
+        }
+
+        Ok(())
+    }
 }
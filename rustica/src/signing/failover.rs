@@ -0,0 +1,556 @@
+/// The failover signer tries an ordered list of signers that hold the same
+/// key material, for example a KMS key and its replica in another region,
+/// moving on to the next one when a signer fails or times out.
+///
+/// Each signer has a circuit breaker. After enough consecutive failures the
+/// signer is skipped for a while so requests do not keep waiting on it, and
+/// is then tried again. Tripping and recovering are sent to the logging
+/// system. If every signer is being skipped they are all tried anyway rather
+/// than failing without trying.
+use super::timeout::TimeoutSigner;
+use super::{default_signer_timeout, KeyState, Signer, SignerType, SigningError};
+use crate::logging::{InternalMessage, Log, Severity};
+
+use async_trait::async_trait;
+use crossbeam_channel::Sender;
+use serde::Deserialize;
+use sshcerts::{ssh::CertType, Certificate, PublicKey};
+
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize)]
+pub struct FailoverSignerConfig {
+    /// The signer to try. Any signer other than a rotation set or another
+    /// failover list can be used.
+    #[serde(flatten)]
+    pub signer: SignerType,
+    /// How many seconds to wait for this signer before trying the next one
+    #[serde(default = "default_signer_timeout")]
+    pub timeout: u64,
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// The signers in the order they are tried
+    signers: Vec<FailoverSignerConfig>,
+    /// How many consecutive failures trip a signer's circuit breaker
+    #[serde(default = "default_failure_threshold")]
+    failure_threshold: u32,
+    /// How many seconds a tripped signer is skipped before it is tried again
+    #[serde(default = "default_reset_interval")]
+    reset_interval: u64,
+}
+
+fn default_failure_threshold() -> u32 {
+    3
+}
+
+fn default_reset_interval() -> u64 {
+    30
+}
+
+#[derive(Default)]
+struct Breaker {
+    /// Failures since the last success
+    consecutive_failures: u32,
+    /// While set, the signer is skipped until this time
+    open_until: Option<Instant>,
+}
+
+struct FailoverMember {
+    name: String,
+    signer: TimeoutSigner,
+    breaker: Mutex<Breaker>,
+}
+
+impl FailoverMember {
+    // The lock is never held across an await so a poisoned lock can only
+    // come from a panic that left the breaker consistent
+    fn breaker(&self) -> MutexGuard<'_, Breaker> {
+        self.breaker.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub struct FailoverSigner {
+    members: Vec<FailoverMember>,
+    failure_threshold: u32,
+    reset_interval: Duration,
+    log_sender: Sender<Log>,
+}
+
+impl FailoverSigner {
+    fn log(&self, severity: Severity, message: String) {
+        let _ = self
+            .log_sender
+            .send(Log::InternalMessage(InternalMessage { severity, message }));
+    }
+
+    fn primary(&self) -> &FailoverMember {
+        &self.members[0]
+    }
+
+    fn record_success(&self, member: &FailoverMember) {
+        let mut breaker = member.breaker();
+        let tripped = breaker.open_until.is_some();
+        *breaker = Breaker::default();
+        drop(breaker);
+
+        if tripped {
+            self.log(
+                Severity::Info,
+                format!("Signer {} has recovered and will be used again", member.name),
+            );
+        }
+    }
+
+    fn record_failure(&self, member: &FailoverMember, error: &SigningError) {
+        let mut breaker = member.breaker();
+        breaker.consecutive_failures = breaker.consecutive_failures.saturating_add(1);
+        if breaker.consecutive_failures < self.failure_threshold {
+            return;
+        }
+
+        // A signer that fails again after being tried following a trip is
+        // tripped again straight away
+        breaker.open_until = Some(Instant::now() + self.reset_interval);
+        let failures = breaker.consecutive_failures;
+        drop(breaker);
+
+        self.log(
+            Severity::Error,
+            format!(
+                "Signer {} failed {failures} times in a row and will be skipped for {} seconds: {error}",
+                member.name,
+                self.reset_interval.as_secs()
+            ),
+        );
+    }
+}
+
+#[async_trait]
+impl Signer for FailoverSigner {
+    async fn sign(&self, cert: Certificate) -> Result<Certificate, SigningError> {
+        let now = Instant::now();
+        let mut available: Vec<&FailoverMember> = self
+            .members
+            .iter()
+            .filter(|member| member.breaker().open_until.is_none_or(|until| until <= now))
+            .collect();
+
+        if available.is_empty() {
+            available = self.members.iter().collect();
+        }
+
+        let mut last_error = SigningError::SigningFailure;
+        for member in available {
+            match member.signer.sign(cert.clone()).await {
+                Ok(signed) => {
+                    self.record_success(member);
+                    return Ok(signed);
+                }
+                Err(e) => {
+                    self.record_failure(member, &e);
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    fn get_signer_public_key(&self, cert_type: CertType) -> Option<PublicKey> {
+        self.primary().signer.get_signer_public_key(cert_type)
+    }
+
+    fn get_trusted_public_keys(&self, cert_type: CertType) -> Vec<(KeyState, PublicKey)> {
+        self.primary().signer.get_trusted_public_keys(cert_type)
+    }
+
+    // X509 certificates are not failed over. Every signer has the same
+    // certificate authorities so they always come from the first.
+    fn get_attested_x509_certificate_authority(&self) -> Option<&rcgen::Certificate> {
+        self.primary()
+            .signer
+            .get_attested_x509_certificate_authority()
+    }
+
+    fn get_client_certificate_authority(&self) -> Option<&rcgen::Certificate> {
+        self.primary().signer.get_client_certificate_authority()
+    }
+
+    /// The list is healthy as long as one of its signers is
+    async fn check_health(&self) -> Result<(), SigningError> {
+        let mut errors = vec![];
+        for member in self.members.iter() {
+            match member.signer.check_health().await {
+                Ok(_) => return Ok(()),
+                Err(e) => errors.push(format!("{}: {e}", member.name)),
+            }
+        }
+
+        Err(SigningError::AccessError(format!(
+            "No signer is healthy ({})",
+            errors.join(", ")
+        )))
+    }
+}
+
+/// The public key of a certificate authority, used to compare them across
+/// signers
+fn ca_public_key(ca: Option<&rcgen::Certificate>) -> Option<Vec<u8>> {
+    ca.map(|ca| ca.get_key_pair().public_key_der())
+}
+
+/// Failing over to a signer with different keys would issue certificates
+/// that hosts and clients do not trust, so every signer must have the same
+/// SSH keys and X509 certificate authorities as the first
+fn check_same_keys(members: &[FailoverMember]) -> Result<(), SigningError> {
+    let keys = |member: &FailoverMember| {
+        (
+            [CertType::User, CertType::Host].map(|cert_type| {
+                member
+                    .signer
+                    .get_signer_public_key(cert_type)
+                    .map(|key| key.fingerprint().hash)
+            }),
+            ca_public_key(member.signer.get_attested_x509_certificate_authority()),
+            ca_public_key(member.signer.get_client_certificate_authority()),
+        )
+    };
+
+    let primary = keys(&members[0]);
+    for member in members.iter().skip(1) {
+        let (ssh, attested_x509, client) = keys(member);
+        let differs = if ssh != primary.0 {
+            Some("SSH keys")
+        } else if attested_x509 != primary.1 {
+            Some("X509 certificate authority")
+        } else if client != primary.2 {
+            Some("client certificate authority")
+        } else {
+            None
+        };
+
+        if let Some(differs) = differs {
+            return Err(SigningError::InvalidFailover(format!(
+                "Signer {} does not have the same {differs} as the first signer",
+                member.name
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+impl Config {
+    pub async fn into_signer(
+        self,
+        authority: &str,
+        log_sender: &Sender<Log>,
+    ) -> Result<Box<dyn Signer + Send + Sync>, SigningError> {
+        if self.signers.is_empty() {
+            return Err(SigningError::InvalidFailover(
+                "At least one signer must be configured".to_owned(),
+            ));
+        }
+
+        if self.failure_threshold == 0 {
+            return Err(SigningError::InvalidFailover(
+                "The failure threshold must be at least one".to_owned(),
+            ));
+        }
+
+        // A zero timeout would fail every request without ever asking the
+        // signer and quietly trip every circuit breaker
+        if self.signers.iter().any(|member| member.timeout == 0) {
+            return Err(SigningError::InvalidFailover(
+                "Signer timeouts must be at least one second".to_owned(),
+            ));
+        }
+
+        let mut members = vec![];
+        for (i, member) in self.signers.into_iter().enumerate() {
+            if matches!(
+                member.signer,
+                SignerType::Rotation(_) | SignerType::Failover(_)
+            ) {
+                return Err(SigningError::InvalidFailover(
+                    "Failover signers must each hold a single set of keys".to_owned(),
+                ));
+            }
+
+            members.push(FailoverMember {
+                name: format!("{} ({}) of authority {authority}", i + 1, member.signer.kind()),
+                signer: TimeoutSigner::new(
+                    member.signer.into_signer(authority, log_sender).await?,
+                    member.timeout,
+                ),
+                breaker: Mutex::new(Breaker::default()),
+            });
+        }
+
+        check_same_keys(&members)?;
+
+        Ok(Box::new(FailoverSigner {
+            members,
+            failure_threshold: self.failure_threshold,
+            reset_interval: Duration::from_secs(self.reset_interval),
+            log_sender: log_sender.clone(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crossbeam_channel::Receiver;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    const KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIKNO7QPgbPauWmF8nfTV6fkYd2fUtxvajI96bjDXE0oM";
+    const OTHER_KEY: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIHUFCpVupUI4AxCTRb+Q/2SFU6/IDpta41yNYa11eiXy";
+
+    /// How a stub signer behaves and how often it was asked to sign
+    #[derive(Clone, Default)]
+    struct Behaviour {
+        failing: Arc<AtomicBool>,
+        calls: Arc<AtomicU32>,
+    }
+
+    impl Behaviour {
+        fn fail(&self, failing: bool) {
+            self.failing.store(failing, Ordering::SeqCst);
+        }
+
+        fn calls(&self) -> u32 {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    struct StubSigner {
+        key: PublicKey,
+        x509: Option<rcgen::Certificate>,
+        behaviour: Behaviour,
+    }
+
+    #[async_trait]
+    impl Signer for StubSigner {
+        async fn sign(&self, cert: Certificate) -> Result<Certificate, SigningError> {
+            self.behaviour.calls.fetch_add(1, Ordering::SeqCst);
+            if self.behaviour.failing.load(Ordering::SeqCst) {
+                return Err(SigningError::SigningFailure);
+            }
+            Ok(cert)
+        }
+
+        fn get_signer_public_key(&self, _cert_type: CertType) -> Option<PublicKey> {
+            Some(self.key.clone())
+        }
+
+        fn get_attested_x509_certificate_authority(&self) -> Option<&rcgen::Certificate> {
+            self.x509.as_ref()
+        }
+
+        fn get_client_certificate_authority(&self) -> Option<&rcgen::Certificate> {
+            None
+        }
+
+        async fn check_health(&self) -> Result<(), SigningError> {
+            Ok(())
+        }
+    }
+
+    fn member(
+        name: &str,
+        key: &str,
+        x509: Option<rcgen::Certificate>,
+    ) -> (FailoverMember, Behaviour) {
+        let behaviour = Behaviour::default();
+        let signer = StubSigner {
+            key: PublicKey::from_string(key).unwrap(),
+            x509,
+            behaviour: behaviour.clone(),
+        };
+
+        let member = FailoverMember {
+            name: name.to_owned(),
+            signer: TimeoutSigner::new(Box::new(signer), 1),
+            breaker: Mutex::new(Breaker::default()),
+        };
+        (member, behaviour)
+    }
+
+    /// A failover list of two signers with the same key that trips after
+    /// two failures
+    fn failover(reset_interval: Duration) -> (FailoverSigner, [Behaviour; 2], Receiver<Log>) {
+        let (primary, primary_behaviour) = member("primary", KEY, None);
+        let (secondary, secondary_behaviour) = member("secondary", KEY, None);
+        let (log_sender, log_receiver) = crossbeam_channel::unbounded();
+
+        let signer = FailoverSigner {
+            members: vec![primary, secondary],
+            failure_threshold: 2,
+            reset_interval,
+            log_sender,
+        };
+        (
+            signer,
+            [primary_behaviour, secondary_behaviour],
+            log_receiver,
+        )
+    }
+
+    fn certificate() -> Certificate {
+        let key = PublicKey::from_string(KEY).unwrap();
+        Certificate::builder(&key, CertType::User, &key).unwrap()
+    }
+
+    /// The severities and messages logged since the last call
+    fn logged(log_receiver: &Receiver<Log>) -> Vec<(Severity, String)> {
+        log_receiver
+            .try_iter()
+            .filter_map(|log| match log {
+                Log::InternalMessage(message) => Some((message.severity, message.message)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn breaker_trips_at_the_failure_threshold() {
+        let (signer, [primary, secondary], log_receiver) = failover(Duration::from_secs(60));
+        primary.fail(true);
+
+        // The first failure fails over without tripping
+        assert!(signer.sign(certificate()).await.is_ok());
+        assert!(logged(&log_receiver).is_empty());
+
+        assert!(signer.sign(certificate()).await.is_ok());
+        assert_eq!(primary.calls(), 2);
+        assert_eq!(secondary.calls(), 2);
+        let logs = logged(&log_receiver);
+        assert_eq!(logs.len(), 1);
+        assert!(matches!(logs[0].0, Severity::Error));
+        assert!(logs[0].1.contains("primary"));
+
+        // While tripped the primary is skipped, even if it has recovered
+        primary.fail(false);
+        assert!(signer.sign(certificate()).await.is_ok());
+        assert_eq!(primary.calls(), 2);
+        assert_eq!(secondary.calls(), 3);
+    }
+
+    #[tokio::test]
+    async fn success_resets_the_failure_count() {
+        let (signer, [primary, _], log_receiver) = failover(Duration::from_secs(60));
+
+        primary.fail(true);
+        assert!(signer.sign(certificate()).await.is_ok());
+        primary.fail(false);
+        assert!(signer.sign(certificate()).await.is_ok());
+        primary.fail(true);
+        assert!(signer.sign(certificate()).await.is_ok());
+
+        // Never two failures in a row so the primary is still tried
+        assert!(logged(&log_receiver).is_empty());
+        primary.fail(false);
+        assert!(signer.sign(certificate()).await.is_ok());
+        assert_eq!(primary.calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn tripped_signers_are_retried_after_the_reset_interval() {
+        let (signer, [primary, secondary], log_receiver) = failover(Duration::from_millis(50));
+        primary.fail(true);
+        signer.sign(certificate()).await.unwrap();
+        signer.sign(certificate()).await.unwrap();
+        logged(&log_receiver);
+
+        // A signer that fails its retry is tripped again straight away
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        signer.sign(certificate()).await.unwrap();
+        assert_eq!(primary.calls(), 3);
+        assert_eq!(logged(&log_receiver).len(), 1);
+        signer.sign(certificate()).await.unwrap();
+        assert_eq!(primary.calls(), 3);
+
+        // A successful retry closes the breaker and is logged
+        primary.fail(false);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        signer.sign(certificate()).await.unwrap();
+        assert_eq!(primary.calls(), 4);
+        let logs = logged(&log_receiver);
+        assert_eq!(logs.len(), 1);
+        assert!(matches!(logs[0].0, Severity::Info));
+        assert!(logs[0].1.contains("primary has recovered"));
+
+        let secondary_calls = secondary.calls();
+        signer.sign(certificate()).await.unwrap();
+        assert_eq!(primary.calls(), 5);
+        assert_eq!(secondary.calls(), secondary_calls);
+    }
+
+    #[tokio::test]
+    async fn every_signer_is_tried_when_all_are_tripped() {
+        let (signer, [primary, secondary], _log_receiver) = failover(Duration::from_secs(60));
+        primary.fail(true);
+        secondary.fail(true);
+
+        for _ in 0..2 {
+            assert!(signer.sign(certificate()).await.is_err());
+        }
+        assert!(signer
+            .members
+            .iter()
+            .all(|member| member.breaker().open_until.is_some()));
+
+        // Both are tripped but still tried rather than failing outright
+        secondary.fail(false);
+        assert!(signer.sign(certificate()).await.is_ok());
+        assert_eq!(primary.calls(), 3);
+        assert_eq!(secondary.calls(), 3);
+    }
+
+    /// A certificate authority for the PKCS#8 encoded key
+    fn x509_ca(key: &[u8]) -> rcgen::Certificate {
+        let mut params = rcgen::CertificateParams::new(vec![]);
+        params.key_pair = Some(rcgen::KeyPair::from_der(key).unwrap());
+        rcgen::Certificate::from_params(params).unwrap()
+    }
+
+    fn generate_key() -> Vec<u8> {
+        rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)
+            .unwrap()
+            .serialize_der()
+    }
+
+    #[test]
+    fn signers_must_have_the_same_keys() {
+        let key = generate_key();
+        let members = [
+            member("1", KEY, Some(x509_ca(&key))).0,
+            member("2", KEY, Some(x509_ca(&key))).0,
+        ];
+        assert!(check_same_keys(&members).is_ok());
+
+        let members = [member("1", KEY, None).0, member("2", OTHER_KEY, None).0];
+        assert!(check_same_keys(&members).is_err());
+
+        let members = [
+            member("1", KEY, Some(x509_ca(&key))).0,
+            member("2", KEY, Some(x509_ca(&generate_key()))).0,
+        ];
+        assert!(check_same_keys(&members).is_err());
+
+        // A signer without an X509 authority cannot stand in for one with it
+        let members = [
+            member("1", KEY, Some(x509_ca(&key))).0,
+            member("2", KEY, None).0,
+        ];
+        assert!(check_same_keys(&members).is_err());
+    }
+}
@@ -1,4 +1,7 @@
 use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Instant;
 
 /// This is the signing module of the Rustica project. The module is designed
 /// to be easily extended, allowing the creation of new signing submodules with
@@ -7,37 +10,103 @@ use std::collections::HashMap;
 /// systems can be simply implemented.
 use async_trait::async_trait;
 
+use crossbeam_channel::Sender;
 use serde::Deserialize;
 use sshcerts::ssh::{CertType, Certificate, PublicKey};
 
+use crate::logging::Log;
+use crate::metrics;
+
 #[cfg(feature = "amazon-kms")]
 mod amazon_kms;
+mod external;
+mod failover;
 mod file;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+mod rotation;
+mod timeout;
 #[cfg(feature = "yubikey-support")]
 mod yubikey;
 
+pub use rotation::KeyState;
+
 #[derive(Deserialize)]
 #[serde(tag = "kind")]
 pub enum SignerType {
     File(file::Config),
+    External(ExternalSigningConfig),
     #[cfg(feature = "yubikey-support")]
     Yubikey(yubikey::Config),
     #[cfg(feature = "amazon-kms")]
     AmazonKMS(amazon_kms::Config),
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(pkcs11::Config),
+    Rotation(rotation::Config),
+    Failover(failover::Config),
 }
 
+type SignerFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<Box<dyn Signer + Send + Sync>, SigningError>> + Send + 'a>>;
+
 impl SignerType {
-    async fn into_signer(self) -> Result<Box<dyn Signer + Send + Sync>, SigningError> {
+    /// The future is boxed because rotation sets and failover lists contain
+    /// other signers. The authority name and log sender are used by signers
+    /// that report events of their own, such as a failover list tripping a
+    /// circuit breaker.
+    fn into_signer<'a>(
+        self,
+        authority: &'a str,
+        log_sender: &'a Sender<Log>,
+    ) -> SignerFuture<'a> {
+        Box::pin(async move {
+            match self {
+                Self::File(x) => x.into_signer().await,
+                Self::External(x) => x.into_signer().await,
+                #[cfg(feature = "yubikey-support")]
+                Self::Yubikey(x) => x.into_signer().await,
+                #[cfg(feature = "amazon-kms")]
+                Self::AmazonKMS(f) => f.into_signer().await,
+                #[cfg(feature = "pkcs11")]
+                Self::Pkcs11(x) => x.into_signer().await,
+                Self::Rotation(x) => x.into_signer(authority, log_sender).await,
+                Self::Failover(x) => x.into_signer(authority, log_sender).await,
+            }
+        })
+    }
+
+    /// The name the signer is configured with
+    fn kind(&self) -> &'static str {
         match self {
-            Self::File(x) => x.into_signer().await,
+            Self::File(_) => "File",
+            Self::External(_) => "External",
             #[cfg(feature = "yubikey-support")]
-            Self::Yubikey(x) => x.into_signer().await,
+            Self::Yubikey(_) => "Yubikey",
             #[cfg(feature = "amazon-kms")]
-            Self::AmazonKMS(f) => f.into_signer().await,
+            Self::AmazonKMS(_) => "AmazonKMS",
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(_) => "Pkcs11",
+            Self::Rotation(_) => "Rotation",
+            Self::Failover(_) => "Failover",
         }
     }
 }
 
+fn default_signer_timeout() -> u64 {
+    10
+}
+
+/// The configuration of a single authority
+#[derive(Deserialize)]
+pub struct AuthorityConfiguration {
+    #[serde(flatten)]
+    pub signer: SignerType,
+    /// How many seconds to wait for the signer before failing a request.
+    /// Defaults to 10 seconds except for failover lists, which are bounded
+    /// by the timeouts of their signers instead.
+    pub timeout: Option<u64>,
+}
+
 #[async_trait]
 pub trait SignerConfig {
     async fn into_signer(self) -> Result<Box<dyn Signer + Send + Sync>, SigningError>;
@@ -52,7 +121,7 @@ pub trait Signer {
     /// is async allowing calls to be made over the network or to other blocking resources.
     /// This call however should execute as fast as possible and have a strict timeout as
     /// the runtime this is executing on is the one fulfilling certificate requests from
-    /// users.
+    /// users. The authority's configured timeout is enforced around this call.
     async fn sign(&self, cert: Certificate) -> Result<Certificate, SigningError>;
 
     /// This function is intentionally not async. This is to discourage this call being reliant
@@ -61,17 +130,52 @@ pub trait Signer {
     /// there. See the AWS signer as an example.
     fn get_signer_public_key(&self, cert_type: CertType) -> Option<PublicKey>;
 
+    /// Return every key of this type that hosts and clients should trust
+    /// along with its state in the rotation. Signers that hold a single key
+    /// can use the default implementation which returns only that key.
+    fn get_trusted_public_keys(&self, cert_type: CertType) -> Vec<(KeyState, PublicKey)> {
+        self.get_signer_public_key(cert_type)
+            .map(|key| (KeyState::Active, key))
+            .into_iter()
+            .collect()
+    }
+
     /// Return the CA certificate used for signing X509 certificate requests.
     /// This function may hide away async code (as it does in the KMS signer)
     /// due to using the remote KeyPair trait imported from the rcgen crate
     fn get_attested_x509_certificate_authority(&self) -> Option<&rcgen::Certificate>;
 
+    /// Return every attested X509 certificate authority that should be
+    /// trusted, including the one used for signing.
+    fn get_trusted_attested_x509_certificate_authorities(&self) -> Vec<&rcgen::Certificate> {
+        self.get_attested_x509_certificate_authority()
+            .into_iter()
+            .collect()
+    }
+
     /// Return the CA certificate used for signing X509 certificate requests.
     /// This function may hide away async code (as it does in the KMS signer)
     /// due to using the remote KeyPair trait imported from the rcgen crate
     fn get_client_certificate_authority(&self) -> Option<&rcgen::Certificate>;
+
+    /// Return every client certificate authority that should be trusted,
+    /// including the one used for signing.
+    fn get_trusted_client_certificate_authorities(&self) -> Vec<&rcgen::Certificate> {
+        self.get_client_certificate_authority().into_iter().collect()
+    }
+
+    /// Check that the key material is still reachable, for example that a
+    /// Yubikey is still connected or that a KMS key can still be accessed.
+    /// This is called periodically to drive the gRPC health service so it
+    /// should be cheap and must not sign anything. Signers that have nothing
+    /// to check can use the default implementation.
+    async fn check_health(&self) -> Result<(), SigningError> {
+        Ok(())
+    }
 }
 
+/// Configuration for a remote signing service that implements the `Signer`
+/// service in `proto/signer.proto`
 #[derive(Deserialize)]
 pub struct ExternalSigningConfig {
     pub server: String,
@@ -85,13 +189,12 @@ pub struct ExternalSigningConfig {
 /// default authority so if people pass an empty string for key_id(authority)
 /// we maintain backwards compatibility.
 ///
-/// The reason this is an enum of one is to support a completely external
-/// signing system in the future if none of the internal options work for
-/// you.
+/// If none of the internal options work for you, an authority can use the
+/// `External` signer to have a completely separate system hold its keys.
 #[derive(Deserialize)]
 pub struct SigningConfiguration {
     pub default_authority: String,
-    pub authority_configurations: HashMap<String, SignerType>,
+    pub authority_configurations: HashMap<String, AuthorityConfiguration>,
 }
 
 /// A `SigningConfiguration` can be coerced into a `SigningMechanism` to
@@ -104,6 +207,20 @@ pub struct SigningMechanism {
     pub authorities: HashMap<String, Box<dyn Signer + Send + Sync>>,
 }
 
+/// The public keys and certificates of an authority that hosts and clients
+/// need in order to trust what it issues
+pub struct AuthorityTrustMaterial {
+    pub authority: String,
+    /// Every published user key, including the active one
+    pub user_cas: Vec<(KeyState, PublicKey)>,
+    /// Every published host key, including the active one
+    pub host_cas: Vec<(KeyState, PublicKey)>,
+    /// PEM encoded. During a rotation this contains several certificates.
+    pub attested_x509_certificate_authority: Option<String>,
+    /// PEM encoded. During a rotation this contains several certificates.
+    pub client_certificate_authority: Option<String>,
+}
+
 #[derive(Debug)]
 pub enum SigningError {
     /// Represents when there was an issue accessing the key material. This
@@ -125,6 +242,16 @@ pub enum SigningError {
     IdenticalUserAndHostKey(String),
     SignerDoesNotHaveSSHKeys,
     SignerDoesNotAllRequiredSSHKeys,
+    /// A rotation set is not usable, for example because it does not have
+    /// exactly one active key
+    InvalidRotation(String),
+    /// A failover list is not usable, for example because its signers do
+    /// not share the same keys
+    InvalidFailover(String),
+    /// The signer did not respond within its timeout, in seconds
+    TimedOut(u64),
+    /// An authority was configured with a timeout of zero
+    InvalidTimeout(String),
 }
 
 impl std::fmt::Display for SigningError {
@@ -137,7 +264,11 @@ impl std::fmt::Display for SigningError {
             Self::DuplicatedKey(a1, a2) => write!(f, "Authorities {a1} and {a2} share at least one key. This is not allowed as it almost always a misconfiguration leading to access that is not correctly restricted"),
             Self::IdenticalUserAndHostKey(authority) => write!(f, "Authority {authority} has an identical key for both user and host certificates. This is not allowed as it's much safer to use separate keys for both."),
             Self::SignerDoesNotHaveSSHKeys => write!(f, "Signer was not configured with SSH keys so it cannot create an SSH certificate"),
-            Self::SignerDoesNotAllRequiredSSHKeys => write!(f, "Signer did not have both user and host keys defined")
+            Self::SignerDoesNotAllRequiredSSHKeys => write!(f, "Signer did not have both user and host keys defined"),
+            Self::InvalidRotation(e) => write!(f, "Invalid key rotation: {e}"),
+            Self::InvalidFailover(e) => write!(f, "Invalid signer failover: {e}"),
+            Self::TimedOut(timeout) => write!(f, "The signer did not respond within {timeout} seconds"),
+            Self::InvalidTimeout(authority) => write!(f, "The timeout for authority {authority} must be at least one second"),
         }
     }
 }
@@ -148,20 +279,17 @@ impl std::fmt::Display for SigningMechanism {
         for signer in self.authorities.iter() {
             output.push_str(&format!("Authority: {}\n", signer.0));
 
-            if let Some(fp) = signer
-                .1
-                .get_signer_public_key(CertType::User)
-                .map(|x| x.fingerprint().hash)
-            {
-                output.push_str(&format!("\tUser CA Fingerprint (SHA256): {fp}\n"));
-            }
-
-            if let Some(fp) = signer
-                .1
-                .get_signer_public_key(CertType::Host)
-                .map(|x| x.fingerprint().hash)
-            {
-                output.push_str(&format!("\tHost CA Fingerprint (SHA256): {fp}\n"));
+            for (cert_type, name) in [(CertType::User, "User"), (CertType::Host, "Host")] {
+                for (state, key) in signer.1.get_trusted_public_keys(cert_type) {
+                    let fp = key.fingerprint().hash;
+                    if state == KeyState::Active {
+                        output.push_str(&format!("\t{name} CA Fingerprint (SHA256): {fp}\n"));
+                    } else {
+                        output.push_str(&format!(
+                            "\t{name} CA Fingerprint (SHA256, {state}): {fp}\n"
+                        ));
+                    }
+                }
             }
 
             if let Some(attested_x509_authority) =
@@ -193,8 +321,11 @@ impl SigningMechanism {
         authority: &str,
         cert: Certificate,
     ) -> Result<Certificate, SigningError> {
-        if let Some(authority) = self.authorities.get(authority) {
-            authority.sign(cert).await
+        if let Some(signer) = self.authorities.get(authority) {
+            let start = Instant::now();
+            let result = signer.sign(cert).await;
+            metrics::signer_request(authority, start.elapsed(), result.is_ok());
+            result
         } else {
             Err(SigningError::UnknownAuthority(authority.to_string()))
         }
@@ -218,6 +349,24 @@ impl SigningMechanism {
             .ok_or(SigningError::SignerDoesNotHaveSSHKeys)
     }
 
+    /// Return every public key of the given type that should be trusted for
+    /// an authority, including keys that are being rotated in or out
+    pub fn get_trusted_public_keys(
+        &self,
+        authority: &str,
+        cert_type: CertType,
+    ) -> Result<Vec<PublicKey>, SigningError> {
+        if let Some(authority) = self.authorities.get(authority) {
+            Ok(authority
+                .get_trusted_public_keys(cert_type)
+                .into_iter()
+                .map(|(_, key)| key)
+                .collect())
+        } else {
+            Err(SigningError::UnknownAuthority(authority.to_string()))
+        }
+    }
+
     /// Return the X509 certificate authority certificate to sign attested X509 requests
     pub fn get_attested_x509_certificate_authority(
         &self,
@@ -242,13 +391,83 @@ impl SigningMechanism {
         }
     }
 
+    /// Return every client certificate authority that should be trusted for
+    /// an authority, including ones that are being rotated in or out
+    pub fn get_trusted_client_certificate_authorities(
+        &self,
+        authority: &str,
+    ) -> Result<Vec<&rcgen::Certificate>, SigningError> {
+        if let Some(authority) = self.authorities.get(authority) {
+            Ok(authority.get_trusted_client_certificate_authorities())
+        } else {
+            Err(SigningError::UnknownAuthority(authority.to_string()))
+        }
+    }
+
+    /// Check every configured authority can still reach its key material.
+    /// The first failure is returned along with the authority it came from.
+    pub async fn check_health(&self) -> Result<(), SigningError> {
+        for (authority, signer) in self.authorities.iter() {
+            signer.check_health().await.map_err(|e| {
+                SigningError::AccessError(format!("Authority {authority} is unhealthy: {e}"))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Collect the trust material for every authority sorted by name.
+    /// Serializing the X509 certificates requires signing them, so this
+    /// should be done once when the configuration is loaded rather than for
+    /// every request.
+    pub fn get_trust_material(&self) -> Result<Vec<AuthorityTrustMaterial>, SigningError> {
+        let mut trust_material = vec![];
+        for (authority, signer) in self.authorities.iter() {
+            // During a rotation there can be several certificates, so their
+            // PEMs are concatenated into a bundle
+            let serialize = |certificates: Vec<&rcgen::Certificate>| {
+                if certificates.is_empty() {
+                    return Ok(None);
+                }
+
+                certificates
+                    .iter()
+                    .map(|certificate| certificate.serialize_pem())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|pems| Some(pems.concat()))
+                    .map_err(|e| {
+                        SigningError::AccessError(format!(
+                            "Could not serialize certificate for authority {authority}: {e}"
+                        ))
+                    })
+            };
+
+            trust_material.push(AuthorityTrustMaterial {
+                authority: authority.to_owned(),
+                user_cas: signer.get_trusted_public_keys(CertType::User),
+                host_cas: signer.get_trusted_public_keys(CertType::Host),
+                attested_x509_certificate_authority: serialize(
+                    signer.get_trusted_attested_x509_certificate_authorities(),
+                )?,
+                client_certificate_authority: serialize(
+                    signer.get_trusted_client_certificate_authorities(),
+                )?,
+            });
+        }
+
+        trust_material.sort_by(|a, b| a.authority.cmp(&b.authority));
+        Ok(trust_material)
+    }
+
     pub fn get_authorities(&self) -> Vec<String> {
         self.authorities.keys().map(|x| x.to_owned()).collect()
     }
 }
 
 impl SigningConfiguration {
-    pub async fn convert_to_signing_mechanism(self) -> Result<SigningMechanism, SigningError> {
+    pub async fn convert_to_signing_mechanism(
+        self,
+        log_sender: &Sender<Log>,
+    ) -> Result<SigningMechanism, SigningError> {
         let authorities = self.authority_configurations;
         // All of the configured signing authorities
         let mut converted_authorities = HashMap::new();
@@ -259,47 +478,50 @@ impl SigningConfiguration {
         // a mistake
         let mut public_keys: HashMap<String, String> = HashMap::new();
         for authority in authorities {
-            // Convert the SignerType in to a Signer trait object
-            let signer = authority.1.into_signer().await?;
+            // Convert the SignerType in to a Signer trait object. Failover
+            // lists time out each of their signers so they are only wrapped
+            // if a timeout is explicitly set.
+            let timeout = match (&authority.1.signer, authority.1.timeout) {
+                (_, Some(timeout)) => Some(timeout),
+                (SignerType::Failover(_), None) => None,
+                (_, None) => Some(default_signer_timeout()),
+            };
+            // A zero timeout would fail every request without ever asking
+            // the signer
+            if timeout == Some(0) {
+                return Err(SigningError::InvalidTimeout(authority.0));
+            }
+            let signer = authority.1.signer.into_signer(&authority.0, log_sender).await?;
+            let signer: Box<dyn Signer + Send + Sync> = match timeout {
+                Some(timeout) => Box::new(timeout::TimeoutSigner::new(signer, timeout)),
+                None => signer,
+            };
 
             // If this has SSH identities configured, make sure they
-            // don't conflict
-            let user_hash = signer
-                .get_signer_public_key(CertType::User)
-                .map(|x| x.fingerprint().hash);
-            let host_hash = signer
-                .get_signer_public_key(CertType::Host)
-                .map(|x| x.fingerprint().hash);
+            // don't conflict. Every key in a rotation set is checked, not
+            // just the active one.
+            let user_hashes: Vec<String> = signer
+                .get_trusted_public_keys(CertType::User)
+                .into_iter()
+                .map(|(_, x)| x.fingerprint().hash)
+                .collect();
+            let host_hashes: Vec<String> = signer
+                .get_trusted_public_keys(CertType::Host)
+                .into_iter()
+                .map(|(_, x)| x.fingerprint().hash)
+                .collect();
 
             // If the user is using the same key for user and host
             // certificate authorities, error and tell them not to do
             // this
-            if let (Some(user_hash), Some(host_hash)) = (user_hash.as_ref(), host_hash.as_ref()) {
-                if user_hash == host_hash {
-                    return Err(SigningError::IdenticalUserAndHostKey(authority.0));
-                }
-            }
-
-            // If the user key is configured
-            if let Some(user_hash) = user_hash {
-                // If this fingerprint is already known in other
-                // authorities
-                if let Some(existing) = public_keys.get(&user_hash) {
-                    return Err(SigningError::DuplicatedKey(
-                        authority.0,
-                        existing.to_owned(),
-                    ));
-                }
-
-                // Remember we've seen this one
-                public_keys.insert(user_hash, authority.0.to_owned());
+            if user_hashes.iter().any(|x| host_hashes.contains(x)) {
+                return Err(SigningError::IdenticalUserAndHostKey(authority.0));
             }
 
-            // If the host key is configured
-            if let Some(host_hash) = host_hash {
+            for hash in user_hashes.into_iter().chain(host_hashes) {
                 // If this fingerprint is already known in other
                 // authorities
-                if let Some(existing) = public_keys.get(&host_hash) {
+                if let Some(existing) = public_keys.get(&hash) {
                     return Err(SigningError::DuplicatedKey(
                         authority.0,
                         existing.to_owned(),
@@ -307,7 +529,7 @@ impl SigningConfiguration {
                 }
 
                 // Remember we've seen this one
-                public_keys.insert(host_hash, authority.0.to_owned());
+                public_keys.insert(hash, authority.0.to_owned());
             }
 
             converted_authorities.insert(authority.0, signer);
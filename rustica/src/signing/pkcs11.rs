@@ -0,0 +1,536 @@
+/// The PKCS#11 signer uses keys stored on any token with a PKCS#11 module,
+/// such as a network HSM or SoftHSM for local testing. Keys are found by
+/// their label and both the private and public key objects must be present
+/// on the token. It currently supports Ecdsa256 and Ecdsa384. To use the
+/// PKCS#11 signer, the `pkcs11` feature must be enabled.
+use super::{Signer, SignerConfig, SigningError};
+
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, AttributeType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+
+use ring::digest;
+use sshcerts::{ssh::CertType, utils::format_signature_for_ssh, Certificate, PublicKey};
+
+use async_trait::async_trait;
+
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+
+use rcgen::{Certificate as X509Certificate, CertificateParams, DnType, IsCa, RcgenError};
+
+/// DER encoded OID for the P256 curve, as found in CKA_EC_PARAMS
+const P256_PARAMS: &[u8] = &[0x06, 0x08, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x03, 0x01, 0x07];
+/// DER encoded OID for the P384 curve, as found in CKA_EC_PARAMS
+const P384_PARAMS: &[u8] = &[0x06, 0x05, 0x2B, 0x81, 0x04, 0x00, 0x22];
+/// DER encoded OID for an EC public key (1.2.840.10045.2.1)
+const EC_PUBLIC_KEY_OID: &[u8] = &[0x06, 0x07, 0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01];
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// The path to the PKCS#11 module to load
+    module_path: String,
+    /// The ID of the slot containing the token with the keys
+    slot: u64,
+    /// The user PIN for the token
+    pin: String,
+    /// The label of the key used to sign user certificates
+    user_key_label: Option<String>,
+    /// The label of the key used to sign host certificates
+    host_key_label: Option<String>,
+    /// The label of the key used to sign X509 certificates
+    x509_key_label: Option<String>,
+    /// The label of the key used to sign client certificates
+    client_certificate_authority_key_label: Option<String>,
+    /// The common name to use in the client certificate authority
+    client_certificate_authority_common_name: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Curve {
+    P256,
+    P384,
+}
+
+impl Curve {
+    /// The length of an uncompressed point on this curve
+    fn point_length(&self) -> usize {
+        match self {
+            Self::P256 => 65,
+            Self::P384 => 97,
+        }
+    }
+
+    /// The length of a single coordinate, and of r and s in a signature
+    fn coordinate_length(&self) -> usize {
+        match self {
+            Self::P256 => 32,
+            Self::P384 => 48,
+        }
+    }
+
+    fn digest_algorithm(&self) -> &'static digest::Algorithm {
+        match self {
+            Self::P256 => &digest::SHA256,
+            Self::P384 => &digest::SHA384,
+        }
+    }
+
+    fn ec_params(&self) -> &'static [u8] {
+        match self {
+            Self::P256 => P256_PARAMS,
+            Self::P384 => P384_PARAMS,
+        }
+    }
+}
+
+/// A key stored on the token
+#[derive(Clone)]
+struct Pkcs11Key {
+    /// The handle of the private key object
+    handle: ObjectHandle,
+    /// The curve the key is on
+    curve: Curve,
+    /// The uncompressed public point of the key
+    point: Vec<u8>,
+}
+
+struct SshKey {
+    key: Pkcs11Key,
+    /// The public key of the CA in SSH format
+    public_key: PublicKey,
+}
+
+struct SshKeys {
+    /// The key that will be used to sign user SSH certificate requests
+    user: SshKey,
+    /// The key that will be used to sign host SSH certificate requests
+    host: SshKey,
+}
+
+pub struct Pkcs11Signer {
+    /// The possibly configured SSH keys to be used for fulfilling SSH
+    /// certificate signing requests
+    ssh_keys: Option<SshKeys>,
+    /// The X509 certificate that will be the issuer for requested x509 certificates
+    x509_certificate: Option<X509Certificate>,
+    /// The X509 certificate that will be the issuer for client certificates
+    client_certificate_authority: Option<X509Certificate>,
+    /// The logged in session with the token. PKCS#11 sessions cannot be used
+    /// concurrently so all operations must hold this lock.
+    session: Arc<Mutex<Session>>,
+}
+
+pub struct Pkcs11RcgenRemoteSigner {
+    key: Pkcs11Key,
+    session: Arc<Mutex<Session>>,
+}
+
+impl rcgen::RemoteKeyPair for Pkcs11RcgenRemoteSigner {
+    fn public_key(&self) -> &[u8] {
+        &self.key.point
+    }
+
+    fn sign(&self, msg: &[u8]) -> Result<Vec<u8>, RcgenError> {
+        let signature =
+            sign_with_token(&self.session, &self.key, msg).map_err(|_| RcgenError::RemoteKeyError)?;
+        Ok(raw_signature_to_der(&signature))
+    }
+
+    fn algorithm(&self) -> &'static rcgen::SignatureAlgorithm {
+        match self.key.curve {
+            Curve::P256 => &rcgen::PKCS_ECDSA_P256_SHA256,
+            Curve::P384 => &rcgen::PKCS_ECDSA_P384_SHA384,
+        }
+    }
+}
+
+/// Hash and sign data with a key on the token. The returned signature is
+/// the raw concatenation of r and s as defined by CKM_ECDSA. This blocks
+/// until the token responds so async callers must run it with
+/// `spawn_blocking`.
+fn sign_with_token(
+    session: &Mutex<Session>,
+    key: &Pkcs11Key,
+    data: &[u8],
+) -> Result<Vec<u8>, SigningError> {
+    // CKM_ECDSA is used instead of the combined hash mechanisms because it
+    // is supported by far more tokens
+    let hash = digest::digest(key.curve.digest_algorithm(), data);
+    let session = session
+        .lock()
+        .map_err(|e| SigningError::AccessError(format!("Could not lock session: {e}")))?;
+
+    let signature = session
+        .sign(&Mechanism::Ecdsa, key.handle, hash.as_ref())
+        .map_err(|e| SigningError::AccessError(e.to_string()))?;
+
+    check_signature_length(key.curve, signature)
+}
+
+/// Tokens return r and s concatenated, each the length of a coordinate on
+/// the curve. Anything else cannot be split back into r and s.
+fn check_signature_length(curve: Curve, signature: Vec<u8>) -> Result<Vec<u8>, SigningError> {
+    if signature.len() != curve.coordinate_length() * 2 {
+        return Err(SigningError::ParsingError);
+    }
+
+    Ok(signature)
+}
+
+/// Encode a single big endian unsigned integer as a DER INTEGER
+fn der_integer(value: &[u8]) -> Vec<u8> {
+    // Zero is still encoded as a single byte
+    let value: &[u8] = match value.iter().position(|x| *x != 0) {
+        Some(start) => &value[start..],
+        None => &[0],
+    };
+
+    let mut encoded = vec![0x02];
+    // Keep the integer positive if the high bit is set
+    if value[0] & 0x80 != 0 {
+        encoded.push(value.len() as u8 + 1);
+        encoded.push(0);
+    } else {
+        encoded.push(value.len() as u8);
+    }
+    encoded.extend_from_slice(value);
+    encoded
+}
+
+/// Convert a raw r || s ECDSA signature into the ASN1 DER form used by X509
+/// and expected by `format_signature_for_ssh`. Both supported curves are small
+/// enough that every length fits in a single byte.
+fn raw_signature_to_der(signature: &[u8]) -> Vec<u8> {
+    let (r, s) = signature.split_at(signature.len() / 2);
+    let mut integers = der_integer(r);
+    integers.extend(der_integer(s));
+
+    let mut encoded = vec![0x30, integers.len() as u8];
+    encoded.extend(integers);
+    encoded
+}
+
+/// Build a DER SubjectPublicKeyInfo for an EC point so it can be converted
+/// to an SSH public key
+fn ec_point_to_spki(curve: Curve, point: &[u8]) -> Vec<u8> {
+    let ec_params = curve.ec_params();
+    let mut algorithm = vec![0x30, (EC_PUBLIC_KEY_OID.len() + ec_params.len()) as u8];
+    algorithm.extend_from_slice(EC_PUBLIC_KEY_OID);
+    algorithm.extend_from_slice(ec_params);
+
+    let mut public_key = vec![0x03, point.len() as u8 + 1, 0x00];
+    public_key.extend_from_slice(point);
+
+    let mut spki = vec![0x30, (algorithm.len() + public_key.len()) as u8];
+    spki.extend(algorithm);
+    spki.extend(public_key);
+    spki
+}
+
+/// Find the private key with the given label and read the public point
+/// from its matching public key object
+fn find_key(session: &Session, label: &str) -> Result<Pkcs11Key, SigningError> {
+    let find = |class: ObjectClass| -> Result<ObjectHandle, SigningError> {
+        session
+            .find_objects(&[
+                Attribute::Class(class),
+                Attribute::Label(label.as_bytes().to_vec()),
+            ])
+            .map_err(|e| SigningError::AccessError(e.to_string()))?
+            .first()
+            .copied()
+            .ok_or(SigningError::AccessError(format!(
+                "Could not find key with label {label}"
+            )))
+    };
+
+    let handle = find(ObjectClass::PRIVATE_KEY)?;
+    let public_handle = find(ObjectClass::PUBLIC_KEY)?;
+
+    let attributes = session
+        .get_attributes(
+            public_handle,
+            &[AttributeType::EcParams, AttributeType::EcPoint],
+        )
+        .map_err(|e| SigningError::AccessError(e.to_string()))?;
+
+    let mut curve = None;
+    let mut point = None;
+    for attribute in attributes {
+        match attribute {
+            Attribute::EcParams(params) if params == P256_PARAMS => curve = Some(Curve::P256),
+            Attribute::EcParams(params) if params == P384_PARAMS => curve = Some(Curve::P384),
+            Attribute::EcPoint(p) => point = Some(p),
+            _ => (),
+        }
+    }
+
+    let curve = curve.ok_or(SigningError::AccessError(format!(
+        "Key {label} is not of a Rustica compatible type"
+    )))?;
+    let point = point.ok_or(SigningError::AccessError(format!(
+        "Could not read public key for {label}"
+    )))?;
+
+    // Most tokens return CKA_EC_POINT wrapped in a DER OCTET STRING but some
+    // return the raw point
+    let point = if point.len() == curve.point_length() {
+        point
+    } else if point.len() == curve.point_length() + 2 && point[0] == 0x04 {
+        point[2..].to_vec()
+    } else {
+        return Err(SigningError::AccessError(format!(
+            "Public key for {label} is malformed"
+        )));
+    };
+
+    Ok(Pkcs11Key {
+        handle,
+        curve,
+        point,
+    })
+}
+
+fn ssh_key_from_token(session: &Session, label: &str) -> Result<SshKey, SigningError> {
+    let key = find_key(session, label)?;
+    let public_key =
+        sshcerts::x509::der_encoding_to_ssh_public_key(&ec_point_to_spki(key.curve, &key.point))
+            .map_err(|_| {
+                SigningError::AccessError(format!("Key is not of a Rustica compatible type {label}"))
+            })?;
+
+    Ok(SshKey { key, public_key })
+}
+
+fn rcgen_certificate_from_token(
+    session: Arc<Mutex<Session>>,
+    common_name: &str,
+    label: &str,
+) -> Result<X509Certificate, SigningError> {
+    let key = {
+        let session = session
+            .lock()
+            .map_err(|e| SigningError::AccessError(format!("Could not lock session: {e}")))?;
+        find_key(&session, label)?
+    };
+
+    let mut ca_params = CertificateParams::new(vec![]);
+    ca_params.is_ca = IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    ca_params
+        .distinguished_name
+        .push(DnType::CommonName, common_name);
+
+    let remote_signer = Pkcs11RcgenRemoteSigner { key, session };
+    ca_params.alg = rcgen::RemoteKeyPair::algorithm(&remote_signer);
+
+    let kp = match rcgen::KeyPair::from_remote(Box::new(remote_signer)) {
+        Ok(kp) => kp,
+        Err(_) => {
+            return Err(SigningError::AccessError(format!(
+                "Could not create remote signer for key {label}"
+            )))
+        }
+    };
+
+    ca_params.key_pair = Some(kp);
+    X509Certificate::from_params(ca_params).map_err(|_| {
+        SigningError::AccessError(format!("Could not create certificate for key {label}"))
+    })
+}
+
+#[async_trait]
+impl SignerConfig for Config {
+    async fn into_signer(self) -> Result<Box<dyn Signer + Send + Sync>, SigningError> {
+        let pkcs11 = Pkcs11::new(&self.module_path).map_err(|e| {
+            SigningError::AccessError(format!(
+                "Could not load PKCS#11 module {}: {e}",
+                self.module_path
+            ))
+        })?;
+        pkcs11
+            .initialize(CInitializeArgs::OsThreads)
+            .map_err(|e| SigningError::AccessError(e.to_string()))?;
+
+        let slot = pkcs11
+            .get_slots_with_token()
+            .map_err(|e| SigningError::AccessError(e.to_string()))?
+            .into_iter()
+            .find(|slot| slot.id() == self.slot)
+            .ok_or(SigningError::AccessError(format!(
+                "No token found in slot {}",
+                self.slot
+            )))?;
+
+        let session = pkcs11
+            .open_ro_session(slot)
+            .map_err(|e| SigningError::AccessError(e.to_string()))?;
+        session
+            .login(UserType::User, Some(&AuthPin::new(self.pin)))
+            .map_err(|e| SigningError::AccessError(format!("Could not log in to token: {e}")))?;
+
+        let ssh_keys = match (&self.user_key_label, &self.host_key_label) {
+            (Some(user), Some(host)) => Some(SshKeys {
+                user: ssh_key_from_token(&session, user)?,
+                host: ssh_key_from_token(&session, host)?,
+            }),
+            (None, None) => None,
+            _ => return Err(SigningError::SignerDoesNotAllRequiredSSHKeys),
+        };
+
+        let session = Arc::new(Mutex::new(session));
+
+        let x509_certificate = match &self.x509_key_label {
+            Some(label) => Some(rcgen_certificate_from_token(
+                session.clone(),
+                "Rustica",
+                label,
+            )?),
+            None => None,
+        };
+
+        let client_certificate_authority = if let (Some(label), Some(cn)) = (
+            &self.client_certificate_authority_key_label,
+            &self.client_certificate_authority_common_name,
+        ) {
+            Some(rcgen_certificate_from_token(session.clone(), cn, label)?)
+        } else {
+            None
+        };
+
+        Ok(Box::new(Pkcs11Signer {
+            ssh_keys,
+            x509_certificate,
+            client_certificate_authority,
+            session,
+        }))
+    }
+}
+
+#[async_trait]
+impl Signer for Pkcs11Signer {
+    async fn sign(&self, cert: Certificate) -> Result<Certificate, SigningError> {
+        let ssh_keys = self
+            .ssh_keys
+            .as_ref()
+            .ok_or(SigningError::SignerDoesNotHaveSSHKeys)?;
+
+        let key = match cert.cert_type {
+            CertType::User => &ssh_keys.user,
+            CertType::Host => &ssh_keys.host,
+        };
+
+        let session = self.session.clone();
+        let token_key = key.key.clone();
+        let tbs_certificate = cert.tbs_certificate();
+        let signature = tokio::task::spawn_blocking(move || {
+            sign_with_token(&session, &token_key, &tbs_certificate)
+        })
+        .await
+        .map_err(|e| SigningError::AccessError(format!("Signing task failed: {e}")))??;
+
+        // Convert to SSH styled signature
+        let signature =
+            match format_signature_for_ssh(&key.public_key, &raw_signature_to_der(&signature)) {
+                Some(s) => s,
+                None => return Err(SigningError::ParsingError),
+            };
+
+        cert.add_signature(&signature)
+            .map_err(|_| SigningError::SigningFailure)
+    }
+
+    fn get_signer_public_key(&self, cert_type: CertType) -> Option<PublicKey> {
+        let ssh_keys = self.ssh_keys.as_ref()?;
+
+        match cert_type {
+            CertType::User => Some(ssh_keys.user.public_key.clone()),
+            CertType::Host => Some(ssh_keys.host.public_key.clone()),
+        }
+    }
+
+    fn get_attested_x509_certificate_authority(&self) -> Option<&rcgen::Certificate> {
+        self.x509_certificate.as_ref()
+    }
+
+    fn get_client_certificate_authority(&self) -> Option<&rcgen::Certificate> {
+        self.client_certificate_authority.as_ref()
+    }
+
+    async fn check_health(&self) -> Result<(), SigningError> {
+        let session = self.session.clone();
+        tokio::task::spawn_blocking(move || -> Result<(), SigningError> {
+            let session = session
+                .lock()
+                .map_err(|e| SigningError::AccessError(format!("Could not lock session: {e}")))?;
+
+            session
+                .get_session_info()
+                .map(|_| ())
+                .map_err(|e| SigningError::AccessError(format!("Token session is not usable: {e}")))
+        })
+        .await
+        .map_err(|e| SigningError::AccessError(format!("Health check task failed: {e}")))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::rand::SystemRandom;
+    use ring::signature::{self, EcdsaKeyPair, KeyPair, UnparsedPublicKey};
+
+    #[test]
+    fn signatures_must_match_the_curve() {
+        assert!(check_signature_length(Curve::P256, vec![1; 64]).is_ok());
+        assert!(check_signature_length(Curve::P384, vec![1; 96]).is_ok());
+
+        for length in [0, 63, 65, 96] {
+            assert!(matches!(
+                check_signature_length(Curve::P256, vec![1; length]),
+                Err(SigningError::ParsingError)
+            ));
+        }
+        assert!(matches!(
+            check_signature_length(Curve::P384, vec![1; 64]),
+            Err(SigningError::ParsingError)
+        ));
+    }
+
+    #[test]
+    fn raw_signatures_convert_to_der() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(
+            &signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+            pkcs8.as_ref(),
+            &rng,
+        )
+        .unwrap();
+        let public_key = UnparsedPublicKey::new(
+            &signature::ECDSA_P256_SHA256_ASN1,
+            key.public_key().as_ref(),
+        );
+
+        // Enough signatures that some have r or s with the high bit set or
+        // with leading zeros
+        for message in 0..32u8 {
+            let raw = key.sign(&rng, &[message]).unwrap();
+            let raw = check_signature_length(Curve::P256, raw.as_ref().to_vec()).unwrap();
+            public_key
+                .verify(&[message], &raw_signature_to_der(&raw))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn integers_are_minimal_and_positive() {
+        assert_eq!(der_integer(&[0, 0, 1]), vec![0x02, 0x01, 0x01]);
+        assert_eq!(der_integer(&[0, 0]), vec![0x02, 0x01, 0x00]);
+        assert_eq!(der_integer(&[0, 0x80]), vec![0x02, 0x02, 0x00, 0x80]);
+    }
+}
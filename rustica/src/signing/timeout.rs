@@ -0,0 +1,65 @@
+/// Signers can hang, for example when a KMS region stops responding or a
+/// Yubikey wedges. Since certificate requests wait on the signer, a hung
+/// signer would hang every request. This wraps a signer so signing and
+/// health checks give up after a fixed time. The timeout can only fire when
+/// the signer yields, so signers that block on hardware must do that work
+/// with `spawn_blocking`.
+use super::{KeyState, Signer, SigningError};
+
+use async_trait::async_trait;
+use sshcerts::{ssh::CertType, Certificate, PublicKey};
+
+use std::time::Duration;
+
+pub struct TimeoutSigner {
+    signer: Box<dyn Signer + Send + Sync>,
+    timeout: Duration,
+}
+
+impl TimeoutSigner {
+    pub fn new(signer: Box<dyn Signer + Send + Sync>, timeout: u64) -> Self {
+        Self {
+            signer,
+            timeout: Duration::from_secs(timeout),
+        }
+    }
+}
+
+#[async_trait]
+impl Signer for TimeoutSigner {
+    async fn sign(&self, cert: Certificate) -> Result<Certificate, SigningError> {
+        tokio::time::timeout(self.timeout, self.signer.sign(cert))
+            .await
+            .map_err(|_| SigningError::TimedOut(self.timeout.as_secs()))?
+    }
+
+    fn get_signer_public_key(&self, cert_type: CertType) -> Option<PublicKey> {
+        self.signer.get_signer_public_key(cert_type)
+    }
+
+    fn get_trusted_public_keys(&self, cert_type: CertType) -> Vec<(KeyState, PublicKey)> {
+        self.signer.get_trusted_public_keys(cert_type)
+    }
+
+    fn get_attested_x509_certificate_authority(&self) -> Option<&rcgen::Certificate> {
+        self.signer.get_attested_x509_certificate_authority()
+    }
+
+    fn get_trusted_attested_x509_certificate_authorities(&self) -> Vec<&rcgen::Certificate> {
+        self.signer.get_trusted_attested_x509_certificate_authorities()
+    }
+
+    fn get_client_certificate_authority(&self) -> Option<&rcgen::Certificate> {
+        self.signer.get_client_certificate_authority()
+    }
+
+    fn get_trusted_client_certificate_authorities(&self) -> Vec<&rcgen::Certificate> {
+        self.signer.get_trusted_client_certificate_authorities()
+    }
+
+    async fn check_health(&self) -> Result<(), SigningError> {
+        tokio::time::timeout(self.timeout, self.signer.check_health())
+            .await
+            .map_err(|_| SigningError::TimedOut(self.timeout.as_secs()))?
+    }
+}
@@ -163,21 +163,29 @@ impl Signer for YubikeySigner {
             CertType::Host => ssh_keys.host.slot,
         };
 
-        match self.yubikey.lock() {
-            Ok(_) => {
-                // Unfortunatly we need to create a new Yubikey here because otherwise
-                // everything will have to be mutable which causes an issue
-                // for the RusticaServer struct
-                let mut yk = Yubikey::new().unwrap();
-                match yk.ssh_cert_signer(&cert.tbs_certificate(), &slot) {
-                    Ok(sig) => cert
-                        .add_signature(&sig)
-                        .map_err(|_| SigningError::SigningFailure),
-                    Err(_) => Err(SigningError::SigningFailure),
-                }
-            }
-            Err(e) => Err(SigningError::AccessError(e.to_string())),
-        }
+        // Talking to the Yubikey blocks the thread it runs on. Doing it off
+        // the async runtime means a wedged Yubikey cannot stall other
+        // requests and the authority's timeout can still fire.
+        let yubikey = self.yubikey.clone();
+        let tbs_certificate = cert.tbs_certificate();
+        let signature = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, SigningError> {
+            let _lock = yubikey
+                .lock()
+                .map_err(|e| SigningError::AccessError(e.to_string()))?;
+
+            // Unfortunatly we need to create a new Yubikey here because otherwise
+            // everything will have to be mutable which causes an issue
+            // for the RusticaServer struct
+            let mut yk = Yubikey::new()
+                .map_err(|e| SigningError::AccessError(format!("Could not open Yubikey: {e}")))?;
+            yk.ssh_cert_signer(&tbs_certificate, &slot)
+                .map_err(|_| SigningError::SigningFailure)
+        })
+        .await
+        .map_err(|e| SigningError::AccessError(format!("Signing task failed: {e}")))??;
+
+        cert.add_signature(&signature)
+            .map_err(|_| SigningError::SigningFailure)
     }
 
     fn get_signer_public_key(&self, cert_type: CertType) -> Option<PublicKey> {
@@ -196,6 +204,33 @@ impl Signer for YubikeySigner {
     fn get_client_certificate_authority(&self) -> Option<&rcgen::Certificate> {
         self.client_certificate_authority.as_ref()
     }
+
+    async fn check_health(&self) -> Result<(), SigningError> {
+        let yubikey = self.yubikey.clone();
+        let user_slot = self.ssh_keys.as_ref().map(|ssh_keys| ssh_keys.user.slot);
+
+        // Like signing, this blocks so it runs off the async runtime
+        tokio::task::spawn_blocking(move || -> Result<(), SigningError> {
+            let _lock = yubikey
+                .lock()
+                .map_err(|e| SigningError::AccessError(e.to_string()))?;
+
+            // Like signing, this needs a new Yubikey handle. Opening it fails if
+            // the Yubikey has been disconnected.
+            let mut yk = Yubikey::new()
+                .map_err(|e| SigningError::AccessError(format!("Could not open Yubikey: {e}")))?;
+
+            if let Some(slot) = user_slot {
+                yk.ssh_cert_fetch_pubkey(&slot).map_err(|_| {
+                    SigningError::AccessError("Could not fetch public key for user key".to_owned())
+                })?;
+            }
+
+            Ok(())
+        })
+        .await
+        .map_err(|e| SigningError::AccessError(format!("Health check task failed: {e}")))?
+    }
 }
 
 pub fn parse_option_slot<'de, D>(deserializer: D) -> Result<Option<SlotId>, D::Error>